@@ -0,0 +1,25 @@
+use std::process::Command;
+
+fn main() {
+    // Commit the binary was built from, part of the simulated enclave measurement.
+    // Builds without the git checkout (e.g. in a container) pass it through ENCLAVA_GIT_HASH.
+    let git_hash = std::env::var("ENCLAVA_GIT_HASH")
+        .ok()
+        .or_else(git_head)
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=ENCLAVA_GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+    println!("cargo:rerun-if-env-changed=ENCLAVA_GIT_HASH");
+}
+
+fn git_head() -> Option<String> {
+    Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+}
@@ -0,0 +1,18 @@
+-- Payments consumed by /chat/agents/answer.
+-- The UNIQUE tx_hash is what stops a paid transaction from being replayed.
+CREATE TABLE payments (
+   id BIGSERIAL PRIMARY KEY,
+   tx_hash VARCHAR(255) NOT NULL,
+   payer_address VARCHAR(255) NOT NULL,
+   agent_ids BIGINT[] NOT NULL,
+   amounts DOUBLE PRECISION[] NOT NULL,
+   block_number BIGINT NOT NULL,
+   consumed_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT uq_payments_tx_hash UNIQUE (tx_hash)
+);
+
+-- Fast lookup of all payments made by a buyer
+CREATE INDEX idx_payments_payer_address ON payments (payer_address);
+
+-- Fast queries on time ranges
+CREATE INDEX idx_payments_consumed_at ON payments (consumed_at);
@@ -0,0 +1,27 @@
+-- Sign-In-With-Ethereum nonces, each one can only be used once
+CREATE TABLE auth_nonces (
+   nonce VARCHAR(64) PRIMARY KEY,
+   address VARCHAR(255) NOT NULL,
+   message TEXT NOT NULL,
+   expires_at TIMESTAMPTZ NOT NULL,
+   used_at TIMESTAMPTZ NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW ()
+);
+
+-- Sessions issued after a successful SIWE verification.
+-- Only the keccak256 hash of the bearer token is stored.
+CREATE TABLE sessions (
+   id BIGSERIAL PRIMARY KEY,
+   token_hash VARCHAR(66) UNIQUE NOT NULL,
+   address VARCHAR(255) NOT NULL,
+   expires_at TIMESTAMPTZ NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW ()
+);
+
+-- Fast lookup of all sessions of an address
+CREATE INDEX idx_sessions_address ON sessions (address);
+
+-- Fast cleanup of expired nonces and sessions
+CREATE INDEX idx_auth_nonces_expires_at ON auth_nonces (expires_at);
+
+CREATE INDEX idx_sessions_expires_at ON sessions (expires_at);
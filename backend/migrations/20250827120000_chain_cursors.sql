@@ -0,0 +1,12 @@
+-- Last block processed by each contract event fetcher, so they resume after a restart
+CREATE TABLE chain_cursors (
+   contract_address VARCHAR(255) NOT NULL,
+   event_name VARCHAR(255) NOT NULL,
+   last_block BIGINT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   PRIMARY KEY (contract_address, event_name)
+);
+
+CREATE TRIGGER trg_chain_cursors_updated_at BEFORE
+UPDATE ON chain_cursors FOR EACH ROW EXECUTE FUNCTION set_updated_at ();
@@ -0,0 +1,19 @@
+-- Contract logs applied to the database, with the block hash they were seen in.
+-- When a stored block hash is no longer canonical the effects of the log are rolled back.
+CREATE TABLE ingested_logs (
+   id BIGSERIAL PRIMARY KEY,
+   contract_address VARCHAR(255) NOT NULL,
+   event_name VARCHAR(255) NOT NULL,
+   block_number BIGINT NOT NULL,
+   block_hash VARCHAR(66) NOT NULL,
+   tx_hash VARCHAR(66) NOT NULL,
+   log_index BIGINT NOT NULL,
+   agent_id BIGINT NULL,
+   nft_id BIGINT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT uq_ingested_logs_tx_log UNIQUE (tx_hash, log_index),
+   CONSTRAINT fk_ingested_logs_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE SET NULL
+);
+
+-- Fast lookup of recent logs of a fetcher when checking for reorgs
+CREATE INDEX idx_ingested_logs_fetcher_block ON ingested_logs (contract_address, event_name, block_number);
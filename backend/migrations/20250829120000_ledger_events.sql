@@ -0,0 +1,44 @@
+-- Local ledger of DatasetUsed events (buyers paying for agents)
+CREATE TABLE usage_events (
+   id BIGSERIAL PRIMARY KEY,
+   ingested_log_id BIGINT UNIQUE NOT NULL,
+   nft_id BIGINT NOT NULL,
+   agent_id BIGINT NULL,
+   user_address VARCHAR(255) NOT NULL,
+   amount DOUBLE PRECISION NOT NULL,
+   amount_wei TEXT NOT NULL,
+   tx_hash VARCHAR(66) NOT NULL,
+   block_number BIGINT NOT NULL,
+   block_timestamp TIMESTAMPTZ NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_usage_events_ingested_log FOREIGN KEY (ingested_log_id) REFERENCES ingested_logs (id) ON DELETE CASCADE,
+   CONSTRAINT fk_usage_events_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE SET NULL
+);
+
+-- Local ledger of AmountClaimed events (owners withdrawing their earnings)
+CREATE TABLE claim_events (
+   id BIGSERIAL PRIMARY KEY,
+   ingested_log_id BIGINT UNIQUE NOT NULL,
+   nft_id BIGINT NOT NULL,
+   agent_id BIGINT NULL,
+   owner_address VARCHAR(255) NOT NULL,
+   amount DOUBLE PRECISION NOT NULL,
+   amount_wei TEXT NOT NULL,
+   tx_hash VARCHAR(66) NOT NULL,
+   block_number BIGINT NOT NULL,
+   block_timestamp TIMESTAMPTZ NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_claim_events_ingested_log FOREIGN KEY (ingested_log_id) REFERENCES ingested_logs (id) ON DELETE CASCADE,
+   CONSTRAINT fk_claim_events_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE SET NULL
+);
+
+-- Payment verification looks events up by tx hash
+CREATE INDEX idx_usage_events_tx_hash ON usage_events (tx_hash);
+
+-- Fast per agent aggregations for owner dashboards
+CREATE INDEX idx_usage_events_agent_id ON usage_events (agent_id);
+
+CREATE INDEX idx_claim_events_agent_id ON claim_events (agent_id);
+
+-- Fast lookup by the buyer
+CREATE INDEX idx_usage_events_user_address ON usage_events (user_address);
@@ -0,0 +1,14 @@
+-- Row groups of each agent dataset with their embedding, used for retrieval at prompt time
+CREATE TABLE dataset_chunks (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   chunk_index INTEGER NOT NULL,
+   first_row BIGINT NOT NULL,
+   last_row BIGINT NOT NULL,
+   content TEXT NOT NULL,
+   embedding DOUBLE PRECISION[] NOT NULL,
+   embedding_model VARCHAR(255) NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_dataset_chunks_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT uq_dataset_chunks_agent_chunk UNIQUE (agent_id, chunk_index)
+);
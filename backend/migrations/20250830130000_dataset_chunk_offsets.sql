@@ -0,0 +1,3 @@
+-- Chunks only keep the rows range they embed, their rows are read again from the encrypted dataset
+-- inside the enclave. Stored plaintext rows are dropped.
+ALTER TABLE dataset_chunks DROP COLUMN content;
@@ -0,0 +1,5 @@
+-- Format of the file uploaded by the owner, datasets are stored normalized to CSV
+CREATE TYPE dataset_format AS ENUM ('csv', 'tsv', 'jsonl', 'parquet', 'xlsx');
+
+-- Datasets uploaded before other formats were supported are all CSV
+ALTER TABLE agents ADD COLUMN format dataset_format NOT NULL DEFAULT 'csv';
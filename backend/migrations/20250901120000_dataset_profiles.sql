@@ -0,0 +1,2 @@
+-- Column profile of the agent dataset (types, null ratios, distinct counts, min/max, samples)
+ALTER TABLE agents ADD COLUMN profile JSONB NULL;
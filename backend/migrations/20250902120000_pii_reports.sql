@@ -0,0 +1,2 @@
+-- PII found in the agent dataset at upload and what was done to each column
+ALTER TABLE agents ADD COLUMN pii_report JSONB NULL;
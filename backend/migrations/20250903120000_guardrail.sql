@@ -0,0 +1,26 @@
+-- Keyed hashes of a sample of the dataset word n-grams, answers are checked against them
+CREATE TABLE dataset_fingerprints (
+   agent_id BIGINT PRIMARY KEY,
+   ngram_size INTEGER NOT NULL,
+   shingles BIGINT[] NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_dataset_fingerprints_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+CREATE TYPE guardrail_verdict AS ENUM ('allowed', 'redacted', 'blocked');
+
+-- Verdict of the output guardrail on every paid answer, reviewed by the dataset owner
+CREATE TABLE guardrail_events (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   tx_hash VARCHAR(255) NOT NULL,
+   buyer_address VARCHAR(255) NOT NULL,
+   prompt TEXT NOT NULL,
+   verdict guardrail_verdict NOT NULL,
+   overlap_ratio DOUBLE PRECISION NOT NULL,
+   pii_types JSONB NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_guardrail_events_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_guardrail_events_agent ON guardrail_events (agent_id, created_at DESC);
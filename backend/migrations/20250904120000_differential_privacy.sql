@@ -0,0 +1,20 @@
+-- Differential privacy mode: answers only come from noisy aggregates and every answer spends
+-- dp_epsilon out of the dp_budget of its buyer
+ALTER TABLE agents
+    ADD COLUMN dp_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN dp_epsilon DOUBLE PRECISION NULL,
+    ADD COLUMN dp_budget DOUBLE PRECISION NULL,
+    -- Clamping bounds of the numeric columns, chosen by the owner at upload so the noise never
+    -- depends on the data. Columns without bounds only allow counts.
+    ADD COLUMN dp_bounds JSONB NULL,
+    ADD CONSTRAINT chk_agents_dp CHECK (NOT dp_enabled OR (dp_epsilon > 0 AND dp_budget >= dp_epsilon));
+
+-- Epsilon spent by each buyer on each differential privacy agent
+CREATE TABLE dp_budgets (
+   agent_id BIGINT NOT NULL,
+   buyer_address VARCHAR(255) NOT NULL,
+   epsilon_spent DOUBLE PRECISION NOT NULL,
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   PRIMARY KEY (agent_id, buyer_address),
+   CONSTRAINT fk_dp_budgets_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
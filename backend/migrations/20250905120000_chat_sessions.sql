@@ -0,0 +1,32 @@
+-- Multi turn conversations of a buyer with the agents selected at the first turn.
+-- Each payment covers CHAT_TURNS_PER_PAYMENT turns, tx_hash is the latest one, answers are attested for it.
+CREATE TABLE chat_sessions (
+   id BIGSERIAL PRIMARY KEY,
+   buyer_address VARCHAR(255) NOT NULL,
+   agent_ids BIGINT[] NOT NULL,
+   tx_hash VARCHAR(255) NOT NULL,
+   turns_paid INTEGER NOT NULL,
+   turns_used INTEGER NOT NULL DEFAULT 0,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT chk_chat_sessions_turns CHECK (turns_used <= turns_paid)
+);
+
+CREATE INDEX idx_chat_sessions_buyer_address ON chat_sessions (buyer_address);
+
+CREATE TYPE chat_role AS ENUM ('user', 'assistant');
+
+-- The prompt of a turn (agent_id NULL) and the answers of the agents, as sent to the buyer
+CREATE TABLE chat_messages (
+   id BIGSERIAL PRIMARY KEY,
+   session_id BIGINT NOT NULL,
+   turn INTEGER NOT NULL,
+   agent_id BIGINT,
+   role chat_role NOT NULL,
+   content TEXT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_chat_messages_session FOREIGN KEY (session_id) REFERENCES chat_sessions (id) ON DELETE CASCADE,
+   CONSTRAINT fk_chat_messages_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_chat_messages_session ON chat_messages (session_id, turn);
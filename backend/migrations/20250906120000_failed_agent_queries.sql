@@ -0,0 +1,20 @@
+CREATE TYPE agent_answer_status AS ENUM ('answered', 'failed', 'timed_out');
+
+-- Agents that gave no answer to a paid turn, the buyer is owed a refund or a credit for them
+CREATE TABLE failed_agent_queries (
+   id BIGSERIAL PRIMARY KEY,
+   agent_id BIGINT NOT NULL,
+   session_id BIGINT NOT NULL,
+   turn INTEGER NOT NULL,
+   buyer_address VARCHAR(255) NOT NULL,
+   tx_hash VARCHAR(255) NOT NULL,
+   prompt TEXT NOT NULL,
+   status agent_answer_status NOT NULL,
+   error_code VARCHAR(64) NOT NULL,
+   error TEXT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_failed_agent_queries_agent FOREIGN KEY (agent_id) REFERENCES agents (id) ON DELETE CASCADE,
+   CONSTRAINT fk_failed_agent_queries_session FOREIGN KEY (session_id) REFERENCES chat_sessions (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_failed_agent_queries_buyer ON failed_agent_queries (buyer_address, created_at DESC);
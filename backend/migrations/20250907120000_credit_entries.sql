@@ -0,0 +1,17 @@
+CREATE TYPE credit_kind AS ENUM ('failed_answer', 'spent', 'settled');
+
+-- Ledger of the credits owed to buyers, the balance of a buyer is the sum of its entries.
+-- Failed agent answers credit the buyer, answers paid with credits and admin settlements debit it.
+CREATE TABLE credit_entries (
+   id BIGSERIAL PRIMARY KEY,
+   buyer_address VARCHAR(255) NOT NULL,
+   amount DOUBLE PRECISION NOT NULL,
+   kind credit_kind NOT NULL,
+   failed_query_id BIGINT,
+   note TEXT,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   CONSTRAINT fk_credit_entries_failed_query FOREIGN KEY (failed_query_id) REFERENCES failed_agent_queries (id) ON DELETE SET NULL,
+   CONSTRAINT uq_credit_entries_failed_query UNIQUE (failed_query_id)
+);
+
+CREATE INDEX idx_credit_entries_buyer ON credit_entries (buyer_address, created_at DESC);
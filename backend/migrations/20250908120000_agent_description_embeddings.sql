@@ -0,0 +1,11 @@
+-- Embedding of the agent description (name, category, description, dataset columns) used to route prompts.
+-- The model is stored so descriptions embedded by an older model are embedded again on startup.
+CREATE EXTENSION IF NOT EXISTS vector;
+
+-- The dimension of EMBEDDING_MODEL (text-embedding-004)
+ALTER TABLE agents ADD COLUMN description_embedding vector(768) NULL;
+ALTER TABLE agents ADD COLUMN description_embedding_model VARCHAR(255) NULL;
+
+-- Embeddings are normalized, the inner product orders them like the cosine similarity
+CREATE INDEX idx_agents_description_embedding
+    ON agents USING hnsw (description_embedding vector_ip_ops);
@@ -0,0 +1,8 @@
+-- Embedding model the dataset chunks of the agent were last ingested with, NULL when never ingested.
+-- A dataset without rows has no chunks, so their absence can not tell whether it was ingested.
+ALTER TABLE agents ADD COLUMN chunks_embedding_model VARCHAR(255) NULL;
+
+UPDATE agents a
+SET chunks_embedding_model = c.embedding_model
+FROM (SELECT DISTINCT ON (agent_id) agent_id, embedding_model FROM dataset_chunks) c
+WHERE a.id = c.agent_id;
@@ -0,0 +1,3 @@
+-- Fingerprints are now hashed with a key derived from the master key, not the master key itself.
+-- The agents without fingerprints have their dataset ingested again on startup.
+DELETE FROM dataset_fingerprints;
@@ -0,0 +1,7 @@
+-- Agents paid by a 'spent' entry and the price paid for each, failed answers are credited from them.
+-- payment_hash stands for the tx hash of the payment, the answers of the agents are attested for it.
+ALTER TABLE credit_entries
+ADD COLUMN payment_hash VARCHAR(255) NULL,
+ADD COLUMN agent_ids BIGINT[] NULL,
+ADD COLUMN agent_amounts DOUBLE PRECISION[] NULL,
+ADD CONSTRAINT uq_credit_entries_payment_hash UNIQUE (payment_hash);
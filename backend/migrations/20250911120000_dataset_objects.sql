@@ -0,0 +1,8 @@
+-- Datasets kept as Postgres large objects when DATASET_STORE is pg, see `storage::pg`.
+-- A replaced or deleted dataset has its large object unlinked in the same transaction.
+CREATE TABLE dataset_objects (
+   key VARCHAR(255) PRIMARY KEY,
+   oid OID NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW (),
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW ()
+);
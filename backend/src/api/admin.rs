@@ -0,0 +1,200 @@
+use std::str::FromStr;
+
+use actix_web::{HttpResponse, Responder, get, post, web};
+use alloy::primitives::Address;
+use tracing::{error, info};
+
+use crate::{
+    database,
+    helpers::{self, auth::AdminUser},
+    state::AppState,
+    types::{
+        CreditBalancesResponse, CreditKind, ErrorResponse, SettleCreditsRequest,
+        SettleCreditsResponse,
+    },
+};
+
+#[utoipa::path(
+    get,
+    path = "/admin/credits",
+    responses(
+        (status = 200, description = "Credit balances of the buyers", body = CreditBalancesResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 403, description = "The session wallet is not an admin", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Admin"
+)]
+#[get("/admin/credits")]
+async fn get_credits_service(app_state: web::Data<AppState>, _admin: AdminUser) -> impl Responder {
+    match database::get_credit_balances(&app_state.db).await {
+        Ok(balances) => HttpResponse::Ok().json(CreditBalancesResponse {
+            success: true,
+            balances,
+        }),
+        Err(e) => {
+            error!("Failed to get credit balances: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get credit balances from database".to_string(),
+                error_code: Some("CREDITS_FETCH_FAILED".to_string()),
+            })
+        }
+    }
+}
+
+/*
+Record that credits were refunded to a buyer outside of the platform (e.g. a refund tx), they are debited from its balance.
+*/
+#[utoipa::path(
+    post,
+    path = "/admin/credits/{address}/settle",
+    params(
+        ("address" = String, Path, description = "Buyer wallet address")
+    ),
+    request_body(
+        content = SettleCreditsRequest,
+        content_type = "application/json",
+        description = "Amount refunded, the whole balance when not set"
+    ),
+    responses(
+        (status = 200, description = "Credits settled", body = SettleCreditsResponse),
+        (status = 400, description = "Invalid address or amount above the balance", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 403, description = "The session wallet is not an admin", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Admin"
+)]
+#[post("/admin/credits/{address}/settle")]
+async fn settle_credits_service(
+    app_state: web::Data<AppState>,
+    admin: AdminUser,
+    path: web::Path<String>,
+    body: web::Json<SettleCreditsRequest>,
+) -> impl Responder {
+    // Ledger entries use the checksummed address of the session
+    let buyer_address = match Address::from_str(path.trim()) {
+        Ok(address) => address.to_string(),
+        Err(_) => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: "Invalid buyer address".to_string(),
+                error_code: Some("INVALID_ADDRESS".to_string()),
+            });
+        }
+    };
+
+    if body
+        .amount
+        .is_some_and(|amount| !amount.is_finite() || amount <= 0.0)
+    {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "The amount must be a positive number".to_string(),
+            error_code: Some("INVALID_AMOUNT".to_string()),
+        });
+    }
+
+    let mut tx = match app_state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to start database transaction".to_string(),
+                error_code: Some("DB_TRANSACTION_FAILED".to_string()),
+            });
+        }
+    };
+
+    let amount = match body.amount {
+        Some(amount) => amount,
+        None => {
+            // Lock before reading the balance to settle, like the debit does
+            let balance = match database::lock_buyer_credits(&mut tx, &buyer_address).await {
+                Ok(()) => database::get_credit_balance(&mut tx, &buyer_address).await,
+                Err(e) => Err(e),
+            };
+
+            match balance {
+                Ok(balance) if balance > 0.0 => balance,
+                Ok(_) => {
+                    tx.rollback().await.ok();
+
+                    return HttpResponse::BadRequest().json(ErrorResponse {
+                        success: false,
+                        message: "The buyer has no credits to settle".to_string(),
+                        error_code: Some("NO_CREDITS".to_string()),
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to get credit balance: {}", e);
+
+                    tx.rollback().await.ok();
+
+                    return HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to get credit balance from database".to_string(),
+                        error_code: Some("CREDITS_FETCH_FAILED".to_string()),
+                    });
+                }
+            }
+        }
+    };
+
+    let balance = match helpers::credits::debit_credits(
+        &mut tx,
+        &buyer_address,
+        amount,
+        CreditKind::Settled,
+        body.note.clone(),
+    )
+    .await
+    {
+        Ok(Some((_, balance))) => balance,
+        Ok(None) => {
+            tx.rollback().await.ok();
+
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: "The amount is above the credit balance of the buyer".to_string(),
+                error_code: Some("INSUFFICIENT_CREDITS".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to settle credits: {}", e);
+
+            tx.rollback().await.ok();
+
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to record credits".to_string(),
+                error_code: Some("CREDITS_UPDATE_FAILED".to_string()),
+            });
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        error!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to commit database transaction".to_string(),
+            error_code: Some("DB_COMMIT_FAILED".to_string()),
+        });
+    }
+
+    info!(
+        "Admin {} settled {} credits of {}",
+        admin.address, amount, buyer_address
+    );
+
+    HttpResponse::Ok().json(SettleCreditsResponse {
+        success: true,
+        buyer_address,
+        settled: amount,
+        balance,
+    })
+}
@@ -0,0 +1,97 @@
+use actix_web::{HttpResponse, Responder, get, post, web};
+use tracing::{error, warn};
+
+use crate::{
+    state::AppState,
+    tee::attestation::verify_agent_attestation,
+    types::{
+        AttestationResponse, ErrorResponse, VerifyAttestationRequest, VerifyAttestationResponse,
+    },
+};
+
+#[utoipa::path(
+    get,
+    path = "/attestation",
+    responses(
+        (status = 200, description = "Enclave attestation retrieved successfully", body = AttestationResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Attestation"
+)]
+#[get("/attestation")]
+async fn get_attestation_service(app_state: web::Data<AppState>) -> impl Responder {
+    let quote = match app_state.tee.quote().await {
+        Ok(quote) => quote,
+        Err(e) => {
+            error!("Failed to generate attestation quote: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to generate attestation quote".to_string(),
+                error_code: Some("ATTESTATION_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(AttestationResponse {
+        success: true,
+        provider: quote.provider,
+        measurement: quote.measurement.to_string(),
+        signer_address: quote.signer_address.to_string(),
+        quote: quote.quote.to_string(),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/attestation/verify",
+    request_body(
+        content = VerifyAttestationRequest,
+        content_type = "application/json",
+        description = "Agent answer and the attestation returned with it by /chat/agents/answer"
+    ),
+    responses(
+        (status = 200, description = "Attestation checked", body = VerifyAttestationResponse),
+        (status = 400, description = "Bad request - malformed attestation", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Attestation"
+)]
+#[post("/attestation/verify")]
+async fn verify_attestation_service(
+    app_state: web::Data<AppState>,
+    body: web::Json<VerifyAttestationRequest>,
+) -> impl Responder {
+    // Attestations must come from the enclave running the agents now
+    let quote = match app_state.tee.quote().await {
+        Ok(quote) => quote,
+        Err(e) => {
+            error!("Failed to get the enclave attestation quote: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get the enclave attestation quote".to_string(),
+                error_code: Some("ATTESTATION_FAILED".to_string()),
+            });
+        }
+    };
+
+    match verify_agent_attestation(
+        &body.attestation,
+        body.agent_id,
+        &body.prompt,
+        &body.response,
+        Some(&quote.measurement),
+    ) {
+        Ok(valid) => HttpResponse::Ok().json(VerifyAttestationResponse {
+            success: true,
+            valid,
+        }),
+        Err(e) => {
+            warn!("Failed to verify attestation: {}", e);
+            HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to verify attestation: {}", e),
+                error_code: Some("INVALID_ATTESTATION".to_string()),
+            })
+        }
+    }
+}
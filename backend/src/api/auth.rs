@@ -0,0 +1,196 @@
+use std::str::FromStr;
+
+use actix_web::{HttpResponse, Responder, post, web};
+use alloy::primitives::Address;
+use chrono::{Duration, Utc};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{SESSION_TTL_HOURS, SIWE_NONCE_TTL_MINUTES},
+    database,
+    helpers::auth,
+    state::AppState,
+    types::{
+        AuthNonceRequest, AuthNonceResponse, AuthVerifyRequest, AuthVerifyResponse, ErrorResponse,
+    },
+};
+
+#[utoipa::path(
+    post,
+    path = "/auth/nonce",
+    request_body(
+        content = AuthNonceRequest,
+        content_type = "application/json",
+        description = "Wallet address that wants to sign in"
+    ),
+    responses(
+        (status = 200, description = "SIWE message generated successfully", body = AuthNonceResponse),
+        (status = 400, description = "Bad request - invalid address", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+#[post("/auth/nonce")]
+async fn get_auth_nonce_service(
+    app_state: web::Data<AppState>,
+    body: web::Json<AuthNonceRequest>,
+) -> impl Responder {
+    let address = match Address::from_str(body.address.trim()) {
+        Ok(address) => address,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: "Invalid address".to_string(),
+                error_code: Some("INVALID_ADDRESS".to_string()),
+            });
+        }
+    };
+
+    let nonce = auth::generate_nonce();
+    let issued_at = Utc::now();
+    let expires_at = issued_at + Duration::minutes(SIWE_NONCE_TTL_MINUTES);
+
+    let message = auth::build_siwe_message(&address, &nonce, issued_at, expires_at);
+
+    if let Err(e) = database::insert_auth_nonce(
+        &app_state.db,
+        &nonce,
+        &address.to_string(),
+        &message,
+        expires_at,
+    )
+    .await
+    {
+        error!("Failed to insert auth nonce: {}", e);
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to generate sign in message".to_string(),
+            error_code: Some("NONCE_INSERT_FAILED".to_string()),
+        });
+    }
+
+    HttpResponse::Ok().json(AuthNonceResponse {
+        success: true,
+        nonce,
+        message,
+        expires_at,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/verify",
+    request_body(
+        content = AuthVerifyRequest,
+        content_type = "application/json",
+        description = "Nonce and the wallet signature of the SIWE message returned by /auth/nonce"
+    ),
+    responses(
+        (status = 200, description = "Signature verified, session created", body = AuthVerifyResponse),
+        (status = 401, description = "Invalid signature or nonce", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Auth"
+)]
+#[post("/auth/verify")]
+async fn verify_auth_signature_service(
+    app_state: web::Data<AppState>,
+    body: web::Json<AuthVerifyRequest>,
+) -> impl Responder {
+    let mut tx = match app_state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to start database transaction".to_string(),
+                error_code: Some("DB_TRANSACTION_FAILED".to_string()),
+            });
+        }
+    };
+
+    // The nonce is consumed even if the signature is wrong, a new one must be requested
+    let auth_nonce = match database::consume_auth_nonce(&mut tx, body.nonce.trim()).await {
+        Ok(Some(auth_nonce)) => auth_nonce,
+        Ok(None) => {
+            return HttpResponse::Unauthorized().json(ErrorResponse {
+                success: false,
+                message: "Invalid, expired or already used nonce".to_string(),
+                error_code: Some("INVALID_NONCE".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to consume auth nonce: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to verify nonce".to_string(),
+                error_code: Some("NONCE_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    match auth::verify_signature(&auth_nonce.message, &body.signature, &auth_nonce.address) {
+        Ok(true) => {}
+        Ok(false) => {
+            tx.commit().await.ok();
+
+            return HttpResponse::Unauthorized().json(ErrorResponse {
+                success: false,
+                message: "Signature does not match the address".to_string(),
+                error_code: Some("INVALID_SIGNATURE".to_string()),
+            });
+        }
+        Err(e) => {
+            warn!("Failed to verify signature: {}", e);
+
+            tx.commit().await.ok();
+
+            return HttpResponse::Unauthorized().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to verify signature: {}", e),
+                error_code: Some("INVALID_SIGNATURE".to_string()),
+            });
+        }
+    }
+
+    let token = auth::generate_session_token();
+    let expires_at = Utc::now() + Duration::hours(SESSION_TTL_HOURS);
+
+    if let Err(e) = database::insert_session(
+        &mut tx,
+        &auth::hash_session_token(&token),
+        &auth_nonce.address,
+        expires_at,
+    )
+    .await
+    {
+        error!("Failed to insert session: {}", e);
+
+        tx.rollback().await.ok(); // Rollback transaction on error
+
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to create session".to_string(),
+            error_code: Some("SESSION_INSERT_FAILED".to_string()),
+        });
+    }
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        error!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to commit database transaction".to_string(),
+            error_code: Some("DB_COMMIT_FAILED".to_string()),
+        });
+    }
+
+    info!("New session created for {}", auth_nonce.address);
+
+    HttpResponse::Ok().json(AuthVerifyResponse {
+        success: true,
+        token,
+        address: auth_nonce.address,
+        expires_at,
+    })
+}
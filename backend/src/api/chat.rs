@@ -0,0 +1,85 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use tracing::error;
+
+use crate::{
+    database,
+    helpers::auth::AuthenticatedUser,
+    state::AppState,
+    types::{ChatSessionResponse, ErrorResponse},
+};
+
+#[utoipa::path(
+    get,
+    path = "/chat/sessions/{id}",
+    params(
+        ("id" = i64, Path, description = "Chat session id")
+    ),
+    responses(
+        (status = 200, description = "Chat session with its paid turns and messages", body = ChatSessionResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 403, description = "The session wallet is not the buyer of the chat session", body = ErrorResponse),
+        (status = 404, description = "Chat session not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Agents"
+)]
+#[get("/chat/sessions/{id}")]
+async fn get_chat_session_service(
+    app_state: web::Data<AppState>,
+    auth_user: AuthenticatedUser,
+    path: web::Path<i64>,
+) -> impl Responder {
+    let session_id = path.into_inner();
+
+    let db = &app_state.db;
+
+    let session = match database::get_chat_session(db, session_id).await {
+        Ok(session) => session,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Chat session with id {} not found", session_id),
+                error_code: Some("SESSION_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get chat session: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get chat session from database".to_string(),
+                error_code: Some("CHAT_SESSION_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    // The prompts are private to the buyer
+    if !session
+        .buyer_address
+        .eq_ignore_ascii_case(&auth_user.address)
+    {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the buyer can read a chat session".to_string(),
+            error_code: Some("NOT_SESSION_BUYER".to_string()),
+        });
+    }
+
+    let messages = match database::get_chat_messages(db, session_id).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("Failed to get chat messages: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get chat session messages from database".to_string(),
+                error_code: Some("CHAT_MESSAGES_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(ChatSessionResponse {
+        success: true,
+        session,
+        messages,
+    })
+}
@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use actix_multipart::Multipart;
 use actix_web::{HttpResponse, Responder, post, web};
@@ -8,13 +8,21 @@ use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::{
-    config::UPLOAD_DIR,
+    config::{DATASET_SAMPLE_ROWS, DP_MAX_EPSILON},
     database,
-    helpers::{self, agents::init_ai_agent_with_dataset},
+    helpers::{
+        self,
+        agents::{init_ai_agent_with_dataset, start_initialized_agent},
+        auth::AuthenticatedUser,
+        dp::DpBoundsError,
+        pii::PiiError,
+        upload::{self, EncryptedUpload, UploadError},
+    },
     state::AppState,
     types::{
         AgentCategory, DatasetDetailsGenerateRequest, DatasetDetailsGenerateResponse,
-        DatasetMetadata, DatasetUploadRequest, DatasetUploadResponse, ErrorResponse, UserDb,
+        DatasetFormat, DatasetMetadata, DatasetUploadRequest, DatasetUploadResponse,
+        DpColumnBounds, DpSettings, ErrorResponse, NewAgent, PiiAction, UserDb,
     },
 };
 
@@ -24,7 +32,7 @@ use crate::{
     request_body(
         content = DatasetDetailsGenerateRequest,
         content_type = "multipart/form-data",
-        description = "Gnerate dataset details using AI(name, description, category). Send the CSV file as 'file'."
+        description = "Gnerate dataset details using AI(name, description, category). Send the dataset file (CSV, TSV, JSON Lines, Parquet or XLSX) as 'file'."
     ),
     responses(
         (status = 200, description = "Dataset Details generated successfully", body = DatasetDetailsGenerateResponse),
@@ -39,19 +47,7 @@ async fn generate_dataset_details_service(
     app_state: web::Data<AppState>,
     mut payload: Multipart,
 ) -> impl Responder {
-    const MAX_FILE_SIZE: usize = 10 * 1024 * 1024; // 10MB
-
-    // Create uploads directory if it doesn't exist
-    if let Err(e) = tokio::fs::create_dir_all(UPLOAD_DIR).await {
-        error!("Failed to create upload directory: {}", e);
-        return HttpResponse::InternalServerError().json(ErrorResponse {
-            success: false,
-            message: "Failed to create upload directory".to_string(),
-            error_code: Some("DIRECTORY_CREATION_FAILED".to_string()),
-        });
-    }
-
-    let mut file_data: Option<(String, Vec<u8>, u64)> = None; // (filename, data, size)
+    let mut csv_sample: Option<String> = None;
 
     while let Some(mut field) = payload.try_next().await.unwrap_or(None) {
         let field_name = field.name().unwrap_or("").to_string();
@@ -63,38 +59,34 @@ async fn generate_dataset_details_service(
                     .and_then(|cd| cd.get_filename().map(|s| s.to_string()));
 
                 if let Some(filename) = filename {
-                    // Validate file extension
-                    if !filename.to_lowercase().ends_with(".csv") {
-                        return HttpResponse::BadRequest().json(ErrorResponse {
-                            success: false,
-                            message: "Only CSV files are allowed".to_string(),
-                            error_code: Some("INVALID_FILE_TYPE".to_string()),
-                        });
-                    }
-
-                    let mut file_size = 0u64;
-                    let mut file_bytes = Vec::new();
-
-                    // Read file data
-                    while let Some(chunk) = field.try_next().await.unwrap_or(None) {
-                        file_size += chunk.len() as u64;
-
-                        // Check file size limit
-                        if file_size > MAX_FILE_SIZE as u64 {
-                            return HttpResponse::PayloadTooLarge().json(ErrorResponse {
+                    // Detect the format from the file extension
+                    let format = match DatasetFormat::from_filename(&filename) {
+                        Some(format) => format,
+                        None => {
+                            return HttpResponse::BadRequest().json(ErrorResponse {
                                 success: false,
                                 message: format!(
-                                    "File too large. Maximum size is {} MB",
-                                    MAX_FILE_SIZE / (1024 * 1024)
+                                    "Unsupported file type. Allowed: {}",
+                                    DatasetFormat::SUPPORTED_EXTENSIONS
                                 ),
-                                error_code: Some("FILE_TOO_LARGE".to_string()),
+                                error_code: Some("INVALID_FILE_TYPE".to_string()),
                             });
                         }
-
-                        file_bytes.extend_from_slice(&chunk);
-                    }
-
-                    file_data = Some((filename, file_bytes, file_size));
+                    };
+
+                    // Only a sample of the file is kept for the AI, the rest is just validated
+                    let sample = match upload::receive_dataset_sample(
+                        &mut field,
+                        format,
+                        DATASET_SAMPLE_ROWS,
+                    )
+                    .await
+                    {
+                        Ok(sample) => sample,
+                        Err(e) => return upload_error_response(e),
+                    };
+
+                    csv_sample = Some(sample);
                 }
             }
             _ => {
@@ -106,9 +98,8 @@ async fn generate_dataset_details_service(
         }
     }
 
-    // Validate that both file and metadata were provided
-    let (filename, file_bytes, file_size) = match file_data {
-        Some(data) => data,
+    let csv_text = match csv_sample {
+        Some(sample) => sample,
         None => {
             return HttpResponse::BadRequest().json(ErrorResponse {
                 success: false,
@@ -118,32 +109,6 @@ async fn generate_dataset_details_service(
         }
     };
 
-    // Validate and count CSV rows
-    let _row_count = match helpers::csv::validate_and_count_csv(&file_bytes) {
-        Ok(count) => count,
-        Err(e) => {
-            warn!("CSV validation failed: {}", e);
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                success: false,
-                message: format!("Invalid CSV format: {}", e),
-                error_code: Some("INVALID_CSV_FORMAT".to_string()),
-            });
-        }
-    };
-
-    // Convert the Csv to plain Text
-    let csv_text = match helpers::csv::csv_bytes_to_string(&file_bytes).await {
-        Ok(text) => text,
-        Err(e) => {
-            warn!("CSV conversion failed: {}", e);
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                success: false,
-                message: format!("Failed to convert CSV to string: {}", e),
-                error_code: Some("CSV_CONVERSION_FAILED".to_string()),
-            });
-        }
-    };
-
     // Generate teh dataset details using AI
     let dataset_details =
         match helpers::agents::generate_dataset_details(&csv_text, &app_state.ai_model).await {
@@ -173,39 +138,33 @@ async fn generate_dataset_details_service(
     request_body(
         content = DatasetUploadRequest,
         content_type = "multipart/form-data",
-        description = "Upload your dataset with metadata. Send the CSV file as 'file' and individual metadata fields: user_address, dataset_price, description, and name."
+        description = "Upload your dataset with metadata. Send the dataset file (CSV, TSV, JSON Lines, Parquet or XLSX) as 'file' and individual metadata fields: dataset_price, description, name and category. The optional pii_actions field is a JSON object mapping column names to drop, hash, mask or keep, columns with detected PII are hashed by default. Set dp_epsilon (per answer) and dp_budget (per buyer) to only answer with differentially private aggregates, and dp_bounds to a JSON object mapping numeric column names to their {min, max} clamping bounds: only these columns can be summed or averaged. The dataset owner is the wallet of the authenticated session."
     ),
     responses(
         (status = 200, description = "Dataset uploaded successfully", body = DatasetUploadResponse),
         (status = 400, description = "Bad request - invalid file or format", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
         (status = 413, description = "File too large", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
+    security(("bearer_auth" = [])),
     tag = "Data Management"
 )]
 #[post("/dataset/upload")]
 pub async fn upload_dataset_service(
     app_state: web::Data<AppState>,
+    auth_user: AuthenticatedUser,
     mut payload: Multipart,
 ) -> impl Responder {
-    const MAX_FILE_SIZE: usize = 10 * 1024 * 1024; // 10MB
-
-    // Create uploads directory if it doesn't exist
-    if let Err(e) = tokio::fs::create_dir_all(UPLOAD_DIR).await {
-        error!("Failed to create upload directory: {}", e);
-        return HttpResponse::InternalServerError().json(ErrorResponse {
-            success: false,
-            message: "Failed to create upload directory".to_string(),
-            error_code: Some("DIRECTORY_CREATION_FAILED".to_string()),
-        });
-    }
-
-    let mut file_data: Option<(String, Vec<u8>, u64)> = None; // (filename, data, size)
-    let mut user_address: Option<String> = None;
+    let mut file_data: Option<(String, DatasetFormat, EncryptedUpload)> = None; // (filename, format, upload)
     let mut dataset_price: Option<f64> = None;
     let mut description: Option<String> = None;
     let mut name: Option<String> = None;
     let mut category: Option<AgentCategory> = None;
+    let mut pii_actions: HashMap<String, PiiAction> = HashMap::new();
+    let mut dp_epsilon: Option<f64> = None;
+    let mut dp_budget: Option<f64> = None;
+    let mut dp_bounds: Option<HashMap<String, DpColumnBounds>> = None;
 
     while let Some(mut field) = payload.try_next().await.unwrap_or(None) {
         let field_name = field.name().unwrap_or("").to_string();
@@ -217,46 +176,29 @@ pub async fn upload_dataset_service(
                     .and_then(|cd| cd.get_filename().map(|s| s.to_string()));
 
                 if let Some(filename) = filename {
-                    // Validate file extension
-                    if !filename.to_lowercase().ends_with(".csv") {
-                        return HttpResponse::BadRequest().json(ErrorResponse {
-                            success: false,
-                            message: "Only CSV files are allowed".to_string(),
-                            error_code: Some("INVALID_FILE_TYPE".to_string()),
-                        });
-                    }
-
-                    let mut file_size = 0u64;
-                    let mut file_bytes = Vec::new();
-
-                    // Read file data
-                    while let Some(chunk) = field.try_next().await.unwrap_or(None) {
-                        file_size += chunk.len() as u64;
-
-                        // Check file size limit
-                        if file_size > MAX_FILE_SIZE as u64 {
-                            return HttpResponse::PayloadTooLarge().json(ErrorResponse {
+                    // Detect the format from the file extension
+                    let format = match DatasetFormat::from_filename(&filename) {
+                        Some(format) => format,
+                        None => {
+                            return HttpResponse::BadRequest().json(ErrorResponse {
                                 success: false,
                                 message: format!(
-                                    "File too large. Maximum size is {} MB",
-                                    MAX_FILE_SIZE / (1024 * 1024)
+                                    "Unsupported file type. Allowed: {}",
+                                    DatasetFormat::SUPPORTED_EXTENSIONS
                                 ),
-                                error_code: Some("FILE_TOO_LARGE".to_string()),
+                                error_code: Some("INVALID_FILE_TYPE".to_string()),
                             });
                         }
+                    };
 
-                        file_bytes.extend_from_slice(&chunk);
-                    }
+                    // Stream the file, as CSV, to an encrypted temporary file and validate it on the way
+                    let upload = match upload::receive_encrypted_dataset(&mut field, format).await {
+                        Ok(upload) => upload,
+                        Err(e) => return upload_error_response(e),
+                    };
 
-                    file_data = Some((filename, file_bytes, file_size));
-                }
-            }
-            "user_address" => {
-                let mut field_bytes = Vec::new();
-                while let Some(chunk) = field.try_next().await.unwrap_or(None) {
-                    field_bytes.extend_from_slice(&chunk);
+                    file_data = Some((filename, format, upload));
                 }
-                user_address = Some(String::from_utf8_lossy(&field_bytes).to_string());
             }
             "dataset_price" => {
                 let mut field_bytes = Vec::new();
@@ -308,6 +250,86 @@ pub async fn upload_dataset_service(
                     }
                 };
             }
+            "pii_actions" => {
+                let mut field_bytes = Vec::new();
+                while let Some(chunk) = field.try_next().await.unwrap_or(None) {
+                    field_bytes.extend_from_slice(&chunk);
+                }
+
+                pii_actions = match serde_json::from_slice(&field_bytes) {
+                    Ok(actions) => actions,
+                    Err(e) => {
+                        return HttpResponse::BadRequest().json(ErrorResponse {
+                            success: false,
+                            message: format!(
+                                "Invalid pii_actions. Must map column names to drop, hash, mask or keep: {}",
+                                e
+                            ),
+                            error_code: Some("INVALID_PII_ACTIONS".to_string()),
+                        });
+                    }
+                };
+            }
+            "dp_bounds" => {
+                let mut field_bytes = Vec::new();
+                while let Some(chunk) = field.try_next().await.unwrap_or(None) {
+                    field_bytes.extend_from_slice(&chunk);
+                }
+
+                let bounds: HashMap<String, DpColumnBounds> = match serde_json::from_slice(
+                    &field_bytes,
+                ) {
+                    Ok(bounds) => bounds,
+                    Err(e) => {
+                        return HttpResponse::BadRequest().json(ErrorResponse {
+                                success: false,
+                                message: format!(
+                                    "Invalid dp_bounds. Must map column names to {{\"min\": number, \"max\": number}}: {}",
+                                    e
+                                ),
+                                error_code: Some("INVALID_DP_SETTINGS".to_string()),
+                            });
+                    }
+                };
+
+                if let Some((column, _)) = bounds.iter().find(|(_, bounds)| {
+                    !(bounds.min.is_finite() && bounds.max.is_finite() && bounds.min < bounds.max)
+                }) {
+                    return HttpResponse::BadRequest().json(ErrorResponse {
+                        success: false,
+                        message: format!(
+                            "Invalid dp_bounds for {}. min and max must be finite with min below max",
+                            column
+                        ),
+                        error_code: Some("INVALID_DP_SETTINGS".to_string()),
+                    });
+                }
+
+                dp_bounds = Some(bounds);
+            }
+            "dp_epsilon" | "dp_budget" => {
+                let mut field_bytes = Vec::new();
+                while let Some(chunk) = field.try_next().await.unwrap_or(None) {
+                    field_bytes.extend_from_slice(&chunk);
+                }
+
+                let value = match String::from_utf8_lossy(&field_bytes).trim().parse::<f64>() {
+                    Ok(value) if value.is_finite() && value > 0.0 => value,
+                    _ => {
+                        return HttpResponse::BadRequest().json(ErrorResponse {
+                            success: false,
+                            message: format!("Invalid {}. Must be a positive number", field_name),
+                            error_code: Some("INVALID_DP_SETTINGS".to_string()),
+                        });
+                    }
+                };
+
+                if field_name == "dp_epsilon" {
+                    dp_epsilon = Some(value);
+                } else {
+                    dp_budget = Some(value);
+                }
+            }
             _ => {
                 // Skip unknown fields
                 while let Some(_chunk) = field.try_next().await.unwrap_or(None) {
@@ -318,7 +340,7 @@ pub async fn upload_dataset_service(
     }
 
     // Validate that both file and metadata were provided
-    let (filename, file_bytes, file_size) = match file_data {
+    let (filename, format, upload) = match file_data {
         Some(data) => data,
         None => {
             return HttpResponse::BadRequest().json(ErrorResponse {
@@ -329,18 +351,10 @@ pub async fn upload_dataset_service(
         }
     };
 
-    // Validate all required fields are present
-    let user_address = match user_address {
-        Some(addr) => addr,
-        None => {
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                success: false,
-                message: "user_address field is required".to_string(),
-                error_code: Some("MISSING_USER_ADDRESS".to_string()),
-            });
-        }
-    };
+    // The dataset owner is always the wallet that signed in
+    let user_address = auth_user.address;
 
+    // Validate all required fields are present
     let dataset_price = match dataset_price {
         Some(price) => price,
         None => {
@@ -385,6 +399,42 @@ pub async fn upload_dataset_service(
         }
     };
 
+    // Differential privacy is opt-in, both the per answer epsilon and the buyer budget are needed
+    let dp = match (dp_epsilon, dp_budget) {
+        (None, None) if dp_bounds.is_some() => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: "dp_bounds need dp_epsilon and dp_budget".to_string(),
+                error_code: Some("INVALID_DP_SETTINGS".to_string()),
+            });
+        }
+        (None, None) => None,
+        (Some(epsilon), Some(budget)) if epsilon <= DP_MAX_EPSILON && budget >= epsilon => {
+            Some(DpSettings {
+                epsilon,
+                budget,
+                bounds: dp_bounds.unwrap_or_default(),
+            })
+        }
+        (Some(_), Some(_)) => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: format!(
+                    "dp_epsilon must be at most {} and dp_budget at least dp_epsilon",
+                    DP_MAX_EPSILON
+                ),
+                error_code: Some("INVALID_DP_SETTINGS".to_string()),
+            });
+        }
+        _ => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: "dp_epsilon and dp_budget must be set together".to_string(),
+                error_code: Some("INVALID_DP_SETTINGS".to_string()),
+            });
+        }
+    };
+
     // Create metadata object
     let metadata = DatasetMetadata {
         user_address: user_address.clone(),
@@ -394,39 +444,26 @@ pub async fn upload_dataset_service(
         category: category.clone(),
     };
 
-    // Validate and count CSV rows
-    let row_count = match helpers::csv::validate_and_count_csv(&file_bytes) {
-        Ok(count) => count,
-        Err(e) => {
-            warn!("CSV validation failed: {}", e);
-            return HttpResponse::BadRequest().json(ErrorResponse {
-                success: false,
-                message: format!("Invalid CSV format: {}", e),
-                error_code: Some("INVALID_CSV_FORMAT".to_string()),
-            });
-        }
-    };
+    // The dataset was validated and its rows counted while it was received
+    let file_size = upload.file_size;
+    let row_count = upload.row_count;
 
     // Generate unique file ID and save file
     let file_id = Uuid::new_v4().to_string();
-    let file_extension = Path::new(&filename)
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("csv");
-
     let filename_without_extension = Path::new(&filename)
         .file_stem()
         .and_then(|stem| stem.to_str())
         .unwrap_or(&filename);
 
-    let unique_filename = format!(
-        "{}_{}.{}",
-        file_id, filename_without_extension, file_extension
-    );
-    let filepath = Path::new(UPLOAD_DIR).join(&unique_filename);
+    // Every format is stored normalized to CSV
+    let unique_filename = format!("{}_{}.csv", file_id, filename_without_extension);
 
-    // Save file to disk
-    if let Err(e) = tokio::fs::write(&filepath, &file_bytes).await {
+    // Move the encrypted file to the dataset store, datasets are only stored encrypted
+    if let Err(e) = app_state
+        .dataset_store
+        .put_file(&unique_filename, upload.path())
+        .await
+    {
         error!("Failed to write file: {}", e);
         return HttpResponse::InternalServerError().json(ErrorResponse {
             success: false,
@@ -470,66 +507,70 @@ pub async fn upload_dataset_service(
 
     debug!("User operation result: {:?}", user_op);
 
-    let user: UserDb = if user_op.is_none() {
+    let user: UserDb = match user_op {
+        Some(user) => user,
         // If user does not exist, insert them
-        if let Err(e) = database::insert_user(&mut tx, &user_address).await {
-            tx.rollback().await.ok(); // Rollback transaction on error
+        None => {
+            if let Err(e) = database::insert_user(&mut tx, &user_address).await {
+                tx.rollback().await.ok(); // Rollback transaction on error
 
-            error!("Failed to insert a new user: {}", e);
-            return HttpResponse::InternalServerError().json(ErrorResponse {
-                success: false,
-                message: "Failed to insert user".to_string(),
-                error_code: Some("USER_INSERT_FAILED".to_string()),
-            });
-        }
+                error!("Failed to insert a new user: {}", e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to insert user".to_string(),
+                    error_code: Some("USER_INSERT_FAILED".to_string()),
+                });
+            }
 
-        let user_ret = match database::get_user_by_address(&mut tx, &user_address).await {
-            Ok(user) => user,
-            Err(e) => {
-                error!("Failed to get user: {}", e);
+            let user_ret = match database::get_user_by_address(&mut tx, &user_address).await {
+                Ok(user) => user,
+                Err(e) => {
+                    error!("Failed to get user: {}", e);
+                    return HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to get user".to_string(),
+                        error_code: Some("USER_FETCH_FAILED".to_string()),
+                    });
+                }
+            };
+
+            if user_ret.is_none() {
+                // throw an error
                 return HttpResponse::InternalServerError().json(ErrorResponse {
                     success: false,
                     message: "Failed to get user".to_string(),
                     error_code: Some("USER_FETCH_FAILED".to_string()),
                 });
             }
-        };
 
-        if user_ret.is_none() {
-            // throw an error
-            return HttpResponse::InternalServerError().json(ErrorResponse {
-                success: false,
-                message: "Failed to get user".to_string(),
-                error_code: Some("USER_FETCH_FAILED".to_string()),
-            });
+            user_ret.unwrap()
         }
-
-        user_ret.unwrap()
-    } else {
-        user_op.unwrap()
     };
 
     let dataset_path = unique_filename;
 
     // Insert a new agent
-    let agent_db = match database::insert_new_agent(
-        &mut tx,
-        &name,
-        &description,
-        dataset_price,
-        user.id,
-        &dataset_path,
-        &category,
-        file_size as f64,
-    )
-    .await
-    {
+    let new_agent = NewAgent {
+        name,
+        description,
+        price: dataset_price,
+        owner_id: user.id,
+        dataset_path: dataset_path.clone(),
+        category,
+        format,
+        dp,
+        dataset_size: file_size as f64,
+    };
+
+    let agent_db = match database::insert_new_agent(&mut tx, &new_agent).await {
         Ok(agent) => agent,
         Err(e) => {
             error!("Failed to insert agent: {}", e);
 
             tx.rollback().await.ok(); // Rollback transaction on error
 
+            delete_stored_dataset(&app_state, &dataset_path).await;
+
             return HttpResponse::InternalServerError().json(ErrorResponse {
                 success: false,
                 message: "Failed to insert agent".to_string(),
@@ -538,19 +579,49 @@ pub async fn upload_dataset_service(
         }
     };
 
-    // Implement training new ai agent using rag with gemini using rig-core
-    if let Err(e) = init_ai_agent_with_dataset(&user, &agent_db, &filepath, &app_state).await {
-        error!("Failed to initialize AI agent with dataset: {}", e);
-        return HttpResponse::InternalServerError().json(ErrorResponse {
-            success: false,
-            message: format!("Failed to initialize AI agent with dataset: {}", e),
-            error_code: Some("AGENT_INIT_FAILED".to_string()),
-        });
-    };
+    // Redact the PII and embed the dataset, all saved in the same transaction
+    let initialized =
+        match init_ai_agent_with_dataset(&user, &agent_db, &pii_actions, &app_state, &mut tx).await
+        {
+            Ok(initialized) => initialized,
+            Err(e) => {
+                tx.rollback().await.ok(); // Rollback transaction on error
+
+                delete_stored_dataset(&app_state, &dataset_path).await;
+
+                if let Some(pii_error) = e.downcast_ref::<PiiError>() {
+                    warn!("Upload rejected: {}", pii_error);
+                    return HttpResponse::BadRequest().json(ErrorResponse {
+                        success: false,
+                        message: pii_error.to_string(),
+                        error_code: Some("INVALID_PII_ACTIONS".to_string()),
+                    });
+                }
+
+                if let Some(bounds_error) = e.downcast_ref::<DpBoundsError>() {
+                    warn!("Upload rejected: {}", bounds_error);
+                    return HttpResponse::BadRequest().json(ErrorResponse {
+                        success: false,
+                        message: bounds_error.to_string(),
+                        error_code: Some("INVALID_DP_SETTINGS".to_string()),
+                    });
+                }
+
+                error!("Failed to initialize AI agent with dataset: {}", e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: format!("Failed to initialize AI agent with dataset: {}", e),
+                    error_code: Some("AGENT_INIT_FAILED".to_string()),
+                });
+            }
+        };
 
     // Commit the transaction
     if let Err(e) = tx.commit().await {
         error!("Failed to commit transaction: {}", e);
+
+        delete_stored_dataset(&app_state, &dataset_path).await;
+
         return HttpResponse::InternalServerError().json(ErrorResponse {
             success: false,
             message: "Failed to commit database transaction".to_string(),
@@ -558,6 +629,22 @@ pub async fn upload_dataset_service(
         });
     }
 
+    // The agent is saved, a failed start is retried when the backend loads the agents again
+    let pii_report = match start_initialized_agent(&agent_db, initialized, &app_state).await {
+        Ok(pii_report) => pii_report,
+        Err(e) => {
+            error!("Failed to start agent {}: {}", agent_db.id, e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: format!(
+                    "Dataset {} saved but its agent failed to start: {}",
+                    agent_db.id, e
+                ),
+                error_code: Some("AGENT_START_FAILED".to_string()),
+            });
+        }
+    };
+
     HttpResponse::Ok().json(DatasetUploadResponse {
         success: true,
         message: "Dataset uploaded and AI agent initialized successfully".to_string(),
@@ -565,7 +652,39 @@ pub async fn upload_dataset_service(
         filename: Some(filename),
         file_size: Some(file_size),
         row_count: Some(row_count),
+        format: Some(format),
         metadata: Some(metadata),
+        pii_report: Some(pii_report),
         dataset_id: agent_db.id,
     })
 }
+
+/// Remove the dataset of an upload that failed, it is not referenced by any agent.
+async fn delete_stored_dataset(app_state: &web::Data<AppState>, dataset_path: &str) {
+    if let Err(e) = app_state.dataset_store.delete(dataset_path).await {
+        warn!("Failed to delete orphan dataset {}: {}", dataset_path, e);
+    }
+}
+
+fn upload_error_response(e: UploadError) -> HttpResponse {
+    let (mut response, error_code) = match &e {
+        UploadError::TooLarge => (HttpResponse::PayloadTooLarge(), "FILE_TOO_LARGE"),
+        UploadError::InvalidCsv(_) => (HttpResponse::BadRequest(), "INVALID_CSV_FORMAT"),
+        UploadError::InvalidDataset { .. } => {
+            (HttpResponse::BadRequest(), "INVALID_DATASET_FORMAT")
+        }
+        UploadError::Read(_) => (HttpResponse::BadRequest(), "FILE_READ_FAILED"),
+        UploadError::Save(_) => (HttpResponse::InternalServerError(), "FILE_SAVE_FAILED"),
+    };
+
+    match &e {
+        UploadError::Save(_) => error!("Upload failed: {}", e),
+        _ => warn!("Upload rejected: {}", e),
+    }
+
+    response.json(ErrorResponse {
+        success: false,
+        message: e.to_string(),
+        error_code: Some(error_code.to_string()),
+    })
+}
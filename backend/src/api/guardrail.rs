@@ -0,0 +1,87 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use tracing::error;
+
+use crate::{
+    config::GUARDRAIL_EVENTS_LIMIT,
+    database,
+    helpers::auth::AuthenticatedUser,
+    state::AppState,
+    types::{ErrorResponse, GuardrailEventsResponse},
+};
+
+#[utoipa::path(
+    get,
+    path = "/agents/{id}/guardrail/events",
+    params(
+        ("id" = i64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "Latest guardrail verdicts on the agent answers", body = GuardrailEventsResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 403, description = "The session wallet does not own the agent", body = ErrorResponse),
+        (status = 404, description = "Agent not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Agents"
+)]
+#[get("/agents/{id}/guardrail/events")]
+async fn get_guardrail_events_service(
+    app_state: web::Data<AppState>,
+    auth_user: AuthenticatedUser,
+    path: web::Path<i64>,
+) -> impl Responder {
+    let agent_id = path.into_inner();
+
+    let db = &app_state.db;
+
+    let agent_db = match database::get_agent_by_id(db, agent_id).await {
+        Ok(agent) => agent,
+        Err(sqlx::Error::RowNotFound) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                success: false,
+                message: format!("Agent with id {} not found", agent_id),
+                error_code: Some("AGENT_NOT_FOUND".to_string()),
+            });
+        }
+        Err(e) => {
+            error!("Failed to get agent: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to get agent from database: {}", e),
+                error_code: Some("AGENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    // Verdicts carry buyer prompts, only the dataset owner may review them
+    if !agent_db
+        .owner_address
+        .eq_ignore_ascii_case(&auth_user.address)
+    {
+        return HttpResponse::Forbidden().json(ErrorResponse {
+            success: false,
+            message: "Only the dataset owner can review the guardrail verdicts".to_string(),
+            error_code: Some("NOT_AGENT_OWNER".to_string()),
+        });
+    }
+
+    let events =
+        match database::get_guardrail_events_by_agent(db, agent_id, GUARDRAIL_EVENTS_LIMIT).await {
+            Ok(events) => events,
+            Err(e) => {
+                error!("Failed to get guardrail events: {}", e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to get guardrail events from database".to_string(),
+                    error_code: Some("GUARDRAIL_EVENTS_FETCH_FAILED".to_string()),
+                });
+            }
+        };
+
+    HttpResponse::Ok().json(GuardrailEventsResponse {
+        success: true,
+        message: "Guardrail events retrieved successfully".to_string(),
+        events,
+    })
+}
@@ -1,24 +1,40 @@
+pub mod admin;
+pub mod attestation;
+pub mod auth;
+pub mod chat;
 pub mod dataset;
+pub mod guardrail;
+pub mod payment;
 pub mod profile;
+pub mod stream;
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::Duration,
+};
 
 use crate::{
-    config::{MAX_ALLOWED_SELECTED_AGENTS, ROUTER_AGENT_MODEL},
-    database, helpers,
+    config::{
+        AGENT_ANSWER_TIMEOUT_SECS, APP_CONFIG, EMBEDDING_MODEL, MAX_ALLOWED_SELECTED_AGENTS,
+        ROUTER_MAX_TOP_K, ROUTER_TOP_K,
+    },
+    database,
+    helpers::{self, auth::AuthenticatedUser},
     state::AppState,
-    tee,
+    tee::{TeeAnswer, protocol::ChatTurn},
     types::{
-        AgentCategory, AgentDb, AgentQueryParams, AgentQueryResult, AgentResponse,
+        AgentAnswerStatus, AgentCategory, AgentDb, AgentDetailsResponse, AgentQueryParams,
+        AgentQueryResult, AgentResponse, AgentSynthesis, ChatRole, ChatSessionDb,
         DatasetStatsResponse, ErrorResponse, GetAgentsForPromptRequest, GetAgentsForPromptResponse,
-        GetResponseFromAgentsRequest, GetResponseFromAgentsResponse,
+        GetResponseFromAgentsRequest, GetResponseFromAgentsResponse, GuardrailVerdict,
+        NewFailedAgentQuery, NewGuardrailEvent,
     },
 };
 use actix_web::{HttpResponse, Responder, get, post, web};
-use rig::{
-    client::ProviderClient,
-    completion::Prompt,
-    providers::gemini::{self},
-};
-use tracing::{debug, error, info, warn};
+use alloy::primitives::Address;
+use futures_util::future::join_all;
+use tracing::{debug, error, warn};
 
 #[utoipa::path(
         responses(
@@ -105,6 +121,11 @@ async fn get_all_agents_service(
         g.owner_id,
         g.dataset_path,
         g.category,
+        g.format,
+        g.dp_enabled,
+        g.dp_epsilon,
+        g.dp_budget,
+        g.dp_bounds,
         g.dataset_size,
         g.status,
         g.created_at,
@@ -119,25 +140,25 @@ async fn get_all_agents_service(
     let mut param_count = 0;
 
     // Add search condition
-    if let Some(search) = &query.search {
-        if !search.trim().is_empty() {
-            param_count += 1;
-            sql.push_str(&format!(" AND name ILIKE ${}", param_count));
-        }
+    if let Some(search) = &query.search
+        && !search.trim().is_empty()
+    {
+        param_count += 1;
+        sql.push_str(&format!(" AND name ILIKE ${}", param_count));
     }
 
     // Add category filter
-    if let Some(category) = &query.category {
+    if query.category.is_some() {
         param_count += 1;
         sql.push_str(&format!(" AND category::text = ${}", param_count));
     }
 
     // Add status filter
-    if let Some(status) = &query.status {
-        if !status.trim().is_empty() {
-            param_count += 1;
-            sql.push_str(&format!(" AND status = ${}", param_count));
-        }
+    if let Some(status) = &query.status
+        && !status.trim().is_empty()
+    {
+        param_count += 1;
+        sql.push_str(&format!(" AND status = ${}", param_count));
     }
 
     // Add ORDER BY clause
@@ -146,20 +167,20 @@ async fn get_all_agents_service(
     // Execute the query
     let mut query_builder = sqlx::query_as::<_, AgentQueryResult>(&sql);
 
-    if let Some(search) = &query.search {
-        if !search.trim().is_empty() {
-            query_builder = query_builder.bind(format!("%{}%", search.trim()));
-        }
+    if let Some(search) = &query.search
+        && !search.trim().is_empty()
+    {
+        query_builder = query_builder.bind(format!("%{}%", search.trim()));
     }
 
     if let Some(category) = &query.category {
         query_builder = query_builder.bind(category.to_string());
     }
 
-    if let Some(status) = &query.status {
-        if !status.trim().is_empty() {
-            query_builder = query_builder.bind(status.trim().to_string());
-        }
+    if let Some(status) = &query.status
+        && !status.trim().is_empty()
+    {
+        query_builder = query_builder.bind(status.trim().to_string());
     }
 
     let query_results = match query_builder.fetch_all(db).await {
@@ -185,6 +206,11 @@ async fn get_all_agents_service(
             owner_id: result.owner_id,
             dataset_path: result.dataset_path,
             category: result.category,
+            format: result.format,
+            dp_enabled: result.dp_enabled,
+            dp_epsilon: result.dp_epsilon,
+            dp_budget: result.dp_budget,
+            dp_bounds: result.dp_bounds,
             dataset_size: result.dataset_size,
             status: result.status,
             created_at: result.created_at,
@@ -205,7 +231,7 @@ async fn get_all_agents_service(
         ("id" = i64, Path, description = "Agent id")
     ),
     responses(
-        (status = 200, description = "Agent fetched successfully", body = AgentDb),
+        (status = 200, description = "Agent fetched successfully, with its dataset profile and PII report", body = AgentDetailsResponse),
         (status = 404, description = "Agent not found", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
@@ -232,11 +258,46 @@ async fn get_agent_by_id_service(
         }
     };
 
-    HttpResponse::Ok().json(agent_db)
+    let mut profile = match database::get_agent_profile(db, agent_id).await {
+        Ok(profile) => profile,
+        Err(e) => {
+            error!("Failed to get agent profile: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to get agent profile from database: {}", e),
+                error_code: Some("AGENT_PROFILE_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    // Exact values would leak what the noisy aggregates protect
+    if agent_db.dp_enabled
+        && let Some(profile) = profile.as_mut()
+    {
+        helpers::profile::strip_profile_values(profile);
+    }
+
+    let pii_report = match database::get_agent_pii_report(db, agent_id).await {
+        Ok(pii_report) => pii_report,
+        Err(e) => {
+            error!("Failed to get agent PII report: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to get agent PII report from database: {}", e),
+                error_code: Some("AGENT_PII_REPORT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    HttpResponse::Ok().json(AgentDetailsResponse {
+        agent: agent_db,
+        profile,
+        pii_report,
+    })
 }
 
 /*
-Endpoint that its job is to get all the agents from database and using gemini ai(rig-core) that will return the ids of the agents that have the response for the prompt.
+Endpoint that embeds the prompt and returns the active agents whose description is the most similar, optionally re-ranked by gemini ai(rig-core) so only the agents that have the response for the prompt are kept.
 */
 #[utoipa::path(
     post,
@@ -247,7 +308,8 @@ Endpoint that its job is to get all the agents from database and using gemini ai
         description = "User prompt to get agents that can respond to it"
     ),
     responses(
-        (status = 200, description = "Agents fetched successfully", body = String),
+        (status = 200, description = "Agents fetched successfully, the most relevant first", body = GetAgentsForPromptResponse),
+        (status = 400, description = "Bad request - empty prompt or invalid top_k", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "Agents"
@@ -259,30 +321,48 @@ async fn get_agents_for_prompt_service(
 ) -> HttpResponse {
     let user_prompt = body.prompt.trim();
 
-    // Get the List of agents from database
+    if user_prompt.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "No prompt specified".to_string(),
+            error_code: Some("NO_PROMPT_SPECIFIED".to_string()),
+        });
+    }
+
+    let top_k = body.top_k.unwrap_or(ROUTER_TOP_K);
+
+    if !(1..=ROUTER_MAX_TOP_K).contains(&top_k) {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: format!("top_k must be between 1 and {}", ROUTER_MAX_TOP_K),
+            error_code: Some("INVALID_TOP_K".to_string()),
+        });
+    }
+
     let db = &app_state.db;
 
-    let agents = match sqlx::query_as!(
-        AgentDb,
-        r#"SELECT
-        g.id,
-        g.name,
-        g.description,
-        g.price,
-        g.owner_id,
-        g.dataset_path,
-        g.status,
-        g.category as "category: AgentCategory",
-        g.dataset_size,
-        g.created_at,
-        g.updated_at,
-        g.nft_id,
-        g.nft_tx, 
-        u.address as "owner_address: String"
-    FROM agents g
-    JOIN users u ON g.owner_id = u.id"#
+    let prompt_embedding = match helpers::router::embed_text(&app_state.ai_model, user_prompt).await
+    {
+        Ok(embedding) => embedding,
+        Err(e) => {
+            error!("Failed to embed prompt: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: format!("Failed to embed prompt: {}", e),
+                error_code: Some("PROMPT_EMBEDDING_FAILED".to_string()),
+            });
+        }
+    };
+
+    let candidates = match database::search_agents_by_embedding(
+        db,
+        &prompt_embedding,
+        EMBEDDING_MODEL,
+        body.category
+            .as_deref()
+            .filter(|category| !category.trim().is_empty()),
+        top_k,
     )
-    .fetch_all(db)
     .await
     {
         Ok(agents) => agents,
@@ -296,80 +376,50 @@ async fn get_agents_for_prompt_service(
         }
     };
 
-    let agents_vec_str: String = agents
-        .iter()
-        .map(|agent| {
-            format!(
-                "{{\"id\":{},\"name\":\"{}\",\"description\":\"{}\", \"category\":\"{}\"}}",
-                agent.id,
-                agent.name,
-                agent.description,
-                agent.category.to_string()
-            )
-        })
-        .collect::<Vec<_>>()
-        .join(", ");
-
-    let model = gemini::Client::from_env();
-    let ai = model
-        .agent(ROUTER_AGENT_MODEL)
-        .preamble("You are an AI agent that your main and only task is to return the agents ids that can respond to the user question. You decide wether to return an agent id by using their available description, name and category. You' ll find this data in your context. Remeber to always only return the response as an array of agents id.If you can't find anyone just return an empty array. Exemple of response : [5, 9]. ")
-        .temperature(0.0)
-        .build();
-
-    let prompt = format!(
-        "User question: {}. Please return the agents ids that can respond to this question. This is all the agents: [{}]",
-        user_prompt, agents_vec_str
+    debug!(
+        "Agents retrieved for the prompt: {:?}",
+        candidates.iter().map(|agent| agent.id).collect::<Vec<_>>()
     );
 
-    let response = match ai.prompt(prompt).await {
-        Ok(response) => response,
-        Err(e) => {
-            error!("Failed to get AI response: {}", e);
-            return HttpResponse::InternalServerError().json(ErrorResponse {
-                success: false,
-                message: format!("Failed to get AI response: {}", e),
-                error_code: Some("AI_RESPONSE_FAILED".to_string()),
-            });
-        }
-    };
-
-    debug!("AI response: {}", response);
+    if !body.rerank || candidates.is_empty() {
+        return HttpResponse::Ok().json(GetAgentsForPromptResponse { agents: candidates });
+    }
 
-    // remove any markdown
-    let formatted_response = response.replace("```", "");
+    // Dataset columns let the router match on what the data actually contains
+    let candidate_ids: Vec<i64> = candidates.iter().map(|agent| agent.id).collect();
 
-    debug!("Formatted AI response: {}", formatted_response);
+    let profiles = database::get_agent_profiles_by_ids(db, &candidate_ids)
+        .await
+        .unwrap_or_else(|e| {
+            warn!(
+                "Failed to get agent profiles, routing without columns: {}",
+                e
+            );
+            HashMap::new()
+        });
 
-    let agents_id_vec = match serde_json::from_str::<Vec<i64>>(&formatted_response) {
-        Ok(vec) => vec,
+    // The similarity order is still a useful answer when the router model fails
+    let agents = match helpers::router::rerank_agents(
+        &app_state.ai_model,
+        user_prompt,
+        candidates.clone(),
+        &profiles,
+    )
+    .await
+    {
+        Ok(agents) => agents,
         Err(e) => {
-            error!("Failed to parse AI response: {}", e);
-            return HttpResponse::InternalServerError().json(ErrorResponse {
-                success: false,
-                message: format!("Failed to parse AI response: {}", e),
-                error_code: Some("AI_RESPONSE_PARSE_FAILED".to_string()),
-            });
+            warn!("Failed to re-rank agents, keeping similarity order: {}", e);
+            candidates
         }
     };
 
-    if agents_id_vec.is_empty() {
-        return HttpResponse::Ok().json(GetAgentsForPromptResponse { agents: Vec::new() });
-    }
-
-    // Filter agents to only include those whose IDs are in agents_id_vec
-    let available_agents: Vec<AgentDb> = agents
-        .into_iter()
-        .filter(|agent| agents_id_vec.contains(&agent.id))
-        .collect();
-
-    HttpResponse::Ok().json(GetAgentsForPromptResponse {
-        agents: available_agents,
-    })
+    HttpResponse::Ok().json(GetAgentsForPromptResponse { agents })
 }
 
 /*
 Endpoint that will use specifid agents ids by user and will return the response from the agents specified.
+A chat session is started at the first turn, following turns send its id and the agents answer with the earlier turns in mind.
 */
 #[utoipa::path(
     post,
@@ -377,128 +427,725 @@ Endpoint that will use specifid agents ids by user and will return the response
     request_body(
         content = GetResponseFromAgentsRequest,
         content_type = "application/json",
-        description = "User prompt and specified agents ids to get response from and tx hashes to verify payment."
+        description = "User prompt and specified agents ids to get response from and the tx hash to verify payment. The tx must be sent by the wallet of the authenticated session. One payment covers several turns of the chat session, the credits of failed answers can pay instead of a tx."
     ),
     responses(
-        (status = 200, description = "Agents responses fetched successfully", body = GetResponseFromAgentsResponse),
+        (status = 200, description = "Agents responses fetched successfully, with their synthesis when requested", body = GetResponseFromAgentsResponse),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 402, description = "All the paid turns of the chat session are used and no new payment was sent, or the credits of the buyer are too low", body = ErrorResponse),
+        (status = 403, description = "Privacy budget of the buyer exhausted on a differential privacy agent", body = ErrorResponse),
+        (status = 404, description = "Chat session not found", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
+    security(("bearer_auth" = [])),
     tag = "Agents"
 )]
 #[post("/chat/agents/answer")]
 async fn get_response_from_agents_service(
     app_state: web::Data<AppState>,
+    auth_user: AuthenticatedUser,
     body: web::Json<GetResponseFromAgentsRequest>,
 ) -> HttpResponse {
-    let agent_ids = &body.agent_ids;
+    let paid_turn = match pay_for_answer(&app_state, &auth_user, &body).await {
+        Ok(paid_turn) => paid_turn,
+        Err(response) => return response,
+    };
+
+    let histories = match chat_histories(&app_state, &paid_turn).await {
+        Ok(histories) => histories,
+        Err(response) => return response,
+    };
+
     let prompt = &body.prompt;
-    let tx_hash = &body.tx_hash;
 
-    if tx_hash.is_empty() {
-        return HttpResponse::BadRequest().json(ErrorResponse {
-            success: false,
-            message: "No tx hash specified".to_string(),
-            error_code: Some("NO_TX_HASH_SPECIFIED".to_string()),
-        });
+    // Agents answer concurrently, a failed agent does not cost the buyer the other answers
+    let agent_responses = join_all(paid_turn.session.agent_ids.iter().map(|agent_id| {
+        answer_agent(
+            &app_state,
+            *agent_id,
+            prompt,
+            histories.get(agent_id).map(Vec::as_slice).unwrap_or(&[]),
+            &paid_turn,
+        )
+    }))
+    .await;
+
+    // The answers are paid for and sent anyway, only the memory of the turn is lost
+    if let Err(e) = save_chat_turn(&app_state, &paid_turn, prompt, &agent_responses).await {
+        error!("Failed to save chat turn: {}", e);
     }
 
+    let synthesis = answers_synthesis(&app_state, &body, &agent_responses).await;
+
+    HttpResponse::Ok().json(GetResponseFromAgentsResponse {
+        agent_responses,
+        synthesis,
+        session_id: paid_turn.session.id,
+        turns_left: paid_turn.turns_left(),
+        success: true,
+    })
+}
+
+/// Answer of an agent to a paid turn, or the failure recorded for the buyer.
+async fn answer_agent(
+    app_state: &AppState,
+    agent_id: i64,
+    prompt: &str,
+    history: &[ChatTurn],
+    paid_turn: &PaidTurn,
+) -> AgentResponse {
+    match try_answer_agent(app_state, agent_id, prompt, history, paid_turn).await {
+        Ok(agent_response) => agent_response,
+        Err(failure) => failed_answer(app_state, agent_id, prompt, paid_turn, failure).await,
+    }
+}
+
+async fn try_answer_agent(
+    app_state: &AppState,
+    agent_id: i64,
+    prompt: &str,
+    history: &[ChatTurn],
+    paid_turn: &PaidTurn,
+) -> Result<AgentResponse, AgentFailure> {
+    if !app_state.tee.has_agent(agent_id) {
+        return Err(AgentFailure::not_running(agent_id));
+    }
+
+    // The executor removes the PII and dataset rows the answer leaks, then signs what is left
+    let answer = tokio::time::timeout(
+        Duration::from_secs(AGENT_ANSWER_TIMEOUT_SECS),
+        app_state
+            .tee
+            .prompt(agent_id, prompt, history, &paid_turn.session.tx_hash),
+    )
+    .await
+    .map_err(|_| AgentFailure::timed_out(agent_id))?
+    .map_err(|e| AgentFailure::ai_response(agent_id, e))?;
+
+    Ok(attested_answer(app_state, agent_id, prompt, paid_turn, answer).await)
+}
+
+/// Why an agent gave no answer to a paid turn.
+struct AgentFailure {
+    status: AgentAnswerStatus,
+    error_code: &'static str,
+    message: String,
+}
+
+impl AgentFailure {
+    fn failed(error_code: &'static str, message: String) -> Self {
+        Self {
+            status: AgentAnswerStatus::Failed,
+            error_code,
+            message,
+        }
+    }
+
+    fn not_running(agent_id: i64) -> Self {
+        Self::failed(
+            "AGENT_NOT_FOUND",
+            format!("Agent with id {} not running", agent_id),
+        )
+    }
+
+    fn ai_response(agent_id: i64, e: color_eyre::Report) -> Self {
+        Self::failed(
+            "AI_RESPONSE_FAILED",
+            format!(
+                "Failed to get AI response from agent with id {} : {}",
+                agent_id, e
+            ),
+        )
+    }
+
+    fn timed_out(agent_id: i64) -> Self {
+        Self {
+            status: AgentAnswerStatus::TimedOut,
+            error_code: "AGENT_TIMEOUT",
+            message: format!(
+                "Agent with id {} did not answer within {} seconds",
+                agent_id, AGENT_ANSWER_TIMEOUT_SECS
+            ),
+        }
+    }
+}
+
+/// Record an agent that gave no answer so the buyer can be credited and get back the privacy budget
+/// the turn charged, and the response telling why.
+async fn failed_answer(
+    app_state: &AppState,
+    agent_id: i64,
+    prompt: &str,
+    paid_turn: &PaidTurn,
+    failure: AgentFailure,
+) -> AgentResponse {
+    error!("{}", failure.message);
+
+    let failed_query = NewFailedAgentQuery {
+        agent_id,
+        session_id: paid_turn.session.id,
+        turn: paid_turn.session.turns_used,
+        buyer_address: paid_turn.payer_address.to_string(),
+        tx_hash: paid_turn.session.tx_hash.clone(),
+        prompt: prompt.to_string(),
+        status: failure.status,
+        error_code: failure.error_code.to_string(),
+        error: failure.message.clone(),
+    };
+
+    let dp_epsilon = paid_turn.dp_epsilons.get(&agent_id).copied();
+
+    match helpers::credits::record_failed_answer(&app_state.db, &failed_query, dp_epsilon).await {
+        Ok(amount) => debug!(
+            "Credited {} to {} for agent {}",
+            amount, failed_query.buyer_address, agent_id
+        ),
+        Err(e) => error!("Failed to record failed agent query: {}", e),
+    }
+
+    AgentResponse {
+        agent_id,
+        prompt: prompt.to_string(),
+        response: String::new(),
+        attestation: None,
+        guardrail_verdict: None,
+        status: failure.status,
+        error: Some(failure.message),
+        error_code: Some(failure.error_code.to_string()),
+    }
+}
+
+/// Turn of a chat session paid for by an answer request.
+struct PaidTurn {
+    /// The session once the turn is used, `turns_used` is the number of the turn
+    session: ChatSessionDb,
+    payer_address: Address,
+    /// Epsilon charged to the privacy budget of the payer by each differential privacy agent
+    dp_epsilons: HashMap<i64, f64>,
+}
+
+impl PaidTurn {
+    fn turns_left(&self) -> i32 {
+        self.session.turns_paid - self.session.turns_used
+    }
+}
+
+/// Checks shared by the answer endpoints, then use a paid turn of the chat session (starting it, or
+/// adding the turns of the request payment) and the privacy budget of its differential privacy agents.
+/// Returns the paid turn, or the response to send.
+async fn pay_for_answer(
+    app_state: &web::Data<AppState>,
+    auth_user: &AuthenticatedUser,
+    body: &GetResponseFromAgentsRequest,
+) -> Result<PaidTurn, HttpResponse> {
+    let prompt = &body.prompt;
+    let tx_hash = &body.tx_hash;
+
     if prompt.is_empty() {
-        return HttpResponse::BadRequest().json(ErrorResponse {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
             success: false,
             message: "No prompt specified".to_string(),
             error_code: Some("NO_PROMPT_SPECIFIED".to_string()),
-        });
+        }));
     }
 
+    // The payer must be the wallet that signed in
+    let payer_address = match Address::from_str(&auth_user.address) {
+        Ok(address) => address,
+        Err(e) => {
+            error!("Invalid session address {}: {}", auth_user.address, e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Invalid session address".to_string(),
+                error_code: Some("INVALID_SESSION_ADDRESS".to_string()),
+            }));
+        }
+    };
+
+    let session = match body.session_id {
+        Some(session_id) => {
+            let session = match database::get_chat_session(&app_state.db, session_id).await {
+                Ok(session) => Some(session),
+                Err(sqlx::Error::RowNotFound) => None,
+                Err(e) => {
+                    error!("Failed to get chat session: {}", e);
+                    return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to get chat session from database".to_string(),
+                        error_code: Some("CHAT_SESSION_FETCH_FAILED".to_string()),
+                    }));
+                }
+            };
+
+            // Sessions of other buyers are not disclosed
+            match session.filter(|session| {
+                session
+                    .buyer_address
+                    .eq_ignore_ascii_case(&payer_address.to_string())
+            }) {
+                Some(session) => Some(session),
+                None => {
+                    return Err(HttpResponse::NotFound().json(ErrorResponse {
+                        success: false,
+                        message: format!("Chat session with id {} not found", session_id),
+                        error_code: Some("SESSION_NOT_FOUND".to_string()),
+                    }));
+                }
+            }
+        }
+        None => None,
+    };
+
+    let agent_ids = match &session {
+        Some(session) => {
+            // The agents are chosen at the first turn, and paid for together
+            if !body.agent_ids.is_empty() && body.agent_ids != session.agent_ids {
+                return Err(HttpResponse::BadRequest().json(ErrorResponse {
+                    success: false,
+                    message: "The agents of a chat session can not be changed".to_string(),
+                    error_code: Some("SESSION_AGENTS_MISMATCH".to_string()),
+                }));
+            }
+
+            &session.agent_ids
+        }
+        None => {
+            if tx_hash.is_empty() && !body.use_credits {
+                return Err(HttpResponse::BadRequest().json(ErrorResponse {
+                    success: false,
+                    message: "No tx hash specified".to_string(),
+                    error_code: Some("NO_TX_HASH_SPECIFIED".to_string()),
+                }));
+            }
+
+            &body.agent_ids
+        }
+    };
+
     if agent_ids.is_empty() {
-        return HttpResponse::BadRequest().json(ErrorResponse {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
             success: false,
             message: "No agents specified".to_string(),
             error_code: Some("NO_AGENTS_SPECIFIED".to_string()),
-        });
+        }));
     }
 
     if agent_ids.len() > MAX_ALLOWED_SELECTED_AGENTS {
-        return HttpResponse::BadRequest().json(ErrorResponse {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
             success: false,
             message: "Too many agents specified".to_string(),
             error_code: Some("TOO_MANY_AGENTS_SPECIFIED".to_string()),
-        });
+        }));
     }
 
-    let mut agent_responses = Vec::new();
+    let unique_agent_ids: HashSet<&i64> = agent_ids.iter().collect();
 
-    // Verify payment using tx hash
-    let pay_sucess = match helpers::agents::verif_selected_agents_payment(
-        &app_state, agent_ids, tx_hash,
-    )
-    .await
-    {
-        Ok(success) => success,
+    if unique_agent_ids.len() != agent_ids.len() {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
+            success: false,
+            message: "Duplicate agents specified".to_string(),
+            error_code: Some("DUPLICATE_AGENTS_SPECIFIED".to_string()),
+        }));
+    }
+
+    let agents = match database::get_agents_by_ids(&app_state.db, agent_ids).await {
+        Ok(agents) => agents,
         Err(e) => {
-            error!("Failed to verify payment: {}", e);
-            return HttpResponse::InternalServerError().json(ErrorResponse {
+            error!("Failed to fetch agents: {}", e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
                 success: false,
-                message: format!("Failed to verify payment: {}", e),
-                error_code: Some("PAYMENT_VERIFICATION_FAILED".to_string()),
-            });
+                message: "Failed to fetch agents".to_string(),
+                error_code: Some("DB_QUERY_FAILED".to_string()),
+            }));
         }
     };
 
-    if !pay_sucess {
-        return HttpResponse::InternalServerError().json(ErrorResponse {
+    if agents.len() != agent_ids.len() {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse {
             success: false,
-            message: "Payment verification failed".to_string(),
-            error_code: Some("PAYMENT_VERIFICATION_FAILED".to_string()),
-        });
+            message: "Some of the specified agents do not exist".to_string(),
+            error_code: Some("AGENT_NOT_FOUND".to_string()),
+        }));
     }
 
-    // Get response from each agent specified
-    for agent_id in agent_ids {
-        tee::call_tee_ai_agent(&app_state, *agent_id, &prompt).await;
-
-        let agent = app_state.tee_agents.get(agent_id);
+    // A session with turns left can go on without a new payment
+    let verified_payment = if tx_hash.is_empty() {
+        None
+    } else {
+        match helpers::agents::verif_selected_agents_payment(
+            app_state,
+            agent_ids,
+            tx_hash,
+            &payer_address,
+        )
+        .await
+        {
+            Ok(Some(payment)) => Some(payment),
+            Ok(None) => {
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Payment verification failed".to_string(),
+                    error_code: Some("PAYMENT_VERIFICATION_FAILED".to_string()),
+                }));
+            }
+            Err(e) => {
+                error!("Failed to verify payment: {}", e);
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: format!("Failed to verify payment: {}", e),
+                    error_code: Some("PAYMENT_VERIFICATION_FAILED".to_string()),
+                }));
+            }
+        }
+    };
 
-        if agent.is_none() {
-            return HttpResponse::InternalServerError().json(ErrorResponse {
+    // Consume the payment, the unique tx hash makes sure it can only be used once
+    let mut tx = match app_state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to start transaction: {}", e);
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
                 success: false,
-                message: format!("Agent with id {} not running", agent_id),
-                error_code: Some("AGENT_NOT_FOUND".to_string()),
-            });
+                message: "Failed to start database transaction".to_string(),
+                error_code: Some("DB_TRANSACTION_FAILED".to_string()),
+            }));
         }
+    };
 
-        let agent = agent.unwrap();
+    if let Some(verified_payment) = &verified_payment {
+        match database::insert_payment(&mut tx, verified_payment).await {
+            Ok(Some(payment)) => {
+                debug!(
+                    "Payment {} consumed by {}",
+                    payment.tx_hash, payment.payer_address
+                );
+            }
+            Ok(None) => {
+                tx.rollback().await.ok();
 
-        let response = match agent.prompt(prompt).await {
-            Ok(response) => response,
+                return Err(HttpResponse::BadRequest().json(ErrorResponse {
+                    success: false,
+                    message: "Transaction hash already used".to_string(),
+                    error_code: Some("PAYMENT_ALREADY_USED".to_string()),
+                }));
+            }
             Err(e) => {
-                error!("Failed to get AI response: {}", e);
-                return HttpResponse::InternalServerError().json(ErrorResponse {
+                error!("Failed to insert payment: {}", e);
+
+                tx.rollback().await.ok();
+
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse {
                     success: false,
-                    message: format!(
-                        "Failed to get AI response from agent with id {} : {}",
-                        agent_id, e
-                    ),
-                    error_code: Some("AI_RESPONSE_FAILED".to_string()),
-                });
+                    message: "Failed to record payment".to_string(),
+                    error_code: Some("PAYMENT_INSERT_FAILED".to_string()),
+                }));
             }
         };
+    }
+
+    // Without a tx the credits of the buyer pay the agents, only when the session needs a payment
+    let needs_payment = session
+        .as_ref()
+        .is_none_or(|session| session.turns_used >= session.turns_paid);
+
+    let payment_hash = match &verified_payment {
+        Some(verified_payment) => Some(verified_payment.tx_hash.clone()),
+        None if body.use_credits && needs_payment => {
+            let price: f64 = agents.iter().map(|agent| agent.price).sum();
 
-        let agent_response = AgentResponse {
-            agent_id: *agent_id,
-            prompt: prompt.clone(),
-            response,
+            match helpers::credits::pay_with_credits(&mut tx, &payer_address.to_string(), &agents)
+                .await
+            {
+                Ok(Some((payment_hash, balance))) => {
+                    debug!(
+                        "{} paid {} with credits, {} left",
+                        payer_address, price, balance
+                    );
+                    Some(payment_hash)
+                }
+                Ok(None) => {
+                    tx.rollback().await.ok();
+
+                    return Err(HttpResponse::PaymentRequired().json(ErrorResponse {
+                        success: false,
+                        message: format!("Not enough credits to pay {} for the agents", price),
+                        error_code: Some("INSUFFICIENT_CREDITS".to_string()),
+                    }));
+                }
+                Err(e) => {
+                    error!("Failed to spend credits: {}", e);
+
+                    tx.rollback().await.ok();
+
+                    return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                        success: false,
+                        message: "Failed to record credits".to_string(),
+                        error_code: Some("CREDITS_UPDATE_FAILED".to_string()),
+                    }));
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Each payment covers `chat_turns_per_payment` turns of the session
+    let turns_per_payment = APP_CONFIG.chat_turns_per_payment;
+
+    let session_id = match (&session, &payment_hash) {
+        (Some(session), Some(payment_hash)) => {
+            database::add_chat_session_turns(&mut tx, session.id, payment_hash, turns_per_payment)
+                .await
+                .map(|_| session.id)
+        }
+        (Some(session), None) => Ok(session.id),
+        (None, payment_hash) => database::insert_chat_session(
+            &mut tx,
+            &payer_address.to_string(),
+            agent_ids,
+            payment_hash.as_deref().unwrap_or(tx_hash),
+            turns_per_payment,
+        )
+        .await
+        .map(|session| session.id),
+    };
+
+    let session_id = match session_id {
+        Ok(session_id) => session_id,
+        Err(e) => {
+            error!("Failed to record chat session payment: {}", e);
+
+            tx.rollback().await.ok();
+
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to record chat session".to_string(),
+                error_code: Some("CHAT_SESSION_UPDATE_FAILED".to_string()),
+            }));
+        }
+    };
+
+    let session = match database::use_chat_session_turn(&mut tx, session_id).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            tx.rollback().await.ok();
+
+            return Err(HttpResponse::PaymentRequired().json(ErrorResponse {
+                success: false,
+                message: format!(
+                    "All the paid turns of chat session {} are used, send a new payment of its agents",
+                    session_id
+                ),
+                error_code: Some("PAYMENT_REQUIRED".to_string()),
+            }));
+        }
+        Err(e) => {
+            error!("Failed to use chat session turn: {}", e);
+
+            tx.rollback().await.ok();
+
+            return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to record chat session".to_string(),
+                error_code: Some("CHAT_SESSION_UPDATE_FAILED".to_string()),
+            }));
+        }
+    };
+
+    // Differential privacy agents charge their epsilon to the buyer budget at every turn
+    let mut dp_epsilons = HashMap::new();
+
+    for agent in agents.iter().filter(|agent| agent.dp_enabled) {
+        let (Some(epsilon), Some(budget)) = (agent.dp_epsilon, agent.dp_budget) else {
+            continue;
         };
 
-        agent_responses.push(agent_response);
+        match database::spend_dp_budget(
+            &mut tx,
+            agent.id,
+            &payer_address.to_string(),
+            epsilon,
+            budget,
+        )
+        .await
+        {
+            Ok(Some(epsilon_spent)) => {
+                debug!(
+                    "Privacy budget of {} on agent {}: {}/{}",
+                    payer_address, agent.id, epsilon_spent, budget
+                );
+
+                dp_epsilons.insert(agent.id, epsilon);
+            }
+            Ok(None) => {
+                tx.rollback().await.ok();
+
+                return Err(HttpResponse::Forbidden().json(ErrorResponse {
+                    success: false,
+                    message: format!("Privacy budget on agent with id {} is exhausted", agent.id),
+                    error_code: Some("DP_BUDGET_EXHAUSTED".to_string()),
+                }));
+            }
+            Err(e) => {
+                error!("Failed to spend privacy budget: {}", e);
+
+                tx.rollback().await.ok();
+
+                return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to record privacy budget".to_string(),
+                    error_code: Some("DP_BUDGET_UPDATE_FAILED".to_string()),
+                }));
+            }
+        }
     }
 
-    HttpResponse::Ok().json(GetResponseFromAgentsResponse {
-        agent_responses,
-        success: true,
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        error!("Failed to commit transaction: {}", e);
+        return Err(HttpResponse::InternalServerError().json(ErrorResponse {
+            success: false,
+            message: "Failed to commit database transaction".to_string(),
+            error_code: Some("DB_COMMIT_FAILED".to_string()),
+        }));
+    }
+
+    Ok(PaidTurn {
+        session,
+        payer_address,
+        dp_epsilons,
     })
 }
 
+/// Earlier turns of the session, for each of its agents.
+async fn chat_histories(
+    app_state: &AppState,
+    paid_turn: &PaidTurn,
+) -> Result<HashMap<i64, Vec<ChatTurn>>, HttpResponse> {
+    match database::get_chat_messages(&app_state.db, paid_turn.session.id).await {
+        Ok(messages) => Ok(helpers::chat::agent_histories(&messages)),
+        Err(e) => {
+            error!("Failed to get chat messages: {}", e);
+            Err(HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get chat session messages from database".to_string(),
+                error_code: Some("CHAT_MESSAGES_FETCH_FAILED".to_string()),
+            }))
+        }
+    }
+}
+
+/// Store the prompt of a turn with the answers sent to the buyer, the next turns follow them.
+async fn save_chat_turn(
+    app_state: &AppState,
+    paid_turn: &PaidTurn,
+    prompt: &str,
+    agent_responses: &[AgentResponse],
+) -> color_eyre::Result<()> {
+    let session = &paid_turn.session;
+
+    let mut tx = app_state.db.begin().await?;
+
+    database::insert_chat_message(
+        &mut tx,
+        session.id,
+        session.turns_used,
+        None,
+        ChatRole::User,
+        prompt,
+    )
+    .await?;
+
+    // Agents that gave no answer are left out of the history
+    for agent_response in agent_responses
+        .iter()
+        .filter(|agent_response| agent_response.status == AgentAnswerStatus::Answered)
+    {
+        database::insert_chat_message(
+            &mut tx,
+            session.id,
+            session.turns_used,
+            Some(agent_response.agent_id),
+            ChatRole::Assistant,
+            &agent_response.response,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Synthesis of the answers when the buyer asked for one. The answers are paid for and sent
+/// without it when it fails.
+async fn answers_synthesis(
+    app_state: &AppState,
+    body: &GetResponseFromAgentsRequest,
+    agent_responses: &[AgentResponse],
+) -> Option<AgentSynthesis> {
+    if !body.synthesize {
+        return None;
+    }
+
+    match helpers::synthesis::synthesize_answers(&app_state.ai_model, &body.prompt, agent_responses)
+        .await
+    {
+        Ok(synthesis) => synthesis,
+        Err(e) => {
+            error!("Failed to synthesize the agents answers: {}", e);
+            None
+        }
+    }
+}
+
+/// Log the guardrail verdict of an answer signed by the enclave for the agent owner.
+async fn attested_answer(
+    app_state: &AppState,
+    agent_id: i64,
+    prompt: &str,
+    paid_turn: &PaidTurn,
+    answer: TeeAnswer,
+) -> AgentResponse {
+    let TeeAnswer {
+        guarded,
+        attestation,
+    } = answer;
+
+    if guarded.verdict != GuardrailVerdict::Allowed {
+        warn!(
+            "Guardrail {:?} answer of agent {} (overlap {:.2}, pii {:?})",
+            guarded.verdict, agent_id, guarded.overlap_ratio, guarded.pii_types
+        );
+    }
+
+    let guardrail_event = NewGuardrailEvent {
+        agent_id,
+        tx_hash: paid_turn.session.tx_hash.clone(),
+        buyer_address: paid_turn.payer_address.to_string(),
+        prompt: prompt.to_string(),
+        verdict: guarded.verdict,
+        overlap_ratio: guarded.overlap_ratio,
+        pii_types: guarded.pii_types,
+    };
+
+    // The owner review log must not cost the buyer an answer already paid for
+    if let Err(e) = database::insert_guardrail_event(&app_state.db, &guardrail_event).await {
+        error!("Failed to log guardrail verdict: {}", e);
+    }
+
+    AgentResponse {
+        agent_id,
+        prompt: prompt.to_string(),
+        response: guarded.response,
+        attestation: Some(attestation),
+        guardrail_verdict: Some(guarded.verdict),
+        status: AgentAnswerStatus::Answered,
+        error: None,
+        error_code: None,
+    }
+}
+
 #[utoipa::path(
     get,
     path = "/datasets/stats",
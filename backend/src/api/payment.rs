@@ -0,0 +1,106 @@
+use std::str::FromStr;
+
+use actix_web::{HttpResponse, Responder, get, web};
+use alloy::primitives::Address;
+
+use crate::{
+    database,
+    state::AppState,
+    types::{ErrorResponse, PaymentResponse, PaymentsResponse},
+};
+
+#[utoipa::path(
+    get,
+    path = "/payments/{tx_hash}",
+    params(
+        ("tx_hash" = String, Path, description = "Hash of the payment transaction")
+    ),
+    responses(
+        (status = 200, description = "Payment retrieved successfully", body = PaymentResponse),
+        (status = 404, description = "The transaction was never consumed as a payment", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Payment"
+)]
+#[get("/payments/{tx_hash}")]
+async fn get_payment_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let tx_hash = path.into_inner();
+
+    let payment = match database::get_payment_by_tx_hash(&app_state.db, tx_hash.trim()).await {
+        Ok(payment) => payment,
+        Err(e) => {
+            tracing::error!("Failed to get payment: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get payment from database".to_string(),
+                error_code: Some("PAYMENT_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    match payment {
+        Some(payment) => HttpResponse::Ok().json(PaymentResponse {
+            success: true,
+            message: "Payment retrieved successfully".to_string(),
+            payment,
+        }),
+        None => HttpResponse::NotFound().json(ErrorResponse {
+            success: false,
+            message: "Payment not found".to_string(),
+            error_code: Some("PAYMENT_NOT_FOUND".to_string()),
+        }),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{address}/payments",
+    params(
+        ("address" = String, Path, description = "Payer address")
+    ),
+    responses(
+        (status = 200, description = "Payments retrieved successfully, most recent first", body = PaymentsResponse),
+        (status = 400, description = "Bad request - invalid address", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "Payment"
+)]
+#[get("/users/{address}/payments")]
+async fn get_user_payments_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    // Payments are stored with the checksummed address of the tx sender
+    let payer_address = match Address::from_str(path.into_inner().trim()) {
+        Ok(address) => address.to_string(),
+        Err(_) => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: "Invalid address".to_string(),
+                error_code: Some("INVALID_ADDRESS".to_string()),
+            });
+        }
+    };
+
+    let payments =
+        match database::get_payments_by_payer_address(&app_state.db, &payer_address).await {
+            Ok(payments) => payments,
+            Err(e) => {
+                tracing::error!("Failed to get payments: {}", e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to get payments from database".to_string(),
+                    error_code: Some("PAYMENT_FETCH_FAILED".to_string()),
+                });
+            }
+        };
+
+    HttpResponse::Ok().json(PaymentsResponse {
+        success: true,
+        message: "Payments retrieved successfully".to_string(),
+        payments,
+    })
+}
@@ -1,9 +1,15 @@
+use std::str::FromStr;
+
 use actix_web::{HttpResponse, Responder, get, web};
+use alloy::primitives::Address;
+use chrono::{Duration, Utc};
 
 use crate::{
+    config::{EARNINGS_DEFAULT_DAYS, EARNINGS_MAX_DAYS},
     database,
+    helpers::ledger,
     state::AppState,
-    types::{ErrorResponse, ProfileResponse},
+    types::{EarningsQueryParams, EarningsResponse, ErrorResponse, ProfileResponse},
 };
 
 #[utoipa::path(
@@ -14,6 +20,7 @@ use crate::{
     ),
     responses(
         (status = 200, description = "User profile retrieved successfully", body = ProfileResponse),
+        (status = 400, description = "Bad request - invalid address", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "User"
@@ -23,7 +30,17 @@ async fn get_profile_service(
     app_state: web::Data<AppState>,
     path: web::Path<String>,
 ) -> impl Responder {
-    let user_address = path.into_inner();
+    // Users and ledger rows are stored with checksummed addresses
+    let user_address = match Address::from_str(path.into_inner().trim()) {
+        Ok(address) => address.to_string(),
+        Err(_) => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: "Invalid address".to_string(),
+                error_code: Some("INVALID_ADDRESS".to_string()),
+            });
+        }
+    };
 
     let db = &app_state.db;
 
@@ -40,9 +57,96 @@ async fn get_profile_service(
         }
     };
 
+    let (earnings, _) = match ledger::get_owner_earnings(&app_state, &user_address).await {
+        Ok(earnings) => earnings,
+        Err(e) => {
+            tracing::error!("Failed to get earnings: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get earnings from database".to_string(),
+                error_code: Some("EARNINGS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
     HttpResponse::Ok().json(ProfileResponse {
         sucess: true,
         message: "User profile retrieved successfully".to_string(),
         agents: agents_db,
+        earnings,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{address}/earnings",
+    params(
+        ("address" = String, Path, description = "Dataset owner address"),
+        ("days" = Option<i64>, Query, description = "Number of days covered by the daily series (default: 30, max: 365)")
+    ),
+    responses(
+        (status = 200, description = "Owner earnings retrieved successfully", body = EarningsResponse),
+        (status = 400, description = "Bad request - invalid address", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "User"
+)]
+#[get("/users/{address}/earnings")]
+async fn get_earnings_service(
+    app_state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<EarningsQueryParams>,
+) -> impl Responder {
+    // Ledger rows and users are stored with checksummed addresses
+    let owner_address = match Address::from_str(path.into_inner().trim()) {
+        Ok(address) => address.to_string(),
+        Err(_) => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                success: false,
+                message: "Invalid address".to_string(),
+                error_code: Some("INVALID_ADDRESS".to_string()),
+            });
+        }
+    };
+
+    let days = query
+        .days
+        .unwrap_or(EARNINGS_DEFAULT_DAYS)
+        .clamp(1, EARNINGS_MAX_DAYS);
+
+    let (summary, agents) = match ledger::get_owner_earnings(&app_state, &owner_address).await {
+        Ok(earnings) => earnings,
+        Err(e) => {
+            tracing::error!("Failed to get earnings: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                success: false,
+                message: "Failed to get earnings from database".to_string(),
+                error_code: Some("EARNINGS_FETCH_FAILED".to_string()),
+            });
+        }
+    };
+
+    let since = Utc::now() - Duration::days(days);
+
+    let daily =
+        match database::get_daily_earnings_by_owner(&app_state.db, &owner_address, since).await {
+            Ok(daily) => daily,
+            Err(e) => {
+                tracing::error!("Failed to get daily earnings: {}", e);
+                return HttpResponse::InternalServerError().json(ErrorResponse {
+                    success: false,
+                    message: "Failed to get daily earnings from database".to_string(),
+                    error_code: Some("EARNINGS_FETCH_FAILED".to_string()),
+                });
+            }
+        };
+
+    HttpResponse::Ok().json(EarningsResponse {
+        success: true,
+        message: "Owner earnings retrieved successfully".to_string(),
+        address: owner_address,
+        summary,
+        agents,
+        daily,
     })
 }
@@ -0,0 +1,264 @@
+use std::time::Duration;
+
+use actix_web::{HttpResponse, post, web};
+use color_eyre::eyre::eyre;
+use futures_util::{StreamExt, future::join_all};
+use tokio::{sync::mpsc, time::Instant};
+use tracing::{error, warn};
+
+use crate::{
+    api::{
+        AgentFailure, PaidTurn, answers_synthesis, attested_answer, chat_histories, failed_answer,
+        pay_for_answer, save_chat_turn,
+    },
+    config::{AGENT_ANSWER_TIMEOUT_SECS, AGENT_STREAM_BUFFER},
+    helpers::auth::AuthenticatedUser,
+    state::AppState,
+    tee::{TeeStreamEvent, protocol::ChatTurn},
+    types::{
+        AgentResponse, AgentStreamEvent, AgentUsage, ErrorResponse, GetResponseFromAgentsRequest,
+        GuardrailVerdict,
+    },
+};
+
+/*
+Streaming variant of `/chat/agents/answer`: the agents answer concurrently and their text is sent as it is generated.
+*/
+#[utoipa::path(
+    post,
+    path = "/chat/agents/answer/stream",
+    request_body(
+        content = GetResponseFromAgentsRequest,
+        content_type = "application/json",
+        description = "Same request as /chat/agents/answer. The tx must be sent by the wallet of the authenticated session."
+    ),
+    responses(
+        (status = 200, description = "Server sent events: `chunk` events tagged by agent id while the agents answer, then a single `done` event with the attested answers, their synthesis when requested, the tokens used and the chat session", body = AgentStreamEvent, content_type = "text/event-stream"),
+        (status = 400, description = "Bad request - invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 402, description = "All the paid turns of the chat session are used and no new payment was sent, or the credits of the buyer are too low", body = ErrorResponse),
+        (status = 403, description = "Privacy budget of the buyer exhausted on a differential privacy agent", body = ErrorResponse),
+        (status = 404, description = "Chat session not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Agents"
+)]
+#[post("/chat/agents/answer/stream")]
+async fn stream_response_from_agents_service(
+    app_state: web::Data<AppState>,
+    auth_user: AuthenticatedUser,
+    body: web::Json<GetResponseFromAgentsRequest>,
+) -> HttpResponse {
+    // Failures up to the payment are regular JSON responses
+    let paid_turn = match pay_for_answer(&app_state, &auth_user, &body).await {
+        Ok(paid_turn) => paid_turn,
+        Err(response) => return response,
+    };
+
+    let histories = match chat_histories(&app_state, &paid_turn).await {
+        Ok(histories) => histories,
+        Err(response) => return response,
+    };
+
+    let request = body.into_inner();
+    let (sender, receiver) = mpsc::channel(AGENT_STREAM_BUFFER);
+
+    tokio::spawn(async move {
+        let answers = join_all(paid_turn.session.agent_ids.iter().map(|agent_id| {
+            stream_agent_answer(
+                &app_state,
+                *agent_id,
+                &request.prompt,
+                histories.get(agent_id).map(Vec::as_slice).unwrap_or(&[]),
+                &paid_turn,
+                &sender,
+            )
+        }))
+        .await;
+
+        let (agent_responses, usage): (Vec<_>, Vec<_>) = answers.into_iter().unzip();
+
+        // The answers are paid for and sent anyway, only the memory of the turn is lost
+        if let Err(e) =
+            save_chat_turn(&app_state, &paid_turn, &request.prompt, &agent_responses).await
+        {
+            error!("Failed to save chat turn: {}", e);
+        }
+
+        let synthesis = answers_synthesis(&app_state, &request, &agent_responses).await;
+
+        let done = AgentStreamEvent::Done {
+            agent_responses,
+            synthesis,
+            usage: usage.into_iter().flatten().collect(),
+            session_id: paid_turn.session.id,
+            turns_left: paid_turn.turns_left(),
+        };
+
+        sender.send(done).await.ok();
+    });
+
+    let events = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+
+        Some((Ok::<_, actix_web::Error>(sse_event(&event)), receiver))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events)
+}
+
+/// Stream the answer of an agent as the executor guardrail releases it, it ends with the signed answer.
+/// A failed agent is recorded for the buyer after sending an `error` event, it is only credited
+/// when none of its answer was sent.
+async fn stream_agent_answer(
+    app_state: &AppState,
+    agent_id: i64,
+    prompt: &str,
+    history: &[ChatTurn],
+    paid_turn: &PaidTurn,
+    sender: &mpsc::Sender<AgentStreamEvent>,
+) -> (AgentResponse, Option<AgentUsage>) {
+    let mut sent = String::new();
+
+    match try_stream_agent_answer(
+        app_state, agent_id, prompt, history, paid_turn, sender, &mut sent,
+    )
+    .await
+    {
+        Ok(answer) => answer,
+        Err(failure) => {
+            sender.send(agent_error(agent_id, &failure)).await.ok();
+
+            let agent_response = if sent.is_empty() {
+                failed_answer(app_state, agent_id, prompt, paid_turn, failure).await
+            } else {
+                partial_answer(agent_id, prompt, sent, failure)
+            };
+
+            (agent_response, None)
+        }
+    }
+}
+
+/// Answer of an agent that failed once part of it was sent. The buyer got that part, and with it
+/// what the turn paid for (privacy budget included), so nothing is given back.
+fn partial_answer(
+    agent_id: i64,
+    prompt: &str,
+    sent: String,
+    failure: AgentFailure,
+) -> AgentResponse {
+    warn!(
+        "Agent {} failed after sending part of its answer: {}",
+        agent_id, failure.message
+    );
+
+    AgentResponse {
+        agent_id,
+        prompt: prompt.to_string(),
+        response: sent,
+        attestation: None,
+        guardrail_verdict: None,
+        status: failure.status,
+        error: Some(failure.message),
+        error_code: Some(failure.error_code.to_string()),
+    }
+}
+
+async fn try_stream_agent_answer(
+    app_state: &AppState,
+    agent_id: i64,
+    prompt: &str,
+    history: &[ChatTurn],
+    paid_turn: &PaidTurn,
+    sender: &mpsc::Sender<AgentStreamEvent>,
+    sent: &mut String,
+) -> Result<(AgentResponse, Option<AgentUsage>), AgentFailure> {
+    if !app_state.tee.has_agent(agent_id) {
+        return Err(AgentFailure::not_running(agent_id));
+    }
+
+    // The timeout covers the whole answer, not each chunk
+    let deadline = Instant::now() + Duration::from_secs(AGENT_ANSWER_TIMEOUT_SECS);
+
+    let mut events = tokio::time::timeout_at(
+        deadline,
+        app_state
+            .tee
+            .prompt_stream(agent_id, prompt, history, &paid_turn.session.tx_hash),
+    )
+    .await
+    .map_err(|_| AgentFailure::timed_out(agent_id))?
+    .map_err(|e| AgentFailure::ai_response(agent_id, e))?;
+
+    // The executor only releases text the output guardrail checked
+    let mut answer = None;
+
+    while let Some(event) = tokio::time::timeout_at(deadline, events.next())
+        .await
+        .map_err(|_| AgentFailure::timed_out(agent_id))?
+    {
+        match event.map_err(|e| AgentFailure::ai_response(agent_id, e))? {
+            // A client that disconnected still finds the answer in its chat session
+            TeeStreamEvent::Chunk(text) => {
+                sent.push_str(&text);
+
+                sender
+                    .send(AgentStreamEvent::Chunk { agent_id, text })
+                    .await
+                    .ok();
+            }
+            TeeStreamEvent::End { usage, answer: end } => {
+                answer = Some((usage, end));
+                break;
+            }
+        }
+    }
+
+    let (usage, answer) = answer.ok_or_else(|| {
+        AgentFailure::ai_response(agent_id, eyre!("Answer stream ended without its end event"))
+    })?;
+
+    if answer.guarded.verdict == GuardrailVerdict::Blocked {
+        sender
+            .send(AgentStreamEvent::Blocked { agent_id })
+            .await
+            .ok();
+    }
+
+    let agent_response = attested_answer(app_state, agent_id, prompt, paid_turn, *answer).await;
+
+    let usage = usage.map(|usage| AgentUsage {
+        agent_id,
+        total_tokens: usage.total_tokens,
+    });
+
+    Ok((agent_response, usage))
+}
+
+fn agent_error(agent_id: i64, failure: &AgentFailure) -> AgentStreamEvent {
+    AgentStreamEvent::Error {
+        agent_id,
+        message: failure.message.clone(),
+        error_code: failure.error_code.to_string(),
+    }
+}
+
+/// Server sent event named after the type of `event`, with the event as JSON data.
+fn sse_event(event: &AgentStreamEvent) -> web::Bytes {
+    let name = match event {
+        AgentStreamEvent::Chunk { .. } => "chunk",
+        AgentStreamEvent::Blocked { .. } => "blocked",
+        AgentStreamEvent::Error { .. } => "error",
+        AgentStreamEvent::Done { .. } => "done",
+    };
+
+    // Compact JSON never contains a line break, the data fits on one line
+    let data = serde_json::to_string(event).unwrap_or_default();
+
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", name, data))
+}
@@ -0,0 +1,429 @@
+//! Mock enclave speaking the backend TEE protocol, to test the remote executor without hardware.
+//!
+//! It decrypts the datasets it receives with `DATASET_MASTER_KEY`, ingests them like a real
+//! enclave (without embeddings), answers prompts with a deterministic description of what it
+//! holds, no model is called, checks them with the output guardrail (`GUARDRAIL_OVERLAP_THRESHOLD`,
+//! `GUARDRAIL_ACTION`) and signs them with `ATTESTATION_SIGNING_KEY` under a simulated quote of
+//! its own build. Run it with `cargo run --bin mock_enclave` and start the backend with
+//! `TEE_BACKEND=remote`.
+
+use std::{collections::HashMap, sync::Arc};
+
+use color_eyre::{Result, eyre::eyre};
+use dashmap::DashMap;
+use tokio::{
+    fs::File,
+    net::{TcpListener, TcpStream},
+};
+use tracing::{error, info};
+
+use enclava_backend::{
+    config::GuardrailPolicy,
+    helpers::{
+        crypto::SealedDataset,
+        guardrail::{GuardrailIndex, GuardrailOutcome},
+        rag,
+        temp_file::TempFile,
+    },
+    tee::{
+        TeeAnswer,
+        attestation::{self, SimulatedAttestationProvider},
+        ingest,
+        protocol::{
+            self, CHUNKS_PER_MESSAGE, FINGERPRINTS_PER_MESSAGE, TeeConnection, TeeErrorCode,
+            TeeRequest, TeeResponse,
+        },
+    },
+    types::{DatasetChunkDb, PiiAction, PiiReport},
+};
+
+struct MockAgent {
+    name: String,
+    columns: Vec<String>,
+    row_count: usize,
+    chunk_count: usize,
+}
+
+struct MockEnclave {
+    agents: DashMap<i64, MockAgent>,
+    master_key: Vec<u8>,
+    attestation: SimulatedAttestationProvider,
+    guardrail: GuardrailIndex,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    dotenvy::dotenv().ok();
+
+    tracing_subscriber::fmt::init();
+
+    let addr = std::env::var("TEE_REMOTE_ADDR").unwrap_or_else(|_| "127.0.0.1:7878".to_string());
+
+    // Only the enclave decrypts datasets, the backend forwards them encrypted
+    let master_key = std::env::var("DATASET_MASTER_KEY")
+        .ok()
+        .and_then(|key| alloy::hex::decode(key).ok())
+        .filter(|key| key.len() == 32)
+        .ok_or_else(|| eyre!("DATASET_MASTER_KEY must be 32 bytes hex encoded"))?;
+
+    let signing_key = std::env::var("ATTESTATION_SIGNING_KEY")
+        .map_err(|_| eyre!("ATTESTATION_SIGNING_KEY must be set"))?;
+
+    let attestation = SimulatedAttestationProvider::from_key(&signing_key)?;
+
+    let listener = TcpListener::bind(&addr).await?;
+
+    info!("Mock enclave listening on {}", addr);
+
+    let enclave = Arc::new(MockEnclave {
+        agents: DashMap::new(),
+        guardrail: GuardrailIndex::new(&master_key, GuardrailPolicy::from_env()),
+        master_key,
+        attestation,
+    });
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+
+        let enclave = enclave.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &enclave).await {
+                error!("Connection with {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, enclave: &MockEnclave) -> Result<()> {
+    let mut connection: TeeConnection = protocol::framed(stream);
+
+    while let Some(request) = protocol::read_message::<TeeRequest>(&mut connection).await? {
+        match request {
+            TeeRequest::LoadAgent {
+                agent_id,
+                name,
+                chunk_count,
+                fingerprint_count,
+                pii_report,
+                ..
+            } => {
+                load_agent(
+                    &mut connection,
+                    enclave,
+                    agent_id,
+                    name,
+                    chunk_count,
+                    fingerprint_count,
+                    pii_report,
+                )
+                .await?
+            }
+            TeeRequest::IngestDataset {
+                agent_id,
+                pii_actions,
+                embed,
+            } => ingest_dataset(&mut connection, enclave, agent_id, pii_actions, embed).await?,
+            TeeRequest::DatasetChunks { .. } | TeeRequest::Fingerprints { .. } => {
+                let response = TeeResponse::Error {
+                    code: TeeErrorCode::InvalidRequest,
+                    message: "Agent data sent without an agent".to_string(),
+                };
+
+                protocol::send_message(&mut connection, &response).await?;
+            }
+            TeeRequest::Quote => {
+                let response = match attestation::enclave_quote(&enclave.attestation) {
+                    Ok(quote) => TeeResponse::Quote { quote },
+                    Err(e) => attestation_error(&e),
+                };
+
+                protocol::send_message(&mut connection, &response).await?;
+            }
+            TeeRequest::Prompt {
+                agent_id,
+                prompt,
+                history,
+                tx_hash,
+            } => {
+                let response = match mock_answer(enclave, agent_id, &prompt, history.len()) {
+                    Some(response) => {
+                        let guarded = enclave.guardrail.check_answer(agent_id, &response);
+
+                        match sign_answer(enclave, agent_id, &prompt, guarded, &tx_hash) {
+                            Ok(answer) => TeeResponse::Answer { agent_id, answer },
+                            Err(e) => attestation_error(&e),
+                        }
+                    }
+                    None => agent_not_loaded(agent_id),
+                };
+
+                protocol::send_message(&mut connection, &response).await?;
+            }
+            TeeRequest::PromptStream {
+                agent_id,
+                prompt,
+                history,
+                tx_hash,
+            } => {
+                let responses = match mock_answer(enclave, agent_id, &prompt, history.len()) {
+                    Some(response) => {
+                        stream_answer(enclave, agent_id, &prompt, &response, &tx_hash)
+                    }
+                    None => vec![agent_not_loaded(agent_id)],
+                };
+
+                for response in responses {
+                    protocol::send_message(&mut connection, &response).await?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Receive the encrypted dataset following a request.
+async fn receive_dataset(
+    connection: &mut TeeConnection,
+    enclave: &MockEnclave,
+) -> Result<SealedDataset> {
+    let file = TempFile::new("enclave-dataset");
+
+    protocol::receive_dataset(connection, file.path()).await?;
+
+    Ok(SealedDataset::open(file, &enclave.master_key))
+}
+
+async fn load_agent(
+    connection: &mut TeeConnection,
+    enclave: &MockEnclave,
+    agent_id: i64,
+    name: String,
+    chunk_count: usize,
+    fingerprint_count: usize,
+    pii_report: Option<PiiReport>,
+) -> Result<()> {
+    let dataset = receive_dataset(connection, enclave).await?;
+
+    let chunks = protocol::read_batches(connection, chunk_count, |request| match request {
+        TeeRequest::DatasetChunks { chunks } => Ok(chunks),
+        request => Err(eyre!("Expected dataset chunks, got {:?}", request)),
+    })
+    .await?;
+
+    let fingerprints =
+        protocol::read_batches(connection, fingerprint_count, |request| match request {
+            TeeRequest::Fingerprints { fingerprints } => Ok(fingerprints),
+            request => Err(eyre!("Expected fingerprints, got {:?}", request)),
+        })
+        .await?;
+
+    let response = match dataset_shape(&dataset) {
+        Ok((columns, row_count)) => {
+            info!(
+                "Loaded agent {} ({} rows, {} chunks)",
+                agent_id,
+                row_count,
+                chunks.len()
+            );
+
+            enclave
+                .guardrail
+                .insert(agent_id, fingerprints, pii_report.as_ref());
+            enclave.agents.insert(
+                agent_id,
+                MockAgent {
+                    name,
+                    columns,
+                    row_count,
+                    chunk_count: chunks.len(),
+                },
+            );
+
+            TeeResponse::Loaded { agent_id }
+        }
+        Err(e) => TeeResponse::Error {
+            code: TeeErrorCode::InvalidRequest,
+            message: format!("Invalid dataset: {}", e),
+        },
+    };
+
+    protocol::send_message(connection, &response).await
+}
+
+fn dataset_shape(dataset: &SealedDataset) -> Result<(Vec<String>, usize)> {
+    let mut reader = dataset.csv_reader()?;
+
+    let columns = reader.headers()?.iter().map(str::to_string).collect();
+    let row_count = reader.records().count();
+
+    Ok((columns, row_count))
+}
+
+async fn ingest_dataset(
+    connection: &mut TeeConnection,
+    enclave: &MockEnclave,
+    agent_id: i64,
+    pii_actions: Option<HashMap<String, PiiAction>>,
+    embed: bool,
+) -> Result<()> {
+    let dataset = receive_dataset(connection, enclave).await?;
+
+    let (mut dataset_ingest, redacted) =
+        match ingest::ingest_dataset(&dataset, pii_actions.as_ref(), None).await {
+            Ok(ingested) => ingested,
+            Err(e) => {
+                return protocol::send_message(connection, &ingest::ingest_error(&e)).await;
+            }
+        };
+
+    // No model is called, the chunks only get their rows range
+    if embed {
+        dataset_ingest.chunks = rag::chunk_dataset(redacted.as_ref().unwrap_or(&dataset))?
+            .into_iter()
+            .map(|chunk| DatasetChunkDb {
+                chunk_index: chunk.chunk_index,
+                first_row: chunk.first_row,
+                last_row: chunk.last_row,
+                embedding: Vec::new(),
+            })
+            .collect();
+    }
+
+    info!(
+        "Ingested dataset of agent {} ({} fingerprints, {} chunks)",
+        agent_id,
+        dataset_ingest.fingerprints.len(),
+        dataset_ingest.chunks.len()
+    );
+
+    let response = TeeResponse::Ingested {
+        agent_id,
+        pii_report: dataset_ingest.pii_report,
+        profile: dataset_ingest.profile,
+        redacted: redacted.is_some(),
+        fingerprint_count: dataset_ingest.fingerprints.len(),
+        chunk_count: dataset_ingest.chunks.len(),
+    };
+
+    protocol::send_message(connection, &response).await?;
+
+    if let Some(redacted) = redacted {
+        protocol::send_dataset(connection, File::open(redacted.path()).await?).await?;
+    }
+
+    protocol::send_batches(
+        connection,
+        &dataset_ingest.fingerprints,
+        FINGERPRINTS_PER_MESSAGE,
+        |fingerprints| TeeResponse::Fingerprints { fingerprints },
+    )
+    .await?;
+
+    protocol::send_batches(
+        connection,
+        &dataset_ingest.chunks,
+        CHUNKS_PER_MESSAGE,
+        |chunks| TeeResponse::DatasetChunks { chunks },
+    )
+    .await
+}
+
+fn attestation_error(error: &color_eyre::Report) -> TeeResponse {
+    TeeResponse::Error {
+        code: TeeErrorCode::AttestationFailed,
+        message: error.to_string(),
+    }
+}
+
+fn mock_answer(
+    enclave: &MockEnclave,
+    agent_id: i64,
+    prompt: &str,
+    earlier_turns: usize,
+) -> Option<String> {
+    enclave.agents.get(&agent_id).map(|agent| {
+        format!(
+            "[mock enclave] {} holds {} rows in {} chunks with the columns {} and received after {} earlier turns: {}",
+            agent.name,
+            agent.row_count,
+            agent.chunk_count,
+            agent.columns.join(", "),
+            earlier_turns,
+            prompt
+        )
+    })
+}
+
+/// The answer streamed word by word through the output guardrail, stopped if it blocks it.
+fn stream_answer(
+    enclave: &MockEnclave,
+    agent_id: i64,
+    prompt: &str,
+    response: &str,
+    tx_hash: &str,
+) -> Vec<TeeResponse> {
+    let mut guard = enclave.guardrail.stream(agent_id);
+    let mut responses = Vec::new();
+
+    for word in response.split_inclusive(' ') {
+        let text = guard.push(word);
+
+        if guard.is_blocked() {
+            break;
+        }
+
+        if !text.is_empty() {
+            responses.push(TeeResponse::Chunk { agent_id, text });
+        }
+    }
+
+    let (text, guarded) = guard.finish();
+
+    if !text.is_empty() {
+        responses.push(TeeResponse::Chunk { agent_id, text });
+    }
+
+    responses.push(
+        match sign_answer(enclave, agent_id, prompt, guarded, tx_hash) {
+            Ok(answer) => TeeResponse::StreamEnd {
+                usage: None,
+                answer,
+            },
+            Err(e) => attestation_error(&e),
+        },
+    );
+
+    responses
+}
+
+/// Sign the answer as the guardrail released it, the buyer receives exactly this text.
+fn sign_answer(
+    enclave: &MockEnclave,
+    agent_id: i64,
+    prompt: &str,
+    guarded: GuardrailOutcome,
+    tx_hash: &str,
+) -> Result<TeeAnswer> {
+    let attestation = attestation::attest_agent_response(
+        &enclave.attestation,
+        agent_id,
+        prompt,
+        &guarded.response,
+        tx_hash,
+    )?;
+
+    Ok(TeeAnswer {
+        guarded,
+        attestation,
+    })
+}
+
+fn agent_not_loaded(agent_id: i64) -> TeeResponse {
+    TeeResponse::Error {
+        code: TeeErrorCode::AgentNotLoaded,
+        message: format!("Agent {} is not loaded", agent_id),
+    }
+}
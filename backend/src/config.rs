@@ -1,11 +1,133 @@
 use once_cell::sync::Lazy;
 
+/// Where agents run, see `tee::TeeExecutor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeeBackend {
+    InProcess,
+    Remote,
+}
+
+impl TeeBackend {
+    pub fn from_string(backend: &str) -> Option<TeeBackend> {
+        match backend {
+            "in_process" => Some(TeeBackend::InProcess),
+            "remote" => Some(TeeBackend::Remote),
+            _ => None,
+        }
+    }
+}
+
+/// Source of enclave attestations, see `tee::attestation::AttestationProvider`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttestationBackend {
+    Simulated,
+}
+
+impl AttestationBackend {
+    pub fn from_string(backend: &str) -> Option<AttestationBackend> {
+        match backend {
+            "simulated" => Some(AttestationBackend::Simulated),
+            _ => None,
+        }
+    }
+}
+
+/// Where dataset files are kept, see `storage::DatasetStore`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatasetStoreBackend {
+    Fs,
+    S3,
+    /// Postgres large objects, in the database of the backend
+    Pg,
+}
+
+impl DatasetStoreBackend {
+    pub fn from_string(backend: &str) -> Option<DatasetStoreBackend> {
+        match backend {
+            "fs" => Some(DatasetStoreBackend::Fs),
+            "s3" => Some(DatasetStoreBackend::S3),
+            "pg" => Some(DatasetStoreBackend::Pg),
+            _ => None,
+        }
+    }
+}
+
+/// What the output guardrail does with an answer that reproduces too much of its dataset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuardrailAction {
+    /// Withhold the whole answer
+    Block,
+    /// Remove the lines copied from the dataset
+    Redact,
+}
+
+impl GuardrailAction {
+    pub fn from_string(action: &str) -> Option<GuardrailAction> {
+        match action {
+            "block" => Some(GuardrailAction::Block),
+            "redact" => Some(GuardrailAction::Redact),
+            _ => None,
+        }
+    }
+}
+
+/// Output guardrail settings, read by the backend and by a remote enclave.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuardrailPolicy {
+    /// Share of an answer found verbatim in the dataset above which the guardrail acts
+    pub overlap_threshold: f64,
+    pub action: GuardrailAction,
+}
+
+impl GuardrailPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            overlap_threshold: std::env::var("GUARDRAIL_OVERLAP_THRESHOLD")
+                .unwrap_or_else(|_| "0.5".to_string())
+                .parse()
+                .ok()
+                .filter(|threshold| (0.0..=1.0).contains(threshold))
+                .expect("GUARDRAIL_OVERLAP_THRESHOLD must be a number between 0 and 1"),
+            action: GuardrailAction::from_string(
+                &std::env::var("GUARDRAIL_ACTION").unwrap_or_else(|_| "redact".to_string()),
+            )
+            .expect("GUARDRAIL_ACTION must be block or redact"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     pub database_url: String,
     pub alchemy_rpc_url: String,
-    pub alchemy_ws_url: String,
     pub port: u16,
+    pub siwe_domain: String,
+    pub siwe_uri: String,
+    pub chain_id: u64,
+    /// Deployment block of the EnclavaPayments contract, where fetchers without a cursor start
+    pub fetcher_start_block: u64,
+    pub confirmation_depth: u64,
+    pub tee_backend: TeeBackend,
+    pub tee_remote_addr: String,
+    pub attestation_backend: AttestationBackend,
+    /// Required by the executor that signs answers (`in_process`), a remote enclave holds its own
+    pub attestation_signing_key: Option<String>,
+    /// Wraps the per dataset encryption keys, 32 bytes hex encoded
+    pub dataset_master_key: [u8; 32],
+    pub dataset_store: DatasetStoreBackend,
+    pub s3_bucket: Option<String>,
+    /// Custom endpoint of an S3 compatible service (e.g. `http://localhost:9000` for MinIO)
+    pub s3_endpoint: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+    /// Key prefix of the datasets inside the bucket
+    pub s3_prefix: String,
+    pub guardrail: GuardrailPolicy,
+    /// Chat turns covered by one payment of the session agents
+    pub chat_turns_per_payment: i32,
+    /// Wallets allowed on the `/admin` endpoints, lowercase
+    pub admin_addresses: Vec<String>,
 }
 
 impl AppConfig {
@@ -14,16 +136,74 @@ impl AppConfig {
 
         let alchemy_http_url =
             std::env::var("ALCHEMY_RPC_URL").expect("ALCHEMY_RPC_URL must be set");
-        let alchemy_ws_url = alchemy_http_url.replace("https://", "wss://");
 
         Self {
             database_url: std::env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
             alchemy_rpc_url: alchemy_http_url,
-            alchemy_ws_url,
             port: std::env::var("PORT")
                 .unwrap_or_else(|_| "8080".to_string())
                 .parse()
                 .expect("PORT must be a valid u16"),
+            siwe_domain: std::env::var("SIWE_DOMAIN")
+                .unwrap_or_else(|_| "localhost:5173".to_string()),
+            siwe_uri: std::env::var("SIWE_URI")
+                .unwrap_or_else(|_| "http://localhost:5173".to_string()),
+            chain_id: std::env::var("CHAIN_ID")
+                .unwrap_or_else(|_| "5545".to_string())
+                .parse()
+                .expect("CHAIN_ID must be a valid u64"),
+            fetcher_start_block: std::env::var("FETCHER_START_BLOCK")
+                .expect(
+                    "FETCHER_START_BLOCK must be set to the deployment block of EnclavaPayments",
+                )
+                .parse()
+                .expect("FETCHER_START_BLOCK must be a valid u64"),
+            confirmation_depth: std::env::var("CONFIRMATION_DEPTH")
+                .unwrap_or_else(|_| "12".to_string())
+                .parse()
+                .expect("CONFIRMATION_DEPTH must be a valid u64"),
+            tee_backend: TeeBackend::from_string(
+                &std::env::var("TEE_BACKEND").unwrap_or_else(|_| "in_process".to_string()),
+            )
+            .expect("TEE_BACKEND must be in_process or remote"),
+            tee_remote_addr: std::env::var("TEE_REMOTE_ADDR")
+                .unwrap_or_else(|_| "127.0.0.1:7878".to_string()),
+            attestation_backend: AttestationBackend::from_string(
+                &std::env::var("ATTESTATION_BACKEND").unwrap_or_else(|_| "simulated".to_string()),
+            )
+            .expect("ATTESTATION_BACKEND must be simulated"),
+            attestation_signing_key: std::env::var("ATTESTATION_SIGNING_KEY")
+                .ok()
+                .filter(|key| !key.is_empty()),
+            dataset_master_key: alloy::hex::decode(
+                std::env::var("DATASET_MASTER_KEY").expect("DATASET_MASTER_KEY must be set"),
+            )
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .expect("DATASET_MASTER_KEY must be 32 bytes hex encoded"),
+            dataset_store: DatasetStoreBackend::from_string(
+                &std::env::var("DATASET_STORE").unwrap_or_else(|_| "fs".to_string()),
+            )
+            .expect("DATASET_STORE must be fs, s3 or pg"),
+            s3_bucket: std::env::var("S3_BUCKET").ok(),
+            s3_endpoint: std::env::var("S3_ENDPOINT").ok(),
+            s3_region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            s3_access_key_id: std::env::var("S3_ACCESS_KEY_ID").ok(),
+            s3_secret_access_key: std::env::var("S3_SECRET_ACCESS_KEY").ok(),
+            s3_prefix: std::env::var("S3_PREFIX").unwrap_or_else(|_| "datasets".to_string()),
+            guardrail: GuardrailPolicy::from_env(),
+            chat_turns_per_payment: std::env::var("CHAT_TURNS_PER_PAYMENT")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .ok()
+                .filter(|turns| *turns > 0)
+                .expect("CHAT_TURNS_PER_PAYMENT must be a positive integer"),
+            admin_addresses: std::env::var("ADMIN_ADDRESSES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|address| address.trim().to_lowercase())
+                .filter(|address| !address.is_empty())
+                .collect(),
         }
     }
 }
@@ -31,8 +211,43 @@ pub const UPLOAD_DIR: &str = "./uploads";
 pub const INIT_AGENT_MODEL: &str = "gemini-2.5-flash";
 pub const ROUTER_AGENT_MODEL: &str = "gemini-2.0-flash-lite";
 pub const DATASET_DETAILS_GEN_AGENT_MODEL: &str = "gemini-2.0-flash-lite";
+pub const SYNTHESIS_AGENT_MODEL: &str = "gemini-2.5-flash";
+pub const EMBEDDING_MODEL: &str = "text-embedding-004";
 pub const ENCLAVA_CONTRACT_ADDRESS: &str = "0x015C507e3E79D5049b003C3bE5b2E208A4Bb7e56";
 pub const MAX_ALLOWED_SELECTED_AGENTS: usize = 3;
+pub const SIWE_NONCE_TTL_MINUTES: i64 = 10;
+pub const SESSION_TTL_HOURS: i64 = 24;
+pub const MAX_LOGS_BLOCK_RANGE: u64 = 500;
+pub const FETCHER_POLL_INTERVAL_SECS: u64 = 5;
+pub const REORG_CHECK_BLOCKS: u64 = 256;
+pub const EARNINGS_DEFAULT_DAYS: i64 = 30;
+pub const EARNINGS_MAX_DAYS: i64 = 365;
+pub const EARNINGS_RPC_TIMEOUT_SECS: u64 = 5; // per contract read, the ledger is used past it
+pub const RAG_CHUNK_ROWS: usize = 50;
+pub const RAG_TOP_K: usize = 8;
+pub const ROUTER_TOP_K: i64 = 10;
+pub const ROUTER_MAX_TOP_K: i64 = 50;
+pub const DATASET_TABLE_NAME: &str = "dataset";
+pub const DATASET_QUERY_MAX_ROWS: usize = 100;
+pub const DATASET_QUERY_TIMEOUT_SECS: u64 = 10; // per query_dataset or dp_aggregate call
+pub const AGENT_MAX_TURNS: usize = 5;
+pub const TEE_REQUEST_TIMEOUT_SECS: u64 = 120;
+pub const TEE_INGEST_TIMEOUT_SECS: u64 = 1800; // redaction, profiling and embedding of a whole dataset
+pub const AGENT_ANSWER_TIMEOUT_SECS: u64 = 90; // per agent, the other answers are still returned
+pub const DATASET_ENCRYPTION_SEGMENT_SIZE: usize = 64 * 1024;
+pub const MAX_DATASET_FILE_SIZE: u64 = 256 * 1024 * 1024; // 256MB
+pub const DATASET_SAMPLE_ROWS: usize = 200;
+pub const PROFILE_SAMPLE_VALUES: usize = 5;
+pub const PROFILE_MAX_DISTINCT: usize = 10_000;
+pub const PII_CARD_MIN_VALID_RATIO: f64 = 0.9;
+pub const GUARDRAIL_NGRAM_SIZE: usize = 5;
+pub const GUARDRAIL_FINGERPRINT_SAMPLING: i64 = 2; // keep 1 shingle out of 2
+pub const GUARDRAIL_EVENTS_LIMIT: i64 = 200;
+pub const DP_MAX_EPSILON: f64 = 10.0; // per answer, above that the noise protects little
+pub const DP_MAX_AGGREGATES_PER_ANSWER: usize = 4;
+pub const AGENT_STREAM_BUFFER: usize = 64; // chunks waiting for a slow client
+pub const CHAT_HISTORY_MAX_TURNS: usize = 10; // earlier turns sent to the model
+pub const CREDIT_ROUNDING_TOLERANCE: f64 = 1e-9; // credits are sums of fractions of prices
 
 // Define a globally accessible static Config instance
 pub static APP_CONFIG: Lazy<AppConfig> = Lazy::new(AppConfig::load);
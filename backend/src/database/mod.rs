@@ -1,6 +1,18 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
 use color_eyre::Result;
+use pgvector::Vector;
+use sqlx::{postgres::types::Oid, types::Json};
 
-use crate::types::{AgentCategory, AgentDb, UserDb};
+use crate::types::{
+    AgentAnswerStatus, AgentCategory, AgentDb, AgentEarningsDb, AuthNonceDb, ChatMessageDb,
+    ChatRole, ChatSessionDb, CreditBalanceDb, CreditKind, DailyEarningsDb, DatasetChunkDb,
+    DatasetFormat, DatasetProfile, DpColumnBounds, GuardrailEventDb, GuardrailVerdict,
+    IngestedBlockDb, IngestedLogDb, NewAgent, NewCreditEntry, NewFailedAgentQuery,
+    NewGuardrailEvent, NewIngestedLog, NewLedgerEvent, PaymentDb, PiiReport, PiiType, SessionDb,
+    UsageEventDb, UserDb, VerifiedPayment,
+};
 
 pub async fn insert_user(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
@@ -22,34 +34,33 @@ pub async fn insert_user(
 
 pub async fn insert_new_agent(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    name: &str,
-    description: &str,
-    price: f64,
-    owner_id: i64,
-    dataset_path: &str,
-    category: &AgentCategory,
-    file_size: f64,
+    agent: &NewAgent,
 ) -> Result<AgentDb, sqlx::Error> {
     let record = sqlx::query_as::<_, AgentDb>(
         r#"
         WITH inserted AS (
-    INSERT INTO agents (name, description, price, owner_id, dataset_path, category, status, dataset_size)
-    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-    RETURNING id, name, description, price, owner_id, dataset_path, category, dataset_size, status, created_at, updated_at, nft_id, nft_tx
+    INSERT INTO agents (name, description, price, owner_id, dataset_path, category, status, dataset_size, format, dp_enabled, dp_epsilon, dp_budget, dp_bounds)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+    RETURNING id, name, description, price, owner_id, dataset_path, category, format, dp_enabled, dp_epsilon, dp_budget, dp_bounds, dataset_size, status, created_at, updated_at, nft_id, nft_tx
 )
 SELECT i.*, u.address AS owner_address
 FROM inserted i
 JOIN users u ON i.owner_id = u.id;
         "#,
     )
-    .bind(name)
-    .bind(description)
-    .bind(price)
-    .bind(owner_id)
-    .bind(dataset_path)
-    .bind(category.clone())
+    .bind(&agent.name)
+    .bind(&agent.description)
+    .bind(agent.price)
+    .bind(agent.owner_id)
+    .bind(&agent.dataset_path)
+    .bind(agent.category.clone())
     .bind("active")
-    .bind(file_size)
+    .bind(agent.dataset_size)
+    .bind(agent.format)
+    .bind(agent.dp.is_some())
+    .bind(agent.dp.as_ref().map(|dp| dp.epsilon))
+    .bind(agent.dp.as_ref().map(|dp| dp.budget))
+    .bind(agent.dp.as_ref().map(|dp| Json(&dp.bounds)))
     .fetch_one(&mut **tx)
     .await?;
 
@@ -91,6 +102,11 @@ pub async fn get_agent_by_id_optional(
         g.dataset_path,
         g.status,
         g.category as "category: AgentCategory",
+        g.format as "format: DatasetFormat",
+        g.dp_enabled,
+        g.dp_epsilon,
+        g.dp_budget,
+        g.dp_bounds as "dp_bounds: Json<HashMap<String, DpColumnBounds>>",
         g.dataset_size,
         g.created_at,
         g.updated_at, 
@@ -151,6 +167,11 @@ pub async fn get_agents_by_ids(
         g.dataset_path,
         g.status,
         g.category as "category: AgentCategory",
+        g.format as "format: DatasetFormat",
+        g.dp_enabled,
+        g.dp_epsilon,
+        g.dp_budget,
+        g.dp_bounds as "dp_bounds: Json<HashMap<String, DpColumnBounds>>",
         g.dataset_size,
         g.created_at,
         g.updated_at, 
@@ -186,6 +207,11 @@ pub async fn get_agents_by_user_address(
         g.dataset_path,
         g.status,
         g.category as "category: AgentCategory",
+        g.format as "format: DatasetFormat",
+        g.dp_enabled,
+        g.dp_epsilon,
+        g.dp_budget,
+        g.dp_bounds as "dp_bounds: Json<HashMap<String, DpColumnBounds>>",
         g.dataset_size,
         g.created_at,
         g.updated_at, 
@@ -220,6 +246,11 @@ pub async fn get_agent_by_id(
         g.dataset_path,
         g.status,
         g.category as "category: AgentCategory",
+        g.format as "format: DatasetFormat",
+        g.dp_enabled,
+        g.dp_epsilon,
+        g.dp_budget,
+        g.dp_bounds as "dp_bounds: Json<HashMap<String, DpColumnBounds>>",
         g.dataset_size,
         g.created_at,
         g.updated_at, 
@@ -237,3 +268,1346 @@ pub async fn get_agent_by_id(
 
     Ok(agent)
 }
+
+/// Consume a verified payment. Returns `None` when the tx hash was already consumed.
+pub async fn insert_payment(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    payment: &VerifiedPayment,
+) -> Result<Option<PaymentDb>, sqlx::Error> {
+    let record = sqlx::query_as!(
+        PaymentDb,
+        r#"
+        INSERT INTO payments (tx_hash, payer_address, agent_ids, amounts, block_number)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (tx_hash) DO NOTHING
+        RETURNING id, tx_hash, payer_address, agent_ids, amounts, block_number, consumed_at
+        "#,
+        payment.tx_hash,
+        payment.payer_address,
+        &payment.agent_ids,
+        &payment.amounts,
+        payment.block_number
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(record)
+}
+
+pub async fn get_payment_by_tx_hash(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    tx_hash: &str,
+) -> Result<Option<PaymentDb>, sqlx::Error> {
+    let payment = sqlx::query_as!(
+        PaymentDb,
+        r#"
+        SELECT id, tx_hash, payer_address, agent_ids, amounts, block_number, consumed_at
+        FROM payments
+        WHERE tx_hash = $1
+        "#,
+        tx_hash
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(payment)
+}
+
+pub async fn is_payment_consumed(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    tx_hash: &str,
+) -> Result<bool, sqlx::Error> {
+    let consumed = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM payments WHERE tx_hash = $1) as "exists!"
+        "#,
+        tx_hash
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(consumed)
+}
+
+// Get Payments by payer address
+pub async fn get_payments_by_payer_address(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    payer_address: &str,
+) -> Result<Vec<PaymentDb>, sqlx::Error> {
+    let payments = sqlx::query_as!(
+        PaymentDb,
+        r#"
+        SELECT id, tx_hash, payer_address, agent_ids, amounts, block_number, consumed_at
+        FROM payments
+        WHERE payer_address = $1
+        ORDER BY consumed_at DESC
+        "#,
+        payer_address
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(payments)
+}
+
+pub async fn insert_auth_nonce(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    nonce: &str,
+    address: &str,
+    message: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO auth_nonces (nonce, address, message, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        nonce,
+        address,
+        message,
+        expires_at
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Mark a nonce as used. Returns `None` when it does not exist, is expired or was already used.
+pub async fn consume_auth_nonce(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    nonce: &str,
+) -> Result<Option<AuthNonceDb>, sqlx::Error> {
+    let auth_nonce = sqlx::query_as!(
+        AuthNonceDb,
+        r#"
+        UPDATE auth_nonces
+        SET used_at = NOW()
+        WHERE nonce = $1 AND used_at IS NULL AND expires_at > NOW()
+        RETURNING address, message
+        "#,
+        nonce
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(auth_nonce)
+}
+
+pub async fn insert_session(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    token_hash: &str,
+    address: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions (token_hash, address, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        token_hash,
+        address,
+        expires_at
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_active_session_by_token_hash(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    token_hash: &str,
+) -> Result<Option<SessionDb>, sqlx::Error> {
+    let session = sqlx::query_as!(
+        SessionDb,
+        r#"
+        SELECT address
+        FROM sessions
+        WHERE token_hash = $1 AND expires_at > NOW()
+        "#,
+        token_hash
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(session)
+}
+
+pub async fn get_chain_cursor(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    contract_address: &str,
+    event_name: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let last_block = sqlx::query_scalar!(
+        r#"
+        SELECT last_block
+        FROM chain_cursors
+        WHERE contract_address = $1 AND event_name = $2
+        "#,
+        contract_address,
+        event_name
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(last_block)
+}
+
+pub async fn upsert_chain_cursor(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    contract_address: &str,
+    event_name: &str,
+    last_block: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO chain_cursors (contract_address, event_name, last_block)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (contract_address, event_name) DO UPDATE SET last_block = EXCLUDED.last_block
+        "#,
+        contract_address,
+        event_name,
+        last_block
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Record an applied contract log. Returns `None` when the log was already ingested.
+pub async fn insert_ingested_log(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    log: &NewIngestedLog,
+    agent_id: Option<i64>,
+    nft_id: Option<i64>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO ingested_logs (contract_address, event_name, block_number, block_hash, tx_hash, log_index, agent_id, nft_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (tx_hash, log_index) DO NOTHING
+        RETURNING id
+        "#,
+        log.contract_address,
+        log.event_name,
+        log.block_number,
+        log.block_hash,
+        log.tx_hash,
+        log.log_index,
+        agent_id,
+        nft_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(id)
+}
+
+// Get the distinct blocks logs of a fetcher were ingested from, starting at from_block
+pub async fn get_ingested_blocks(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    contract_address: &str,
+    event_name: &str,
+    from_block: i64,
+) -> Result<Vec<IngestedBlockDb>, sqlx::Error> {
+    let blocks = sqlx::query_as!(
+        IngestedBlockDb,
+        r#"
+        SELECT DISTINCT block_number, block_hash
+        FROM ingested_logs
+        WHERE contract_address = $1 AND event_name = $2 AND block_number >= $3
+        ORDER BY block_number
+        "#,
+        contract_address,
+        event_name,
+        from_block
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(blocks)
+}
+
+/// Delete the ingested logs of a fetcher from `from_block` onwards and return them.
+pub async fn delete_ingested_logs_from_block(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    contract_address: &str,
+    event_name: &str,
+    from_block: i64,
+) -> Result<Vec<IngestedLogDb>, sqlx::Error> {
+    let logs = sqlx::query_as!(
+        IngestedLogDb,
+        r#"
+        DELETE FROM ingested_logs
+        WHERE contract_address = $1 AND event_name = $2 AND block_number >= $3
+        RETURNING event_name, block_number, agent_id, nft_id
+        "#,
+        contract_address,
+        event_name,
+        from_block
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(logs)
+}
+
+/// Undo `update_agent_with_nft_details`, only if the agent is still linked to `nft_id`.
+pub async fn clear_agent_nft_details(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    nft_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let update_result = sqlx::query!(
+        r#"
+        UPDATE agents
+        SET nft_id = NULL, nft_tx = NULL
+        WHERE id = $1 AND nft_id = $2
+        "#,
+        agent_id,
+        nft_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(update_result.rows_affected() == 1)
+}
+
+pub async fn insert_usage_event(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ingested_log_id: i64,
+    ingested_log: &NewIngestedLog,
+    usage: &NewLedgerEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO usage_events (ingested_log_id, nft_id, agent_id, user_address, amount, amount_wei, tx_hash, block_number, block_timestamp)
+        VALUES ($1, $2, (SELECT id FROM agents WHERE nft_id = $2), $3, $4, $5, $6, $7, $8)
+        "#,
+        ingested_log_id,
+        usage.nft_id,
+        usage.address,
+        usage.amount,
+        usage.amount_wei,
+        ingested_log.tx_hash,
+        ingested_log.block_number,
+        usage.block_timestamp
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_claim_event(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ingested_log_id: i64,
+    ingested_log: &NewIngestedLog,
+    claim: &NewLedgerEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO claim_events (ingested_log_id, nft_id, agent_id, owner_address, amount, amount_wei, tx_hash, block_number, block_timestamp)
+        VALUES ($1, $2, (SELECT id FROM agents WHERE nft_id = $2), $3, $4, $5, $6, $7, $8)
+        "#,
+        ingested_log_id,
+        claim.nft_id,
+        claim.address,
+        claim.amount,
+        claim.amount_wei,
+        ingested_log.tx_hash,
+        ingested_log.block_number,
+        claim.block_timestamp
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Get the DatasetUsed events emitted by a transaction
+pub async fn get_usage_events_by_tx_hash(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    tx_hash: &str,
+) -> Result<Vec<UsageEventDb>, sqlx::Error> {
+    let usage_events = sqlx::query_as!(
+        UsageEventDb,
+        r#"
+        SELECT id, nft_id, agent_id, user_address, amount, amount_wei, tx_hash, block_number, block_timestamp
+        FROM usage_events
+        WHERE tx_hash = $1
+        ORDER BY id
+        "#,
+        tx_hash
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(usage_events)
+}
+
+// Get the indexed earnings of every agent owned by an address
+pub async fn get_agent_earnings_by_owner(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    owner_address: &str,
+) -> Result<Vec<AgentEarningsDb>, sqlx::Error> {
+    let agent_earnings = sqlx::query_as!(
+        AgentEarningsDb,
+        r#"
+        SELECT
+            g.id AS agent_id,
+            g.name AS agent_name,
+            g.nft_id,
+            g.price,
+            COALESCE(ue.total_earned, 0) AS "total_earned!",
+            COALESCE(ce.total_claimed, 0) AS "total_claimed!",
+            COALESCE(ue.query_count, 0) AS "query_count!",
+            ue.last_used_at
+        FROM agents g
+        JOIN users u ON g.owner_id = u.id
+        LEFT JOIN (
+            SELECT
+                agent_id,
+                SUM(amount) AS total_earned,
+                COUNT(*) AS query_count,
+                MAX(COALESCE(block_timestamp, created_at)) AS last_used_at
+            FROM usage_events
+            GROUP BY agent_id
+        ) ue ON ue.agent_id = g.id
+        LEFT JOIN (
+            SELECT agent_id, SUM(amount) AS total_claimed
+            FROM claim_events
+            GROUP BY agent_id
+        ) ce ON ce.agent_id = g.id
+        WHERE u.address = $1
+        ORDER BY g.id
+        "#,
+        owner_address
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(agent_earnings)
+}
+
+// Get the daily earnings of the agents owned by an address since a date
+pub async fn get_daily_earnings_by_owner(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    owner_address: &str,
+    since: DateTime<Utc>,
+) -> Result<Vec<DailyEarningsDb>, sqlx::Error> {
+    let daily_earnings = sqlx::query_as!(
+        DailyEarningsDb,
+        r#"
+        SELECT
+            (COALESCE(e.block_timestamp, e.created_at) AT TIME ZONE 'UTC')::date AS "day!",
+            SUM(e.amount) AS "earned!",
+            COUNT(*) AS "query_count!"
+        FROM usage_events e
+        JOIN agents g ON e.agent_id = g.id
+        JOIN users u ON g.owner_id = u.id
+        WHERE u.address = $1
+          AND COALESCE(e.block_timestamp, e.created_at) >= $2
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        owner_address,
+        since
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(daily_earnings)
+}
+
+// Get the embedded chunks of an agent dataset for an embedding model
+pub async fn get_dataset_chunks(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    embedding_model: &str,
+) -> Result<Vec<DatasetChunkDb>, sqlx::Error> {
+    let chunks = sqlx::query_as!(
+        DatasetChunkDb,
+        r#"
+        SELECT chunk_index, first_row, last_row, embedding
+        FROM dataset_chunks
+        WHERE agent_id = $1 AND embedding_model = $2
+        ORDER BY chunk_index
+        "#,
+        agent_id,
+        embedding_model
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(chunks)
+}
+
+// Replace the embedded chunks of an agent dataset, and mark it as ingested with `embedding_model`
+pub async fn replace_dataset_chunks(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    chunks: &[DatasetChunkDb],
+    embedding_model: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM dataset_chunks
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    for chunk in chunks {
+        sqlx::query!(
+            r#"
+            INSERT INTO dataset_chunks (agent_id, chunk_index, first_row, last_row, embedding, embedding_model)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            agent_id,
+            chunk.chunk_index,
+            chunk.first_row,
+            chunk.last_row,
+            &chunk.embedding,
+            embedding_model
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE agents
+        SET chunks_embedding_model = $1
+        WHERE id = $2
+        "#,
+        embedding_model,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Embedding model the chunks of each ingested agent dataset were made with, by agent id
+pub async fn get_agent_chunks_embedding_models(
+    db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<HashMap<i64, String>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT id, chunks_embedding_model as "chunks_embedding_model!"
+        FROM agents
+        WHERE chunks_embedding_model IS NOT NULL
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| (record.id, record.chunks_embedding_model))
+        .collect())
+}
+
+pub async fn update_agent_profile(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    profile: &DatasetProfile,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE agents
+        SET profile = $1
+        WHERE id = $2
+        "#,
+        Json(profile) as _,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_agent_profile(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+) -> Result<Option<DatasetProfile>, sqlx::Error> {
+    let profile = sqlx::query_scalar!(
+        r#"
+        SELECT profile as "profile: Json<DatasetProfile>"
+        FROM agents
+        WHERE id = $1
+        "#,
+        agent_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(profile.flatten().map(|profile| profile.0))
+}
+
+// Profiles of every agent that has one, by agent id
+pub async fn get_agent_profiles(
+    db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<HashMap<i64, DatasetProfile>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT id, profile as "profile!: Json<DatasetProfile>"
+        FROM agents
+        WHERE profile IS NOT NULL
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| (record.id, record.profile.0))
+        .collect())
+}
+
+// Profiles of some agents that have one, by agent id
+pub async fn get_agent_profiles_by_ids(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_ids: &[i64],
+) -> Result<HashMap<i64, DatasetProfile>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT id, profile as "profile!: Json<DatasetProfile>"
+        FROM agents
+        WHERE id = ANY($1) AND profile IS NOT NULL
+        "#,
+        agent_ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| (record.id, record.profile.0))
+        .collect())
+}
+
+// The vector column needs pgvector, these queries are checked at runtime
+pub async fn update_agent_description_embedding(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    embedding: &[f64],
+    embedding_model: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE agents
+        SET description_embedding = $1, description_embedding_model = $2
+        WHERE id = $3
+        "#,
+    )
+    .bind(to_vector(embedding))
+    .bind(embedding_model)
+    .bind(agent_id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+fn to_vector(embedding: &[f64]) -> Vector {
+    Vector::from(embedding.iter().map(|&v| v as f32).collect::<Vec<f32>>())
+}
+
+// Ids of the agents whose description was not embedded with an embedding model
+pub async fn get_agents_without_description_embedding(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    embedding_model: &str,
+) -> Result<Vec<i64>, sqlx::Error> {
+    let agent_ids = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM agents
+        WHERE description_embedding IS NULL
+           OR description_embedding_model IS DISTINCT FROM $1
+        "#,
+        embedding_model
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(agent_ids)
+}
+
+// Active agents closest to an embedding, most similar first.
+// Embeddings are normalized, their inner product is the cosine similarity. `<#>` is the negative
+// inner product, ordering by it ascending uses the HNSW index of the column.
+pub async fn search_agents_by_embedding(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    embedding: &[f64],
+    embedding_model: &str,
+    category: Option<&str>,
+    limit: i64,
+) -> Result<Vec<AgentDb>, sqlx::Error> {
+    let agents = sqlx::query_as::<_, AgentDb>(
+        r#"
+        SELECT
+        g.id,
+        g.name,
+        g.description,
+        g.price,
+        g.owner_id,
+        g.dataset_path,
+        g.status,
+        g.category,
+        g.format,
+        g.dp_enabled,
+        g.dp_epsilon,
+        g.dp_budget,
+        g.dp_bounds,
+        g.dataset_size,
+        g.created_at,
+        g.updated_at,
+        g.nft_id,
+        g.nft_tx,
+        u.address as owner_address
+    FROM agents g
+    JOIN users u ON g.owner_id = u.id
+    WHERE g.status = 'active'
+      AND g.description_embedding_model = $2
+      AND ($3::text IS NULL OR g.category::text = $3)
+    ORDER BY g.description_embedding <#> $1
+    LIMIT $4
+        "#,
+    )
+    .bind(to_vector(embedding))
+    .bind(embedding_model)
+    .bind(category)
+    .bind(limit)
+    .fetch_all(db)
+    .await?;
+
+    Ok(agents)
+}
+
+pub async fn update_agent_pii_report(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    pii_report: &PiiReport,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE agents
+        SET pii_report = $1
+        WHERE id = $2
+        "#,
+        Json(pii_report) as _,
+        agent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_agent_pii_report(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+) -> Result<Option<PiiReport>, sqlx::Error> {
+    let pii_report = sqlx::query_scalar!(
+        r#"
+        SELECT pii_report as "pii_report: Json<PiiReport>"
+        FROM agents
+        WHERE id = $1
+        "#,
+        agent_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(pii_report.flatten().map(|pii_report| pii_report.0))
+}
+
+// Get the n-gram fingerprints of an agent dataset for an n-gram size
+pub async fn get_dataset_fingerprints(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    ngram_size: i32,
+) -> Result<Option<Vec<i64>>, sqlx::Error> {
+    let shingles = sqlx::query_scalar!(
+        r#"
+        SELECT shingles
+        FROM dataset_fingerprints
+        WHERE agent_id = $1 AND ngram_size = $2
+        "#,
+        agent_id,
+        ngram_size
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(shingles)
+}
+
+pub async fn upsert_dataset_fingerprints(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    shingles: &[i64],
+    ngram_size: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO dataset_fingerprints (agent_id, ngram_size, shingles)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (agent_id)
+        DO UPDATE SET ngram_size = EXCLUDED.ngram_size, shingles = EXCLUDED.shingles, created_at = NOW()
+        "#,
+        agent_id,
+        ngram_size,
+        shingles
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_guardrail_event(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    event: &NewGuardrailEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO guardrail_events (agent_id, tx_hash, buyer_address, prompt, verdict, overlap_ratio, pii_types)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        event.agent_id,
+        event.tx_hash,
+        event.buyer_address,
+        event.prompt,
+        event.verdict as GuardrailVerdict,
+        event.overlap_ratio,
+        Json(&event.pii_types) as _
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Get the latest guardrail verdicts on the answers of an agent
+pub async fn get_guardrail_events_by_agent(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    agent_id: i64,
+    limit: i64,
+) -> Result<Vec<GuardrailEventDb>, sqlx::Error> {
+    let events = sqlx::query_as!(
+        GuardrailEventDb,
+        r#"
+        SELECT
+            id,
+            agent_id,
+            tx_hash,
+            buyer_address,
+            prompt,
+            verdict as "verdict: GuardrailVerdict",
+            overlap_ratio,
+            pii_types as "pii_types: Json<Vec<PiiType>>",
+            created_at
+        FROM guardrail_events
+        WHERE agent_id = $1
+        ORDER BY created_at DESC, id DESC
+        LIMIT $2
+        "#,
+        agent_id,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(events)
+}
+
+// Spend `epsilon` of the privacy budget of a buyer on an agent.
+// Returns the epsilon spent so far, or None when the budget does not allow it.
+pub async fn spend_dp_budget(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    buyer_address: &str,
+    epsilon: f64,
+    budget: f64,
+) -> Result<Option<f64>, sqlx::Error> {
+    let epsilon_spent = sqlx::query_scalar!(
+        r#"
+        INSERT INTO dp_budgets (agent_id, buyer_address, epsilon_spent)
+        SELECT $1::BIGINT, $2::VARCHAR, $3::FLOAT8
+        WHERE $3::FLOAT8 <= $4::FLOAT8
+        ON CONFLICT (agent_id, buyer_address)
+        DO UPDATE SET epsilon_spent = dp_budgets.epsilon_spent + EXCLUDED.epsilon_spent, updated_at = NOW()
+        WHERE dp_budgets.epsilon_spent + EXCLUDED.epsilon_spent <= $4
+        RETURNING epsilon_spent
+        "#,
+        agent_id,
+        buyer_address,
+        epsilon,
+        budget
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(epsilon_spent)
+}
+
+// Give back `epsilon` spent by a buyer on an agent for an answer it never received.
+pub async fn refund_dp_budget(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    agent_id: i64,
+    buyer_address: &str,
+    epsilon: f64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE dp_budgets
+        SET epsilon_spent = GREATEST(epsilon_spent - $3, 0), updated_at = NOW()
+        WHERE agent_id = $1 AND buyer_address = $2
+        "#,
+        agent_id,
+        buyer_address,
+        epsilon
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_chat_session(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    buyer_address: &str,
+    agent_ids: &[i64],
+    tx_hash: &str,
+    turns_paid: i32,
+) -> Result<ChatSessionDb, sqlx::Error> {
+    let session = sqlx::query_as!(
+        ChatSessionDb,
+        r#"
+        INSERT INTO chat_sessions (buyer_address, agent_ids, tx_hash, turns_paid)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, buyer_address, agent_ids, tx_hash, turns_paid, turns_used, created_at, updated_at
+        "#,
+        buyer_address,
+        agent_ids,
+        tx_hash,
+        turns_paid
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(session)
+}
+
+pub async fn get_chat_session(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    session_id: i64,
+) -> Result<ChatSessionDb, sqlx::Error> {
+    let session = sqlx::query_as!(
+        ChatSessionDb,
+        r#"
+        SELECT id, buyer_address, agent_ids, tx_hash, turns_paid, turns_used, created_at, updated_at
+        FROM chat_sessions
+        WHERE id = $1
+        "#,
+        session_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(session)
+}
+
+// Add the turns covered by a new payment to a session, its answers are attested for that payment from now on
+pub async fn add_chat_session_turns(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    session_id: i64,
+    tx_hash: &str,
+    turns: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE chat_sessions
+        SET turns_paid = turns_paid + $3, tx_hash = $2, updated_at = NOW()
+        WHERE id = $1
+        "#,
+        session_id,
+        tx_hash,
+        turns
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Use one paid turn of a session.
+// Returns the updated session, or None when all its paid turns are used.
+pub async fn use_chat_session_turn(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    session_id: i64,
+) -> Result<Option<ChatSessionDb>, sqlx::Error> {
+    let session = sqlx::query_as!(
+        ChatSessionDb,
+        r#"
+        UPDATE chat_sessions
+        SET turns_used = turns_used + 1, updated_at = NOW()
+        WHERE id = $1 AND turns_used < turns_paid
+        RETURNING id, buyer_address, agent_ids, tx_hash, turns_paid, turns_used, created_at, updated_at
+        "#,
+        session_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(session)
+}
+
+pub async fn insert_chat_message(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    session_id: i64,
+    turn: i32,
+    agent_id: Option<i64>,
+    role: ChatRole,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO chat_messages (session_id, turn, agent_id, role, content)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        session_id,
+        turn,
+        agent_id,
+        role as ChatRole,
+        content
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_chat_messages(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    session_id: i64,
+) -> Result<Vec<ChatMessageDb>, sqlx::Error> {
+    let messages = sqlx::query_as!(
+        ChatMessageDb,
+        r#"
+        SELECT id, session_id, turn, agent_id, role as "role: ChatRole", content, created_at
+        FROM chat_messages
+        WHERE session_id = $1
+        ORDER BY turn, id
+        "#,
+        session_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(messages)
+}
+
+pub async fn insert_failed_agent_query(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    query: &NewFailedAgentQuery,
+) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO failed_agent_queries (agent_id, session_id, turn, buyer_address, tx_hash, prompt, status, error_code, error)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id
+        "#,
+        query.agent_id,
+        query.session_id,
+        query.turn,
+        query.buyer_address,
+        query.tx_hash,
+        query.prompt,
+        query.status as AgentAnswerStatus,
+        query.error_code,
+        query.error
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(row.id)
+}
+
+// Serialize the credit updates of a buyer until the end of the transaction,
+// so a balance read in it stays valid
+pub async fn lock_buyer_credits(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    buyer_address: &str,
+) -> Result<(), sqlx::Error> {
+    // Not a macro, sqlx can not describe the void result of the lock function
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(buyer_address)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_credit_balance(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    buyer_address: &str,
+) -> Result<f64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COALESCE(SUM(amount), 0) as "balance!"
+        FROM credit_entries
+        WHERE buyer_address = $1
+        "#,
+        buyer_address
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(row.balance)
+}
+
+pub async fn insert_credit_entry(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    entry: &NewCreditEntry,
+) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO credit_entries (buyer_address, amount, kind, failed_query_id, note)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        entry.buyer_address,
+        entry.amount,
+        entry.kind as CreditKind,
+        entry.failed_query_id,
+        entry.note
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(row.id)
+}
+
+/// Record the agents paid by the credit entry `entry_id`, the price paid for each and the hash
+/// standing for the payment.
+pub async fn set_credit_entry_payment(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    entry_id: i64,
+    payment_hash: &str,
+    agent_ids: &[i64],
+    amounts: &[f64],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE credit_entries
+        SET payment_hash = $2, agent_ids = $3, agent_amounts = $4
+        WHERE id = $1
+        "#,
+        entry_id,
+        payment_hash,
+        agent_ids,
+        amounts
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Amount paid for an agent by a payment, on chain or with credits
+pub async fn get_paid_amount(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    payment_hash: &str,
+    agent_id: i64,
+) -> Result<Option<f64>, sqlx::Error> {
+    let amount = sqlx::query_scalar!(
+        r#"
+        SELECT paid.amount as "amount!"
+        FROM (
+            SELECT amounts[array_position(agent_ids, $2)] AS amount
+            FROM payments
+            WHERE tx_hash = $1
+            UNION ALL
+            SELECT agent_amounts[array_position(agent_ids, $2)] AS amount
+            FROM credit_entries
+            WHERE payment_hash = $1
+        ) paid
+        WHERE paid.amount IS NOT NULL
+        LIMIT 1
+        "#,
+        payment_hash,
+        agent_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(amount)
+}
+
+// Credit balance of every buyer with ledger entries, largest first
+pub async fn get_credit_balances(
+    db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Vec<CreditBalanceDb>, sqlx::Error> {
+    let balances = sqlx::query_as!(
+        CreditBalanceDb,
+        r#"
+        SELECT
+            buyer_address,
+            COALESCE(SUM(amount), 0) as "balance!",
+            COALESCE(SUM(amount) FILTER (WHERE kind = 'failed_answer'), 0) as "credited!",
+            COALESCE(-SUM(amount) FILTER (WHERE kind = 'spent'), 0) as "spent!",
+            COALESCE(-SUM(amount) FILTER (WHERE kind = 'settled'), 0) as "settled!",
+            MAX(created_at) as "last_entry_at!"
+        FROM credit_entries
+        GROUP BY buyer_address
+        ORDER BY 2 DESC
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(balances)
+}
+
+/// Create an empty large object, filled by `write_large_object`.
+pub async fn create_large_object(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<Oid, sqlx::Error> {
+    let oid = sqlx::query_scalar!(r#"SELECT lo_create(0) AS "oid!""#)
+        .fetch_one(&mut **tx)
+        .await?;
+
+    Ok(oid)
+}
+
+pub async fn write_large_object(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    oid: Oid,
+    offset: i64,
+    data: &[u8],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("SELECT lo_put($1, $2, $3)", oid, offset, data)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Read at most `length` bytes of a large object from `offset`, fewer once its end is reached.
+pub async fn read_large_object(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    oid: Oid,
+    offset: i64,
+    length: i32,
+) -> Result<Vec<u8>, sqlx::Error> {
+    let data = sqlx::query_scalar!(
+        r#"SELECT lo_get($1, $2, $3) AS "data!""#,
+        oid,
+        offset,
+        length
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(data)
+}
+
+/// Large object of the dataset stored under `key`, locked until the end of the transaction.
+/// Keys without a dataset are locked too, concurrent first writes of a key wait for each other.
+pub async fn lock_dataset_object(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    key: &str,
+) -> Result<Option<Oid>, sqlx::Error> {
+    sqlx::query!("SELECT pg_advisory_xact_lock(hashtext($1))", key)
+        .execute(&mut **tx)
+        .await?;
+
+    let oid = sqlx::query_scalar!("SELECT oid FROM dataset_objects WHERE key = $1", key)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    Ok(oid)
+}
+
+pub async fn get_dataset_object(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    key: &str,
+) -> Result<Option<Oid>, sqlx::Error> {
+    let oid = sqlx::query_scalar!("SELECT oid FROM dataset_objects WHERE key = $1", key)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    Ok(oid)
+}
+
+pub async fn upsert_dataset_object(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    key: &str,
+    oid: Oid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO dataset_objects (key, oid)
+        VALUES ($1, $2)
+        ON CONFLICT (key) DO UPDATE SET oid = EXCLUDED.oid, updated_at = NOW()
+        "#,
+        key,
+        oid
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Remove the dataset stored under `key` and unlink its large object, false if there was none.
+pub async fn delete_dataset_object(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    key: &str,
+) -> Result<bool, sqlx::Error> {
+    let oid = sqlx::query_scalar!(
+        "DELETE FROM dataset_objects WHERE key = $1 RETURNING oid",
+        key
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    match oid {
+        Some(oid) => {
+            unlink_large_object(tx, oid).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+pub async fn unlink_large_object(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    oid: Oid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query_scalar!("SELECT lo_unlink($1)", oid)
+        .fetch_one(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_dataset_object_keys(
+    db: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let keys = sqlx::query_scalar!("SELECT key FROM dataset_objects ORDER BY key")
+        .fetch_all(db)
+        .await?;
+
+    Ok(keys)
+}
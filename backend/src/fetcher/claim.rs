@@ -0,0 +1,47 @@
+use alloy::{primitives::utils::format_units, rpc::types::Log, sol_types::SolEvent};
+use chrono::DateTime;
+use color_eyre::{Result, eyre::Context};
+
+use crate::{
+    fetcher::{ContractEvent, ingested_log_from, mint::AmountClaimed},
+    helpers::ledger::handle_amount_claimed,
+    types::{NewLedgerEvent, WebAppState},
+};
+
+/// Decode an `AmountClaimed` log and store it in the local ledger.
+pub async fn handle_claim_log(app_state: &WebAppState, log: &Log) -> Result<()> {
+    tracing::info!("New AmountClaimed event: {:?}", log);
+
+    let event = match AmountClaimed::decode_log_data(log.data()) {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::warn!("Skipping undecodable AmountClaimed log: {}", e);
+            return Ok(());
+        }
+    };
+
+    tracing::trace!(
+        "Amount Claimed! tokenId: {:?}, owner: {:?}, amount: {}",
+        event.tokenId,
+        event.owner,
+        event.amount
+    );
+
+    let ingested_log = ingested_log_from(log, ContractEvent::AmountClaimed)?;
+
+    let claim = NewLedgerEvent {
+        nft_id: event.tokenId.to_string().parse()?,
+        address: event.owner.to_string(),
+        amount: format_units(event.amount, 18)?.parse()?,
+        amount_wei: event.amount.to_string(),
+        block_timestamp: log
+            .block_timestamp
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp as i64, 0)),
+    };
+
+    handle_amount_claimed(app_state, &claim, &ingested_log)
+        .await
+        .with_context(|| format!("Failed to handle amount claim {:?}", claim))?;
+
+    Ok(())
+}
@@ -1,16 +1,259 @@
+pub mod claim;
 pub mod mint;
+pub mod reorg;
+pub mod usage;
 
-use color_eyre::eyre::Result;
+use std::str::FromStr;
 
-use crate::{fetcher::mint::mint_nft_fetcher, types::WebAppState};
+use alloy::{
+    primitives::Address,
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{Filter, Log},
+};
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    config::{
+        APP_CONFIG, ENCLAVA_CONTRACT_ADDRESS, FETCHER_POLL_INTERVAL_SECS, MAX_LOGS_BLOCK_RANGE,
+    },
+    database,
+    fetcher::reorg::rollback_reorged_logs,
+    types::{NewIngestedLog, WebAppState},
+};
+
+/// Enclava contract events ingested by the fetchers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContractEvent {
+    DatasetNFTMinted,
+    DatasetUsed,
+    AmountClaimed,
+}
+
+impl ContractEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContractEvent::DatasetNFTMinted => "DatasetNFTMinted",
+            ContractEvent::DatasetUsed => "DatasetUsed",
+            ContractEvent::AmountClaimed => "AmountClaimed",
+        }
+    }
+
+    pub fn signature(&self) -> &'static str {
+        match self {
+            ContractEvent::DatasetNFTMinted => "DatasetNFTMinted(address,uint256,string)",
+            ContractEvent::DatasetUsed => "DatasetUsed(uint256,address,uint256)",
+            ContractEvent::AmountClaimed => "AmountClaimed(uint256,address,uint256)",
+        }
+    }
+
+    async fn handle_log(&self, app_state: &WebAppState, log: &Log) -> Result<()> {
+        match self {
+            ContractEvent::DatasetNFTMinted => mint::handle_mint_log(app_state, log).await,
+            ContractEvent::DatasetUsed => usage::handle_usage_log(app_state, log).await,
+            ContractEvent::AmountClaimed => claim::handle_claim_log(app_state, log).await,
+        }
+    }
+}
 
 pub async fn open_all_logs_fetcher(app_state: &WebAppState) -> Result<()> {
-    let app_state = app_state.clone();
-    tokio::spawn(async move {
-        if let Err(e) = mint_nft_fetcher(&app_state).await {
-            tracing::error!("Failed to start mint nft fetcher: {}", e);
-        };
-    });
+    for event in [
+        ContractEvent::DatasetNFTMinted,
+        ContractEvent::DatasetUsed,
+        ContractEvent::AmountClaimed,
+    ] {
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = contract_event_fetcher(&app_state, event).await {
+                tracing::error!("Failed to start {} fetcher: {}", event.name(), e);
+            };
+        });
+    }
+
+    Ok(())
+}
+
+/// Poll the enclava contract for `event` logs, forever.
+pub async fn contract_event_fetcher(app_state: &WebAppState, event: ContractEvent) -> Result<()> {
+    tracing::info!("Starting {} fetcher...", event.name());
+
+    // Create the provider.
+    let rpc_url = &APP_CONFIG.alchemy_rpc_url;
+
+    let provider = ProviderBuilder::new().connect_http(rpc_url.parse()?);
+
+    let contract_address = Address::from_str(ENCLAVA_CONTRACT_ADDRESS)?;
+
+    // Resume from the last processed block
+    let mut next_block =
+        load_next_block(app_state, &contract_address.to_string(), event.name()).await?;
+
+    tracing::debug!("{} Next Block: {}", event.name(), next_block);
+
+    loop {
+        // Rewind past any ingested block that is no longer on the canonical chain
+        match rollback_reorged_logs(
+            app_state,
+            &provider,
+            &contract_address.to_string(),
+            event.name(),
+        )
+        .await
+        {
+            Ok(Some(reorged_block)) => next_block = next_block.min(reorged_block),
+            Ok(None) => {}
+            Err(e) => tracing::error!("Failed to check {} logs for reorgs: {}", event.name(), e),
+        }
+
+        // Page through the confirmed blocks
+        loop {
+            match fetch_event_logs_page(app_state, &provider, contract_address, event, next_block)
+                .await
+            {
+                Ok(Some((to_block, head_block))) => {
+                    next_block = to_block + 1;
+
+                    // Keep paging without waiting until we reach the confirmed head
+                    if to_block < head_block {
+                        continue;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    // The cursor was not advanced, the same page will be retried
+                    tracing::error!(
+                        "Failed to fetch {} logs from block {}: {}",
+                        event.name(),
+                        next_block,
+                        e
+                    );
+                }
+            }
+
+            break;
+        }
+
+        // Sleep for a while before polling again
+        tokio::time::sleep(tokio::time::Duration::from_secs(FETCHER_POLL_INTERVAL_SECS)).await;
+    }
+}
+
+/// Fetch and handle one page of `event` logs starting at `next_block`.
+/// Only blocks with `CONFIRMATION_DEPTH` confirmations are fetched.
+/// Returns the last block of the page and the confirmed head, or `None` when already up to date.
+async fn fetch_event_logs_page(
+    app_state: &WebAppState,
+    provider: &impl Provider,
+    contract_address: Address,
+    event: ContractEvent,
+    next_block: u64,
+) -> Result<Option<(u64, u64)>> {
+    let current_block = provider.get_block_number().await?;
+
+    let head_block = confirmed_head(current_block);
+
+    tracing::debug!(
+        "{} Current Block: {} Confirmed Head: {}",
+        event.name(),
+        current_block,
+        head_block
+    );
+
+    let (from_block, to_block) = match next_block_range(next_block, head_block) {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+
+    let filter = Filter::new()
+        .address(contract_address)
+        .event(event.signature())
+        .from_block(from_block)
+        .to_block(to_block);
+
+    let filtered_logs = provider.get_logs(&filter).await?;
+
+    // Handle the logs in order, the cursor must not pass an unhandled log
+    for log in filtered_logs {
+        event.handle_log(app_state, &log).await?;
+    }
+
+    // Every handler of the page has committed, move the cursor
+    save_cursor(
+        app_state,
+        &contract_address.to_string(),
+        event.name(),
+        to_block,
+    )
+    .await?;
+
+    Ok(Some((to_block, head_block)))
+}
+
+/// First block a fetcher should scan, resumed from its saved cursor.
+pub async fn load_next_block(
+    app_state: &WebAppState,
+    contract_address: &str,
+    event_name: &str,
+) -> Result<u64> {
+    let cursor = database::get_chain_cursor(&app_state.db, contract_address, event_name).await?;
+
+    let next_block = match cursor {
+        Some(last_block) => (last_block + 1) as u64,
+        None => APP_CONFIG.fetcher_start_block,
+    };
+
+    Ok(next_block)
+}
+
+/// Latest block considered final, `CONFIRMATION_DEPTH` blocks behind the chain tip.
+pub fn confirmed_head(current_block: u64) -> u64 {
+    current_block.saturating_sub(APP_CONFIG.confirmation_depth)
+}
+
+/// Inclusive block range of the next page to fetch, capped to `MAX_LOGS_BLOCK_RANGE` blocks.
+/// Returns `None` when the fetcher is already past `head_block`.
+pub fn next_block_range(next_block: u64, head_block: u64) -> Option<(u64, u64)> {
+    if next_block > head_block {
+        return None;
+    }
+
+    let to_block = head_block.min(next_block + MAX_LOGS_BLOCK_RANGE - 1);
+
+    Some((next_block, to_block))
+}
+
+/// Save `last_block` as processed. Must only be called once every log up to it is handled.
+pub async fn save_cursor(
+    app_state: &WebAppState,
+    contract_address: &str,
+    event_name: &str,
+    last_block: u64,
+) -> Result<()> {
+    let mut tx = app_state.db.begin().await?;
+
+    database::upsert_chain_cursor(&mut tx, contract_address, event_name, last_block as i64).await?;
+
+    tx.commit().await?;
 
     Ok(())
 }
+
+/// Chain position of a fetched log. Logs returned by `get_logs` are always mined.
+pub fn ingested_log_from(log: &Log, event: ContractEvent) -> Result<NewIngestedLog> {
+    let (Some(block_number), Some(block_hash), Some(tx_hash), Some(log_index)) = (
+        log.block_number,
+        log.block_hash,
+        log.transaction_hash,
+        log.log_index,
+    ) else {
+        return Err(eyre!("Log {:?} is not mined yet", log));
+    };
+
+    Ok(NewIngestedLog {
+        contract_address: log.address().to_string(),
+        event_name: event.name().to_string(),
+        block_number: block_number as i64,
+        block_hash: block_hash.to_string(),
+        tx_hash: tx_hash.to_string(),
+        log_index: log_index as i64,
+    })
+}
@@ -0,0 +1,97 @@
+use alloy::{eips::BlockNumberOrTag, providers::Provider};
+use color_eyre::Result;
+
+use crate::{config::REORG_CHECK_BLOCKS, database, fetcher::ContractEvent, types::WebAppState};
+
+/// Compare the block hashes of recently ingested logs with the canonical chain.
+///
+/// When a stored hash no longer matches, every log of the fetcher from that block onwards
+/// is rolled back and the cursor is rewound, so the canonical logs get ingested again.
+/// Usage and claim events are removed along with their ingested log (ON DELETE CASCADE),
+/// mints also unlink the NFT from their agent.
+/// Returns the block the fetcher must resume from when a rollback happened.
+pub async fn rollback_reorged_logs(
+    app_state: &WebAppState,
+    provider: &impl Provider,
+    contract_address: &str,
+    event_name: &str,
+) -> Result<Option<u64>> {
+    let current_block = provider.get_block_number().await?;
+    let from_block = current_block.saturating_sub(REORG_CHECK_BLOCKS);
+
+    let ingested_blocks = database::get_ingested_blocks(
+        &app_state.db,
+        contract_address,
+        event_name,
+        from_block as i64,
+    )
+    .await?;
+
+    let mut reorged_block = None;
+
+    for ingested_block in ingested_blocks {
+        let canonical_block = provider
+            .get_block_by_number(BlockNumberOrTag::Number(ingested_block.block_number as u64))
+            .await?;
+
+        let canonical_hash = canonical_block.map(|block| block.header.hash.to_string());
+
+        if canonical_hash.as_deref() != Some(ingested_block.block_hash.as_str()) {
+            tracing::warn!(
+                "Reorg detected at block {}: ingested hash {} but canonical hash is {:?}",
+                ingested_block.block_number,
+                ingested_block.block_hash,
+                canonical_hash
+            );
+
+            reorged_block = Some(ingested_block.block_number);
+            break;
+        }
+    }
+
+    let reorged_block = match reorged_block {
+        Some(block_number) => block_number,
+        None => return Ok(None),
+    };
+
+    let mut tx = app_state.db.begin().await?;
+
+    let rolled_back_logs = database::delete_ingested_logs_from_block(
+        &mut tx,
+        contract_address,
+        event_name,
+        reorged_block,
+    )
+    .await?;
+
+    for log in &rolled_back_logs {
+        if log.event_name != ContractEvent::DatasetNFTMinted.name() {
+            continue;
+        }
+
+        if let (Some(agent_id), Some(nft_id)) = (log.agent_id, log.nft_id)
+            && database::clear_agent_nft_details(&mut tx, agent_id, nft_id).await?
+        {
+            tracing::info!(
+                "Agent {} unlinked from NFT {} after reorg of block {}",
+                agent_id,
+                nft_id,
+                log.block_number
+            );
+        }
+    }
+
+    // Everything before the reorged block is still canonical
+    database::upsert_chain_cursor(&mut tx, contract_address, event_name, reorged_block - 1).await?;
+
+    tx.commit().await?;
+
+    tracing::info!(
+        "Rolled back {} {} logs from block {}",
+        rolled_back_logs.len(),
+        event_name,
+        reorged_block
+    );
+
+    Ok(Some(reorged_block as u64))
+}
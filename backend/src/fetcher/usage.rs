@@ -0,0 +1,47 @@
+use alloy::{primitives::utils::format_units, rpc::types::Log, sol_types::SolEvent};
+use chrono::DateTime;
+use color_eyre::{Result, eyre::Context};
+
+use crate::{
+    fetcher::{ContractEvent, ingested_log_from, mint::DatasetUsed},
+    helpers::ledger::handle_dataset_used,
+    types::{NewLedgerEvent, WebAppState},
+};
+
+/// Decode a `DatasetUsed` log and store it in the local ledger.
+pub async fn handle_usage_log(app_state: &WebAppState, log: &Log) -> Result<()> {
+    tracing::info!("New DatasetUsed event: {:?}", log);
+
+    let event = match DatasetUsed::decode_log_data(log.data()) {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::warn!("Skipping undecodable DatasetUsed log: {}", e);
+            return Ok(());
+        }
+    };
+
+    tracing::trace!(
+        "Dataset Used! tokenId: {:?}, user: {:?}, amount: {}",
+        event.tokenId,
+        event.user,
+        event.amount
+    );
+
+    let ingested_log = ingested_log_from(log, ContractEvent::DatasetUsed)?;
+
+    let usage = NewLedgerEvent {
+        nft_id: event.tokenId.to_string().parse()?,
+        address: event.user.to_string(),
+        amount: format_units(event.amount, 18)?.parse()?,
+        amount_wei: event.amount.to_string(),
+        block_timestamp: log
+            .block_timestamp
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp as i64, 0)),
+    };
+
+    handle_dataset_used(app_state, &usage, &ingested_log)
+        .await
+        .with_context(|| format!("Failed to handle dataset usage {:?}", usage))?;
+
+    Ok(())
+}
@@ -1,55 +1,143 @@
-use std::path::{Path, PathBuf};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
 use actix_web::web;
 use alloy::{
-    primitives::utils::format_units,
+    primitives::{Address, B256, utils::format_units},
     providers::{Provider, ProviderBuilder},
-    sol,
     sol_types::SolEvent,
 };
-use dashmap::DashMap;
-use rig::{agent::Agent, completion::Prompt, providers::gemini::completion::CompletionModel};
+use futures_util::StreamExt;
+use rig::{
+    agent::Agent,
+    completion::Prompt,
+    message::Message,
+    providers::gemini::{self, completion::CompletionModel},
+    streaming::{StreamedAssistantContent, StreamingChat},
+    tool::Tool,
+};
+use sqlx::types::Json;
+use tokio::sync::mpsc;
 
-use color_eyre::{Result, eyre::Context};
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
 use serde_json::json;
 
 use crate::{
     config::{
-        APP_CONFIG, DATASET_DETAILS_GEN_AGENT_MODEL, ENCLAVA_CONTRACT_ADDRESS, INIT_AGENT_MODEL,
-        UPLOAD_DIR,
+        AGENT_MAX_TURNS, AGENT_STREAM_BUFFER, APP_CONFIG, DATASET_DETAILS_GEN_AGENT_MODEL,
+        EMBEDDING_MODEL, ENCLAVA_CONTRACT_ADDRESS, GUARDRAIL_NGRAM_SIZE, INIT_AGENT_MODEL,
+        RAG_TOP_K,
     },
     database,
+    fetcher::mint::DatasetUsed,
+    helpers::{
+        crypto::SealedDataset,
+        dp::{self, DpAggregateTool},
+        guardrail::StreamGuard,
+        rag, router,
+        sql::{self, QueryDatasetTool},
+    },
     state::AppState,
-    types::{AgentCategory, AgentDb, DatasetAIDetails, UserDb},
+    tee::{
+        AgentLoad, TeeAnswer, TeeExecutor, TeeStream, TeeStreamEvent,
+        ingest::DatasetIngest,
+        protocol::{ChatTurn, TokenUsage},
+    },
+    types::{
+        AgentAttestation, AgentCategory, AgentDb, DatasetAIDetails, DatasetChunkDb, DatasetFormat,
+        DpColumnBounds, PiiAction, PiiReport, UserDb, VerifiedPayment,
+    },
 };
 
-sol! {
-    event DatasetUsed(uint256 indexed tokenId, address indexed user, uint256 amount);
+/// Dataset of a new agent once saved, the agent is started from it after the transaction commits.
+pub struct InitializedAgent {
+    pub pii_report: PiiReport,
+    fingerprints: Vec<i64>,
+    chunks: Vec<DatasetChunkDb>,
 }
 
 pub async fn init_ai_agent_with_dataset(
     _user: &UserDb,
     agent_db: &AgentDb,
-    dataset_csv_path: &PathBuf,
+    pii_actions: &HashMap<String, PiiAction>,
     app_state: &web::Data<AppState>,
-) -> Result<()> {
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> Result<InitializedAgent> {
     // Initialize the AI agent with the specified model and dataset
     let ai_model = &app_state.ai_model;
 
-    let agent = init_agent(&dataset_csv_path, ai_model, &agent_db).await?;
+    // The executor redacts the PII before anything reads the dataset, the stored copy included,
+    // then fingerprints, profiles and embeds it. The backend never decrypts the dataset.
+    let DatasetIngest {
+        pii_report,
+        profile,
+        fingerprints,
+        chunks,
+    } = app_state
+        .tee
+        .ingest_dataset(agent_db, Some(pii_actions))
+        .await?;
+
+    let pii_report = pii_report
+        .ok_or_else(|| eyre!("No PII report for the dataset of agent {}", agent_db.id))?;
+
+    // The owner bounds can only be checked against the columns the executor found
+    if let Some(bounds) = &agent_db.dp_bounds {
+        dp::check_bounds(bounds, &profile)?;
+    }
 
-    // Save the agent to the AppState tee_agents using its id
-    app_state.tee_agents.insert(agent_db.id, agent);
+    database::update_agent_pii_report(tx, agent_db.id, &pii_report).await?;
 
-    Ok(())
+    // Fingerprints of the rows, answers copying them are caught by the output guardrail
+    database::upsert_dataset_fingerprints(
+        tx,
+        agent_db.id,
+        &fingerprints,
+        GUARDRAIL_NGRAM_SIZE as i32,
+    )
+    .await?;
+
+    // Profile of the columns so buyers can judge the dataset before paying
+    database::update_agent_profile(tx, agent_db.id, &profile).await?;
+
+    // Embed the description once, prompts are routed to the agent by similarity
+    router::embed_agent_description(tx, ai_model, agent_db, Some(&profile)).await?;
+
+    // Embedded chunks are stored with the agent so a restart does not embed again.
+    // Differential privacy agents never see rows, they have none.
+    database::replace_dataset_chunks(tx, agent_db.id, &chunks, EMBEDDING_MODEL).await?;
+
+    Ok(InitializedAgent {
+        pii_report,
+        fingerprints,
+        chunks,
+    })
+}
+
+/// Hand a new agent over to the enclave, once its row is committed so no running agent lacks one.
+pub async fn start_initialized_agent(
+    agent_db: &AgentDb,
+    initialized: InitializedAgent,
+    app_state: &web::Data<AppState>,
+) -> Result<PiiReport> {
+    let load = AgentLoad {
+        chunks: initialized.chunks,
+        fingerprints: initialized.fingerprints,
+        pii_report: Some(initialized.pii_report.clone()),
+    };
+
+    app_state.tee.load_agent(agent_db, load).await?;
+
+    Ok(initialized.pii_report)
 }
 
 pub async fn load_db_agents(
     db: &sqlx::Pool<sqlx::Postgres>,
     ai_model: &rig::providers::gemini::Client,
-) -> Result<DashMap<i64, Agent<CompletionModel>>> {
-    let tee_agents = DashMap::new();
-
+    tee: &dyn TeeExecutor,
+) -> Result<()> {
     let db_agents = sqlx::query_as!(
         AgentDb,
         r#"
@@ -62,6 +150,11 @@ pub async fn load_db_agents(
         g.dataset_path,
         g.status,
         g.category as "category: AgentCategory",
+        g.format as "format: DatasetFormat",
+        g.dp_enabled,
+        g.dp_epsilon,
+        g.dp_budget,
+        g.dp_bounds as "dp_bounds: Json<HashMap<String, DpColumnBounds>>",
         g.dataset_size,
         g.created_at,
         g.updated_at,
@@ -75,15 +168,65 @@ pub async fn load_db_agents(
     .fetch_all(db)
     .await?;
 
+    let profiles = database::get_agent_profiles(db).await?;
+    let chunks_embedding_models = database::get_agent_chunks_embedding_models(db).await?;
+    let ngram_size = GUARDRAIL_NGRAM_SIZE as i32;
+
     for agent_db in db_agents {
-        let dataset_csv_path = Path::new(UPLOAD_DIR).join(&agent_db.dataset_path);
+        let mut chunks = if agent_db.dp_enabled {
+            Vec::new()
+        } else {
+            database::get_dataset_chunks(db, agent_db.id, EMBEDDING_MODEL).await?
+        };
+
+        let mut fingerprints =
+            database::get_dataset_fingerprints(db, agent_db.id, ngram_size).await?;
+
+        // Agents created before profiling, fingerprinting or retrieval existed, or after
+        // `EMBEDDING_MODEL` changed: the executor ingests their dataset again. A dataset
+        // without rows has no chunks, its stored embedding model tells it was ingested.
+        let chunks_outdated = !agent_db.dp_enabled
+            && chunks_embedding_models
+                .get(&agent_db.id)
+                .map(String::as_str)
+                != Some(EMBEDDING_MODEL);
+
+        if !profiles.contains_key(&agent_db.id) || fingerprints.is_none() || chunks_outdated {
+            tracing::info!("Ingesting dataset of agent {}", agent_db.id);
+
+            let ingest = tee.ingest_dataset(&agent_db, None).await?;
+
+            let mut tx = db.begin().await?;
+
+            database::update_agent_profile(&mut tx, agent_db.id, &ingest.profile).await?;
+            database::upsert_dataset_fingerprints(
+                &mut tx,
+                agent_db.id,
+                &ingest.fingerprints,
+                ngram_size,
+            )
+            .await?;
+            database::replace_dataset_chunks(&mut tx, agent_db.id, &ingest.chunks, EMBEDDING_MODEL)
+                .await?;
+
+            tx.commit().await?;
+
+            chunks = ingest.chunks;
+            fingerprints = Some(ingest.fingerprints);
+        }
 
-        let agent = init_agent(&dataset_csv_path, ai_model, &agent_db).await?;
+        let load = AgentLoad {
+            chunks,
+            fingerprints: fingerprints.unwrap_or_default(),
+            pii_report: database::get_agent_pii_report(db, agent_db.id).await?,
+        };
 
-        tee_agents.insert(agent_db.id, agent);
+        tee.load_agent(&agent_db, load).await?;
     }
 
-    Ok(tee_agents)
+    router::backfill_description_embeddings(db, ai_model).await?;
+
+    Ok(())
 }
 
 pub async fn generate_dataset_details(
@@ -116,26 +259,147 @@ pub async fn generate_dataset_details(
     Ok(dataset_details)
 }
 
+/// A `DatasetUsed` payment found for a tx: (nft_id, user, amount).
+type DatasetUsage = (i64, Address, f64);
+
 pub async fn verif_selected_agents_payment(
     app_state: &web::Data<AppState>,
     agent_ids: &Vec<i64>,
     tx_hash: &str,
-) -> Result<bool> {
-    // Check if the tx hash is already handled
-    if app_state.handled_txs.contains(tx_hash) {
-        tracing::error!("Transaction hash {} already handled", tx_hash);
-        return Ok(false);
-    }
+    payer_address: &Address,
+) -> Result<Option<VerifiedPayment>> {
+    // Normalize the hash so the same tx can not be replayed with a different casing
+    let tx_hash = B256::from_str(tx_hash.trim())
+        .context("Invalid tx hash")?
+        .to_string();
 
     // Get all agents from the database
     let db = &app_state.db;
 
+    // Check if the tx hash is already consumed
+    if database::is_payment_consumed(db, &tx_hash).await? {
+        tracing::error!("Transaction hash {} already handled", tx_hash);
+        return Ok(None);
+    }
+
     let agents_db = database::get_agents_by_ids(db, agent_ids).await?;
 
+    if agents_db.len() != agent_ids.len() {
+        tracing::error!("Some of the requested agents {:?} do not exist", agent_ids);
+        return Ok(None);
+    }
+
+    // Every requested agent must be backed by a minted NFT to be payable
+    if let Some(agent) = agents_db.iter().find(|agent| agent.nft_id.is_none()) {
+        tracing::error!("Agent {} has no minted NFT yet", agent.id);
+        return Ok(None);
+    }
+
     let total_price_to_pay = agents_db.iter().fold(0.0, |acc, agent| acc + agent.price);
 
     tracing::debug!("Total price to pay By Used Agents: {}", total_price_to_pay);
 
+    // Prefer the local ledger, only ask the RPC when the tx is not indexed yet
+    let usage_events = database::get_usage_events_by_tx_hash(db, &tx_hash).await?;
+
+    let (dataset_usages, block_number) = if !usage_events.is_empty() {
+        tracing::debug!("Payment {} found in the local ledger", tx_hash);
+
+        let block_number = usage_events[0].block_number;
+
+        let mut dataset_usages = Vec::new();
+
+        for usage_event in usage_events {
+            let user = Address::from_str(&usage_event.user_address)?;
+            dataset_usages.push((usage_event.nft_id, user, usage_event.amount));
+        }
+
+        (dataset_usages, block_number)
+    } else {
+        match fetch_dataset_usages_from_rpc(&tx_hash, payer_address).await? {
+            Some(usages) => usages,
+            None => return Ok(None),
+        }
+    };
+
+    let mut total_amount_paid = 0.0;
+    let mut paid_agent_ids = Vec::new();
+    let mut paid_amounts = Vec::new();
+
+    for (nft_id, user, amount_paid) in dataset_usages {
+        tracing::debug!("Amount paid: {}", amount_paid);
+        tracing::debug!("NFT ID: {}", nft_id);
+
+        if user != *payer_address {
+            tracing::error!(
+                "DatasetUsed log for nft_id {} was paid by {} but the caller is {}",
+                nft_id,
+                user,
+                payer_address
+            );
+            return Ok(None);
+        }
+
+        // Get the agent that has the nft_id
+        let agent = agents_db.iter().find(|agent| agent.nft_id == Some(nft_id));
+
+        if agent.is_none() {
+            tracing::error!("Agent with nft_id {} not found", nft_id);
+            return Ok(None);
+        }
+
+        let agent = agent.unwrap();
+
+        if paid_agent_ids.contains(&agent.id) {
+            tracing::error!("Agent {} has more than one DatasetUsed log", agent.id);
+            return Ok(None);
+        }
+
+        if agent.price > amount_paid {
+            tracing::error!(
+                "Agent {} price is {} but only {} was paid",
+                agent.id,
+                agent.price,
+                amount_paid
+            );
+            return Ok(None);
+        }
+
+        total_amount_paid += amount_paid;
+        paid_agent_ids.push(agent.id);
+        paid_amounts.push(amount_paid);
+    }
+
+    // Each requested agent needs its own DatasetUsed log
+    if let Some(agent_id) = agent_ids.iter().find(|id| !paid_agent_ids.contains(id)) {
+        tracing::error!("No DatasetUsed log found for agent {}", agent_id);
+        return Ok(None);
+    }
+
+    if total_amount_paid < total_price_to_pay {
+        tracing::error!(
+            "Total amount paid {} is less than total price to pay {}",
+            total_amount_paid,
+            total_price_to_pay
+        );
+        return Ok(None);
+    }
+
+    Ok(Some(VerifiedPayment {
+        tx_hash,
+        payer_address: payer_address.to_string(),
+        agent_ids: paid_agent_ids,
+        amounts: paid_amounts,
+        block_number,
+    }))
+}
+
+/// Read the `DatasetUsed` logs of a tx that is not in the local ledger yet.
+/// Returns `None` when the tx is not a successful enclava payment sent by `payer_address`.
+async fn fetch_dataset_usages_from_rpc(
+    tx_hash: &str,
+    payer_address: &Address,
+) -> Result<Option<(Vec<DatasetUsage>, i64)>> {
     let rpc_url = &APP_CONFIG.alchemy_rpc_url;
 
     let provider = ProviderBuilder::new().connect_http(rpc_url.parse()?);
@@ -144,7 +408,7 @@ pub async fn verif_selected_agents_payment(
 
     if tx_receipt.is_none() {
         tracing::error!("Transaction receipt not found for tx hash: {}", tx_hash);
-        return Ok(false);
+        return Ok(None);
     }
 
     let tx_receipt = tx_receipt.unwrap();
@@ -154,99 +418,309 @@ pub async fn verif_selected_agents_payment(
 
     if !tx_success {
         tracing::error!("Transaction of {} is not successful", tx_hash);
-        return Ok(false);
+        return Ok(None);
     }
 
     // Chck if the tx is for the correct enclava smart contract
     let tx_contract = tx_receipt.to;
+    let enclava_contract: Address = ENCLAVA_CONTRACT_ADDRESS.parse()?;
 
-    if tx_contract != Some(ENCLAVA_CONTRACT_ADDRESS.parse()?) {
+    if tx_contract != Some(enclava_contract) {
         tracing::error!(
             "Transaction of {} is not for the correct contract. Expected: {} Found: {:?}",
             tx_hash,
             ENCLAVA_CONTRACT_ADDRESS,
             tx_contract
         );
-        return Ok(false);
+        return Ok(None);
+    }
+
+    // Check that the tx was sent by the caller
+    if tx_receipt.from != *payer_address {
+        tracing::error!(
+            "Transaction of {} was sent by {} but the caller is {}",
+            tx_hash,
+            tx_receipt.from,
+            payer_address
+        );
+        return Ok(None);
     }
 
     // Get the tx logs and decode them
-    let tx_logs = tx_receipt.logs();
+    let mut dataset_usages = Vec::new();
 
-    let mut total_amount_paid = 0.0;
+    for log in tx_receipt.logs() {
+        // Only trust events emitted by the enclava contract itself
+        if log.address() != enclava_contract {
+            continue;
+        }
+
+        if let Ok(decoded_log) = DatasetUsed::decode_log_data(log.data()) {
+            let amount_paid: f64 = format_units(decoded_log.amount, 18)?.parse()?;
+            let nft_id: i64 = decoded_log.tokenId.to_string().parse()?;
 
-    for log in tx_logs {
-        let log_data = log.data();
+            dataset_usages.push((nft_id, decoded_log.user, amount_paid));
+        }
+    }
 
-        if let Ok(decoded_log) = DatasetUsed::decode_log_data(log_data) {
-            let amount_paid_u256 = decoded_log.amount;
-            let token_nft_id = decoded_log.tokenId;
+    let block_number = tx_receipt.block_number.unwrap_or_default() as i64;
 
-            let amount_paid: f64 = format_units(amount_paid_u256, 18)?.parse()?;
-            let nft_id: i64 = token_nft_id.to_string().parse()?;
+    Ok(Some((dataset_usages, block_number)))
+}
 
-            tracing::debug!("Amount paid: {}", amount_paid);
-            tracing::debug!("NFT ID: {}", nft_id);
+/// A dataset agent ready to answer prompts.
+pub enum DatasetAgent {
+    Rag(Agent<CompletionModel>),
+    /// Built again for every answer, so each answer gets its own aggregate allowance
+    Dp {
+        ai_model: gemini::Client,
+        name: String,
+        preamble: String,
+        tool: DpAggregateTool,
+    },
+}
 
-            // Get the agent that has the nft_id
-            let agent = agents_db.iter().find(|agent| agent.nft_id == Some(nft_id));
+impl DatasetAgent {
+    pub async fn answer(&self, prompt: &str, history: &[ChatTurn]) -> Result<String> {
+        match self {
+            DatasetAgent::Rag(agent) => prompt_agent(agent, prompt, history).await,
+            DatasetAgent::Dp {
+                ai_model,
+                name,
+                preamble,
+                tool,
+            } => {
+                let agent = dp_answer_agent(ai_model, name, preamble, tool);
+
+                prompt_agent(&agent, prompt, history).await
+            }
+        }
+    }
 
-            if agent.is_none() {
-                tracing::error!("Agent with nft_id {} not found", nft_id);
-                return Ok(false);
+    /// Stream the answer to `prompt` through `guard`, the final answer is signed by `attest`.
+    /// It is produced by a task that stops early when the returned stream is dropped or the
+    /// guardrail blocks the answer.
+    pub fn answer_stream(
+        self: Arc<Self>,
+        prompt: String,
+        history: Vec<ChatTurn>,
+        mut guard: StreamGuard,
+        attest: impl FnOnce(&str) -> Result<AgentAttestation> + Send + 'static,
+    ) -> TeeStream {
+        let (sender, receiver) = mpsc::channel(AGENT_STREAM_BUFFER);
+
+        tokio::spawn(async move {
+            let result = match self.as_ref() {
+                DatasetAgent::Rag(agent) => {
+                    stream_agent(agent, &prompt, &history, &mut guard, &sender).await
+                }
+                DatasetAgent::Dp {
+                    ai_model,
+                    name,
+                    preamble,
+                    tool,
+                } => {
+                    let agent = dp_answer_agent(ai_model, name, preamble, tool);
+
+                    stream_agent(&agent, &prompt, &history, &mut guard, &sender).await
+                }
+            };
+
+            match result {
+                Ok(usage) => {
+                    let (text, guarded) = guard.finish();
+
+                    if !text.is_empty() {
+                        sender.send(Ok(TeeStreamEvent::Chunk(text))).await.ok();
+                    }
+
+                    let end = attest(&guarded.response).map(|attestation| TeeStreamEvent::End {
+                        usage,
+                        answer: Box::new(TeeAnswer {
+                            guarded,
+                            attestation,
+                        }),
+                    });
+
+                    sender.send(end).await.ok();
+                }
+                Err(e) => {
+                    sender.send(Err(e)).await.ok();
+                }
             }
+        });
+
+        Box::pin(futures_util::stream::unfold(
+            receiver,
+            |mut receiver| async move { receiver.recv().await.map(|event| (event, receiver)) },
+        ))
+    }
+}
+
+// Leave room for the dataset tool calls before the final answer
+async fn prompt_agent(
+    agent: &Agent<CompletionModel>,
+    prompt: &str,
+    history: &[ChatTurn],
+) -> Result<String> {
+    let mut history = chat_history(history);
+
+    Ok(agent
+        .prompt(prompt)
+        .with_history(&mut history)
+        .multi_turn(AGENT_MAX_TURNS)
+        .await?)
+}
 
-            let agent = agent.unwrap();
+fn chat_history(history: &[ChatTurn]) -> Vec<Message> {
+    history
+        .iter()
+        .flat_map(|turn| {
+            [
+                Message::user(turn.prompt.clone()),
+                Message::assistant(turn.response.clone()),
+            ]
+        })
+        .collect()
+}
 
-            if agent.price > amount_paid {
-                tracing::error!(
-                    "Agent {} price is {} but only {} was paid",
-                    agent.id,
-                    agent.price,
-                    amount_paid
-                );
-                return Ok(false);
+/// Send the answer of `agent` to `sender` as `guard` releases it, returns the tokens used.
+///
+/// Tools can not run in the middle of a stream: when the model calls one, the answer is produced
+/// by a regular multi turn prompt instead and sent as a single chunk, without usage.
+async fn stream_agent(
+    agent: &Agent<CompletionModel>,
+    prompt: &str,
+    history: &[ChatTurn],
+    guard: &mut StreamGuard,
+    sender: &mpsc::Sender<Result<TeeStreamEvent>>,
+) -> Result<Option<TokenUsage>> {
+    let mut stream = agent.stream_chat(prompt, chat_history(history)).await?;
+    let mut streamed_text = false;
+    let mut usage = None;
+
+    while let Some(content) = stream.next().await {
+        let text = match content? {
+            StreamedAssistantContent::Text(text) => {
+                streamed_text = true;
+                text.text
+            }
+            StreamedAssistantContent::ToolCall(_) => {
+                let answer = prompt_agent(agent, prompt, history).await?;
+
+                // Keep what the model said before calling the tool apart from the answer
+                let answer = if streamed_text {
+                    format!("\n\n{}", answer)
+                } else {
+                    answer
+                };
+
+                send_guarded(guard, &answer, sender).await;
+                break;
             }
+            StreamedAssistantContent::Final(response) => {
+                // Gemini only reports the total of a streamed answer, zero when it did not
+                let total_tokens = response.usage_metadata.total_token_count;
+
+                usage = (total_tokens > 0).then_some(TokenUsage {
+                    total_tokens: total_tokens as u64,
+                });
+                continue;
+            }
+            _ => continue,
+        };
 
-            total_amount_paid += amount_paid;
+        if !send_guarded(guard, &text, sender).await {
+            break;
         }
     }
 
-    if total_amount_paid < total_price_to_pay {
-        tracing::error!(
-            "Total amount paid {} is less than total price to pay {}",
-            total_amount_paid,
-            total_price_to_pay
-        );
-        return Ok(false);
+    Ok(usage)
+}
+
+/// Send what `guard` releases of the next `text` of an answer. Returns false when the answer
+/// must stop: the guardrail blocked it, or the client disconnected and the receiver is gone.
+async fn send_guarded(
+    guard: &mut StreamGuard,
+    text: &str,
+    sender: &mpsc::Sender<Result<TeeStreamEvent>>,
+) -> bool {
+    let released = guard.push(text);
+
+    if guard.is_blocked() {
+        return false;
     }
 
-    // Add the transaction hash to the app_state for future reference
-    app_state.handled_txs.insert(tx_hash.to_string());
+    released.is_empty()
+        || sender
+            .send(Ok(TeeStreamEvent::Chunk(released)))
+            .await
+            .is_ok()
+}
 
-    Ok(true)
+/// Agent of a single differential privacy answer, with the whole aggregate allowance.
+fn dp_answer_agent(
+    ai_model: &gemini::Client,
+    name: &str,
+    preamble: &str,
+    tool: &DpAggregateTool,
+) -> Agent<CompletionModel> {
+    ai_model
+        .agent(INIT_AGENT_MODEL)
+        .name(name)
+        .preamble(preamble)
+        .tool(tool.for_answer())
+        .temperature(0.0)
+        .build()
 }
 
-async fn init_agent(
-    dataset_csv_path: &PathBuf,
-    ai_model: &rig::providers::gemini::Client,
+/// Build an agent over its dataset. Called by the executor that runs the agent.
+pub async fn init_agent(
+    dataset: &SealedDataset,
+    ai_model: &gemini::Client,
     agent_db: &AgentDb,
-) -> Result<Agent<CompletionModel>> {
+    chunks: Vec<DatasetChunkDb>,
+) -> Result<DatasetAgent> {
+    if agent_db.dp_enabled {
+        return init_dp_agent(dataset, ai_model, agent_db).await;
+    }
+
     let agent_builder = ai_model.agent(INIT_AGENT_MODEL);
 
-    let dataset_content = tokio::fs::read_to_string(dataset_csv_path).await?;
+    // Only the columns go in the preamble, the rows are retrieved per prompt
+    let dataset_columns = dataset
+        .csv_reader()?
+        .headers()?
+        .iter()
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let dataset_rows = chunks.last().map(|chunk| chunk.last_row).unwrap_or(0);
 
     let agent_instruction = format!(
-        "You are an AI agent ({}) who is responsible for answering questions about the csv dataset added to you (it is your only context). Do not use any other knowledge source to answer questions. Return only the answer. PLease Do not reveal any personal information about specific user like its email, name, phone number, etc. The Dataset description is {}. The Dataset Category is {}. The Dataset csv : {}",
-        agent_db.name,
-        agent_db.description,
-        agent_db.category.to_string(),
-        dataset_content
+        "You are an AI agent ({}) who is responsible for answering questions about a csv dataset (it is your only context). Do not use any other knowledge source to answer questions. Return only the answer. PLease Do not reveal any personal information about specific user like its email, name, phone number, etc. The Dataset description is {}. The Dataset Category is {}. The Dataset has {} rows with the columns: {}. Only the rows most relevant to the question are added to your context, each group of rows says which rows of the dataset it contains. If the provided rows are not enough to answer, say so instead of guessing.",
+        agent_db.name, agent_db.description, agent_db.category, dataset_rows, dataset_columns
+    );
+
+    let dataset_index = rag::build_dataset_index(dataset, chunks, ai_model)?;
+
+    // Aggregations are computed with SQL on the agent own copy of the dataset
+    let dataset_table = sql::load_dataset_table(dataset).await?;
+
+    let agent_instruction = format!(
+        "{} You can also query the whole dataset with the {} tool, the table is {}. Always use it for counts, sums, averages, minimums, maximums, rankings or any question that needs more rows than the ones provided, and answer from its results.",
+        agent_instruction,
+        QueryDatasetTool::NAME,
+        dataset_table.schema()
     );
 
     let agent = agent_builder
         .name(&agent_db.name)
         .preamble(&agent_instruction)
+        .dynamic_context(RAG_TOP_K, dataset_index)
+        .tool(QueryDatasetTool {
+            table: dataset_table,
+        })
         .temperature(0.0)
         .additional_params(json!(
             {
@@ -256,5 +730,42 @@ async fn init_agent(
         ))
         .build();
 
-    Ok(agent)
+    Ok(DatasetAgent::Rag(agent))
+}
+
+/// Differential privacy agent: no rows in its context and no raw SQL, only noisy aggregates.
+async fn init_dp_agent(
+    dataset: &SealedDataset,
+    ai_model: &gemini::Client,
+    agent_db: &AgentDb,
+) -> Result<DatasetAgent> {
+    let epsilon = agent_db
+        .dp_epsilon
+        .ok_or_else(|| eyre!("Agent {} has no dp_epsilon", agent_db.id))?;
+
+    let dataset_table = sql::load_dataset_table(dataset).await?;
+
+    let preamble = format!(
+        "You are an AI agent ({}) who is responsible for answering questions about a csv dataset protected by differential privacy. The Dataset description is {}. The Dataset Category is {}. The table is {}. You never see individual rows: answer only from the results of the {} tool, they are noisy estimates so present them as approximate. Refuse questions about specific individuals or records.",
+        agent_db.name,
+        agent_db.description,
+        agent_db.category,
+        dataset_table.schema(),
+        DpAggregateTool::NAME
+    );
+
+    let bounds = agent_db
+        .dp_bounds
+        .as_ref()
+        .map(|bounds| bounds.0.clone())
+        .unwrap_or_default();
+
+    let tool = DpAggregateTool::new(dataset_table, epsilon, &bounds);
+
+    Ok(DatasetAgent::Dp {
+        ai_model: ai_model.clone(),
+        name: agent_db.name.clone(),
+        preamble,
+        tool,
+    })
 }
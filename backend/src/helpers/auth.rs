@@ -0,0 +1,187 @@
+use std::{future::Future, pin::Pin, str::FromStr};
+
+use actix_web::{FromRequest, HttpRequest, HttpResponse, dev::Payload, error::InternalError, web};
+use alloy::{
+    hex,
+    primitives::{Address, Signature, keccak256},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use color_eyre::{Result, eyre::Context};
+
+use crate::{config::APP_CONFIG, database, state::AppState, types::ErrorResponse};
+
+/// Wallet address proven by a valid session token (`Authorization: Bearer <token>`).
+///
+/// Add it as a handler argument to require authentication on an endpoint.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub address: String,
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let token = req
+                .headers()
+                .get("Authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(|token| token.trim().to_string());
+
+            let token = match token {
+                Some(token) if !token.is_empty() => token,
+                _ => return Err(unauthorized("Missing bearer token", "MISSING_AUTH_TOKEN")),
+            };
+
+            let app_state = match req.app_data::<web::Data<AppState>>() {
+                Some(app_state) => app_state,
+                None => {
+                    tracing::error!("AppState is not registered, can not authenticate request");
+                    return Err(unauthorized(
+                        "Authentication unavailable",
+                        "AUTH_UNAVAILABLE",
+                    ));
+                }
+            };
+
+            let session = database::get_active_session_by_token_hash(
+                &app_state.db,
+                &hash_session_token(&token),
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to get session: {}", e);
+                unauthorized("Failed to verify session", "SESSION_FETCH_FAILED")
+            })?;
+
+            match session {
+                Some(session) => Ok(AuthenticatedUser {
+                    address: session.address,
+                }),
+                None => Err(unauthorized(
+                    "Invalid or expired session token",
+                    "INVALID_AUTH_TOKEN",
+                )),
+            }
+        })
+    }
+}
+
+/// Authenticated wallet listed in `ADMIN_ADDRESSES`.
+///
+/// Add it as a handler argument to restrict an endpoint to the admins.
+#[derive(Debug, Clone)]
+pub struct AdminUser {
+    pub address: String,
+}
+
+impl FromRequest for AdminUser {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let authenticated_user = AuthenticatedUser::from_request(req, payload);
+
+        Box::pin(async move {
+            let user = authenticated_user.await?;
+
+            // Admin addresses are stored lowercase
+            if !APP_CONFIG
+                .admin_addresses
+                .contains(&user.address.to_lowercase())
+            {
+                let message = "Only admins can use this endpoint";
+                let response = HttpResponse::Forbidden().json(ErrorResponse {
+                    success: false,
+                    message: message.to_string(),
+                    error_code: Some("NOT_ADMIN".to_string()),
+                });
+
+                return Err(InternalError::from_response(message, response).into());
+            }
+
+            Ok(AdminUser {
+                address: user.address,
+            })
+        })
+    }
+}
+
+fn unauthorized(message: &str, error_code: &str) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized().json(ErrorResponse {
+        success: false,
+        message: message.to_string(),
+        error_code: Some(error_code.to_string()),
+    });
+
+    InternalError::from_response(message.to_string(), response).into()
+}
+
+/// Build the EIP-4361 (Sign-In with Ethereum) message for `address`.
+pub fn build_siwe_message(
+    address: &Address,
+    nonce: &str,
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> String {
+    format!(
+        "{domain} wants you to sign in with your Ethereum account:\n\
+         {address}\n\
+         \n\
+         Sign in to Enclava to publish datasets and query agents.\n\
+         \n\
+         URI: {uri}\n\
+         Version: 1\n\
+         Chain ID: {chain_id}\n\
+         Nonce: {nonce}\n\
+         Issued At: {issued_at}\n\
+         Expiration Time: {expires_at}",
+        domain = APP_CONFIG.siwe_domain,
+        address = address.to_checksum(None),
+        uri = APP_CONFIG.siwe_uri,
+        chain_id = APP_CONFIG.chain_id,
+        nonce = nonce,
+        issued_at = issued_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        expires_at = expires_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+    )
+}
+
+pub fn generate_nonce() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+pub fn generate_session_token() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+/// Only the hash of a session token is stored in the database.
+pub fn hash_session_token(token: &str) -> String {
+    keccak256(token.as_bytes()).to_string()
+}
+
+/// Recover the address that produced an EIP-191 `signature` over `message`.
+pub fn recover_signer(message: &str, signature: &str) -> Result<Address> {
+    let signature = Signature::from_str(signature).context("Invalid signature format")?;
+
+    let signer = signature
+        .recover_address_from_msg(message)
+        .context("Failed to recover signer from signature")?;
+
+    Ok(signer)
+}
+
+/// Check that `signature` over `message` was produced by `expected_address`.
+pub fn verify_signature(message: &str, signature: &str, expected_address: &str) -> Result<bool> {
+    let expected_address = Address::from_str(expected_address).context("Invalid wallet address")?;
+
+    let signer = recover_signer(message, signature)?;
+
+    tracing::debug!("Signature recovered signer: {}", signer);
+
+    Ok(signer == expected_address)
+}
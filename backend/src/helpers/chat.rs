@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::CHAT_HISTORY_MAX_TURNS,
+    tee::protocol::ChatTurn,
+    types::{ChatMessageDb, ChatRole},
+};
+
+/// Earlier turns of a session as each agent saw them: the buyer prompt and the answer of that
+/// agent, oldest first and capped at the last `CHAT_HISTORY_MAX_TURNS` turns.
+pub fn agent_histories(messages: &[ChatMessageDb]) -> HashMap<i64, Vec<ChatTurn>> {
+    let prompts: HashMap<i32, &str> = messages
+        .iter()
+        .filter(|message| message.role == ChatRole::User)
+        .map(|message| (message.turn, message.content.as_str()))
+        .collect();
+
+    let mut histories: HashMap<i64, Vec<ChatTurn>> = HashMap::new();
+
+    for message in messages
+        .iter()
+        .filter(|message| message.role == ChatRole::Assistant)
+    {
+        let (Some(agent_id), Some(prompt)) = (message.agent_id, prompts.get(&message.turn)) else {
+            continue;
+        };
+
+        histories.entry(agent_id).or_default().push(ChatTurn {
+            prompt: prompt.to_string(),
+            response: message.content.clone(),
+        });
+    }
+
+    for history in histories.values_mut() {
+        let skipped = history.len().saturating_sub(CHAT_HISTORY_MAX_TURNS);
+        history.drain(..skipped);
+    }
+
+    histories
+}
@@ -0,0 +1,124 @@
+use alloy::primitives::keccak256;
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    config::{APP_CONFIG, CREDIT_ROUNDING_TOLERANCE},
+    database,
+    types::{AgentDb, CreditKind, NewCreditEntry, NewFailedAgentQuery},
+};
+
+/// Record an agent that gave no answer to a paid turn and credit the buyer with the share of what
+/// the payment of the turn paid for the agent, one payment covers `chat_turns_per_payment` turns.
+/// The `dp_epsilon` charged to the buyer privacy budget for the turn is given back.
+/// Returns the amount credited.
+pub async fn record_failed_answer(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    failed_query: &NewFailedAgentQuery,
+    dp_epsilon: Option<f64>,
+) -> Result<f64> {
+    // The price of the agent may have changed since the payment
+    let paid = database::get_paid_amount(db, &failed_query.tx_hash, failed_query.agent_id)
+        .await?
+        .ok_or_else(|| {
+            eyre!(
+                "No payment {} found for agent {}",
+                failed_query.tx_hash,
+                failed_query.agent_id
+            )
+        })?;
+    let amount = paid / f64::from(APP_CONFIG.chat_turns_per_payment);
+
+    let mut tx = db.begin().await?;
+
+    let failed_query_id = database::insert_failed_agent_query(&mut tx, failed_query).await?;
+
+    let entry = NewCreditEntry {
+        buyer_address: failed_query.buyer_address.clone(),
+        amount,
+        kind: CreditKind::FailedAnswer,
+        failed_query_id: Some(failed_query_id),
+        note: None,
+    };
+
+    database::insert_credit_entry(&mut tx, &entry).await?;
+
+    if let Some(epsilon) = dp_epsilon {
+        database::refund_dp_budget(
+            &mut tx,
+            failed_query.agent_id,
+            &failed_query.buyer_address,
+            epsilon,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(amount)
+}
+
+/// Debit `amount` from the credits of a buyer, within `tx`.
+/// Returns the ledger entry and the balance left, or `None` when the balance is too low.
+pub async fn debit_credits(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    buyer_address: &str,
+    amount: f64,
+    kind: CreditKind,
+    note: Option<String>,
+) -> Result<Option<(i64, f64)>> {
+    database::lock_buyer_credits(tx, buyer_address).await?;
+
+    let balance = database::get_credit_balance(tx, buyer_address).await?;
+
+    if amount > balance + CREDIT_ROUNDING_TOLERANCE {
+        return Ok(None);
+    }
+
+    let entry = NewCreditEntry {
+        buyer_address: buyer_address.to_string(),
+        amount: -amount,
+        kind,
+        failed_query_id: None,
+        note,
+    };
+
+    let entry_id = database::insert_credit_entry(tx, &entry).await?;
+
+    Ok(Some((entry_id, (balance - amount).max(0.0))))
+}
+
+/// Pay the current price of `agents` with the credits of a buyer, within `tx`.
+/// Returns the hash standing for the payment and the balance left, or `None` when the balance is
+/// too low.
+pub async fn pay_with_credits(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    buyer_address: &str,
+    agents: &[AgentDb],
+) -> Result<Option<(String, f64)>> {
+    let agent_ids: Vec<i64> = agents.iter().map(|agent| agent.id).collect();
+    let amounts: Vec<f64> = agents.iter().map(|agent| agent.price).collect();
+
+    let Some((entry_id, balance)) = debit_credits(
+        tx,
+        buyer_address,
+        amounts.iter().sum(),
+        CreditKind::Spent,
+        None,
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let payment_hash = credit_payment_hash(entry_id);
+
+    database::set_credit_entry_payment(tx, entry_id, &payment_hash, &agent_ids, &amounts).await?;
+
+    Ok(Some((payment_hash, balance)))
+}
+
+/// Stands for the tx hash of agents paid with credits, the answers are attested for it.
+/// It is a hash so it can never be the hash of an actual tx.
+fn credit_payment_hash(entry_id: i64) -> String {
+    keccak256(format!("enclava-credit:{}", entry_id)).to_string()
+}
@@ -0,0 +1,622 @@
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{
+        Aead, Payload,
+        stream::{DecryptorBE32, EncryptorBE32},
+    },
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use color_eyre::{Result, eyre::eyre};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    config::DATASET_ENCRYPTION_SEGMENT_SIZE, helpers::temp_file::TempFile, storage::DatasetStore,
+};
+
+// Encrypted dataset layout:
+// MAGIC | data key nonce (12) | wrapped data key (32 + 16) | stream nonce (7) | segments
+// Each segment is DATASET_ENCRYPTION_SEGMENT_SIZE plaintext bytes + a 16 bytes tag, the last
+// one may be shorter (STREAM construction, segments can not be reordered or truncated).
+const MAGIC: &[u8] = b"ENCLAVA-DS1";
+const KEY_NONCE_SIZE: usize = 12;
+const WRAPPED_KEY_SIZE: usize = 32 + TAG_SIZE;
+const STREAM_NONCE_SIZE: usize = 7;
+const TAG_SIZE: usize = 16;
+
+/// Check if `data` starts like an encrypted dataset.
+pub fn is_encrypted_dataset(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypt `reader` into `writer` with a fresh data key wrapped by `master_key`.
+/// Returns the number of plaintext bytes encrypted.
+pub async fn encrypt_dataset_stream<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    master_key: &[u8],
+) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut encryptor = DatasetEncryptor::new(writer, master_key).await?;
+    let mut buffer = vec![0u8; DATASET_ENCRYPTION_SEGMENT_SIZE];
+
+    loop {
+        let read = reader.read(&mut buffer).await?;
+
+        if read == 0 {
+            break;
+        }
+
+        encryptor.write(&buffer[..read]).await?;
+    }
+
+    encryptor.finish().await
+}
+
+/// Push side of `encrypt_dataset_stream`, for datasets received chunk by chunk (uploads).
+pub struct DatasetEncryptor<W> {
+    writer: W,
+    sealer: SegmentSealer,
+}
+
+impl<W: AsyncWrite + Unpin> DatasetEncryptor<W> {
+    /// Write the header of a new encrypted dataset to `writer`.
+    pub async fn new(mut writer: W, master_key: &[u8]) -> Result<Self> {
+        let (header, sealer) = SegmentSealer::new(master_key)?;
+
+        writer.write_all(&header).await?;
+
+        Ok(Self { writer, sealer })
+    }
+
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            if let Some(ciphertext) = self.sealer.push(&mut data)? {
+                self.writer.write_all(&ciphertext).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Seal the last segment. Returns the number of plaintext bytes encrypted.
+    pub async fn finish(mut self) -> Result<u64> {
+        let total = self.sealer.total;
+        let ciphertext = self.sealer.finish()?;
+
+        self.writer.write_all(&ciphertext).await?;
+        self.writer.flush().await?;
+
+        Ok(total)
+    }
+}
+
+/// Cuts a dataset in segments and seals them, for the async and the blocking writers.
+struct SegmentSealer {
+    encryptor: EncryptorBE32<Aes256Gcm>,
+    segment: Vec<u8>,
+    total: u64,
+}
+
+impl SegmentSealer {
+    /// Returns the header of a new encrypted dataset, with a fresh data key wrapped by the
+    /// master key, and the sealer of its segments.
+    fn new(master_key: &[u8]) -> Result<(Vec<u8>, Self)> {
+        let data_key: [u8; 32] = rand::random();
+        let key_nonce: [u8; KEY_NONCE_SIZE] = rand::random();
+        let stream_nonce: [u8; STREAM_NONCE_SIZE] = rand::random();
+
+        let wrapped_key = master_cipher(master_key)
+            .encrypt(
+                Nonce::from_slice(&key_nonce),
+                Payload {
+                    msg: &data_key,
+                    aad: MAGIC,
+                },
+            )
+            .map_err(|_| eyre!("Failed to wrap dataset key"))?;
+
+        let header = [MAGIC, &key_nonce, &wrapped_key, &stream_nonce].concat();
+
+        let encryptor = EncryptorBE32::from_aead(
+            Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key)),
+            stream_nonce.as_slice().into(),
+        );
+
+        let sealer = Self {
+            encryptor,
+            segment: Vec::with_capacity(DATASET_ENCRYPTION_SEGMENT_SIZE),
+            total: 0,
+        };
+
+        Ok((header, sealer))
+    }
+
+    /// Take the start of `data` that fits in the current segment.
+    /// Returns the previous segment, sealed, when it was full.
+    fn push(&mut self, data: &mut &[u8]) -> Result<Option<Vec<u8>>> {
+        // A full segment is only sealed once more data follows, the last one is left to `finish`
+        let ciphertext = if self.segment.len() == DATASET_ENCRYPTION_SEGMENT_SIZE {
+            let ciphertext = self
+                .encryptor
+                .encrypt_next(self.segment.as_slice())
+                .map_err(|_| eyre!("Failed to encrypt dataset"))?;
+            self.segment.clear();
+            Some(ciphertext)
+        } else {
+            None
+        };
+
+        let take = (DATASET_ENCRYPTION_SEGMENT_SIZE - self.segment.len()).min(data.len());
+        self.segment.extend_from_slice(&data[..take]);
+        self.total += take as u64;
+        *data = &data[take..];
+
+        Ok(ciphertext)
+    }
+
+    /// Seal the last segment.
+    fn finish(self) -> Result<Vec<u8>> {
+        self.encryptor
+            .encrypt_last(self.segment.as_slice())
+            .map_err(|_| eyre!("Failed to encrypt dataset"))
+    }
+}
+
+/// Blocking reader of the plaintext of a dataset written by `encrypt_dataset_stream`,
+/// decrypted one segment at a time.
+pub struct DatasetReader<R> {
+    reader: R,
+    /// `None` once the last segment was decrypted
+    decryptor: Option<DecryptorBE32<Aes256Gcm>>,
+    segment: Vec<u8>,
+    segment_len: usize,
+    next_segment: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DatasetReader<R> {
+    /// Read the header of the dataset and unwrap its data key with `master_key`.
+    pub fn new(mut reader: R, master_key: &[u8]) -> Result<Self> {
+        let mut header =
+            vec![0u8; MAGIC.len() + KEY_NONCE_SIZE + WRAPPED_KEY_SIZE + STREAM_NONCE_SIZE];
+
+        if read_full(&mut reader, &mut header)? != header.len() || !is_encrypted_dataset(&header) {
+            return Err(eyre!("Dataset is not encrypted or its header is truncated"));
+        }
+
+        let (key_nonce, rest) = header[MAGIC.len()..].split_at(KEY_NONCE_SIZE);
+        let (wrapped_key, stream_nonce) = rest.split_at(WRAPPED_KEY_SIZE);
+
+        let data_key = master_cipher(master_key)
+            .decrypt(
+                Nonce::from_slice(key_nonce),
+                Payload {
+                    msg: wrapped_key,
+                    aad: MAGIC,
+                },
+            )
+            .map_err(|_| eyre!("Failed to unwrap dataset key, wrong DATASET_MASTER_KEY?"))?;
+
+        let decryptor = DecryptorBE32::from_aead(
+            Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key)),
+            stream_nonce.into(),
+        );
+
+        let mut segment = vec![0u8; DATASET_ENCRYPTION_SEGMENT_SIZE + TAG_SIZE];
+        let segment_len = read_full(&mut reader, &mut segment)?;
+
+        Ok(Self {
+            reader,
+            decryptor: Some(decryptor),
+            next_segment: vec![0u8; segment.len()],
+            segment,
+            segment_len,
+            plaintext: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// Decrypt the next segment, false at the end of the dataset.
+    fn decrypt_segment(&mut self) -> Result<bool> {
+        if self.decryptor.is_none() {
+            return Ok(false);
+        }
+
+        // A full segment is only the last one when nothing follows it
+        let next_len = if self.segment_len == self.segment.len() {
+            read_full(&mut self.reader, &mut self.next_segment)?
+        } else {
+            0
+        };
+
+        self.plaintext = if next_len == 0 {
+            let decryptor = self.decryptor.take().expect("checked above");
+
+            decryptor
+                .decrypt_last(&self.segment[..self.segment_len])
+                .map_err(|_| eyre!("Encrypted dataset is corrupted"))?
+        } else {
+            let decryptor = self.decryptor.as_mut().expect("checked above");
+
+            let plaintext = decryptor
+                .decrypt_next(self.segment.as_slice())
+                .map_err(|_| eyre!("Encrypted dataset is corrupted"))?;
+
+            std::mem::swap(&mut self.segment, &mut self.next_segment);
+            self.segment_len = next_len;
+
+            plaintext
+        };
+
+        self.position = 0;
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DatasetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if !self.decrypt_segment().map_err(std::io::Error::other)? {
+                return Ok(0);
+            }
+        }
+
+        let read = (self.plaintext.len() - self.position).min(buf.len());
+
+        buf[..read].copy_from_slice(&self.plaintext[self.position..self.position + read]);
+        self.position += read;
+
+        Ok(read)
+    }
+}
+
+/// An encrypted dataset in a temporary file.
+///
+/// It is only decrypted while one of its readers is read: every pass over the dataset opens its
+/// own reader, the plaintext is never held whole in memory.
+pub struct SealedDataset {
+    file: TempFile,
+    master_key: Vec<u8>,
+}
+
+impl SealedDataset {
+    /// Download the encrypted dataset stored under `key`, its data key is wrapped by `master_key`.
+    pub async fn fetch(store: &dyn DatasetStore, key: &str, master_key: &[u8]) -> Result<Self> {
+        let file = TempFile::new("dataset");
+
+        store.get_file(key, file.path()).await?;
+
+        Ok(Self::open(file, master_key))
+    }
+
+    /// An encrypted dataset already written to `file`.
+    pub fn open(file: TempFile, master_key: &[u8]) -> Self {
+        Self {
+            file,
+            master_key: master_key.to_vec(),
+        }
+    }
+
+    /// Start a new dataset, encrypted as it is written.
+    pub fn create(master_key: &[u8]) -> Result<SealedDatasetWriter> {
+        let file = TempFile::new("dataset");
+
+        let mut writer = BufWriter::new(File::create(file.path())?);
+
+        let (header, sealer) = SegmentSealer::new(master_key)?;
+
+        writer.write_all(&header)?;
+
+        Ok(SealedDatasetWriter {
+            file,
+            writer,
+            sealer,
+            master_key: master_key.to_vec(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    pub fn master_key(&self) -> &[u8] {
+        &self.master_key
+    }
+
+    pub fn reader(&self) -> Result<DatasetReader<File>> {
+        DatasetReader::new(File::open(self.path())?, &self.master_key)
+    }
+
+    pub fn csv_reader(&self) -> Result<csv::Reader<DatasetReader<File>>> {
+        Ok(csv::Reader::from_reader(self.reader()?))
+    }
+}
+
+/// Blocking writer of a new `SealedDataset`, see `SealedDataset::create`.
+pub struct SealedDatasetWriter {
+    file: TempFile,
+    writer: BufWriter<File>,
+    sealer: SegmentSealer,
+    master_key: Vec<u8>,
+}
+
+impl SealedDatasetWriter {
+    /// Seal the last segment.
+    pub fn finish(self) -> Result<SealedDataset> {
+        let Self {
+            file,
+            mut writer,
+            sealer,
+            master_key,
+        } = self;
+
+        writer.write_all(&sealer.finish()?)?;
+        writer.flush()?;
+
+        Ok(SealedDataset { file, master_key })
+    }
+}
+
+impl Write for SealedDatasetWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut data = buf;
+
+        while !data.is_empty() {
+            if let Some(ciphertext) = self.sealer.push(&mut data).map_err(std::io::Error::other)? {
+                self.writer.write_all(&ciphertext)?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Encrypt `data` with a data key wrapped by `master_key` and store it under `key`.
+pub async fn write_encrypted_dataset(
+    store: &dyn DatasetStore,
+    key: &str,
+    data: &[u8],
+    master_key: &[u8],
+) -> Result<()> {
+    let mut ciphertext = Vec::new();
+
+    encrypt_dataset_stream(&mut &data[..], &mut ciphertext, master_key).await?;
+
+    store.put(key, ciphertext).await
+}
+
+/// Encrypt, in place, every plaintext dataset left in the store by older versions.
+/// Returns the number of encrypted datasets.
+pub async fn encrypt_existing_datasets(
+    store: &dyn DatasetStore,
+    master_key: &[u8],
+) -> Result<usize> {
+    let mut encrypted = 0;
+
+    for key in store.list().await? {
+        let data = store.get(&key).await?;
+
+        if is_encrypted_dataset(&data) {
+            tracing::debug!("{} is already encrypted", key);
+            continue;
+        }
+
+        // Stores replace datasets atomically, an interrupted run never loses one
+        write_encrypted_dataset(store, &key, &data, master_key).await?;
+
+        tracing::info!("Encrypted {}", key);
+        encrypted += 1;
+    }
+
+    Ok(encrypted)
+}
+
+fn master_cipher(master_key: &[u8]) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(master_key))
+}
+
+/// Fill `buf` as much as possible, returns less than its length only at the end of the stream.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        let read = reader.read(&mut buf[filled..])?;
+
+        if read == 0 {
+            break;
+        }
+
+        filled += read;
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::storage::fs::FsDatasetStore;
+
+    const HEADER_SIZE: usize = MAGIC.len() + KEY_NONCE_SIZE + WRAPPED_KEY_SIZE + STREAM_NONCE_SIZE;
+    const SEALED_SEGMENT_SIZE: usize = DATASET_ENCRYPTION_SEGMENT_SIZE + TAG_SIZE;
+
+    fn plaintext(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn encrypt(data: &[u8], master_key: &[u8]) -> Vec<u8> {
+        let mut ciphertext = Vec::new();
+
+        let total = encrypt_dataset_stream(&mut &data[..], &mut ciphertext, master_key)
+            .await
+            .unwrap();
+        assert_eq!(total, data.len() as u64);
+
+        ciphertext
+    }
+
+    fn decrypt(ciphertext: &[u8], master_key: &[u8]) -> Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+
+        DatasetReader::new(Cursor::new(ciphertext), master_key)?.read_to_end(&mut plaintext)?;
+
+        Ok(plaintext)
+    }
+
+    #[tokio::test]
+    async fn round_trip_across_segments() {
+        let master_key = [7u8; 32];
+
+        for len in [
+            0,
+            1,
+            DATASET_ENCRYPTION_SEGMENT_SIZE,
+            3 * DATASET_ENCRYPTION_SEGMENT_SIZE,
+            2 * DATASET_ENCRYPTION_SEGMENT_SIZE + 1234,
+        ] {
+            let data = plaintext(len);
+            let ciphertext = encrypt(&data, &master_key).await;
+
+            assert!(is_encrypted_dataset(&ciphertext));
+            assert_eq!(decrypt(&ciphertext, &master_key).unwrap(), data, "{}", len);
+        }
+    }
+
+    #[test]
+    fn sealed_dataset_writer_round_trip() {
+        let master_key = [7u8; 32];
+        let data = plaintext(2 * DATASET_ENCRYPTION_SEGMENT_SIZE + 99);
+
+        let mut writer = SealedDataset::create(&master_key).unwrap();
+
+        // Writes smaller than a segment and across segment boundaries
+        for chunk in data.chunks(10_000) {
+            writer.write_all(chunk).unwrap();
+        }
+
+        let dataset = writer.finish().unwrap();
+
+        let mut decrypted = Vec::new();
+        dataset
+            .reader()
+            .unwrap()
+            .read_to_end(&mut decrypted)
+            .unwrap();
+
+        assert_eq!(decrypted, data);
+    }
+
+    #[tokio::test]
+    async fn truncated_dataset_is_rejected() {
+        let master_key = [7u8; 32];
+        let ciphertext = encrypt(
+            &plaintext(2 * DATASET_ENCRYPTION_SEGMENT_SIZE + 10),
+            &master_key,
+        )
+        .await;
+
+        // Whole segments dropped, inside a segment and inside the header
+        for len in [
+            HEADER_SIZE + SEALED_SEGMENT_SIZE,
+            HEADER_SIZE + 2 * SEALED_SEGMENT_SIZE,
+            ciphertext.len() - 1,
+            HEADER_SIZE + 100,
+            HEADER_SIZE - 1,
+        ] {
+            assert!(decrypt(&ciphertext[..len], &master_key).is_err(), "{}", len);
+        }
+    }
+
+    #[tokio::test]
+    async fn tampered_dataset_is_rejected() {
+        let master_key = [7u8; 32];
+        let ciphertext = encrypt(
+            &plaintext(2 * DATASET_ENCRYPTION_SEGMENT_SIZE + 10),
+            &master_key,
+        )
+        .await;
+
+        // A byte of the first segment, of the tag of the last one and of the wrapped key
+        for position in [
+            HEADER_SIZE + 5,
+            ciphertext.len() - 1,
+            MAGIC.len() + KEY_NONCE_SIZE,
+        ] {
+            let mut tampered = ciphertext.clone();
+            tampered[position] ^= 1;
+
+            assert!(decrypt(&tampered, &master_key).is_err(), "{}", position);
+        }
+
+        // Segments swapped
+        let mut swapped = ciphertext.clone();
+        let (first, second) = swapped[HEADER_SIZE..].split_at_mut(SEALED_SEGMENT_SIZE);
+        first.swap_with_slice(&mut second[..SEALED_SEGMENT_SIZE]);
+
+        assert!(decrypt(&swapped, &master_key).is_err());
+    }
+
+    #[tokio::test]
+    async fn wrong_master_key_is_rejected() {
+        let ciphertext = encrypt(&plaintext(100), &[7u8; 32]).await;
+
+        assert!(DatasetReader::new(Cursor::new(&ciphertext), &[8u8; 32]).is_err());
+    }
+
+    #[tokio::test]
+    async fn encrypt_existing_datasets_is_idempotent() {
+        let master_key = [7u8; 32];
+        let root = std::env::temp_dir().join(format!("enclava-store-{}", uuid::Uuid::new_v4()));
+        let store = FsDatasetStore::new(&root);
+
+        let first = plaintext(100);
+        let second = plaintext(DATASET_ENCRYPTION_SEGMENT_SIZE + 1);
+
+        store.put("first.csv", first.clone()).await.unwrap();
+        write_encrypted_dataset(&store, "second.csv", &second, &master_key)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            encrypt_existing_datasets(&store, &master_key)
+                .await
+                .unwrap(),
+            1
+        );
+
+        let encrypted = store.get("first.csv").await.unwrap();
+        let already_encrypted = store.get("second.csv").await.unwrap();
+
+        assert_eq!(
+            encrypt_existing_datasets(&store, &master_key)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // Encrypted files are left untouched, not encrypted twice
+        assert_eq!(store.get("first.csv").await.unwrap(), encrypted);
+        assert_eq!(store.get("second.csv").await.unwrap(), already_encrypted);
+
+        assert_eq!(decrypt(&encrypted, &master_key).unwrap(), first);
+        assert_eq!(decrypt(&already_encrypted, &master_key).unwrap(), second);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
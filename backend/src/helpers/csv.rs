@@ -1,33 +1,232 @@
-use color_eyre::Result;
-
-pub fn validate_and_count_csv(data: &[u8]) -> Result<usize> {
-    let content = String::from_utf8(data.to_vec())?;
-
-    let mut reader = csv::Reader::from_reader(content.as_bytes());
-    let mut row_count = 0;
-
-    // Validate headers exist
-    let _headers = reader.headers()?;
-
-    // Count and validate rows
-    for result in reader.records() {
-        match result {
-            Ok(_) => row_count += 1,
-            Err(e) => {
-                return Err(color_eyre::eyre::eyre!(
-                    "Invalid CSV row at {}: {}",
-                    row_count + 1,
-                    e
+use color_eyre::{Result, eyre::eyre};
+use csv_core::{ReadFieldResult, Reader};
+
+/// Validates a CSV file chunk by chunk while it is uploaded, without keeping it in memory.
+///
+/// Checks what `csv::Reader` would: the data is UTF-8 and every record has as many fields
+/// as the header. Optionally keeps the first rows as a sample of the dataset.
+pub struct CsvStreamValidator {
+    reader: Reader,
+    // Field contents are not needed, they are written over in this buffer
+    field_buffer: Vec<u8>,
+    header_fields: Option<usize>,
+    record_fields: usize,
+    row_count: usize,
+    utf8_tail: Vec<u8>,
+    sample_rows: usize,
+    sample: Vec<u8>,
+}
+
+impl CsvStreamValidator {
+    /// `sample_rows` is the number of rows, after the header, kept by `into_sample`.
+    pub fn new(sample_rows: usize) -> Self {
+        Self {
+            reader: Reader::new(),
+            field_buffer: vec![0u8; 4096],
+            header_fields: None,
+            record_fields: 0,
+            row_count: 0,
+            utf8_tail: Vec::new(),
+            sample_rows,
+            sample: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) -> Result<()> {
+        self.check_utf8(chunk)?;
+
+        let sampling = self.is_sampling();
+        let mut sample_end = None;
+        let mut consumed = 0;
+
+        while consumed < chunk.len() {
+            let (result, read, _) = self
+                .reader
+                .read_field(&chunk[consumed..], &mut self.field_buffer);
+            consumed += read;
+
+            match result {
+                ReadFieldResult::Field { record_end } => {
+                    self.end_field(record_end)?;
+
+                    // The sample stops at the end of its last record
+                    if sampling && sample_end.is_none() && !self.is_sampling() {
+                        sample_end = Some(consumed);
+                    }
+                }
+                ReadFieldResult::InputEmpty | ReadFieldResult::OutputFull => {}
+                ReadFieldResult::End => break,
+            }
+        }
+
+        if sampling {
+            self.sample
+                .extend_from_slice(&chunk[..sample_end.unwrap_or(chunk.len())]);
+        }
+
+        Ok(())
+    }
+
+    /// Validate the end of the data. Returns the number of rows, header excluded.
+    pub fn finish(&mut self) -> Result<usize> {
+        if !self.utf8_tail.is_empty() {
+            return Err(eyre!("Invalid UTF-8 data at the end of the file"));
+        }
+
+        // Flush the last record when the file does not end with a new line
+        loop {
+            match self.reader.read_field(&[], &mut self.field_buffer).0 {
+                ReadFieldResult::Field { record_end } => self.end_field(record_end)?,
+                ReadFieldResult::End => break,
+                ReadFieldResult::InputEmpty | ReadFieldResult::OutputFull => {}
+            }
+        }
+
+        Ok(self.row_count)
+    }
+
+    /// The header and first rows of the file, or all of it when it is shorter.
+    pub fn into_sample(self) -> String {
+        String::from_utf8_lossy(&self.sample).into_owned()
+    }
+
+    fn is_sampling(&self) -> bool {
+        self.sample_rows > 0 && (self.header_fields.is_none() || self.row_count < self.sample_rows)
+    }
+
+    fn end_field(&mut self, record_end: bool) -> Result<()> {
+        self.record_fields += 1;
+
+        if record_end {
+            self.end_record()?;
+        }
+
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<()> {
+        let fields = std::mem::take(&mut self.record_fields);
+
+        match self.header_fields {
+            None => self.header_fields = Some(fields),
+            Some(expected) if expected != fields => {
+                return Err(eyre!(
+                    "Invalid CSV row at {}: found record with {} fields, but the header has {} fields",
+                    self.row_count + 1,
+                    fields,
+                    expected
                 ));
             }
+            Some(_) => self.row_count += 1,
         }
+
+        Ok(())
     }
 
-    Ok(row_count)
+    fn check_utf8(&mut self, chunk: &[u8]) -> Result<()> {
+        // Only copy when a character was split over the previous chunk
+        let joined;
+        let data = if self.utf8_tail.is_empty() {
+            chunk
+        } else {
+            joined = [std::mem::take(&mut self.utf8_tail).as_slice(), chunk].concat();
+            joined.as_slice()
+        };
+
+        match std::str::from_utf8(data) {
+            Ok(_) => Ok(()),
+            // Incomplete character at the end, the next chunk completes it
+            Err(e) if e.error_len().is_none() => {
+                self.utf8_tail = data[e.valid_up_to()..].to_vec();
+                Ok(())
+            }
+            Err(e) => Err(eyre!("Invalid UTF-8 data: {}", e)),
+        }
+    }
 }
 
-pub async fn csv_bytes_to_string(data: &[u8]) -> Result<String> {
-    let content = String::from_utf8(data.to_vec())?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Row count and sample of `chunks` validated one after the other.
+    fn validate(chunks: &[&[u8]], sample_rows: usize) -> Result<(usize, String)> {
+        let mut validator = CsvStreamValidator::new(sample_rows);
+
+        for chunk in chunks {
+            validator.update(chunk)?;
+        }
+
+        let rows = validator.finish()?;
+
+        Ok((rows, validator.into_sample()))
+    }
+
+    #[test]
+    fn multi_byte_character_split_across_chunks() {
+        let data = "name,city\nRené,Zürich\n".as_bytes();
+        // Inside the two bytes of "é"
+        let split = "name,city\nRen".len() + 1;
+
+        let (rows, sample) = validate(&[&data[..split], &data[split..]], 10).unwrap();
+
+        assert_eq!(rows, 1);
+        assert_eq!(sample, "name,city\nRené,Zürich\n");
+    }
+
+    #[test]
+    fn quoted_new_line_split_across_chunks() {
+        let data = b"id,note\n1,\"first\nsecond\"\n2,plain\n";
+        let split = "id,note\n1,\"first\n".len();
+
+        let (rows, sample) = validate(&[&data[..split], &data[split..]], 10).unwrap();
 
-    Ok(content)
+        assert_eq!(rows, 2);
+        assert_eq!(sample.as_bytes(), data);
+    }
+
+    #[test]
+    fn last_record_without_new_line() {
+        let (rows, _) = validate(&[b"a,b\n1,2\n3,4"], 0).unwrap();
+
+        assert_eq!(rows, 2);
+
+        assert!(validate(&[b"a,b\n1,2\n3"], 0).is_err());
+    }
+
+    #[test]
+    fn field_count_mismatch() {
+        assert!(validate(&[b"a,b\n1,2\n3,4,5\n"], 0).is_err());
+        assert!(validate(&[b"a,b\n1,2\n", b"3\n"], 0).is_err());
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        // Incomplete character at the end of the file
+        assert!(validate(&[b"a,b\n1,\xc3"], 0).is_err());
+        // Invalid byte in the middle
+        assert!(validate(&[b"a,b\n1,\xff\n"], 0).is_err());
+        // Incomplete character completed by an invalid byte
+        assert!(validate(&[b"a,b\n1,\xc3", b"x\n"], 0).is_err());
+    }
+
+    #[test]
+    fn sample_ends_at_a_record_boundary() {
+        let data = b"h1,h2\n1,a\n2,b\n3,c\n4,d\n";
+
+        let (rows, sample) = validate(&[data], 2).unwrap();
+
+        assert_eq!(rows, 4);
+        assert_eq!(sample, "h1,h2\n1,a\n2,b\n");
+
+        // Same sample when the records are cut across chunks
+        let chunks: Vec<&[u8]> = data.chunks(3).collect();
+        let (rows, sample) = validate(&chunks, 2).unwrap();
+
+        assert_eq!(rows, 4);
+        assert_eq!(sample, "h1,h2\n1,a\n2,b\n");
+
+        // No sample kept
+        assert_eq!(validate(&[data], 0).unwrap().1, "");
+    }
 }
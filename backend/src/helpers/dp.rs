@@ -0,0 +1,615 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use color_eyre::Result;
+use rig::{completion::ToolDefinition, tool::Tool};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::{Row, sqlite::SqliteRow};
+
+use crate::{
+    config::{DATASET_QUERY_TIMEOUT_SECS, DATASET_TABLE_NAME, DP_MAX_AGGREGATES_PER_ANSWER},
+    helpers::sql::{ColumnType, DatasetTable, QueryTimedOut, quote_identifier, run_bounded_query},
+    types::{DatasetProfile, DpColumnBounds, InferredColumnType},
+};
+
+/// Filters only compare a row to a constant, they can not make rows depend on each other.
+const FILTER_OPERATORS: [&str; 6] = ["=", "!=", "<", "<=", ">", ">="];
+
+/// Bounds of the owner that do not fit the uploaded dataset.
+#[derive(Debug, thiserror::Error)]
+pub enum DpBoundsError {
+    #[error("dp_bounds references unknown column {0}")]
+    UnknownColumn(String),
+    #[error("dp_bounds column {0} is not numeric")]
+    NotNumeric(String),
+}
+
+/// Check that the owner `bounds` name numeric columns of the dataset.
+pub fn check_bounds(
+    bounds: &HashMap<String, DpColumnBounds>,
+    profile: &DatasetProfile,
+) -> Result<(), DpBoundsError> {
+    for name in bounds.keys() {
+        match profile.columns.iter().find(|column| column.name == *name) {
+            None => return Err(DpBoundsError::UnknownColumn(name.clone())),
+            Some(column)
+                if !matches!(
+                    column.inferred_type,
+                    InferredColumnType::Integer | InferredColumnType::Real
+                ) =>
+            {
+                return Err(DpBoundsError::NotNumeric(name.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DpAggregateError {
+    #[error("Invalid aggregate: {0}")]
+    InvalidAggregate(String),
+    #[error(
+        "The {0} aggregates allowed for this answer are spent, answer with the results you have"
+    )]
+    AllowanceSpent(usize),
+    #[error("Aggregate failed: {0}")]
+    QueryFailed(#[from] sqlx::Error),
+    #[error("Aggregate task failed: {0}")]
+    TaskFailed(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    TimedOut(#[from] QueryTimedOut),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DpFunction {
+    Count,
+    Sum,
+    Avg,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DpFilter {
+    pub column: String,
+    /// One of `FILTER_OPERATORS`
+    pub op: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DpAggregateArgs {
+    pub function: DpFunction,
+    /// Numeric column of sum and avg, ignored by count
+    pub column: Option<String>,
+    #[serde(default)]
+    pub filters: Vec<DpFilter>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DpAggregateOutput {
+    /// Noisy estimate of the aggregate
+    pub value: f64,
+    /// Privacy cost of this aggregate
+    pub epsilon: f64,
+}
+
+/// Differentially private aggregates over an agent dataset (Laplace mechanism).
+///
+/// Filters only compare a row to constants, so adding or removing one record changes a count by
+/// at most 1 and a sum by at most the largest absolute value of its clamping bounds.
+/// An answer makes at most `DP_MAX_AGGREGATES_PER_ANSWER` calls, each spending an equal share
+/// of the answer epsilon.
+#[derive(Debug, Clone)]
+pub struct DpAggregateTool {
+    table: DatasetTable,
+    /// Epsilon spent by each call
+    epsilon: f64,
+    /// Clamping bounds chosen by the owner, only these columns can be summed or averaged
+    bounds: HashMap<String, DpColumnBounds>,
+    /// Calls made for the current answer
+    calls: Arc<AtomicUsize>,
+}
+
+impl DpAggregateTool {
+    /// Only the numeric columns the owner gave `bounds` for can be summed or averaged: bounds
+    /// taken from the data itself would make the noise depend on the records it protects.
+    pub fn new(
+        table: DatasetTable,
+        answer_epsilon: f64,
+        bounds: &HashMap<String, DpColumnBounds>,
+    ) -> Self {
+        let bounds = table
+            .columns
+            .iter()
+            .filter(|(_, column_type)| *column_type != ColumnType::Text)
+            .filter_map(|(name, _)| bounds.get(name).map(|bounds| (name.clone(), *bounds)))
+            .collect();
+
+        Self {
+            table,
+            epsilon: answer_epsilon / DP_MAX_AGGREGATES_PER_ANSWER as f64,
+            bounds,
+            calls: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Copy of the tool with the whole allowance of a new answer.
+    pub fn for_answer(&self) -> Self {
+        Self {
+            calls: Arc::new(AtomicUsize::new(0)),
+            ..self.clone()
+        }
+    }
+
+    fn numeric_column<'a>(&self, column: Option<&'a str>) -> Result<&'a str, DpAggregateError> {
+        match column {
+            Some(column) if self.bounds.contains_key(column) => Ok(column),
+            Some(column) => Err(DpAggregateError::InvalidAggregate(format!(
+                "{} has no bounds set by the dataset owner, it can only be counted",
+                column
+            ))),
+            None => Err(DpAggregateError::InvalidAggregate(
+                "sum and avg need a column".to_string(),
+            )),
+        }
+    }
+
+    /// Noisy number of matching rows, or of their non null `column` values.
+    async fn count(
+        &self,
+        column: Option<&str>,
+        filters: &[DpFilter],
+        epsilon: f64,
+    ) -> Result<f64, DpAggregateError> {
+        let counted = column
+            .map(quote_identifier)
+            .unwrap_or_else(|| "*".to_string());
+
+        let count: i64 = self
+            .aggregate(&format!("COUNT({})", counted), filters)
+            .await?
+            .try_get(0)?;
+
+        Ok(count as f64 + laplace_noise(1.0 / epsilon))
+    }
+
+    /// Noisy sum of `column` with every value clamped to the column bounds.
+    async fn sum(
+        &self,
+        column: &str,
+        filters: &[DpFilter],
+        epsilon: f64,
+    ) -> Result<f64, DpAggregateError> {
+        let DpColumnBounds { min, max } = self.bounds[column];
+
+        let clamped_sum = format!(
+            "CAST(COALESCE(SUM(MIN(MAX({}, {}), {})), 0) AS REAL)",
+            quote_identifier(column),
+            min,
+            max
+        );
+
+        let sum: f64 = self.aggregate(&clamped_sum, filters).await?.try_get(0)?;
+
+        let sensitivity = min.abs().max(max.abs()).max(f64::MIN_POSITIVE);
+
+        Ok(sum + laplace_noise(sensitivity / epsilon))
+    }
+
+    /// Run `SELECT <aggregate>` over the rows matching `filters`, values are bound parameters.
+    async fn aggregate(
+        &self,
+        aggregate: &str,
+        filters: &[DpFilter],
+    ) -> Result<SqliteRow, DpAggregateError> {
+        let mut conditions = Vec::new();
+
+        for filter in filters {
+            if !self
+                .table
+                .columns
+                .iter()
+                .any(|(name, _)| *name == filter.column)
+            {
+                return Err(DpAggregateError::InvalidAggregate(format!(
+                    "unknown column {}",
+                    filter.column
+                )));
+            }
+
+            if !FILTER_OPERATORS.contains(&filter.op.as_str()) {
+                return Err(DpAggregateError::InvalidAggregate(format!(
+                    "unsupported operator {}",
+                    filter.op
+                )));
+            }
+
+            if !matches!(
+                filter.value,
+                Value::Number(_) | Value::String(_) | Value::Bool(_)
+            ) {
+                return Err(DpAggregateError::InvalidAggregate(
+                    "filter values must be numbers, strings or booleans".to_string(),
+                ));
+            }
+
+            conditions.push(format!(
+                "{} {} ?",
+                quote_identifier(&filter.column),
+                filter.op
+            ));
+        }
+
+        let mut sql = format!("SELECT {} FROM {}", aggregate, DATASET_TABLE_NAME);
+
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+
+        let values: Vec<Value> = filters.iter().map(|filter| filter.value.clone()).collect();
+
+        run_bounded_query(
+            &self.table.pool,
+            Duration::from_secs(DATASET_QUERY_TIMEOUT_SECS),
+            move |mut conn| async move {
+                let mut query = sqlx::query(&sql);
+
+                for value in &values {
+                    query = match value {
+                        Value::Number(number) => query.bind(number.as_f64()),
+                        Value::Bool(flag) => query.bind(*flag as i64),
+                        value => query.bind(value.as_str().unwrap_or_default().to_string()),
+                    };
+                }
+
+                query.fetch_one(&mut *conn).await
+            },
+        )
+        .await
+    }
+}
+
+impl Tool for DpAggregateTool {
+    const NAME: &'static str = "dp_aggregate";
+
+    type Error = DpAggregateError;
+    type Args = DpAggregateArgs;
+    type Output = DpAggregateOutput;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        let numeric_columns: Vec<&String> = self
+            .table
+            .columns
+            .iter()
+            .map(|(name, _)| name)
+            .filter(|name| self.bounds.contains_key(*name))
+            .collect();
+
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: format!(
+                "Compute a count, sum or average over the rows of the dataset matching optional filters. Results are noisy estimates that protect individual records, report them as approximate. At most {} calls are allowed per answer. The table schema is: {}. Columns that can be summed or averaged: {:?}.",
+                DP_MAX_AGGREGATES_PER_ANSWER,
+                self.table.schema(),
+                numeric_columns
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "function": {
+                        "type": "string",
+                        "enum": ["count", "sum", "avg"]
+                    },
+                    "column": {
+                        "type": "string",
+                        "description": "Numeric column to sum or average"
+                    },
+                    "filters": {
+                        "type": "array",
+                        "description": "Conditions every counted row must match",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "column": { "type": "string" },
+                                "op": { "type": "string", "enum": FILTER_OPERATORS },
+                                "value": { "type": ["number", "string", "boolean"] }
+                            },
+                            "required": ["column", "op", "value"]
+                        }
+                    }
+                },
+                "required": ["function"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        if self.calls.fetch_add(1, Ordering::SeqCst) >= DP_MAX_AGGREGATES_PER_ANSWER {
+            return Err(DpAggregateError::AllowanceSpent(
+                DP_MAX_AGGREGATES_PER_ANSWER,
+            ));
+        }
+
+        let value = match args.function {
+            DpFunction::Count => {
+                let count = self.count(None, &args.filters, self.epsilon).await?;
+
+                count.round().max(0.0)
+            }
+            DpFunction::Sum => {
+                let column = self.numeric_column(args.column.as_deref())?;
+
+                self.sum(column, &args.filters, self.epsilon).await?
+            }
+            DpFunction::Avg => {
+                let column = self.numeric_column(args.column.as_deref())?;
+                let DpColumnBounds { min, max } = self.bounds[column];
+
+                // Half of the budget for each of the sum and the count
+                let sum = self.sum(column, &args.filters, self.epsilon / 2.0).await?;
+                let count = self
+                    .count(Some(column), &args.filters, self.epsilon / 2.0)
+                    .await?;
+
+                (sum / count.max(1.0)).clamp(min, max)
+            }
+        };
+
+        tracing::debug!(
+            "DP aggregate {:?} spent epsilon {}",
+            args.function,
+            self.epsilon
+        );
+
+        Ok(DpAggregateOutput {
+            value,
+            epsilon: self.epsilon,
+        })
+    }
+}
+
+/// Sample of a Laplace(0, scale) distribution.
+fn laplace_noise(scale: f64) -> f64 {
+    // Uniform in (-0.5, 0.5), -0.5 would give an infinite sample
+    let u = loop {
+        let u = rand::random::<f64>() - 0.5;
+
+        if u.abs() < 0.5 {
+            break u;
+        }
+    };
+
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+    use super::*;
+    use crate::types::ColumnProfile;
+
+    /// Table of (age INTEGER, city TEXT) rows, ages can be summed between 0 and 10.
+    async fn tool(rows: &[(i64, &str)]) -> DpAggregateTool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(SqliteConnectOptions::from_str("sqlite::memory:").unwrap())
+            .await
+            .unwrap();
+
+        let columns = vec![
+            ("age".to_string(), ColumnType::Integer),
+            ("city".to_string(), ColumnType::Text),
+        ];
+        let table = DatasetTable { pool, columns };
+
+        sqlx::query(&table.schema())
+            .execute(&table.pool)
+            .await
+            .unwrap();
+
+        for (age, city) in rows {
+            sqlx::query(&format!("INSERT INTO {} VALUES (?, ?)", DATASET_TABLE_NAME))
+                .bind(age)
+                .bind(city)
+                .execute(&table.pool)
+                .await
+                .unwrap();
+        }
+
+        let bounds = HashMap::from([
+            (
+                "age".to_string(),
+                DpColumnBounds {
+                    min: 0.0,
+                    max: 10.0,
+                },
+            ),
+            ("city".to_string(), DpColumnBounds { min: 0.0, max: 1.0 }),
+        ]);
+
+        DpAggregateTool::new(table, 1.0, &bounds)
+    }
+
+    fn filter(column: &str, op: &str, value: Value) -> DpFilter {
+        DpFilter {
+            column: column.to_string(),
+            op: op.to_string(),
+            value,
+        }
+    }
+
+    fn profile(columns: &[(&str, InferredColumnType)]) -> DatasetProfile {
+        DatasetProfile {
+            row_count: 0,
+            columns: columns
+                .iter()
+                .map(|(name, inferred_type)| ColumnProfile {
+                    name: name.to_string(),
+                    inferred_type: *inferred_type,
+                    null_count: 0,
+                    null_ratio: 0.0,
+                    distinct_count: 0,
+                    distinct_capped: false,
+                    min: None,
+                    max: None,
+                    sample_values: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn check_bounds_only_accepts_numeric_columns() {
+        let profile = profile(&[
+            ("age", InferredColumnType::Integer),
+            ("score", InferredColumnType::Real),
+            ("city", InferredColumnType::Text),
+        ]);
+        let bounds =
+            |name: &str| HashMap::from([(name.to_string(), DpColumnBounds { min: 0.0, max: 1.0 })]);
+
+        assert!(check_bounds(&bounds("age"), &profile).is_ok());
+        assert!(check_bounds(&bounds("score"), &profile).is_ok());
+        assert!(check_bounds(&HashMap::new(), &profile).is_ok());
+        assert!(matches!(
+            check_bounds(&bounds("city"), &profile),
+            Err(DpBoundsError::NotNumeric(_))
+        ));
+        assert!(matches!(
+            check_bounds(&bounds("missing"), &profile),
+            Err(DpBoundsError::UnknownColumn(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn text_columns_never_get_bounds() {
+        let tool = tool(&[]).await;
+
+        assert!(tool.numeric_column(Some("age")).is_ok());
+        assert!(tool.numeric_column(Some("city")).is_err());
+        assert!(tool.numeric_column(None).is_err());
+    }
+
+    #[tokio::test]
+    async fn aggregate_rejects_invalid_filters() {
+        let tool = tool(&[(30, "Paris")]).await;
+
+        for filters in [
+            vec![filter("missing", "=", json!(1))],
+            vec![filter("age", "LIKE", json!("3%"))],
+            vec![filter("age", "= 1 OR 1 =", json!(1))],
+            vec![filter("age", "=", Value::Null)],
+            vec![filter("age", "IN", json!([1, 2]))],
+            vec![filter("city", "=", json!({ "name": "Paris" }))],
+            vec![
+                filter("city", "=", json!("Paris")),
+                filter("age", "=", json!([30])),
+            ],
+        ] {
+            assert!(matches!(
+                tool.aggregate("COUNT(*)", &filters).await,
+                Err(DpAggregateError::InvalidAggregate(_))
+            ));
+        }
+
+        // Values are bound, never part of the query
+        let count: i64 = tool
+            .aggregate(
+                "COUNT(*)",
+                &[filter("city", "=", json!("Paris' OR '1' = '1"))],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn sum_clamps_values_to_the_bounds() {
+        let tool = tool(&[(5, "Paris"), (50, "Paris"), (-20, "Lyon"), (7, "Lyon")]).await;
+
+        // An epsilon that large makes the noise negligible
+        let sum = tool.sum("age", &[], 1e12).await.unwrap();
+        assert!((sum - 22.0).abs() < 1e-3, "{}", sum);
+
+        let sum = tool
+            .sum("age", &[filter("city", "=", json!("Paris"))], 1e12)
+            .await
+            .unwrap();
+        assert!((sum - 15.0).abs() < 1e-3, "{}", sum);
+    }
+
+    #[tokio::test]
+    async fn allowance_is_spent_per_answer() {
+        let tool = tool(&[(5, "Paris")]).await;
+
+        let count = || DpAggregateArgs {
+            function: DpFunction::Count,
+            column: None,
+            filters: Vec::new(),
+        };
+
+        let answer = tool.for_answer();
+
+        for _ in 0..DP_MAX_AGGREGATES_PER_ANSWER {
+            assert!(answer.call(count()).await.is_ok());
+        }
+
+        assert!(matches!(
+            answer.call(count()).await,
+            Err(DpAggregateError::AllowanceSpent(_))
+        ));
+
+        // Copies of the spent tool share its calls, a new answer starts over
+        assert!(answer.clone().call(count()).await.is_err());
+        assert!(answer.for_answer().call(count()).await.is_ok());
+        assert!(tool.for_answer().call(count()).await.is_ok());
+
+        // Each call spends its share of the answer epsilon
+        let output = tool.for_answer().call(count()).await.unwrap();
+        assert_eq!(output.epsilon, 1.0 / DP_MAX_AGGREGATES_PER_ANSWER as f64);
+    }
+
+    #[test]
+    fn laplace_noise_follows_the_distribution() {
+        let scale = 2.0;
+        let samples: Vec<f64> = (0..50_000).map(|_| laplace_noise(scale)).collect();
+        let n = samples.len() as f64;
+
+        assert!(samples.iter().all(|sample| sample.is_finite()));
+
+        // Centered, E|X| = scale and P(|X| > t) = exp(-t / scale)
+        let mean = samples.iter().sum::<f64>() / n;
+        let mean_abs = samples.iter().map(|sample| sample.abs()).sum::<f64>() / n;
+        let tail = samples
+            .iter()
+            .filter(|sample| sample.abs() > 3.0 * scale)
+            .count() as f64
+            / n;
+        let positive = samples.iter().filter(|sample| **sample > 0.0).count() as f64 / n;
+
+        assert!(mean.abs() < 0.1, "mean {}", mean);
+        assert!(
+            (mean_abs - scale).abs() < 0.1,
+            "mean absolute value {}",
+            mean_abs
+        );
+        assert!((tail - (-3.0f64).exp()).abs() < 0.01, "tail {}", tail);
+        assert!((positive - 0.5).abs() < 0.02, "positive share {}", positive);
+    }
+}
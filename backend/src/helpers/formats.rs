@@ -0,0 +1,136 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use calamine::{Reader, Xlsx, open_workbook};
+use color_eyre::{Result, eyre::eyre};
+use parquet::{
+    file::reader::{FileReader, SerializedFileReader},
+    record::Field,
+};
+use serde_json::Value;
+
+use crate::types::DatasetFormat;
+
+/// Convert the dataset file at `path` to CSV, the representation agents consume.
+/// Rows are written as they are read, except for XLSX where calamine loads the whole sheet.
+pub fn normalize_to_csv<W: Write>(format: DatasetFormat, path: &Path, output: W) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(output);
+
+    match format {
+        DatasetFormat::Csv => delimited_to_csv(path, b',', &mut writer)?,
+        DatasetFormat::Tsv => delimited_to_csv(path, b'\t', &mut writer)?,
+        DatasetFormat::JsonLines => json_lines_to_csv(path, &mut writer)?,
+        DatasetFormat::Parquet => parquet_to_csv(path, &mut writer)?,
+        DatasetFormat::Xlsx => xlsx_to_csv(path, &mut writer)?,
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn delimited_to_csv<W: Write>(
+    path: &Path,
+    delimiter: u8,
+    writer: &mut csv::Writer<W>,
+) -> Result<()> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_path(path)?;
+
+    writer.write_record(reader.byte_headers()?)?;
+
+    for record in reader.byte_records() {
+        writer.write_record(&record?)?;
+    }
+
+    Ok(())
+}
+
+/// One JSON object per line, the keys of the first object are the columns.
+fn json_lines_to_csv<W: Write>(path: &Path, writer: &mut csv::Writer<W>) -> Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut columns: Option<Vec<String>> = None;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let object: serde_json::Map<String, Value> = serde_json::from_str(&line)
+            .map_err(|e| eyre!("Invalid JSON object at line {}: {}", index + 1, e))?;
+
+        let header_written = columns.is_some();
+        let columns = columns.get_or_insert_with(|| object.keys().cloned().collect());
+
+        if !header_written {
+            writer.write_record(columns.iter())?;
+        }
+
+        if let Some(key) = object.keys().find(|key| !columns.contains(key)) {
+            return Err(eyre!(
+                "Field {} at line {} is not in the first line",
+                key,
+                index + 1
+            ));
+        }
+
+        writer.write_record(columns.iter().map(|column| json_cell(object.get(column))))?;
+    }
+
+    Ok(())
+}
+
+fn json_cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(value)) => value.clone(),
+        // Numbers, booleans and nested values keep their JSON text
+        Some(value) => value.to_string(),
+    }
+}
+
+fn parquet_to_csv<W: Write>(path: &Path, writer: &mut csv::Writer<W>) -> Result<()> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+
+    let columns = reader.metadata().file_metadata().schema().get_fields();
+
+    writer.write_record(columns.iter().map(|column| column.name()))?;
+
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+
+        writer.write_record(row.get_column_iter().map(|(_, field)| parquet_cell(field)))?;
+    }
+
+    Ok(())
+}
+
+fn parquet_cell(field: &Field) -> String {
+    match field {
+        Field::Null => String::new(),
+        // The Display impl quotes strings
+        Field::Str(value) => value.clone(),
+        field => field.to_string(),
+    }
+}
+
+/// The first sheet is the dataset, its first row the header.
+fn xlsx_to_csv<W: Write>(path: &Path, writer: &mut csv::Writer<W>) -> Result<()> {
+    let mut workbook: Xlsx<_> = open_workbook(path)?;
+
+    let range = workbook
+        .worksheet_range_at(0)
+        .ok_or_else(|| eyre!("The workbook has no sheet"))??;
+
+    for row in range.rows() {
+        writer.write_record(row.iter().map(|cell| cell.to_string()))?;
+    }
+
+    Ok(())
+}
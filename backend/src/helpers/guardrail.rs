@@ -0,0 +1,552 @@
+use std::{
+    collections::{BTreeSet, HashSet, VecDeque},
+    io::{BufRead, BufReader},
+    sync::Arc,
+};
+
+use alloy::primitives::keccak256;
+use color_eyre::Result;
+use dashmap::DashMap;
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    config::{
+        GUARDRAIL_FINGERPRINT_SAMPLING, GUARDRAIL_NGRAM_SIZE, GuardrailAction, GuardrailPolicy,
+    },
+    helpers::{crypto::SealedDataset, pii},
+    types::{GuardrailVerdict, PiiAction, PiiReport, PiiType},
+};
+
+/// Answer sent instead of one the guardrail blocked.
+pub const BLOCKED_ANSWER: &str =
+    "This answer was withheld because it reproduced too much of the dataset.";
+
+const REDACTED_LINES: &str = "[redacted: dataset content]";
+
+/// HKDF label of the key the dataset fingerprints are hashed with.
+const FINGERPRINT_KEY_LABEL: &[u8] = b"enclava guardrail fingerprint";
+
+/// Dataset fingerprints of the agents running in an executor, their answers are checked against
+/// them. Only the executor holding the datasets can hash answers the way their rows were hashed.
+pub struct GuardrailIndex {
+    fingerprint_key: [u8; 32],
+    policy: GuardrailPolicy,
+    agents: DashMap<i64, Arc<AgentGuard>>,
+}
+
+struct AgentGuard {
+    fingerprint_key: [u8; 32],
+    policy: GuardrailPolicy,
+    fingerprints: HashSet<i64>,
+    /// PII the owner chose to keep in the dataset, answers may contain it
+    kept_pii: HashSet<PiiType>,
+}
+
+/// An answer after the guardrail, with what was found in it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardrailOutcome {
+    pub response: String,
+    pub verdict: GuardrailVerdict,
+    /// Share of the answer words covered by n-grams of the dataset
+    pub overlap_ratio: f64,
+    pub pii_types: Vec<PiiType>,
+}
+
+impl GuardrailIndex {
+    /// Index of fingerprints built by `dataset_fingerprints` from datasets sealed with `master_key`.
+    pub fn new(master_key: &[u8], policy: GuardrailPolicy) -> Self {
+        Self {
+            fingerprint_key: fingerprint_key(master_key),
+            policy,
+            agents: DashMap::new(),
+        }
+    }
+
+    pub fn insert(&self, agent_id: i64, shingles: Vec<i64>, pii_report: Option<&PiiReport>) {
+        let kept_pii = pii_report
+            .map(|report| {
+                report
+                    .columns
+                    .iter()
+                    .filter(|column| column.action == PiiAction::Keep)
+                    .flat_map(|column| column.detected.iter().copied())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let guard = AgentGuard {
+            fingerprint_key: self.fingerprint_key,
+            policy: self.policy,
+            fingerprints: shingles.into_iter().collect(),
+            kept_pii,
+        };
+
+        self.agents.insert(agent_id, Arc::new(guard));
+    }
+
+    /// Redact the PII of an answer of `agent_id`, unless its owner kept it, and block or redact it when it reproduces
+    /// more of the dataset than the overlap threshold of the policy.
+    pub fn check_answer(&self, agent_id: i64, answer: &str) -> GuardrailOutcome {
+        check_text(self.guard(agent_id).as_deref(), answer)
+    }
+
+    /// Guardrail of an answer of `agent_id` received in chunks.
+    pub fn stream(&self, agent_id: i64) -> StreamGuard {
+        StreamGuard {
+            guard: self.guard(agent_id),
+            pending: String::new(),
+            answer: String::new(),
+            response: String::new(),
+            verdict: GuardrailVerdict::Allowed,
+            pii_types: BTreeSet::new(),
+        }
+    }
+
+    // Clone the guard out so the map is not locked while an answer is checked
+    fn guard(&self, agent_id: i64) -> Option<Arc<AgentGuard>> {
+        self.agents.get(&agent_id).map(|guard| guard.clone())
+    }
+}
+
+/// Checks a streamed answer one batch of complete lines at a time, so PII and copied rows are
+/// caught before they are sent. An n-gram spanning two batches is not matched.
+pub struct StreamGuard {
+    guard: Option<Arc<AgentGuard>>,
+    /// Received text of the current, incomplete, line
+    pending: String,
+    answer: String,
+    /// Text released so far, the attested answer unless it gets blocked
+    response: String,
+    verdict: GuardrailVerdict,
+    pii_types: BTreeSet<PiiType>,
+}
+
+impl StreamGuard {
+    /// Add a chunk of the answer, returns the checked text that can be sent (possibly empty).
+    pub fn push(&mut self, chunk: &str) -> String {
+        self.answer.push_str(chunk);
+
+        if self.is_blocked() {
+            return String::new();
+        }
+
+        self.pending.push_str(chunk);
+
+        let Some(end) = self.pending.rfind('\n') else {
+            return String::new();
+        };
+
+        let lines: String = self.pending.drain(..=end).collect();
+
+        match self.check(&lines[..end]) {
+            // Consecutive copied lines share one marker, as in a whole answer
+            Some(checked)
+                if checked == REDACTED_LINES
+                    && self.response.trim_end().ends_with(REDACTED_LINES) =>
+            {
+                String::new()
+            }
+            Some(checked) => {
+                let released = checked + "\n";
+                self.response.push_str(&released);
+                released
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Whether the answer was blocked, nothing more is released once it is.
+    pub fn is_blocked(&self) -> bool {
+        self.verdict == GuardrailVerdict::Blocked
+    }
+
+    /// Check the last line and get the outcome of the whole answer.
+    /// Returns the remaining text to send with it.
+    pub fn finish(mut self) -> (String, GuardrailOutcome) {
+        let pending = std::mem::take(&mut self.pending);
+
+        let rest = if self.is_blocked() || pending.is_empty() {
+            String::new()
+        } else {
+            self.check(&pending).unwrap_or_default()
+        };
+
+        let (_, _, overlap_ratio) = overlap(self.guard.as_deref(), &self.answer);
+
+        let response = if self.is_blocked() {
+            BLOCKED_ANSWER.to_string()
+        } else {
+            self.response + &rest
+        };
+
+        let outcome = GuardrailOutcome {
+            response,
+            verdict: self.verdict,
+            overlap_ratio,
+            pii_types: self.pii_types.into_iter().collect(),
+        };
+
+        (rest, outcome)
+    }
+
+    /// Check complete lines, `None` when they got the answer blocked.
+    fn check(&mut self, lines: &str) -> Option<String> {
+        let outcome = check_text(self.guard.as_deref(), lines);
+
+        self.pii_types.extend(outcome.pii_types);
+
+        match outcome.verdict {
+            GuardrailVerdict::Blocked => {
+                self.verdict = GuardrailVerdict::Blocked;
+                return None;
+            }
+            GuardrailVerdict::Redacted => self.verdict = GuardrailVerdict::Redacted,
+            GuardrailVerdict::Allowed => {}
+        }
+
+        Some(outcome.response)
+    }
+}
+
+fn check_text(guard: Option<&AgentGuard>, answer: &str) -> GuardrailOutcome {
+    let kept_pii = guard
+        .map(|guard| guard.kept_pii.clone())
+        .unwrap_or_default();
+
+    let pii_types: Vec<PiiType> = pii::find_pii(answer)
+        .into_iter()
+        .map(|(_, pii_type)| pii_type)
+        .filter(|pii_type| !kept_pii.contains(pii_type))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let (lines, covered, overlap_ratio) = overlap(guard, answer);
+
+    let mut verdict = GuardrailVerdict::Allowed;
+    let mut response = answer.to_string();
+
+    // Without a guard nothing of the answer is covered
+    if let Some(GuardrailPolicy {
+        overlap_threshold: threshold,
+        action,
+    }) = guard.map(|guard| guard.policy)
+        && overlap_ratio > 0.0
+        && overlap_ratio >= threshold
+    {
+        match action {
+            GuardrailAction::Block => {
+                return GuardrailOutcome {
+                    response: BLOCKED_ANSWER.to_string(),
+                    verdict: GuardrailVerdict::Blocked,
+                    overlap_ratio,
+                    pii_types,
+                };
+            }
+            GuardrailAction::Redact => {
+                response = redact_lines(answer, &lines, &covered, threshold);
+                verdict = GuardrailVerdict::Redacted;
+            }
+        }
+    }
+
+    let redacted = redact_pii(&response, &kept_pii);
+
+    if redacted != response {
+        response = redacted;
+        verdict = GuardrailVerdict::Redacted;
+    }
+
+    GuardrailOutcome {
+        response,
+        verdict,
+        overlap_ratio,
+        pii_types,
+    }
+}
+
+/// Line and dataset coverage of every word of the answer, and the share of covered words.
+fn overlap(guard: Option<&AgentGuard>, answer: &str) -> (Vec<usize>, Vec<bool>, f64) {
+    let mut lines = Vec::new();
+    let mut words = Vec::new();
+
+    for (line, text) in answer.lines().enumerate() {
+        for word in words_of(text) {
+            lines.push(line);
+            words.push(word);
+        }
+    }
+
+    let covered = match guard {
+        Some(guard) => covered_words(&words, guard),
+        None => vec![false; words.len()],
+    };
+
+    let overlap_ratio = if words.is_empty() {
+        0.0
+    } else {
+        covered.iter().filter(|covered| **covered).count() as f64 / words.len() as f64
+    };
+
+    (lines, covered, overlap_ratio)
+}
+
+/// Keyed hashes of a sample of the word n-grams of the dataset rows.
+///
+/// The same n-grams are sampled from answers, so the share of sampled answer n-grams found here
+/// estimates how much of the answer was copied from the dataset.
+pub fn dataset_fingerprints(dataset: &SealedDataset) -> Result<Vec<i64>> {
+    let mut lines = BufReader::new(dataset.reader()?).lines();
+
+    // The header alone is not dataset content
+    lines.next().transpose()?;
+
+    let fingerprint_key = fingerprint_key(dataset.master_key());
+    let mut ngram: VecDeque<String> = VecDeque::with_capacity(GUARDRAIL_NGRAM_SIZE);
+    let mut shingles = Vec::new();
+
+    for line in lines {
+        for word in words_of(&line?) {
+            if ngram.len() == GUARDRAIL_NGRAM_SIZE {
+                ngram.pop_front();
+            }
+
+            ngram.push_back(word);
+
+            if ngram.len() == GUARDRAIL_NGRAM_SIZE {
+                let hash = shingle_hash(ngram.make_contiguous(), &fingerprint_key);
+
+                if is_sampled(hash) {
+                    shingles.push(hash);
+                }
+            }
+        }
+    }
+
+    shingles.sort_unstable();
+    shingles.dedup();
+
+    Ok(shingles)
+}
+
+/// Lowercase words, punctuation and formatting (CSV, markdown tables...) are ignored.
+fn words_of(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Key of the shingle hashes, derived from the master key so no dataset key is reused for them.
+fn fingerprint_key(master_key: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+
+    Hkdf::<Sha256>::new(None, master_key)
+        .expand(FINGERPRINT_KEY_LABEL, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    key
+}
+
+// Keyed, so stored fingerprints can not be matched against guessed rows
+fn shingle_hash(words: &[String], fingerprint_key: &[u8; 32]) -> i64 {
+    let hash = keccak256([fingerprint_key.as_slice(), words.join(" ").as_bytes()].concat());
+
+    i64::from_be_bytes(hash[..8].try_into().expect("keccak256 is 32 bytes"))
+}
+
+fn is_sampled(hash: i64) -> bool {
+    hash.rem_euclid(GUARDRAIL_FINGERPRINT_SAMPLING) == 0
+}
+
+/// Words of the answer inside an n-gram found in the dataset.
+fn covered_words(words: &[String], guard: &AgentGuard) -> Vec<bool> {
+    let mut covered = vec![false; words.len()];
+
+    for (start, ngram) in words.windows(GUARDRAIL_NGRAM_SIZE).enumerate() {
+        let hash = shingle_hash(ngram, &guard.fingerprint_key);
+
+        if is_sampled(hash) && guard.fingerprints.contains(&hash) {
+            covered[start..start + GUARDRAIL_NGRAM_SIZE].fill(true);
+        }
+    }
+
+    covered
+}
+
+/// Replace the answer lines mostly copied from the dataset, consecutive ones by a single marker.
+fn redact_lines(answer: &str, lines: &[usize], covered: &[bool], threshold: f64) -> String {
+    let line_count = answer.lines().count();
+    let mut words = vec![0usize; line_count];
+    let mut covered_count = vec![0usize; line_count];
+
+    for (line, covered) in lines.iter().zip(covered) {
+        words[*line] += 1;
+
+        if *covered {
+            covered_count[*line] += 1;
+        }
+    }
+
+    let mut redacted: Vec<&str> = Vec::with_capacity(line_count);
+
+    for (line, text) in answer.lines().enumerate() {
+        let copied =
+            words[line] > 0 && covered_count[line] as f64 / words[line] as f64 >= threshold;
+
+        if !copied {
+            redacted.push(text);
+        } else if redacted.last() != Some(&REDACTED_LINES) {
+            redacted.push(REDACTED_LINES);
+        }
+    }
+
+    redacted.join("\n")
+}
+
+fn redact_pii(text: &str, kept_pii: &HashSet<PiiType>) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for (span, pii_type) in pii::find_pii(text) {
+        if kept_pii.contains(&pii_type) {
+            continue;
+        }
+
+        redacted.push_str(&text[last..span.start]);
+        redacted.push_str(match pii_type {
+            PiiType::Email => "[redacted email]",
+            PiiType::Phone => "[redacted phone]",
+            PiiType::Iban => "[redacted iban]",
+            PiiType::CreditCard => "[redacted card number]",
+            PiiType::WalletAddress => "[redacted wallet address]",
+            PiiType::PersonName => "[redacted name]",
+        });
+        last = span.end;
+    }
+
+    redacted.push_str(&text[last..]);
+
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const MASTER_KEY: [u8; 32] = [7u8; 32];
+
+    const ROWS: [&str; 4] = [
+        "alice martin,lyon,bakery owner,sourdough croissants baguettes,opened in nineteen ninety",
+        "bruno keller,geneva,watchmaker,mechanical chronographs repairs,apprentice since childhood",
+        "chloe dubois,nantes,fisher,sardines mackerel oysters,sells at the harbour market",
+        "david moreau,lille,brewer,amber ales winter stouts,family recipe from grandfather",
+    ];
+
+    fn policy(action: GuardrailAction) -> GuardrailPolicy {
+        GuardrailPolicy {
+            overlap_threshold: 0.5,
+            action,
+        }
+    }
+
+    /// Index of an agent over `ROWS`, fingerprinted under `dataset_key`.
+    fn index(action: GuardrailAction, dataset_key: &[u8]) -> GuardrailIndex {
+        let mut writer = SealedDataset::create(dataset_key).unwrap();
+
+        writeln!(writer, "name,city,job,products,story").unwrap();
+
+        for row in ROWS {
+            writeln!(writer, "{}", row).unwrap();
+        }
+
+        let fingerprints = dataset_fingerprints(&writer.finish().unwrap()).unwrap();
+        let index = GuardrailIndex::new(&MASTER_KEY, policy(action));
+
+        index.insert(1, fingerprints, None);
+
+        index
+    }
+
+    fn copied_rows() -> String {
+        format!("Here are the rows:\n{}\n{}\n", ROWS[0], ROWS[1])
+    }
+
+    #[test]
+    fn answer_copying_rows_is_blocked() {
+        let outcome = index(GuardrailAction::Block, &MASTER_KEY).check_answer(1, &copied_rows());
+
+        assert_eq!(outcome.verdict, GuardrailVerdict::Blocked);
+        assert_eq!(outcome.response, BLOCKED_ANSWER);
+        assert!(outcome.overlap_ratio >= 0.5);
+    }
+
+    #[test]
+    fn answer_copying_rows_is_redacted() {
+        let outcome = index(GuardrailAction::Redact, &MASTER_KEY).check_answer(1, &copied_rows());
+
+        assert_eq!(outcome.verdict, GuardrailVerdict::Redacted);
+        assert_eq!(
+            outcome.response,
+            format!("Here are the rows:\n{}", REDACTED_LINES)
+        );
+    }
+
+    #[test]
+    fn answer_under_the_threshold_passes() {
+        let answer = "Four shops are listed, alice martin runs a bakery in lyon.";
+
+        let outcome = index(GuardrailAction::Block, &MASTER_KEY).check_answer(1, answer);
+
+        assert_eq!(outcome.verdict, GuardrailVerdict::Allowed);
+        assert_eq!(outcome.response, answer);
+        assert!(outcome.overlap_ratio < 0.5);
+    }
+
+    #[test]
+    fn fingerprints_of_another_key_match_nothing() {
+        let outcome = index(GuardrailAction::Block, &[9u8; 32]).check_answer(1, &copied_rows());
+
+        assert_eq!(outcome.verdict, GuardrailVerdict::Allowed);
+        assert_eq!(outcome.overlap_ratio, 0.0);
+    }
+
+    #[test]
+    fn row_split_across_chunks_is_caught() {
+        let index = index(GuardrailAction::Block, &MASTER_KEY);
+        let mut guard = index.stream(1);
+        let (start, end) = ROWS[2].split_at(ROWS[2].len() / 2);
+
+        assert_eq!(guard.push("Found it:\n"), "Found it:\n");
+        assert_eq!(guard.push(start), "");
+        assert!(!guard.is_blocked());
+
+        assert_eq!(guard.push(&format!("{}\n", end)), "");
+        assert!(guard.is_blocked());
+
+        let (rest, outcome) = guard.finish();
+
+        assert_eq!(rest, "");
+        assert_eq!(outcome.verdict, GuardrailVerdict::Blocked);
+        assert_eq!(outcome.response, BLOCKED_ANSWER);
+    }
+
+    #[test]
+    fn row_split_across_chunks_is_redacted() {
+        let index = index(GuardrailAction::Redact, &MASTER_KEY);
+        let mut guard = index.stream(1);
+        let (start, end) = ROWS[3].split_at(20);
+
+        assert_eq!(guard.push(start), "");
+        assert_eq!(guard.push(end), "");
+
+        let (rest, outcome) = guard.finish();
+
+        assert_eq!(rest, REDACTED_LINES);
+        assert_eq!(outcome.verdict, GuardrailVerdict::Redacted);
+        assert_eq!(outcome.response, REDACTED_LINES);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{future::IntoFuture, str::FromStr, time::Duration};
+
+use alloy::{
+    primitives::{Address, U256, utils::format_units},
+    sol,
+};
+use color_eyre::Result;
+use futures_util::future::join_all;
+
+use crate::{
+    config::{EARNINGS_RPC_TIMEOUT_SECS, ENCLAVA_CONTRACT_ADDRESS},
+    database,
+    types::{AgentEarnings, EarningsSummary, NewIngestedLog, NewLedgerEvent, WebAppState},
+};
+
+// View functions of the enclava contract used by the earnings dashboard
+sol! {
+    #[sol(rpc)]
+    interface IEnclavaPayments {
+        function getUnclaimedAmount(uint256 tokenId) external view returns (uint256);
+        function getTotalEarnedByOwner(address owner) external view returns (uint256);
+        function getTotalUnclaimedByOwner(address owner) external view returns (uint256);
+    }
+}
+
+/// Store a `DatasetUsed` event, together with its log position so a reorg removes it.
+pub async fn handle_dataset_used(
+    app_state: &WebAppState,
+    usage: &NewLedgerEvent,
+    ingested_log: &NewIngestedLog,
+) -> Result<()> {
+    let mut tx = app_state.db.begin().await?;
+
+    let ingested_log_id =
+        match database::insert_ingested_log(&mut tx, ingested_log, None, Some(usage.nft_id)).await?
+        {
+            Some(id) => id,
+            None => {
+                tracing::warn!(
+                    "Skipping usage, log {}:{} already ingested",
+                    ingested_log.tx_hash,
+                    ingested_log.log_index
+                );
+                return Ok(());
+            }
+        };
+
+    database::insert_usage_event(&mut tx, ingested_log_id, ingested_log, usage).await?;
+
+    // Commit the transaction
+    tx.commit().await?;
+
+    tracing::info!(
+        "Usage of NFT {} by {} stored in the ledger",
+        usage.nft_id,
+        usage.address
+    );
+
+    Ok(())
+}
+
+/// Store an `AmountClaimed` event, together with its log position so a reorg removes it.
+pub async fn handle_amount_claimed(
+    app_state: &WebAppState,
+    claim: &NewLedgerEvent,
+    ingested_log: &NewIngestedLog,
+) -> Result<()> {
+    let mut tx = app_state.db.begin().await?;
+
+    let ingested_log_id =
+        match database::insert_ingested_log(&mut tx, ingested_log, None, Some(claim.nft_id)).await?
+        {
+            Some(id) => id,
+            None => {
+                tracing::warn!(
+                    "Skipping claim, log {}:{} already ingested",
+                    ingested_log.tx_hash,
+                    ingested_log.log_index
+                );
+                return Ok(());
+            }
+        };
+
+    database::insert_claim_event(&mut tx, ingested_log_id, ingested_log, claim).await?;
+
+    // Commit the transaction
+    tx.commit().await?;
+
+    tracing::info!(
+        "Claim of NFT {} by {} stored in the ledger",
+        claim.nft_id,
+        claim.address
+    );
+
+    Ok(())
+}
+
+/// Earnings of every agent owned by `owner_address`, from the local ledger and the contract.
+///
+/// Contract reads are best effort: when the RPC fails or takes longer than
+/// `EARNINGS_RPC_TIMEOUT_SECS`, unclaimed amounts fall back to what the indexed events say
+/// (earned - claimed) and the on chain totals are `None`.
+pub async fn get_owner_earnings(
+    app_state: &WebAppState,
+    owner_address: &str,
+) -> Result<(EarningsSummary, Vec<AgentEarnings>)> {
+    let agent_earnings =
+        database::get_agent_earnings_by_owner(&app_state.db, owner_address).await?;
+
+    let contract =
+        IEnclavaPayments::new(Address::from_str(ENCLAVA_CONTRACT_ADDRESS)?, &app_state.rpc);
+
+    let unclaimed_amounts = join_all(agent_earnings.iter().map(|earnings| {
+        let contract = &contract;
+
+        async move {
+            let nft_id = earnings.nft_id?;
+
+            match read_contract(contract.getUnclaimedAmount(U256::from(nft_id)).call()).await {
+                Ok(amount) => wei_to_f64(amount),
+                Err(e) => {
+                    tracing::warn!("Failed to read unclaimed amount of NFT {}: {}", nft_id, e);
+                    None
+                }
+            }
+        }
+    }))
+    .await;
+
+    let agents: Vec<AgentEarnings> = agent_earnings
+        .into_iter()
+        .zip(unclaimed_amounts)
+        .map(|(earnings, unclaimed)| AgentEarnings {
+            unclaimed: unclaimed
+                .unwrap_or_else(|| (earnings.total_earned - earnings.total_claimed).max(0.0)),
+            earnings,
+        })
+        .collect();
+
+    let (onchain_total_earned, onchain_total_unclaimed) = match Address::from_str(owner_address) {
+        Ok(owner) => {
+            let total_earned_call = contract.getTotalEarnedByOwner(owner);
+            let total_unclaimed_call = contract.getTotalUnclaimedByOwner(owner);
+            let (total_earned, total_unclaimed) = tokio::join!(
+                read_contract(total_earned_call.call()),
+                read_contract(total_unclaimed_call.call()),
+            );
+
+            (
+                read_owner_total(total_earned),
+                read_owner_total(total_unclaimed),
+            )
+        }
+        Err(_) => (None, None),
+    };
+
+    let summary = EarningsSummary {
+        total_earned: agents.iter().map(|a| a.earnings.total_earned).sum(),
+        total_claimed: agents.iter().map(|a| a.earnings.total_claimed).sum(),
+        total_unclaimed: onchain_total_unclaimed
+            .unwrap_or_else(|| agents.iter().map(|a| a.unclaimed).sum()),
+        total_queries: agents.iter().map(|a| a.earnings.query_count).sum(),
+        onchain_total_earned,
+        onchain_total_unclaimed,
+    };
+
+    Ok((summary, agents))
+}
+
+/// Contract read bounded by `EARNINGS_RPC_TIMEOUT_SECS`, so a slow RPC does not stall the profile.
+async fn read_contract<E: std::fmt::Display>(
+    call: impl IntoFuture<Output = std::result::Result<U256, E>>,
+) -> std::result::Result<U256, String> {
+    match tokio::time::timeout(Duration::from_secs(EARNINGS_RPC_TIMEOUT_SECS), call).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!(
+            "no response within {} seconds",
+            EARNINGS_RPC_TIMEOUT_SECS
+        )),
+    }
+}
+
+fn read_owner_total<E: std::fmt::Display>(result: std::result::Result<U256, E>) -> Option<f64> {
+    match result {
+        Ok(amount) => wei_to_f64(amount),
+        Err(e) => {
+            tracing::warn!("Failed to read owner totals from the contract: {}", e);
+            None
+        }
+    }
+}
+
+fn wei_to_f64(amount: U256) -> Option<f64> {
+    format_units(amount, 18).ok()?.parse().ok()
+}
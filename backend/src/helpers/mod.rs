@@ -1,3 +1,19 @@
 pub mod agents;
+pub mod auth;
+pub mod chat;
+pub mod credits;
+pub mod crypto;
 pub mod csv;
-pub mod nft;
\ No newline at end of file
+pub mod dp;
+pub mod formats;
+pub mod guardrail;
+pub mod ledger;
+pub mod nft;
+pub mod pii;
+pub mod profile;
+pub mod rag;
+pub mod router;
+pub mod sql;
+pub mod synthesis;
+pub mod temp_file;
+pub mod upload;
@@ -1,19 +1,36 @@
-use color_eyre::{
-    Result,
-    eyre::{self, Context},
-};
+use color_eyre::Result;
 
-use crate::{database, fetcher::mint::DatasetNFTMint, types::WebAppState};
+use crate::{
+    database,
+    fetcher::mint::DatasetNFTMint,
+    types::{NewIngestedLog, WebAppState},
+};
 
+/// Link a minted NFT to its agent.
+///
+/// Mints that can never be applied (unknown dataset, wrong owner, already linked) are skipped
+/// with a warning so the fetcher can move on. Database errors are returned so the log is retried.
+/// The log position is recorded in the same transaction so a reorg can roll the link back.
 pub async fn handle_new_nft_mint(
     app_state: &WebAppState,
     nft_minted: &DatasetNFTMint,
+    ingested_log: &NewIngestedLog,
 ) -> Result<()> {
     // Check if the dataset_id exists in the database
     let dataset_id = nft_minted.dataset_id.clone();
 
     // Convert it to i64
-    let dataset_id = dataset_id.parse::<i64>()?;
+    let dataset_id = match dataset_id.parse::<i64>() {
+        Ok(dataset_id) => dataset_id,
+        Err(e) => {
+            tracing::warn!(
+                "Skipping mint with invalid dataset id {}: {}",
+                dataset_id,
+                e
+            );
+            return Ok(());
+        }
+    };
 
     tracing::trace!("Dataset ID After i64: {}", dataset_id);
 
@@ -22,19 +39,26 @@ pub async fn handle_new_nft_mint(
     let agent = match database::get_agent_by_id_optional(&mut tx, dataset_id).await? {
         Some(agent) => agent,
         None => {
-            return Err(eyre::eyre!(
-                "Dataset ID {} does not exist in the database",
+            tracing::warn!(
+                "Skipping mint, dataset ID {} does not exist in the database",
                 dataset_id
-            ));
+            );
+            return Ok(());
         }
     };
 
     if agent.nft_id.is_some() {
-        return Err(eyre::eyre!("Agent already has a minted NFT"));
+        tracing::warn!("Skipping mint, agent {} already has a minted NFT", agent.id);
+        return Ok(());
     }
 
     if agent.owner_address != nft_minted.to.to_string() {
-        return Err(eyre::eyre!("NFT minted to wrong address"));
+        tracing::warn!(
+            "Skipping mint, NFT of agent {} minted to wrong address {}",
+            agent.id,
+            nft_minted.to
+        );
+        return Ok(());
     }
 
     let nft_id: i64 = nft_minted.token_id.to_string().parse()?;
@@ -43,6 +67,19 @@ pub async fn handle_new_nft_mint(
     tracing::trace!("NFT ID: {}", nft_id);
     tracing::trace!("NFT TX: {:?}", nft_tx);
 
+    // Record where the mint was seen on chain
+    let ingested =
+        database::insert_ingested_log(&mut tx, ingested_log, Some(agent.id), Some(nft_id)).await?;
+
+    if ingested.is_none() {
+        tracing::warn!(
+            "Skipping mint, log {}:{} already ingested",
+            ingested_log.tx_hash,
+            ingested_log.log_index
+        );
+        return Ok(());
+    }
+
     // Update the agent with the nft_id and nft_tx
     database::update_agent_with_nft_details(&mut tx, agent.id, nft_id, nft_tx).await?;
 
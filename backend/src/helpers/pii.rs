@@ -0,0 +1,682 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+};
+
+use alloy::{
+    hex,
+    primitives::{Address, keccak256},
+};
+use color_eyre::Result;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::{
+    config::PII_CARD_MIN_VALID_RATIO,
+    helpers::{crypto::SealedDataset, sql},
+    types::{PiiAction, PiiColumnReport, PiiReport, PiiType},
+};
+
+/// Action applied to a column with detected PII when its owner did not choose one.
+pub const DEFAULT_PII_ACTION: PiiAction = PiiAction::Hash;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PiiError {
+    #[error("pii_actions references unknown column {0}")]
+    UnknownColumn(String),
+}
+
+static EMAIL_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}")
+        .expect("Invalid email regex")
+});
+
+// Digits are ASCII only, `\d` also matches other scripts and multi-byte characters.
+// International or North American formats only, bare digit runs are too ambiguous
+static PHONE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\+[0-9][0-9 ().-]{6,}[0-9]|\([0-9]{3}\) ?[0-9]{3}[-. ][0-9]{4}")
+        .expect("Invalid phone regex")
+});
+
+static IBAN_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\b[A-Z]{2}[0-9]{2}(?: ?[A-Z0-9]){11,30}\b").expect("Invalid IBAN regex")
+});
+
+static CARD_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b[0-9](?:[ -]?[0-9]){12,18}\b").expect("Invalid card regex"));
+
+static WALLET_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b0x[0-9a-fA-F]{40}\b").expect("Invalid wallet regex"));
+
+/// Scan a dataset for PII and apply the owner `actions` (column name -> action).
+///
+/// Returns the redacted dataset, or `None` when no column had to change, and the report
+/// stored with the agent.
+pub fn redact_dataset(
+    dataset: &SealedDataset,
+    actions: &HashMap<String, PiiAction>,
+) -> Result<(Option<SealedDataset>, PiiReport)> {
+    let mut reader = dataset.csv_reader()?;
+
+    let headers = reader.headers()?.clone();
+    let names = sql::column_names(&headers);
+
+    if let Some(unknown) = actions.keys().find(|column| !names.contains(column)) {
+        return Err(PiiError::UnknownColumn(unknown.clone()).into());
+    }
+
+    // First pass: find the PII of every column
+    let mut scans: Vec<ColumnScan> = names.iter().map(|name| ColumnScan::new(name)).collect();
+
+    for record in reader.records() {
+        let record = record?;
+
+        for (scan, value) in scans.iter_mut().zip(record.iter()) {
+            scan.add(value);
+        }
+    }
+
+    let plans: Vec<ColumnPlan> = names
+        .iter()
+        .zip(scans)
+        .map(|(name, scan)| scan.plan(actions.get(name).copied()))
+        .collect();
+
+    let mut report_columns: Vec<PiiColumnReport> = names
+        .iter()
+        .zip(&plans)
+        .filter(|(name, plan)| {
+            !plan.detected.is_empty() || plan.hint.is_some() || actions.contains_key(*name)
+        })
+        .map(|(name, plan)| PiiColumnReport {
+            name: name.clone(),
+            detected: plan.detected.iter().copied().collect(),
+            name_hint: plan.hint.is_some(),
+            matches: plan.matches,
+            action: plan.action,
+            redacted_values: 0,
+        })
+        .collect();
+
+    if plans.iter().all(|plan| plan.action == PiiAction::Keep) {
+        return Ok((
+            None,
+            PiiReport {
+                columns: report_columns,
+            },
+        ));
+    }
+
+    // Second pass: rewrite the dataset, hashes are salted per dataset
+    let salt: [u8; 16] = rand::random();
+    let mut redacted_counts = vec![0i64; plans.len()];
+
+    let mut reader = dataset.csv_reader()?;
+    let mut writer = csv::Writer::from_writer(SealedDataset::create(dataset.master_key())?);
+
+    writer.write_record(
+        headers
+            .iter()
+            .zip(&plans)
+            .filter(|(_, plan)| plan.action != PiiAction::Drop)
+            .map(|(header, _)| header),
+    )?;
+
+    for record in reader.records() {
+        let record = record?;
+        let mut redacted = Vec::with_capacity(plans.len());
+
+        for (i, (value, plan)) in record.iter().zip(&plans).enumerate() {
+            match plan.action {
+                PiiAction::Drop => {}
+                PiiAction::Keep => redacted.push(value.to_string()),
+                action => {
+                    let new_value = plan.redact(value, action, &salt);
+
+                    if new_value != value {
+                        redacted_counts[i] += 1;
+                    }
+
+                    redacted.push(new_value);
+                }
+            }
+        }
+
+        writer.write_record(&redacted)?;
+    }
+
+    for column in report_columns.iter_mut() {
+        if let Some(i) = names.iter().position(|name| *name == column.name) {
+            column.redacted_values = match column.action {
+                PiiAction::Drop => plans[i].non_empty as i64,
+                _ => redacted_counts[i],
+            };
+        }
+    }
+
+    let redacted = writer.into_inner()?.finish()?;
+
+    Ok((
+        Some(redacted),
+        PiiReport {
+            columns: report_columns,
+        },
+    ))
+}
+
+/// What the first pass found in a column.
+struct ColumnScan {
+    hint: Option<PiiType>,
+    types: BTreeSet<PiiType>,
+    matches: i64,
+    non_empty: usize,
+    card_candidates: usize,
+    card_valid: usize,
+}
+
+impl ColumnScan {
+    fn new(name: &str) -> Self {
+        Self {
+            hint: name_hint(name),
+            types: BTreeSet::new(),
+            matches: 0,
+            non_empty: 0,
+            card_candidates: 0,
+            card_valid: 0,
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        if value.trim().is_empty() {
+            return;
+        }
+
+        self.non_empty += 1;
+
+        let spans = find_column_pii(value, self.hint);
+
+        if !spans.is_empty() {
+            self.matches += 1;
+        }
+
+        for (_, pii_type) in &spans {
+            self.types.insert(*pii_type);
+
+            if *pii_type == PiiType::CreditCard {
+                self.card_valid += 1;
+            }
+        }
+
+        self.card_candidates += CARD_REGEX.find_iter(value).count();
+    }
+
+    fn plan(mut self, action: Option<PiiAction>) -> ColumnPlan {
+        // Long numeric ids pass the Luhn check one time out of ten, a card column almost always
+        if self.types.contains(&PiiType::CreditCard)
+            && self.hint != Some(PiiType::CreditCard)
+            && (self.card_valid as f64) < PII_CARD_MIN_VALID_RATIO * self.card_candidates as f64
+        {
+            self.types.remove(&PiiType::CreditCard);
+        }
+
+        // Names can only be told from the column name
+        if self.hint == Some(PiiType::PersonName) {
+            self.types.insert(PiiType::PersonName);
+            self.matches = self.non_empty as i64;
+        }
+
+        let whole_value = self.types.contains(&PiiType::PersonName)
+            || (action.is_some() && self.types.is_empty());
+
+        let action = action.unwrap_or(if self.types.is_empty() {
+            PiiAction::Keep
+        } else {
+            DEFAULT_PII_ACTION
+        });
+
+        ColumnPlan {
+            hint: self.hint,
+            detected: self.types,
+            matches: self.matches,
+            non_empty: self.non_empty,
+            action,
+            whole_value,
+        }
+    }
+}
+
+/// How the second pass rewrites a column.
+struct ColumnPlan {
+    hint: Option<PiiType>,
+    detected: BTreeSet<PiiType>,
+    matches: i64,
+    non_empty: usize,
+    action: PiiAction,
+    /// Redact whole values instead of the PII found in them
+    whole_value: bool,
+}
+
+impl ColumnPlan {
+    fn redact(&self, value: &str, action: PiiAction, salt: &[u8]) -> String {
+        if value.trim().is_empty() {
+            return value.to_string();
+        }
+
+        // A whole value is not shaped like the PII found in some of the values, mask it as a name
+        if self.whole_value {
+            return replace_pii(value, Some(PiiType::PersonName), action, salt);
+        }
+
+        let mut redacted = String::with_capacity(value.len());
+        let mut last = 0;
+
+        for (span, pii_type) in find_column_pii(value, self.hint) {
+            if !self.detected.contains(&pii_type) {
+                continue;
+            }
+
+            redacted.push_str(&value[last..span.start]);
+            redacted.push_str(&replace_pii(
+                &value[span.clone()],
+                Some(pii_type),
+                action,
+                salt,
+            ));
+            last = span.end;
+        }
+
+        redacted.push_str(&value[last..]);
+
+        redacted
+    }
+}
+
+fn replace_pii(text: &str, pii_type: Option<PiiType>, action: PiiAction, salt: &[u8]) -> String {
+    match action {
+        PiiAction::Hash => {
+            let hash = keccak256([salt, text.as_bytes()].concat());
+            format!("pii_{}", hex::encode(&hash[..8]))
+        }
+        PiiAction::Mask => mask(text, pii_type),
+        PiiAction::Drop | PiiAction::Keep => text.to_string(),
+    }
+}
+
+fn mask(text: &str, pii_type: Option<PiiType>) -> String {
+    let first_char = |text: &str| text.chars().next().map(String::from).unwrap_or_default();
+
+    match pii_type {
+        Some(PiiType::Email) => match text.split_once('@') {
+            Some((local, domain)) => format!("{}***@{}", first_char(local), domain),
+            None => format!("{}***", first_char(text)),
+        },
+        Some(PiiType::WalletAddress) => format!("{}...{}", &text[..6], &text[text.len() - 4..]),
+        // Keep the last 4 characters, like printed card numbers
+        Some(PiiType::Phone | PiiType::Iban | PiiType::CreditCard) => {
+            let total = text.chars().filter(char::is_ascii_alphanumeric).count();
+            let mut seen = 0;
+
+            text.chars()
+                .map(|c| {
+                    if !c.is_ascii_alphanumeric() {
+                        return c;
+                    }
+
+                    seen += 1;
+
+                    if seen > total.saturating_sub(4) {
+                        c
+                    } else {
+                        '*'
+                    }
+                })
+                .collect()
+        }
+        Some(PiiType::PersonName) | None => format!("{}***", first_char(text)),
+    }
+}
+
+/// PII of a value, validated and without overlaps. A phone column hint also accepts bare numbers.
+fn find_column_pii(value: &str, hint: Option<PiiType>) -> Vec<(Range<usize>, PiiType)> {
+    let spans = find_pii(value);
+
+    if spans.is_empty() && hint == Some(PiiType::Phone) {
+        let trimmed = value.trim();
+        let start = value.find(trimmed).unwrap_or(0);
+
+        if trimmed
+            .chars()
+            .all(|c| c.is_ascii_digit() || " +-().".contains(c))
+            && is_valid_phone(trimmed)
+        {
+            return vec![(start..start + trimmed.len(), PiiType::Phone)];
+        }
+    }
+
+    spans
+}
+
+/// Regex finding a PII type, and the check its matches must pass.
+type PiiDetector = (&'static Regex, PiiType, fn(&str) -> bool);
+
+/// PII found in free text, validated and without overlaps, in order.
+pub fn find_pii(value: &str) -> Vec<(Range<usize>, PiiType)> {
+    let detectors: [PiiDetector; 5] = [
+        (&WALLET_REGEX, PiiType::WalletAddress, is_valid_wallet),
+        (&EMAIL_REGEX, PiiType::Email, |_| true),
+        (&IBAN_REGEX, PiiType::Iban, is_valid_iban),
+        (&CARD_REGEX, PiiType::CreditCard, is_valid_card),
+        (&PHONE_REGEX, PiiType::Phone, is_valid_phone),
+    ];
+
+    let mut spans: Vec<(Range<usize>, PiiType)> = Vec::new();
+
+    for (regex, pii_type, is_valid) in detectors {
+        for found in regex.find_iter(value) {
+            let range = found.range();
+
+            let overlaps = spans
+                .iter()
+                .any(|(span, _)| span.start < range.end && range.start < span.end);
+
+            if !overlaps && is_valid(found.as_str()) {
+                spans.push((range, pii_type));
+            }
+        }
+    }
+
+    spans.sort_by_key(|(span, _)| span.start);
+
+    spans
+}
+
+/// Luhn checksum of a 13 to 19 digits card number.
+fn is_valid_card(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| {
+            if i.is_multiple_of(2) {
+                digit
+            } else if digit * 2 > 9 {
+                digit * 2 - 9
+            } else {
+                digit * 2
+            }
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+/// ISO 13616 check: the first 4 characters moved to the end, letters as numbers, mod 97 is 1.
+fn is_valid_iban(candidate: &str) -> bool {
+    let iban: Vec<char> = candidate.chars().filter(|c| !c.is_whitespace()).collect();
+
+    if !(15..=34).contains(&iban.len()) {
+        return false;
+    }
+
+    let mut remainder = 0u32;
+
+    for c in iban[4..].iter().chain(&iban[..4]) {
+        let Some(value) = c.to_digit(36) else {
+            return false;
+        };
+
+        remainder = if value >= 10 {
+            (remainder * 100 + value) % 97
+        } else {
+            (remainder * 10 + value) % 97
+        };
+    }
+
+    remainder == 1
+}
+
+/// E.164 numbers have at most 15 digits.
+fn is_valid_phone(candidate: &str) -> bool {
+    let digits = candidate.chars().filter(char::is_ascii_digit).count();
+
+    (8..=15).contains(&digits)
+}
+
+fn is_valid_wallet(candidate: &str) -> bool {
+    let hex = &candidate[2..];
+
+    // Mixed case addresses carry an EIP-55 checksum
+    if hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase()) {
+        return Address::parse_checksummed(candidate, None).is_ok();
+    }
+
+    true
+}
+
+fn name_hint(column: &str) -> Option<PiiType> {
+    let name: String = column
+        .to_lowercase()
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect();
+
+    const PERSON_NAMES: [&str; 7] = [
+        "firstname",
+        "lastname",
+        "fullname",
+        "surname",
+        "givenname",
+        "familyname",
+        "customername",
+    ];
+
+    if name.contains("email") || name == "mail" {
+        Some(PiiType::Email)
+    } else if name.contains("phone") || name.contains("mobile") || name == "tel" {
+        Some(PiiType::Phone)
+    } else if name.contains("iban") {
+        Some(PiiType::Iban)
+    } else if name.contains("creditcard") || name.contains("cardnumber") || name == "ccnumber" {
+        Some(PiiType::CreditCard)
+    } else if name.contains("wallet") {
+        Some(PiiType::WalletAddress)
+    } else if PERSON_NAMES
+        .iter()
+        .any(|person_name| name.contains(person_name))
+    {
+        Some(PiiType::PersonName)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const MASTER_KEY: [u8; 32] = [7u8; 32];
+
+    #[test]
+    fn luhn_card_numbers() {
+        for valid in [
+            "4111 1111 1111 1111",
+            "5500-0000-0000-0004",
+            "378282246310005",
+            "6011111111111117",
+        ] {
+            assert!(is_valid_card(valid), "{}", valid);
+        }
+
+        for invalid in [
+            "4111 1111 1111 1112",
+            "5500-0000-0000-0005",
+            // Luhn valid but too short or too long for a card
+            "000000000000",
+            "41111111111111111115",
+        ] {
+            assert!(!is_valid_card(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn iban_mod_97() {
+        for valid in [
+            "GB82 WEST 1234 5698 7654 32",
+            "DE89370400440532013000",
+            "FR1420041010050500013M02606",
+        ] {
+            assert!(is_valid_iban(valid), "{}", valid);
+        }
+
+        for invalid in [
+            "GB82 WEST 1234 5698 7654 33",
+            "DE89370400440532013001",
+            "GB82WEST1234",
+            "GB82 WEST 1234 5698 7654 3_",
+        ] {
+            assert!(!is_valid_iban(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn phone_digit_counts() {
+        for valid in ["+33 6 12 34 56 78", "(415) 555-2671", "+1 415 555 2671"] {
+            assert!(is_valid_phone(valid), "{}", valid);
+        }
+
+        for invalid in ["+1 234 56", "+1234567890123456"] {
+            assert!(!is_valid_phone(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn eip55_wallets() {
+        for valid in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            // Single case addresses carry no checksum
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            "0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED",
+        ] {
+            assert!(is_valid_wallet(valid), "{}", valid);
+        }
+
+        assert!(!is_valid_wallet(
+            "0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        ));
+    }
+
+    fn sealed(csv: &str) -> SealedDataset {
+        let mut writer = SealedDataset::create(&MASTER_KEY).unwrap();
+
+        writer.write_all(csv.as_bytes()).unwrap();
+
+        writer.finish().unwrap()
+    }
+
+    fn rows(dataset: &SealedDataset) -> Vec<Vec<String>> {
+        let mut reader = dataset.csv_reader().unwrap();
+
+        let headers = reader
+            .headers()
+            .unwrap()
+            .iter()
+            .map(str::to_string)
+            .collect();
+
+        std::iter::once(headers)
+            .chain(
+                reader
+                    .records()
+                    .map(|record| record.unwrap().iter().map(str::to_string).collect()),
+            )
+            .collect()
+    }
+
+    #[test]
+    fn redact_dataset_round_trip() {
+        let dataset = sealed(
+            "full_name,email,phone,wallet,amount\n\
+             Alice Martin,alice@example.com,+33 6 12 34 56 78,0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed,12\n\
+             Bruno Keller,bruno@example.com,+41 79 123 45 67,,30\n\
+             Alice Martin,alice@example.com,,,7\n",
+        );
+
+        let actions = HashMap::from([
+            ("full_name".to_string(), PiiAction::Drop),
+            ("email".to_string(), PiiAction::Hash),
+            ("phone".to_string(), PiiAction::Mask),
+            ("wallet".to_string(), PiiAction::Keep),
+        ]);
+
+        let (redacted, report) = redact_dataset(&dataset, &actions).unwrap();
+        let rows = rows(&redacted.unwrap());
+
+        assert_eq!(rows[0], ["email", "phone", "wallet", "amount"]);
+
+        // Hashes are salted per dataset, the same value still gets the same hash
+        assert!(rows[1][0].starts_with("pii_"));
+        assert_ne!(rows[1][0], rows[2][0]);
+        assert_eq!(rows[1][0], rows[3][0]);
+
+        assert_eq!(rows[1][1], "+** * ** ** 56 78");
+        assert_eq!(rows[2][1], "+** ** *** 45 67");
+        assert_eq!(rows[3][1], "");
+
+        assert_eq!(rows[1][2], "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert_eq!(
+            rows.iter().skip(1).map(|row| &row[3]).collect::<Vec<_>>(),
+            ["12", "30", "7"]
+        );
+
+        let column = |name: &str| {
+            report
+                .columns
+                .iter()
+                .find(|column| column.name == name)
+                .unwrap()
+        };
+
+        assert_eq!(column("full_name").detected, [PiiType::PersonName]);
+        assert_eq!(column("full_name").redacted_values, 3);
+        assert_eq!(column("email").detected, [PiiType::Email]);
+        assert_eq!(column("email").redacted_values, 3);
+        assert_eq!(column("phone").redacted_values, 2);
+        assert_eq!(column("wallet").detected, [PiiType::WalletAddress]);
+        assert_eq!(column("wallet").redacted_values, 0);
+        assert!(report.columns.iter().all(|column| column.name != "amount"));
+    }
+
+    #[test]
+    fn redact_dataset_keeps_a_dataset_without_pii() {
+        let dataset = sealed("city,amount\nlyon,12\ngeneva,30\n");
+
+        let (redacted, report) = redact_dataset(&dataset, &HashMap::new()).unwrap();
+
+        assert!(redacted.is_none());
+        assert!(report.columns.is_empty());
+    }
+
+    #[test]
+    fn redact_dataset_rejects_unknown_columns() {
+        let dataset = sealed("email,amount\nalice@example.com,12\n");
+        let actions = HashMap::from([("ssn".to_string(), PiiAction::Drop)]);
+
+        let Err(error) = redact_dataset(&dataset, &actions) else {
+            panic!("Unknown column accepted");
+        };
+
+        assert!(matches!(
+            error.downcast_ref::<PiiError>(),
+            Some(PiiError::UnknownColumn(column)) if column == "ssn"
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use color_eyre::Result;
+
+use crate::{
+    config::{PROFILE_MAX_DISTINCT, PROFILE_SAMPLE_VALUES},
+    helpers::{crypto::SealedDataset, sql},
+    types::{ColumnProfile, DatasetProfile, InferredColumnType},
+};
+
+/// Profile every column of a dataset in a single pass.
+pub fn profile_dataset(dataset: &SealedDataset) -> Result<DatasetProfile> {
+    let mut reader = dataset.csv_reader()?;
+
+    let names = sql::column_names(reader.headers()?);
+    let mut columns: Vec<ColumnStats> = names.iter().map(|_| ColumnStats::new()).collect();
+    let mut row_count = 0;
+
+    for record in reader.records() {
+        let record = record?;
+        row_count += 1;
+
+        for (stats, value) in columns.iter_mut().zip(record.iter()) {
+            stats.add(value.trim());
+        }
+    }
+
+    let columns = names
+        .into_iter()
+        .zip(columns)
+        .map(|(name, stats)| stats.finish(name, row_count))
+        .collect();
+
+    Ok(DatasetProfile { row_count, columns })
+}
+
+/// Drop the values of a profile, kept private for differential privacy agents.
+/// Only the schema and the null / distinct shares are left.
+pub fn strip_profile_values(profile: &mut DatasetProfile) {
+    for column in &mut profile.columns {
+        column.min = None;
+        column.max = None;
+        column.sample_values.clear();
+    }
+}
+
+struct ColumnStats {
+    null_count: i64,
+    distinct: HashSet<String>,
+    distinct_capped: bool,
+    samples: Vec<String>,
+    // Candidate types, each one is ruled out by the first value that does not parse
+    integer: bool,
+    real: bool,
+    boolean: bool,
+    date: bool,
+    datetime: bool,
+    min_integer: Option<i64>,
+    max_integer: Option<i64>,
+    min_real: Option<f64>,
+    max_real: Option<f64>,
+    min_text: Option<String>,
+    max_text: Option<String>,
+}
+
+impl ColumnStats {
+    fn new() -> Self {
+        Self {
+            null_count: 0,
+            distinct: HashSet::new(),
+            distinct_capped: false,
+            samples: Vec::new(),
+            integer: true,
+            real: true,
+            boolean: true,
+            date: true,
+            datetime: true,
+            min_integer: None,
+            max_integer: None,
+            min_real: None,
+            max_real: None,
+            min_text: None,
+            max_text: None,
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        if value.is_empty() {
+            self.null_count += 1;
+            return;
+        }
+
+        // Distinct values are only tracked up to a limit to bound memory on large datasets
+        if !self.distinct.contains(value) {
+            if self.distinct.len() < PROFILE_MAX_DISTINCT {
+                self.distinct.insert(value.to_string());
+
+                if self.samples.len() < PROFILE_SAMPLE_VALUES {
+                    self.samples.push(value.to_string());
+                }
+            } else {
+                self.distinct_capped = true;
+            }
+        }
+
+        if self.integer {
+            match value.parse::<i64>() {
+                Ok(number) => {
+                    self.min_integer = Some(self.min_integer.map_or(number, |min| min.min(number)));
+                    self.max_integer = Some(self.max_integer.map_or(number, |max| max.max(number)));
+                }
+                Err(_) => self.integer = false,
+            }
+        }
+
+        if self.real {
+            match value.parse::<f64>() {
+                Ok(number) => {
+                    self.min_real = Some(self.min_real.map_or(number, |min| min.min(number)));
+                    self.max_real = Some(self.max_real.map_or(number, |max| max.max(number)));
+                }
+                Err(_) => self.real = false,
+            }
+        }
+
+        self.boolean = self.boolean
+            && (value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false"));
+
+        self.date = self.date && NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok();
+
+        self.datetime = self.datetime
+            && (DateTime::parse_from_rfc3339(value).is_ok()
+                || NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").is_ok());
+
+        if self.min_text.as_deref().is_none_or(|min| value < min) {
+            self.min_text = Some(value.to_string());
+        }
+
+        if self.max_text.as_deref().is_none_or(|max| value > max) {
+            self.max_text = Some(value.to_string());
+        }
+    }
+
+    fn finish(self, name: String, row_count: i64) -> ColumnProfile {
+        let inferred_type = if self.distinct.is_empty() {
+            // Only empty values
+            InferredColumnType::Text
+        } else if self.integer {
+            InferredColumnType::Integer
+        } else if self.real {
+            InferredColumnType::Real
+        } else if self.boolean {
+            InferredColumnType::Boolean
+        } else if self.date {
+            InferredColumnType::Date
+        } else if self.datetime {
+            InferredColumnType::Datetime
+        } else {
+            InferredColumnType::Text
+        };
+
+        let (min, max) = match inferred_type {
+            InferredColumnType::Integer => (
+                self.min_integer.map(|min| min.to_string()),
+                self.max_integer.map(|max| max.to_string()),
+            ),
+            InferredColumnType::Real => (
+                self.min_real.map(|min| min.to_string()),
+                self.max_real.map(|max| max.to_string()),
+            ),
+            _ => (self.min_text, self.max_text),
+        };
+
+        let null_ratio = if row_count > 0 {
+            self.null_count as f64 / row_count as f64
+        } else {
+            0.0
+        };
+
+        ColumnProfile {
+            name,
+            inferred_type,
+            null_count: self.null_count,
+            null_ratio,
+            distinct_count: self.distinct.len() as i64,
+            distinct_capped: self.distinct_capped,
+            min,
+            max,
+            sample_values: self.samples,
+        }
+    }
+}
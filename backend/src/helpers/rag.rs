@@ -0,0 +1,160 @@
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use rig::{
+    OneOrMany,
+    client::EmbeddingsClient,
+    embeddings::{Embedding, EmbeddingsBuilder},
+    providers::gemini::{self, embedding::EmbeddingModel},
+    vector_store::in_memory_store::{InMemoryVectorIndex, InMemoryVectorStore},
+};
+
+use crate::{
+    config::{EMBEDDING_MODEL, RAG_CHUNK_ROWS},
+    helpers::crypto::SealedDataset,
+    types::{DatasetChunk, DatasetChunkDb},
+};
+
+/// Split a dataset into groups of `RAG_CHUNK_ROWS` rows.
+/// Every chunk repeats the header so it can be understood on its own.
+pub fn chunk_dataset(dataset: &SealedDataset) -> Result<Vec<DatasetChunk>> {
+    let mut reader = dataset.csv_reader()?;
+
+    let header_line = csv_line(reader.headers()?)?;
+
+    let mut chunks = Vec::new();
+    let mut rows = String::new();
+    let mut first_row = 1;
+    let mut row_number = 0;
+
+    for record in reader.records() {
+        row_number += 1;
+        rows.push_str(&csv_line(&record?)?);
+
+        if (row_number - first_row + 1) as usize == RAG_CHUNK_ROWS {
+            chunks.push(new_chunk(
+                chunks.len(),
+                first_row,
+                row_number,
+                &header_line,
+                &rows,
+            ));
+
+            rows.clear();
+            first_row = row_number + 1;
+        }
+    }
+
+    if !rows.is_empty() {
+        chunks.push(new_chunk(
+            chunks.len(),
+            first_row,
+            row_number,
+            &header_line,
+            &rows,
+        ));
+    }
+
+    Ok(chunks)
+}
+
+fn new_chunk(
+    chunk_index: usize,
+    first_row: i64,
+    last_row: i64,
+    header_line: &str,
+    rows: &str,
+) -> DatasetChunk {
+    DatasetChunk {
+        chunk_index: chunk_index as i32,
+        first_row,
+        last_row,
+        content: format!(
+            "Rows {} to {} of the dataset:\n{}{}",
+            first_row, last_row, header_line, rows
+        ),
+    }
+}
+
+fn csv_line(record: &csv::StringRecord) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(record)?;
+
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Chunk a dataset and embed every chunk with `EMBEDDING_MODEL`.
+pub async fn embed_dataset(
+    dataset: &SealedDataset,
+    ai_model: &gemini::Client,
+) -> Result<Vec<DatasetChunkDb>> {
+    let chunks = chunk_dataset(dataset).context("Failed to chunk dataset")?;
+
+    if chunks.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let embeddings = EmbeddingsBuilder::new(ai_model.embedding_model(EMBEDDING_MODEL))
+        .documents(chunks)?
+        .build()
+        .await
+        .context("Failed to embed dataset chunks")?;
+
+    let chunks = embeddings
+        .into_iter()
+        .map(|(chunk, embedding)| DatasetChunkDb {
+            chunk_index: chunk.chunk_index,
+            first_row: chunk.first_row,
+            last_row: chunk.last_row,
+            embedding: embedding.first().vec,
+        })
+        .collect();
+
+    Ok(chunks)
+}
+
+/// Build the in memory vector index searched by an agent at prompt time.
+///
+/// Only the rows range of the stored chunks is kept, their rows are read again from `dataset`.
+pub fn build_dataset_index(
+    dataset: &SealedDataset,
+    chunks: Vec<DatasetChunkDb>,
+    ai_model: &gemini::Client,
+) -> Result<InMemoryVectorIndex<EmbeddingModel, DatasetChunk>> {
+    let dataset_chunks = chunk_dataset(dataset).context("Failed to chunk dataset")?;
+
+    if dataset_chunks.len() != chunks.len() {
+        return Err(eyre!(
+            "Dataset has {} chunks but {} are embedded",
+            dataset_chunks.len(),
+            chunks.len()
+        ));
+    }
+
+    let mut documents = Vec::with_capacity(chunks.len());
+
+    for (chunk, embedded) in dataset_chunks.into_iter().zip(chunks) {
+        if (chunk.chunk_index, chunk.first_row, chunk.last_row)
+            != (embedded.chunk_index, embedded.first_row, embedded.last_row)
+        {
+            return Err(eyre!(
+                "Embedded chunk {} does not match the dataset rows",
+                embedded.chunk_index
+            ));
+        }
+
+        let embedding = Embedding {
+            document: chunk.content.clone(),
+            vec: embedded.embedding,
+        };
+
+        documents.push((chunk, OneOrMany::one(embedding)));
+    }
+
+    let store = InMemoryVectorStore::from_documents_with_id_f(documents, |chunk| {
+        format!("chunk-{}", chunk.chunk_index)
+    });
+
+    Ok(store.index(ai_model.embedding_model(EMBEDDING_MODEL)))
+}
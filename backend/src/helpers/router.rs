@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use color_eyre::{Result, eyre::Context};
+use rig::{
+    client::EmbeddingsClient, completion::Prompt, embeddings::EmbeddingModel as _,
+    providers::gemini,
+};
+use serde_json::json;
+
+use crate::{
+    config::{EMBEDDING_MODEL, ROUTER_AGENT_MODEL},
+    database,
+    types::{AgentDb, DatasetProfile},
+};
+
+/// Text embedded to route prompts to an agent: what the owner says about the dataset and the
+/// columns it actually contains.
+fn routing_text(agent_db: &AgentDb, profile: Option<&DatasetProfile>) -> String {
+    let mut text = format!(
+        "{}\nCategory: {}\n{}",
+        agent_db.name, agent_db.category, agent_db.description
+    );
+
+    if let Some(profile) = profile {
+        text.push_str("\nColumns: ");
+        text.push_str(&column_names(profile).join(", "));
+    }
+
+    text
+}
+
+fn column_names(profile: &DatasetProfile) -> Vec<&str> {
+    profile
+        .columns
+        .iter()
+        .map(|column| column.name.as_str())
+        .collect()
+}
+
+/// Embed a text with `EMBEDDING_MODEL`, scaled to unit length so a dot product is the cosine
+/// similarity.
+pub async fn embed_text(ai_model: &gemini::Client, text: &str) -> Result<Vec<f64>> {
+    let embedding = ai_model
+        .embedding_model(EMBEDDING_MODEL)
+        .embed_text(text)
+        .await
+        .context("Failed to embed text")?;
+
+    let norm = embedding.vec.iter().map(|v| v * v).sum::<f64>().sqrt();
+
+    if norm == 0.0 {
+        return Ok(embedding.vec);
+    }
+
+    Ok(embedding.vec.into_iter().map(|v| v / norm).collect())
+}
+
+/// Embed the description of an agent and store it with the agent, done once at upload.
+pub async fn embed_agent_description(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ai_model: &gemini::Client,
+    agent_db: &AgentDb,
+    profile: Option<&DatasetProfile>,
+) -> Result<()> {
+    let embedding = embed_text(ai_model, &routing_text(agent_db, profile)).await?;
+
+    database::update_agent_description_embedding(tx, agent_db.id, &embedding, EMBEDDING_MODEL)
+        .await?;
+
+    Ok(())
+}
+
+/// Embed the descriptions that were never embedded with `EMBEDDING_MODEL`
+/// (agents created before routing by similarity, or after `EMBEDDING_MODEL` changed).
+pub async fn backfill_description_embeddings(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    ai_model: &gemini::Client,
+) -> Result<()> {
+    let agent_ids = database::get_agents_without_description_embedding(db, EMBEDDING_MODEL).await?;
+
+    if agent_ids.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("Embedding the description of {} agents", agent_ids.len());
+
+    let agents = database::get_agents_by_ids(db, &agent_ids).await?;
+    let profiles = database::get_agent_profiles_by_ids(db, &agent_ids).await?;
+
+    for agent_db in &agents {
+        let mut tx = db.begin().await?;
+
+        embed_agent_description(&mut tx, ai_model, agent_db, profiles.get(&agent_db.id)).await?;
+
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// Let the router model keep the candidates that can answer the prompt, most relevant first.
+/// Only the short list found by similarity is sent, never every agent.
+pub async fn rerank_agents(
+    ai_model: &gemini::Client,
+    prompt: &str,
+    candidates: Vec<AgentDb>,
+    profiles: &HashMap<i64, DatasetProfile>,
+) -> Result<Vec<AgentDb>> {
+    if candidates.is_empty() {
+        return Ok(candidates);
+    }
+
+    let agent = ai_model
+        .agent(ROUTER_AGENT_MODEL)
+        .preamble("You are an AI agent that your main and only task is to return the ids of the agents that can respond to the user question, the most relevant first. You decide wether to return an agent id by using their name, category, description and dataset columns, given as a JSON array. Remeber to always only return the response as a JSON array of agents id. If you can't find anyone just return an empty array. Exemple of response : [5, 9]. ")
+        .temperature(0.0)
+        .build();
+
+    // Serialized rather than formatted, names and descriptions are free text
+    let candidates_json = json!(
+        candidates
+            .iter()
+            .map(|agent_db| json!({
+                "id": agent_db.id,
+                "name": agent_db.name,
+                "category": agent_db.category.to_string(),
+                "description": agent_db.description,
+                "columns": profiles
+                    .get(&agent_db.id)
+                    .map(column_names)
+                    .unwrap_or_default(),
+            }))
+            .collect::<Vec<_>>()
+    );
+
+    let response = agent
+        .prompt(format!(
+            "User question: {}\n\nAgents: {}",
+            prompt, candidates_json
+        ))
+        .await?;
+
+    tracing::debug!("Router AI response: {}", response);
+
+    // Remove any markdown from the response
+    let formatted_response = response.replace("```json", "").replace("```", "");
+
+    let agent_ids: Vec<i64> = serde_json::from_str(formatted_response.trim())
+        .context("Failed to parse router AI response as agent ids")?;
+
+    // The model may return an id that was not a candidate, only candidates are kept
+    let mut candidates: HashMap<i64, AgentDb> = candidates
+        .into_iter()
+        .map(|agent_db| (agent_db.id, agent_db))
+        .collect();
+
+    Ok(agent_ids
+        .into_iter()
+        .filter_map(|agent_id| candidates.remove(&agent_id))
+        .collect())
+}
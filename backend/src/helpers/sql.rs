@@ -0,0 +1,469 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use rig::{completion::ToolDefinition, tool::Tool};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use sqlx::{
+    Column, Row, Sqlite, SqlitePool, TypeInfo, ValueRef,
+    pool::PoolConnection,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
+};
+
+use crate::{
+    config::{DATASET_QUERY_MAX_ROWS, DATASET_QUERY_TIMEOUT_SECS, DATASET_TABLE_NAME},
+    helpers::crypto::SealedDataset,
+};
+
+/// SQLite virtual machine instructions run between two checks of the query deadline.
+const PROGRESS_HANDLER_OPS: i32 = 1000;
+
+/// `SQLITE_INTERRUPT`, the result code of a statement stopped by the progress handler.
+const SQLITE_INTERRUPT: &str = "9";
+
+/// SQLite column type inferred from the values of a CSV column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl ColumnType {
+    pub fn sql_name(&self) -> &'static str {
+        match self {
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Text => "TEXT",
+        }
+    }
+}
+
+/// An agent dataset loaded in its own in memory SQLite database.
+#[derive(Debug, Clone)]
+pub struct DatasetTable {
+    pub pool: SqlitePool,
+    pub columns: Vec<(String, ColumnType)>,
+}
+
+impl DatasetTable {
+    /// `CREATE TABLE` statement of the dataset, shown to the model so it can write queries.
+    pub fn schema(&self) -> String {
+        create_table_sql(&self.columns)
+    }
+}
+
+/// Load a dataset into a typed `DATASET_TABLE_NAME` table of a private in memory database.
+///
+/// The rows are read twice, once to type the columns and once to insert them.
+/// The pool keeps a single connection that never expires, the database lives as long as the pool.
+/// The connection is switched to `query_only` once the rows are inserted.
+pub async fn load_dataset_table(dataset: &SealedDataset) -> Result<DatasetTable> {
+    let mut reader = dataset.csv_reader()?;
+
+    let headers = column_names(reader.headers()?);
+    let mut column_types = vec![ColumnType::Integer; headers.len()];
+
+    for record in reader.records() {
+        let record = record?;
+
+        for (column_type, value) in column_types.iter_mut().zip(record.iter()) {
+            *column_type = narrow_column_type(*column_type, value);
+        }
+    }
+
+    let columns: Vec<(String, ColumnType)> = headers.into_iter().zip(column_types).collect();
+
+    let pool = SqlitePoolOptions::new()
+        .min_connections(1)
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(SqliteConnectOptions::from_str("sqlite::memory:")?)
+        .await?;
+
+    sqlx::query(&create_table_sql(&columns))
+        .execute(&pool)
+        .await?;
+
+    let insert_sql = format!(
+        "INSERT INTO {} VALUES ({})",
+        DATASET_TABLE_NAME,
+        vec!["?"; columns.len()].join(", ")
+    );
+
+    let mut tx = pool.begin().await?;
+
+    for record in dataset.csv_reader()?.records() {
+        let record = record?;
+        let mut query = sqlx::query(&insert_sql);
+
+        for (i, (_, column_type)) in columns.iter().enumerate() {
+            let value = record.get(i).map(str::trim).filter(|v| !v.is_empty());
+
+            query = match (column_type, value) {
+                (_, None) => query.bind(None::<String>),
+                (ColumnType::Integer, Some(v)) => query.bind(v.parse::<i64>()?),
+                (ColumnType::Real, Some(v)) => query.bind(v.parse::<f64>()?),
+                (ColumnType::Text, Some(v)) => query.bind(v.to_string()),
+            };
+        }
+
+        query.execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+
+    // The dataset must never be modified by agent queries
+    sqlx::query("PRAGMA query_only = ON").execute(&pool).await?;
+
+    Ok(DatasetTable { pool, columns })
+}
+
+/// Column names made unique and never empty, so they can always be quoted as identifiers.
+pub fn column_names(headers: &csv::StringRecord) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for (i, header) in headers.iter().enumerate() {
+        let mut name = header.trim().to_string();
+
+        if name.is_empty() {
+            name = format!("column_{}", i + 1);
+        }
+
+        if names.iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+            name = format!("{}_{}", name, i + 1);
+        }
+
+        names.push(name);
+    }
+
+    names
+}
+
+/// Type of a column once `value` is seen, starting from Integer: Integer if every non empty
+/// value is an integer, Real if they are all numbers, Text otherwise.
+fn narrow_column_type(column_type: ColumnType, value: &str) -> ColumnType {
+    let value = value.trim();
+
+    if value.is_empty() || column_type == ColumnType::Text {
+        return column_type;
+    }
+
+    if column_type == ColumnType::Integer && value.parse::<i64>().is_ok() {
+        ColumnType::Integer
+    } else if value.parse::<f64>().is_ok() {
+        ColumnType::Real
+    } else {
+        ColumnType::Text
+    }
+}
+
+fn create_table_sql(columns: &[(String, ColumnType)]) -> String {
+    let columns = columns
+        .iter()
+        .map(|(name, column_type)| format!("{} {}", quote_identifier(name), column_type.sql_name()))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!("CREATE TABLE {} ({})", DATASET_TABLE_NAME, columns)
+}
+
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Query ran longer than {0:?} and was interrupted, write a cheaper query")]
+pub struct QueryTimedOut(pub Duration);
+
+/// Run `query` on the connection of a dataset `pool`, interrupting it after `timeout`.
+///
+/// A progress handler makes SQLite abort the statement past the deadline, so the single
+/// connection of the pool is given back even when the task is no longer awaited.
+/// The query runs in its own task because tool calls must be Sync and sqlx futures are not.
+pub async fn run_bounded_query<T, E, F, Fut>(
+    pool: &SqlitePool,
+    timeout: Duration,
+    query: F,
+) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<sqlx::Error> + From<tokio::task::JoinError> + From<QueryTimedOut>,
+    F: FnOnce(PoolConnection<Sqlite>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, sqlx::Error>> + Send,
+{
+    let deadline = Instant::now() + timeout;
+    let pool = pool.clone();
+
+    let task = tokio::spawn(async move {
+        let mut conn = pool.acquire().await?;
+
+        // Replaces the handler of the previous query on this connection
+        conn.lock_handle()
+            .await?
+            .set_progress_handler(PROGRESS_HANDLER_OPS, move || Instant::now() < deadline);
+
+        query(conn).await
+    });
+
+    match tokio::time::timeout(timeout, task).await {
+        Err(_) => Err(QueryTimedOut(timeout).into()),
+        Ok(Err(error)) => Err(error.into()),
+        Ok(Ok(Err(error)))
+            if error
+                .as_database_error()
+                .and_then(|error| error.code())
+                .is_some_and(|code| code == SQLITE_INTERRUPT) =>
+        {
+            Err(QueryTimedOut(timeout).into())
+        }
+        Ok(Ok(result)) => Ok(result?),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueryDatasetError {
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("Query failed: {0}")]
+    QueryFailed(#[from] sqlx::Error),
+    #[error("Query task failed: {0}")]
+    TaskFailed(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    TimedOut(#[from] QueryTimedOut),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryDatasetArgs {
+    pub query: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryDatasetOutput {
+    pub rows: Vec<Map<String, Value>>,
+    /// True when the result was cut at `DATASET_QUERY_MAX_ROWS` rows
+    pub truncated: bool,
+}
+
+/// Read only SQL access of an agent to its own dataset table.
+#[derive(Debug, Clone)]
+pub struct QueryDatasetTool {
+    pub table: DatasetTable,
+}
+
+impl Tool for QueryDatasetTool {
+    const NAME: &'static str = "query_dataset";
+
+    type Error = QueryDatasetError;
+    type Args = QueryDatasetArgs;
+    type Output = QueryDatasetOutput;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: format!(
+                "Run a single read only SQLite SELECT query against the dataset and get the resulting rows as JSON. Use it to compute counts, sums, averages, groupings and filters instead of estimating them. The table schema is: {}. At most {} rows are returned and queries running longer than {} seconds are interrupted.",
+                self.table.schema(),
+                DATASET_QUERY_MAX_ROWS,
+                DATASET_QUERY_TIMEOUT_SECS
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": format!("A SQLite SELECT (or WITH ... SELECT) query on the {} table", DATASET_TABLE_NAME)
+                    }
+                },
+                "required": ["query"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let query = validate_query(&args.query)?;
+
+        tracing::debug!("Running dataset query: {}", query);
+
+        // Fetch one more row than allowed to know if the result was truncated, the line break
+        // ends a trailing line comment of the query
+        let limited_query = format!(
+            "SELECT * FROM ({}\n) LIMIT {}",
+            query,
+            DATASET_QUERY_MAX_ROWS + 1
+        );
+
+        let rows = run_bounded_query::<_, QueryDatasetError, _, _>(
+            &self.table.pool,
+            Duration::from_secs(DATASET_QUERY_TIMEOUT_SECS),
+            move |mut conn| async move { sqlx::query(&limited_query).fetch_all(&mut *conn).await },
+        )
+        .await?;
+
+        let truncated = rows.len() > DATASET_QUERY_MAX_ROWS;
+
+        let rows = rows
+            .iter()
+            .take(DATASET_QUERY_MAX_ROWS)
+            .map(row_to_json)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(QueryDatasetOutput { rows, truncated })
+    }
+}
+
+/// Only a single SELECT statement is accepted, it is run wrapped in a sub query.
+fn validate_query(query: &str) -> Result<&str, QueryDatasetError> {
+    let query = query.trim().trim_end_matches(';').trim();
+
+    if has_statement_separator(query) {
+        return Err(QueryDatasetError::InvalidQuery(
+            "only a single statement is allowed".to_string(),
+        ));
+    }
+
+    let keyword = query
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_uppercase();
+
+    if keyword != "SELECT" && keyword != "WITH" {
+        return Err(QueryDatasetError::InvalidQuery(
+            "only SELECT queries are allowed".to_string(),
+        ));
+    }
+
+    Ok(query)
+}
+
+/// True if `query` has a `;` outside of string literals, quoted identifiers and comments.
+fn has_statement_separator(query: &str) -> bool {
+    let mut chars = query.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => return true,
+            // A doubled quote inside a literal reads as the end of one literal and the start of
+            // the next, which skips it all the same
+            '\'' | '"' | '`' => {
+                chars.by_ref().find(|next| *next == c);
+            }
+            '[' => {
+                chars.by_ref().find(|next| *next == ']');
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                chars.by_ref().find(|next| *next == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+
+                let mut previous = '\0';
+                chars.by_ref().find(|next| {
+                    let end = previous == '*' && *next == '/';
+                    previous = *next;
+                    end
+                });
+            }
+            _ => {}
+        }
+    }
+
+    false
+}
+
+fn row_to_json(row: &SqliteRow) -> Result<Map<String, Value>, sqlx::Error> {
+    let mut object = Map::new();
+
+    for column in row.columns() {
+        let raw = row.try_get_raw(column.ordinal())?;
+
+        let value = if raw.is_null() {
+            Value::Null
+        } else {
+            match raw.type_info().name() {
+                "INTEGER" => json!(row.try_get::<i64, _>(column.ordinal())?),
+                "REAL" => json!(row.try_get::<f64, _>(column.ordinal())?),
+                _ => json!(row.try_get::<String, _>(column.ordinal())?),
+            }
+        };
+
+        object.insert(column.name().to_string(), value);
+    }
+
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn memory_pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(SqliteConnectOptions::from_str("sqlite::memory:").unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn validate_query_accepts_semicolons_in_literals_and_comments() {
+        for query in [
+            "SELECT * FROM dataset WHERE name = 'a;b'",
+            "SELECT \"a;b\" FROM dataset;",
+            "SELECT [a;b], `c;d` FROM dataset",
+            "SELECT 1 -- first; second\nFROM dataset",
+            "SELECT 1 /* a; b */ FROM dataset",
+            "SELECT 'it''s; fine' FROM dataset",
+        ] {
+            assert!(validate_query(query).is_ok(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn validate_query_rejects_several_statements() {
+        for query in [
+            "SELECT 1; DROP TABLE dataset",
+            "SELECT 'a;b'; SELECT 2",
+            "SELECT /* ; */ 1; SELECT 2",
+            "DELETE FROM dataset",
+            "PRAGMA query_only = OFF",
+        ] {
+            assert!(validate_query(query).is_err(), "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn run_bounded_query_interrupts_and_frees_the_connection() {
+        let pool = memory_pool().await;
+
+        let endless = "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n) \
+                       SELECT COUNT(*) FROM n";
+
+        let started = Instant::now();
+        let result = run_bounded_query::<_, QueryDatasetError, _, _>(
+            &pool,
+            Duration::from_millis(200),
+            move |mut conn| async move { sqlx::query(endless).fetch_one(&mut *conn).await },
+        )
+        .await;
+
+        assert!(matches!(result, Err(QueryDatasetError::TimedOut(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // The single connection is available again with a fresh deadline
+        let row = run_bounded_query::<_, QueryDatasetError, _, _>(
+            &pool,
+            Duration::from_secs(5),
+            |mut conn| async move { sqlx::query("SELECT 42").fetch_one(&mut *conn).await },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(row.get::<i64, _>(0), 42);
+    }
+}
@@ -0,0 +1,35 @@
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+/// A file in the temporary directory, removed on drop.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Path of a new file, `kind` only tells the temporary files apart.
+    pub fn new(kind: &str) -> Self {
+        Self {
+            path: std::env::temp_dir().join(format!("enclava-{}-{}", kind, Uuid::new_v4())),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!(
+                "Failed to remove temporary file {}: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+}
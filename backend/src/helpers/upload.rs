@@ -0,0 +1,209 @@
+use std::{io::Write, path::Path};
+
+use actix_multipart::Field;
+use futures_util::TryStreamExt;
+use tokio::{fs::File, io::AsyncWriteExt, sync::mpsc};
+
+use crate::{
+    config::{APP_CONFIG, MAX_DATASET_FILE_SIZE},
+    helpers::{crypto::DatasetEncryptor, csv::CsvStreamValidator, formats, temp_file::TempFile},
+    types::DatasetFormat,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("File too large. Maximum size is {} MB", MAX_DATASET_FILE_SIZE / (1024 * 1024))]
+    TooLarge,
+    #[error("Invalid CSV format: {0}")]
+    InvalidCsv(color_eyre::Report),
+    #[error("Invalid {format:?} file: {error}")]
+    InvalidDataset {
+        format: DatasetFormat,
+        error: color_eyre::Report,
+    },
+    #[error("Failed to read uploaded file: {0}")]
+    Read(#[from] actix_multipart::MultipartError),
+    #[error("Failed to save file: {0}")]
+    Save(color_eyre::Report),
+}
+
+/// An uploaded dataset, normalized to CSV, validated and encrypted in a temporary file.
+pub struct EncryptedUpload {
+    file: TempFile,
+    /// Size of the uploaded file
+    pub file_size: u64,
+    pub row_count: usize,
+}
+
+impl EncryptedUpload {
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+}
+
+/// Where the normalized CSV of an upload goes, chunk by chunk.
+struct CsvSink {
+    validator: CsvStreamValidator,
+    encryptor: Option<DatasetEncryptor<File>>,
+    csv_size: u64,
+}
+
+impl CsvSink {
+    async fn write(&mut self, chunk: &[u8]) -> Result<(), UploadError> {
+        // Conversion can grow a compressed file a lot, the limit applies to the CSV as well
+        self.csv_size += chunk.len() as u64;
+
+        if self.csv_size > MAX_DATASET_FILE_SIZE {
+            return Err(UploadError::TooLarge);
+        }
+
+        self.validator
+            .update(chunk)
+            .map_err(UploadError::InvalidCsv)?;
+
+        if let Some(encryptor) = &mut self.encryptor {
+            encryptor.write(chunk).await.map_err(UploadError::Save)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Stream a dataset file field, normalized to CSV, to an encrypted temporary file and validate
+/// it on the way. Only a few chunks of the file are in memory at a time.
+pub async fn receive_encrypted_dataset(
+    field: &mut Field,
+    format: DatasetFormat,
+) -> Result<EncryptedUpload, UploadError> {
+    let file = TempFile::new("upload");
+
+    let output = File::create(file.path())
+        .await
+        .map_err(|e| UploadError::Save(e.into()))?;
+
+    let mut sink = CsvSink {
+        validator: CsvStreamValidator::new(0),
+        encryptor: Some(
+            DatasetEncryptor::new(output, &APP_CONFIG.dataset_master_key)
+                .await
+                .map_err(UploadError::Save)?,
+        ),
+        csv_size: 0,
+    };
+
+    let file_size = receive_normalized(field, format, &mut sink).await?;
+
+    let row_count = sink.validator.finish().map_err(UploadError::InvalidCsv)?;
+
+    if let Some(encryptor) = sink.encryptor {
+        encryptor.finish().await.map_err(UploadError::Save)?;
+    }
+
+    Ok(EncryptedUpload {
+        file,
+        file_size,
+        row_count,
+    })
+}
+
+/// Validate a dataset file field without storing it.
+/// Returns its header and first `sample_rows` rows, normalized to CSV.
+pub async fn receive_dataset_sample(
+    field: &mut Field,
+    format: DatasetFormat,
+    sample_rows: usize,
+) -> Result<String, UploadError> {
+    let mut sink = CsvSink {
+        validator: CsvStreamValidator::new(sample_rows),
+        encryptor: None,
+        csv_size: 0,
+    };
+
+    receive_normalized(field, format, &mut sink).await?;
+
+    sink.validator.finish().map_err(UploadError::InvalidCsv)?;
+
+    Ok(sink.validator.into_sample())
+}
+
+/// Pass the field to `sink` as CSV. Returns the size of the uploaded file.
+async fn receive_normalized(
+    field: &mut Field,
+    format: DatasetFormat,
+    sink: &mut CsvSink,
+) -> Result<u64, UploadError> {
+    let mut file_size = 0u64;
+
+    // CSV goes straight to the sink
+    if format == DatasetFormat::Csv {
+        while let Some(chunk) = field.try_next().await? {
+            file_size += chunk.len() as u64;
+
+            if file_size > MAX_DATASET_FILE_SIZE {
+                return Err(UploadError::TooLarge);
+            }
+
+            sink.write(&chunk).await?;
+        }
+
+        return Ok(file_size);
+    }
+
+    // Other formats need the whole file to be read (Parquet footer, XLSX zip directory)
+    let raw_file = TempFile::new("upload");
+
+    let mut raw = File::create(raw_file.path())
+        .await
+        .map_err(|e| UploadError::Save(e.into()))?;
+
+    while let Some(chunk) = field.try_next().await? {
+        file_size += chunk.len() as u64;
+
+        if file_size > MAX_DATASET_FILE_SIZE {
+            return Err(UploadError::TooLarge);
+        }
+
+        raw.write_all(&chunk)
+            .await
+            .map_err(|e| UploadError::Save(e.into()))?;
+    }
+
+    raw.flush().await.map_err(|e| UploadError::Save(e.into()))?;
+
+    // Convert on a blocking thread, the CSV comes back through a bounded channel
+    let (sender, mut receiver) = mpsc::channel(4);
+    let raw_path = raw_file.path().to_path_buf();
+
+    let converter = tokio::task::spawn_blocking(move || {
+        formats::normalize_to_csv(format, &raw_path, ChannelWriter(sender))
+    });
+
+    while let Some(chunk) = receiver.recv().await {
+        // Dropping the receiver on error stops the converter
+        sink.write(&chunk).await?;
+    }
+
+    converter
+        .await
+        .map_err(|e| UploadError::Save(e.into()))?
+        .map_err(|error| UploadError::InvalidDataset { format, error })?;
+
+    Ok(file_size)
+}
+
+/// Blocking writer sending what is written to an async task.
+struct ChannelWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.blocking_send(buf.to_vec()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Upload was cancelled")
+        })?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,9 @@
+pub mod api;
+pub mod config;
+pub mod database;
+pub mod fetcher;
+pub mod helpers;
+pub mod state;
+pub mod storage;
+pub mod tee;
+pub mod types;
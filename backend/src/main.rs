@@ -1,21 +1,13 @@
-mod api;
-mod config;
-mod database;
-mod fetcher;
-mod helpers;
-mod state;
-mod tee;
-mod types;
-
 use actix_cors::Cors;
 use actix_web::{App, HttpServer, web};
 
 use tracing::{error, info};
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
 use utoipa_actix_web::AppExt;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::{config::APP_CONFIG, state::AppState};
+use enclava_backend::{api, config::APP_CONFIG, fetcher, helpers, state::AppState, storage};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -43,6 +35,11 @@ async fn main() -> std::io::Result<()> {
 
     info!("Logger initialized Successfully");
 
+    // One off maintenance commands: `enclava_backend <command>`
+    if let Some(command) = std::env::args().nth(1) {
+        return run_command(&command).await;
+    }
+
     // Initialize a new application state
     let app_state = web::Data::new(AppState::new().await);
 
@@ -51,10 +48,7 @@ async fn main() -> std::io::Result<()> {
         .await
         .map_err(|e| {
             error!("Failed to start fetchers: {:?}", e);
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Enclava Fetcher error: {:?}", e),
-            )
+            std::io::Error::other(format!("Enclava Fetcher error: {:?}", e))
         })?;
 
     let port = APP_CONFIG.port;
@@ -83,14 +77,68 @@ async fn main() -> std::io::Result<()> {
             .service(api::get_all_agents_service)
             .service(api::get_agents_for_prompt_service)
             .service(api::get_response_from_agents_service)
+            .service(api::stream::stream_response_from_agents_service)
+            .service(api::chat::get_chat_session_service)
+            .service(api::admin::get_credits_service)
+            .service(api::admin::settle_credits_service)
             .service(api::get_datasets_stats_service)
             .service(api::profile::get_profile_service)
+            .service(api::profile::get_earnings_service)
+            .service(api::payment::get_payment_service)
+            .service(api::payment::get_user_payments_service)
             .service(api::get_agent_by_id_service)
+            .service(api::guardrail::get_guardrail_events_service)
+            .service(api::auth::get_auth_nonce_service)
+            .service(api::auth::verify_auth_signature_service)
+            .service(api::attestation::get_attestation_service)
+            .service(api::attestation::verify_attestation_service)
             .split_for_parts();
 
+        // Session tokens issued by /auth/verify
+        let mut app_api = app_api;
+        if let Some(components) = app_api.components.as_mut() {
+            components.add_security_scheme(
+                "bearer_auth",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+
         app.service(SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", app_api))
     })
     .bind(("127.0.0.1", port))?
     .run()
     .await
 }
+
+async fn run_command(command: &str) -> std::io::Result<()> {
+    match command {
+        // Encrypt the plaintext datasets uploaded before encryption at rest
+        "encrypt-datasets" => {
+            let dataset_store = storage::new_dataset_store().map_err(|e| {
+                error!("Failed to initialize the dataset store: {:?}", e);
+                std::io::Error::other(format!("Dataset store error: {:?}", e))
+            })?;
+
+            let encrypted = helpers::crypto::encrypt_existing_datasets(
+                dataset_store.as_ref(),
+                &APP_CONFIG.dataset_master_key,
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to encrypt datasets: {:?}", e);
+                std::io::Error::other(format!("Dataset encryption error: {:?}", e))
+            })?;
+
+            info!("{} datasets encrypted", encrypted);
+
+            Ok(())
+        }
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Unknown command {}, available commands: encrypt-datasets",
+                command
+            ),
+        )),
+    }
+}
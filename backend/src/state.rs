@@ -1,18 +1,25 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 
-use dashmap::{DashMap, DashSet};
-use rig::{agent::Agent, client::ProviderClient, providers};
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
+use rig::{client::ProviderClient, providers};
 use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
 
-use crate::{config::APP_CONFIG, helpers::agents::load_db_agents};
+use crate::{
+    config::APP_CONFIG,
+    helpers::agents::load_db_agents,
+    storage::{self, DatasetStore},
+    tee::{self, TeeExecutor},
+};
 
 use tracing::info;
 
 pub struct AppState {
     pub db: Pool<Postgres>,
     pub ai_model: providers::gemini::Client,
-    pub tee_agents: DashMap<i64, Agent<providers::gemini::completion::CompletionModel>>,
-    pub handled_txs: DashSet<String>,
+    /// Http provider of the chain, shared by the request handlers
+    pub rpc: DynProvider,
+    pub dataset_store: Arc<dyn DatasetStore>,
+    pub tee: Box<dyn TeeExecutor>,
 }
 
 impl AppState {
@@ -33,20 +40,51 @@ impl AppState {
 
         info!("AI model client initialized successfully");
 
-        // Normally those tee agent will be on another enclave that will never stops, but for now we should intize them again using agents db table.
-        let tee_agents = load_db_agents(&db, &ai_model)
+        let rpc = ProviderBuilder::new()
+            .connect_http(
+                APP_CONFIG
+                    .alchemy_rpc_url
+                    .parse()
+                    .expect("ALCHEMY_RPC_URL must be a valid url"),
+            )
+            .erased();
+
+        let dataset_store =
+            storage::new_dataset_store().expect("Failed to initialize the dataset store");
+
+        info!(
+            "Dataset store {} initialized successfully",
+            dataset_store.name()
+        );
+
+        let tee = tee::new_executor(&ai_model, dataset_store.clone())
+            .expect("Failed to initialize the tee executor");
+
+        info!("Tee executor {} initialized successfully", tee.name());
+
+        // The enclave does not persist agents, hand every agent of the agents db table over again.
+        load_db_agents(&db, &ai_model, tee.as_ref())
             .await
             .expect("Failed to load agents from database");
 
-        info!("{} Tee agents loaded successfully", tee_agents.len());
+        info!("{} Tee agents loaded successfully", tee.agent_count());
+
+        let quote = tee
+            .quote()
+            .await
+            .expect("Failed to get the enclave attestation quote");
 
-        let handled_txs = DashSet::new();
+        info!(
+            "Attestation provider {} initialized, measurement {} signer {}",
+            quote.provider, quote.measurement, quote.signer_address
+        );
 
         Self {
             db,
             ai_model,
-            tee_agents,
-            handled_txs,
+            rpc,
+            dataset_store,
+            tee,
         }
     }
 }
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+
+use crate::storage::{DatasetStore, StoreFuture, validate_key};
+
+/// Datasets stored as files of a local directory.
+pub struct FsDatasetStore {
+    root: PathBuf,
+}
+
+impl FsDatasetStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl DatasetStore for FsDatasetStore {
+    fn name(&self) -> &'static str {
+        "fs"
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            validate_key(key)?;
+
+            // Create the datasets directory if it doesn't exist
+            tokio::fs::create_dir_all(&self.root).await?;
+
+            // Write next to the final file and swap, readers never see a partial dataset
+            let tmp_path = self.root.join(format!(".{}.tmp", key));
+
+            tokio::fs::write(&tmp_path, data).await?;
+            tokio::fs::rename(&tmp_path, self.root.join(key)).await?;
+
+            Ok(())
+        })
+    }
+
+    fn put_file<'a>(&'a self, key: &'a str, path: &'a Path) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            validate_key(key)?;
+
+            tokio::fs::create_dir_all(&self.root).await?;
+
+            // Copy rather than rename, the source may be on another filesystem
+            let tmp_path = self.root.join(format!(".{}.tmp", key));
+
+            tokio::fs::copy(path, &tmp_path).await?;
+            tokio::fs::rename(&tmp_path, self.root.join(key)).await?;
+
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            validate_key(key)?;
+
+            Ok(tokio::fs::read(self.root.join(key)).await?)
+        })
+    }
+
+    fn get_file<'a>(&'a self, key: &'a str, path: &'a Path) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            validate_key(key)?;
+
+            tokio::fs::copy(self.root.join(key), path).await?;
+
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            validate_key(key)?;
+
+            tokio::fs::remove_file(self.root.join(key)).await?;
+
+            Ok(())
+        })
+    }
+
+    fn list(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+
+            let mut entries = match tokio::fs::read_dir(&self.root).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(keys),
+                Err(e) => return Err(e.into()),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                if !entry.file_type().await?.is_file() {
+                    continue;
+                }
+
+                if let Some(key) = entry.file_name().to_str() {
+                    // Skip the temporary files of interrupted writes
+                    if validate_key(key).is_ok() {
+                        keys.push(key.to_string());
+                    }
+                }
+            }
+
+            Ok(keys)
+        })
+    }
+}
@@ -0,0 +1,57 @@
+pub mod fs;
+pub mod pg;
+pub mod s3;
+
+use std::{future::Future, path::Path, pin::Pin, sync::Arc};
+
+use color_eyre::Result;
+
+use crate::{
+    config::{APP_CONFIG, DatasetStoreBackend, UPLOAD_DIR},
+    storage::{fs::FsDatasetStore, pg::PgDatasetStore, s3::S3DatasetStore},
+};
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Where dataset files are kept, addressed by the `dataset_path` key of their agent.
+///
+/// Every dataset read and write goes through this trait so several backend instances
+/// can share the same datasets.
+pub trait DatasetStore: Send + Sync {
+    /// Backend name, for logs.
+    fn name(&self) -> &'static str;
+
+    /// Store `data` under `key`, replacing any previous content atomically.
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> StoreFuture<'a, ()>;
+
+    /// Store the content of the local file at `path` under `key` without loading it in memory.
+    fn put_file<'a>(&'a self, key: &'a str, path: &'a Path) -> StoreFuture<'a, ()>;
+
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Vec<u8>>;
+
+    /// Write the content stored under `key` to the local file at `path` without loading it in memory.
+    fn get_file<'a>(&'a self, key: &'a str, path: &'a Path) -> StoreFuture<'a, ()>;
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()>;
+
+    /// Keys of every stored dataset.
+    fn list(&self) -> StoreFuture<'_, Vec<String>>;
+}
+
+/// Build the store selected by `DATASET_STORE`.
+pub fn new_dataset_store() -> Result<Arc<dyn DatasetStore>> {
+    match APP_CONFIG.dataset_store {
+        DatasetStoreBackend::Fs => Ok(Arc::new(FsDatasetStore::new(UPLOAD_DIR))),
+        DatasetStoreBackend::S3 => Ok(Arc::new(S3DatasetStore::from_config()?)),
+        DatasetStoreBackend::Pg => Ok(Arc::new(PgDatasetStore::from_config()?)),
+    }
+}
+
+/// Keys are flat file names, never paths.
+pub fn validate_key(key: &str) -> Result<()> {
+    if key.is_empty() || key.starts_with('.') || key.contains(['/', '\\']) {
+        return Err(color_eyre::eyre::eyre!("Invalid dataset key {}", key));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,272 @@
+use std::path::Path;
+
+use color_eyre::{Result, eyre::eyre};
+use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    config::APP_CONFIG,
+    database,
+    storage::{DatasetStore, StoreFuture, validate_key},
+};
+
+/// Datasets are written and read in slices of this size, never held whole by a query.
+const LARGE_OBJECT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Datasets stored as Postgres large objects, in the database of the backend.
+pub struct PgDatasetStore {
+    db: Pool<Postgres>,
+}
+
+impl PgDatasetStore {
+    pub fn new(db: Pool<Postgres>) -> Self {
+        Self { db }
+    }
+
+    /// Store using its own connections to `DATABASE_URL`, opened on first use.
+    pub fn from_config() -> Result<Self> {
+        let db = PgPoolOptions::new()
+            .max_connections(2)
+            .connect_lazy(&APP_CONFIG.database_url)?;
+
+        Ok(Self::new(db))
+    }
+
+    /// Store the content of `reader` under `key` in a new large object. The previous one is
+    /// unlinked in the same transaction, readers see either the old or the new dataset.
+    async fn write(&self, key: &str, mut reader: impl AsyncRead + Unpin + Send) -> Result<()> {
+        validate_key(key)?;
+
+        let mut tx = self.db.begin().await?;
+
+        let oid = database::create_large_object(&mut tx).await?;
+
+        let mut buffer = vec![0u8; LARGE_OBJECT_CHUNK_SIZE];
+        let mut offset = 0;
+
+        loop {
+            let read = reader.read(&mut buffer).await?;
+
+            if read == 0 {
+                break;
+            }
+
+            database::write_large_object(&mut tx, oid, offset, &buffer[..read]).await?;
+            offset += read as i64;
+        }
+
+        if let Some(previous) = database::lock_dataset_object(&mut tx, key).await? {
+            database::unlink_large_object(&mut tx, previous).await?;
+        }
+
+        database::upsert_dataset_object(&mut tx, key, oid).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Copy the dataset stored under `key` to `writer`.
+    async fn read(&self, key: &str, mut writer: impl AsyncWrite + Unpin + Send) -> Result<()> {
+        validate_key(key)?;
+
+        // Every slice comes from the same snapshot, even if the dataset is replaced meanwhile
+        let mut tx = self
+            .db
+            .begin_with("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .await?;
+
+        let oid = database::get_dataset_object(&mut tx, key)
+            .await?
+            .ok_or_else(|| eyre!("No dataset stored under {}", key))?;
+
+        let mut offset = 0;
+
+        loop {
+            let data =
+                database::read_large_object(&mut tx, oid, offset, LARGE_OBJECT_CHUNK_SIZE as i32)
+                    .await?;
+
+            writer.write_all(&data).await?;
+            offset += data.len() as i64;
+
+            if data.len() < LARGE_OBJECT_CHUNK_SIZE {
+                break;
+            }
+        }
+
+        writer.flush().await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+impl DatasetStore for PgDatasetStore {
+    fn name(&self) -> &'static str {
+        "pg"
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> StoreFuture<'a, ()> {
+        Box::pin(self.write(key, std::io::Cursor::new(data)))
+    }
+
+    fn put_file<'a>(&'a self, key: &'a str, path: &'a Path) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let file = tokio::fs::File::open(path).await?;
+
+            self.write(key, file).await
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            let mut data = Vec::new();
+
+            self.read(key, &mut data).await?;
+
+            Ok(data)
+        })
+    }
+
+    fn get_file<'a>(&'a self, key: &'a str, path: &'a Path) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let file = tokio::fs::File::create(path).await?;
+
+            self.read(key, file).await
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            validate_key(key)?;
+
+            let mut tx = self.db.begin().await?;
+
+            if !database::delete_dataset_object(&mut tx, key).await? {
+                return Err(eyre!("No dataset stored under {}", key));
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    }
+
+    fn list(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(async move { Ok(database::get_dataset_object_keys(&self.db).await?) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::types::Oid;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::helpers::temp_file::TempFile;
+
+    // Runs against the database the query macros are checked with
+    async fn database_store() -> PgDatasetStore {
+        let db = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&std::env::var("DATABASE_URL").expect("DATABASE_URL must be set"))
+            .await
+            .unwrap();
+
+        PgDatasetStore::new(db)
+    }
+
+    // Tests share the database, each one uses its own keys
+    fn unique_key(name: &str) -> String {
+        format!("{}-{}", Uuid::new_v4(), name)
+    }
+
+    async fn stored_oid(store: &PgDatasetStore, key: &str) -> Option<Oid> {
+        let mut tx = store.db.begin().await.unwrap();
+
+        database::get_dataset_object(&mut tx, key).await.unwrap()
+    }
+
+    async fn large_object_exists(store: &PgDatasetStore, oid: Oid) -> bool {
+        sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM pg_largeobject_metadata WHERE oid = $1)",
+        )
+        .bind(oid)
+        .fetch_one(&store.db)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn put_get_and_delete() {
+        let store = database_store().await;
+        let key = unique_key("a.csv");
+
+        store.put(&key, b"a,b\n1,2\n".to_vec()).await.unwrap();
+        let replaced = stored_oid(&store, &key).await.unwrap();
+
+        store.put(&key, b"a,b\n3,4\n".to_vec()).await.unwrap();
+        let current = stored_oid(&store, &key).await.unwrap();
+
+        assert_eq!(store.get(&key).await.unwrap(), b"a,b\n3,4\n");
+        assert!(store.list().await.unwrap().contains(&key));
+
+        // The replaced large object does not leak
+        assert!(!large_object_exists(&store, replaced).await);
+
+        store.delete(&key).await.unwrap();
+
+        assert!(!large_object_exists(&store, current).await);
+        assert!(store.get(&key).await.is_err());
+        assert!(store.delete(&key).await.is_err());
+        assert!(!store.list().await.unwrap().contains(&key));
+    }
+
+    #[tokio::test]
+    async fn files_are_streamed_both_ways() {
+        let store = database_store().await;
+        let key = unique_key("big.csv");
+
+        // Several slices, the last one partial
+        let data: Vec<u8> = (0..3 * LARGE_OBJECT_CHUNK_SIZE + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+
+        let source = TempFile::new("pg-source");
+        std::fs::write(source.path(), &data).unwrap();
+
+        store.put_file(&key, source.path()).await.unwrap();
+
+        let destination = TempFile::new("pg-destination");
+        store.get_file(&key, destination.path()).await.unwrap();
+
+        assert_eq!(std::fs::read(destination.path()).unwrap(), data);
+
+        store.delete(&key).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn empty_dataset() {
+        let store = database_store().await;
+        let key = unique_key("empty.csv");
+
+        store.put(&key, Vec::new()).await.unwrap();
+
+        assert!(store.get(&key).await.unwrap().is_empty());
+
+        store.delete(&key).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_keys_are_rejected() {
+        let store = database_store().await;
+
+        for key in ["", ".hidden", "../a.csv", "nested/a.csv", "nested\\a.csv"] {
+            assert!(store.put(key, Vec::new()).await.is_err(), "{}", key);
+            assert!(store.get(key).await.is_err(), "{}", key);
+            assert!(store.delete(key).await.is_err(), "{}", key);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::{path::Path, sync::Arc};
+
+use color_eyre::{Result, eyre::eyre};
+use futures_util::TryStreamExt;
+use object_store::{
+    ObjectStore, PutPayload, aws::AmazonS3Builder, buffered::BufWriter, path::Path as ObjectPath,
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    config::APP_CONFIG,
+    storage::{DatasetStore, StoreFuture, validate_key},
+};
+
+/// Datasets stored in an S3 compatible bucket (AWS S3, MinIO, ...).
+pub struct S3DatasetStore {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+}
+
+impl S3DatasetStore {
+    /// Store keeping the datasets under `prefix` of `store`.
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: impl Into<String>) -> Self {
+        Self {
+            store,
+            prefix: prefix.into(),
+        }
+    }
+
+    pub fn from_config() -> Result<Self> {
+        let bucket = APP_CONFIG
+            .s3_bucket
+            .as_ref()
+            .ok_or_else(|| eyre!("S3_BUCKET must be set when DATASET_STORE is s3"))?;
+
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .with_region(&APP_CONFIG.s3_region);
+
+        // Custom endpoints (MinIO and other stand-ins) only support path style requests
+        if let Some(endpoint) = &APP_CONFIG.s3_endpoint {
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_virtual_hosted_style_request(false)
+                .with_allow_http(endpoint.starts_with("http://"));
+        }
+
+        if let (Some(access_key_id), Some(secret_access_key)) = (
+            &APP_CONFIG.s3_access_key_id,
+            &APP_CONFIG.s3_secret_access_key,
+        ) {
+            builder = builder
+                .with_access_key_id(access_key_id)
+                .with_secret_access_key(secret_access_key);
+        }
+
+        Ok(Self::new(
+            Arc::new(builder.build()?),
+            APP_CONFIG.s3_prefix.clone(),
+        ))
+    }
+
+    fn object_path(&self, key: &str) -> Result<ObjectPath> {
+        validate_key(key)?;
+
+        Ok(ObjectPath::from(format!("{}/{}", self.prefix, key)))
+    }
+}
+
+impl DatasetStore for S3DatasetStore {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.object_path(key)?;
+
+            self.store.put(&path, PutPayload::from(data)).await?;
+
+            Ok(())
+        })
+    }
+
+    fn put_file<'a>(&'a self, key: &'a str, path: &'a Path) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let object_path = self.object_path(key)?;
+
+            let mut file = tokio::fs::File::open(path).await?;
+
+            // Large files are sent as a multipart upload, only completed on shutdown
+            let mut writer = BufWriter::new(self.store.clone(), object_path);
+
+            if let Err(e) = tokio::io::copy(&mut file, &mut writer).await {
+                writer.abort().await.ok();
+                return Err(e.into());
+            }
+
+            writer.shutdown().await?;
+
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            let path = self.object_path(key)?;
+
+            let data = self.store.get(&path).await?.bytes().await?;
+
+            Ok(data.to_vec())
+        })
+    }
+
+    fn get_file<'a>(&'a self, key: &'a str, path: &'a Path) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let object_path = self.object_path(key)?;
+
+            let mut stream = self.store.get(&object_path).await?.into_stream();
+            let mut file = tokio::fs::File::create(path).await?;
+
+            while let Some(bytes) = stream.try_next().await? {
+                file.write_all(&bytes).await?;
+            }
+
+            file.flush().await?;
+
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.object_path(key)?;
+
+            self.store.delete(&path).await?;
+
+            Ok(())
+        })
+    }
+
+    fn list(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(async move {
+            let prefix = ObjectPath::from(self.prefix.as_str());
+
+            let objects: Vec<_> = self.store.list(Some(&prefix)).try_collect().await?;
+
+            let keys = objects
+                .into_iter()
+                .filter_map(|object| object.location.filename().map(str::to_string))
+                .collect();
+
+            Ok(keys)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use object_store::memory::InMemory;
+
+    use super::*;
+    use crate::helpers::temp_file::TempFile;
+
+    fn memory_store() -> (Arc<InMemory>, S3DatasetStore) {
+        let memory = Arc::new(InMemory::new());
+        let store = S3DatasetStore::new(memory.clone(), "datasets");
+
+        (memory, store)
+    }
+
+    #[tokio::test]
+    async fn put_get_and_delete() {
+        let (memory, store) = memory_store();
+
+        store.put("a.csv", b"a,b\n1,2\n".to_vec()).await.unwrap();
+        store.put("a.csv", b"a,b\n3,4\n".to_vec()).await.unwrap();
+
+        assert_eq!(store.get("a.csv").await.unwrap(), b"a,b\n3,4\n");
+
+        // Objects are kept under the prefix
+        assert!(
+            memory
+                .head(&ObjectPath::from("datasets/a.csv"))
+                .await
+                .is_ok()
+        );
+
+        store.delete("a.csv").await.unwrap();
+
+        assert!(store.get("a.csv").await.is_err());
+        assert!(store.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn files_are_streamed_both_ways() {
+        let (_, store) = memory_store();
+
+        // Larger than the multipart chunks of the buffered writer
+        let data: Vec<u8> = (0..12 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+        let source = TempFile::new("s3-source");
+        std::fs::write(source.path(), &data).unwrap();
+
+        store.put_file("big.csv", source.path()).await.unwrap();
+
+        let destination = TempFile::new("s3-destination");
+        store.get_file("big.csv", destination.path()).await.unwrap();
+
+        assert_eq!(std::fs::read(destination.path()).unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn list_only_returns_prefixed_keys() {
+        let (memory, store) = memory_store();
+
+        store.put("a.csv", Vec::new()).await.unwrap();
+        store.put("b.json", Vec::new()).await.unwrap();
+        memory
+            .put(&ObjectPath::from("other/c.csv"), PutPayload::new())
+            .await
+            .unwrap();
+
+        let mut keys = store.list().await.unwrap();
+        keys.sort();
+
+        assert_eq!(keys, ["a.csv", "b.json"]);
+    }
+
+    #[tokio::test]
+    async fn invalid_keys_are_rejected() {
+        let (_, store) = memory_store();
+
+        for key in ["", ".hidden", "../a.csv", "nested/a.csv", "nested\\a.csv"] {
+            assert!(store.put(key, Vec::new()).await.is_err(), "{}", key);
+            assert!(store.get(key).await.is_err(), "{}", key);
+            assert!(store.delete(key).await.is_err(), "{}", key);
+        }
+
+        assert!(store.list().await.unwrap().is_empty());
+    }
+}
@@ -1,5 +0,0 @@
-use crate::types::WebAppState;
-
-pub async fn call_tee_ai_agent(_app_state: &WebAppState, _agent_id: i64, _prompt: &str) {
-    // TODO: Call the tee agent and return the response to the user
-}
@@ -0,0 +1,248 @@
+use std::{str::FromStr, sync::Arc};
+
+use alloy::{
+    hex,
+    primitives::{Address, B256, Bytes, Signature, U256, keccak256},
+    signers::{SignerSync, local::PrivateKeySigner},
+    sol_types::SolValue,
+};
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{APP_CONFIG, AttestationBackend},
+    types::AgentAttestation,
+};
+
+/// Prefix of the quotes produced by `SimulatedAttestationProvider`.
+pub const SIMULATED_QUOTE_PREFIX: &[u8] = b"ENCLAVA-SIMULATED-QUOTE-V1";
+
+/// Source of enclave attestation quotes and of the key answers are signed with.
+///
+/// The quote binds `signer_address` to the enclave `measurement` (through its report data),
+/// so a valid signature from that address proves the answer came from the measured enclave.
+pub trait AttestationProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn measurement(&self) -> B256;
+
+    fn signer_address(&self) -> Address;
+
+    fn quote(&self) -> Result<Vec<u8>>;
+
+    /// EIP-191 signature of `hash` with the enclave key.
+    fn sign(&self, hash: &B256) -> Result<Signature>;
+}
+
+/// Identity of an enclave: its measurement and the key it signs answers with, bound by its quote.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnclaveQuote {
+    pub provider: String,
+    pub measurement: B256,
+    pub signer_address: Address,
+    pub quote: Bytes,
+}
+
+/// Attestation for machines without TEE hardware.
+///
+/// The measurement is the hash of the crate version and of the commit it was built from, and the
+/// quote is signed by the enclave key itself, so it only proves which build answered, not that
+/// it ran in an enclave.
+pub struct SimulatedAttestationProvider {
+    signer: PrivateKeySigner,
+    measurement: B256,
+}
+
+impl SimulatedAttestationProvider {
+    /// Provider signing with the private key `signing_key` (hex encoded).
+    pub fn from_key(signing_key: &str) -> Result<Self> {
+        let signer = PrivateKeySigner::from_str(signing_key)
+            .context("ATTESTATION_SIGNING_KEY must be a valid private key")?;
+
+        Self::new(signer)
+    }
+
+    pub fn new(signer: PrivateKeySigner) -> Result<Self> {
+        let measurement = keccak256(
+            format!("{}@{}", env!("CARGO_PKG_VERSION"), env!("ENCLAVA_GIT_HASH")).as_bytes(),
+        );
+
+        Ok(Self {
+            signer,
+            measurement,
+        })
+    }
+}
+
+impl AttestationProvider for SimulatedAttestationProvider {
+    fn name(&self) -> &'static str {
+        "simulated"
+    }
+
+    fn measurement(&self) -> B256 {
+        self.measurement
+    }
+
+    fn signer_address(&self) -> Address {
+        self.signer.address()
+    }
+
+    fn quote(&self) -> Result<Vec<u8>> {
+        let report_data = report_data(&self.signer.address());
+        let body = simulated_quote_body(&self.measurement, &report_data);
+
+        let signature = self.sign(&keccak256(&body))?;
+
+        Ok([body, signature.as_bytes().to_vec()].concat())
+    }
+
+    fn sign(&self, hash: &B256) -> Result<Signature> {
+        Ok(self.signer.sign_message_sync(hash.as_slice())?)
+    }
+}
+
+/// Build the provider selected by `ATTESTATION_BACKEND`, for the executor that runs the agents.
+///
+/// The signing key is required: a key generated at startup would change the signer of every
+/// answer on each restart.
+pub fn new_attestation_provider() -> Result<Arc<dyn AttestationProvider>> {
+    let signing_key = APP_CONFIG
+        .attestation_signing_key
+        .as_deref()
+        .ok_or_else(|| eyre!("ATTESTATION_SIGNING_KEY must be set"))?;
+
+    match APP_CONFIG.attestation_backend {
+        AttestationBackend::Simulated => Ok(Arc::new(SimulatedAttestationProvider::from_key(
+            signing_key,
+        )?)),
+    }
+}
+
+/// Quote of the enclave `provider` runs in.
+pub fn enclave_quote(provider: &dyn AttestationProvider) -> Result<EnclaveQuote> {
+    Ok(EnclaveQuote {
+        provider: provider.name().to_string(),
+        measurement: provider.measurement(),
+        signer_address: provider.signer_address(),
+        quote: provider.quote()?.into(),
+    })
+}
+
+/// Report data embedded in a quote: the hash of the enclave signing address.
+fn report_data(signer_address: &Address) -> B256 {
+    keccak256(signer_address.as_slice())
+}
+
+fn simulated_quote_body(measurement: &B256, report_data: &B256) -> Vec<u8> {
+    [
+        SIMULATED_QUOTE_PREFIX,
+        measurement.as_slice(),
+        report_data.as_slice(),
+    ]
+    .concat()
+}
+
+/// keccak256(abi.encode(agent_id, prompt_hash, response_hash, tx_hash)), signed by the enclave.
+pub fn attestation_payload_hash(
+    agent_id: i64,
+    prompt_hash: &B256,
+    response_hash: &B256,
+    tx_hash: &B256,
+) -> B256 {
+    keccak256((U256::from(agent_id), *prompt_hash, *response_hash, *tx_hash).abi_encode())
+}
+
+/// Sign an agent answer for the payment `tx_hash`.
+pub fn attest_agent_response(
+    provider: &dyn AttestationProvider,
+    agent_id: i64,
+    prompt: &str,
+    response: &str,
+    tx_hash: &str,
+) -> Result<AgentAttestation> {
+    let tx_hash = B256::from_str(tx_hash).context("Invalid tx hash")?;
+    let prompt_hash = keccak256(prompt.as_bytes());
+    let response_hash = keccak256(response.as_bytes());
+
+    let payload_hash = attestation_payload_hash(agent_id, &prompt_hash, &response_hash, &tx_hash);
+
+    let signature = provider.sign(&payload_hash)?;
+
+    Ok(AgentAttestation {
+        provider: provider.name().to_string(),
+        measurement: provider.measurement().to_string(),
+        signer_address: provider.signer_address().to_string(),
+        quote: hex::encode_prefixed(provider.quote()?),
+        prompt_hash: prompt_hash.to_string(),
+        response_hash: response_hash.to_string(),
+        tx_hash: tx_hash.to_string(),
+        payload_hash: payload_hash.to_string(),
+        signature: hex::encode_prefixed(signature.as_bytes()),
+    })
+}
+
+/// Check that `attestation` covers `prompt` and `response` for `agent_id` and was signed by the
+/// enclave key bound in its quote. When `expected_measurement` is given the quote must match it.
+///
+/// Only simulated quotes can be checked for now.
+pub fn verify_agent_attestation(
+    attestation: &AgentAttestation,
+    agent_id: i64,
+    prompt: &str,
+    response: &str,
+    expected_measurement: Option<&B256>,
+) -> Result<bool> {
+    let prompt_hash = keccak256(prompt.as_bytes());
+    let response_hash = keccak256(response.as_bytes());
+    let tx_hash = B256::from_str(&attestation.tx_hash).context("Invalid tx hash")?;
+
+    if prompt_hash.to_string() != attestation.prompt_hash
+        || response_hash.to_string() != attestation.response_hash
+    {
+        return Ok(false);
+    }
+
+    let payload_hash = attestation_payload_hash(agent_id, &prompt_hash, &response_hash, &tx_hash);
+
+    if payload_hash.to_string() != attestation.payload_hash {
+        return Ok(false);
+    }
+
+    let signer_address =
+        Address::from_str(&attestation.signer_address).context("Invalid signer address")?;
+    let measurement = B256::from_str(&attestation.measurement).context("Invalid measurement")?;
+
+    if expected_measurement.is_some_and(|expected| *expected != measurement) {
+        return Ok(false);
+    }
+
+    // The answer must be signed by the enclave key
+    let signature = Signature::from_str(&attestation.signature).context("Invalid signature")?;
+
+    if signature.recover_address_from_msg(payload_hash.as_slice())? != signer_address {
+        return Ok(false);
+    }
+
+    // The quote must bind that key to the measurement
+    let quote = hex::decode(&attestation.quote).context("Invalid quote")?;
+
+    let body_len = SIMULATED_QUOTE_PREFIX.len() + 64;
+
+    if quote.len() != body_len + 65 || !quote.starts_with(SIMULATED_QUOTE_PREFIX) {
+        return Ok(false);
+    }
+
+    let (body, quote_signature) = quote.split_at(body_len);
+
+    if body != simulated_quote_body(&measurement, &report_data(&signer_address)) {
+        return Ok(false);
+    }
+
+    let quote_signature =
+        Signature::try_from(quote_signature).context("Invalid quote signature")?;
+
+    Ok(quote_signature.recover_address_from_msg(keccak256(body).as_slice())? == signer_address)
+}
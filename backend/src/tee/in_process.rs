@@ -0,0 +1,181 @@
+use std::{collections::HashMap, sync::Arc};
+
+use color_eyre::{Result, eyre::eyre};
+use dashmap::DashMap;
+use rig::providers::gemini;
+
+use crate::{
+    config::APP_CONFIG,
+    helpers::{
+        agents::{DatasetAgent, init_agent},
+        crypto::SealedDataset,
+        guardrail::GuardrailIndex,
+    },
+    storage::DatasetStore,
+    tee::{
+        AgentLoad, TeeAnswer, TeeExecutor, TeeFuture, TeeStream,
+        attestation::{self, AttestationProvider, EnclaveQuote},
+        ingest::{self, DatasetIngest},
+        protocol::ChatTurn,
+    },
+    types::{AgentDb, PiiAction},
+};
+
+/// Runs agents inside the backend process. There is no isolation, for development only.
+pub struct InProcessTeeExecutor {
+    ai_model: gemini::Client,
+    dataset_store: Arc<dyn DatasetStore>,
+    attestation: Arc<dyn AttestationProvider>,
+    agents: DashMap<i64, Arc<DatasetAgent>>,
+    guardrail: GuardrailIndex,
+}
+
+impl InProcessTeeExecutor {
+    pub fn new(
+        ai_model: gemini::Client,
+        dataset_store: Arc<dyn DatasetStore>,
+        attestation: Arc<dyn AttestationProvider>,
+    ) -> Self {
+        Self {
+            ai_model,
+            dataset_store,
+            attestation,
+            agents: DashMap::new(),
+            guardrail: GuardrailIndex::new(&APP_CONFIG.dataset_master_key, APP_CONFIG.guardrail),
+        }
+    }
+
+    // Clone the agent out so the map is not locked while the model answers
+    fn agent(&self, agent_id: i64) -> Result<Arc<DatasetAgent>> {
+        match self.agents.get(&agent_id) {
+            Some(agent) => Ok(agent.clone()),
+            None => Err(eyre!("Agent {} not running", agent_id)),
+        }
+    }
+}
+
+impl TeeExecutor for InProcessTeeExecutor {
+    fn name(&self) -> &'static str {
+        "in_process"
+    }
+
+    fn ingest_dataset<'a>(
+        &'a self,
+        agent_db: &'a AgentDb,
+        pii_actions: Option<&'a HashMap<String, PiiAction>>,
+    ) -> TeeFuture<'a, DatasetIngest> {
+        Box::pin(async move {
+            let dataset = SealedDataset::fetch(
+                self.dataset_store.as_ref(),
+                &agent_db.dataset_path,
+                &APP_CONFIG.dataset_master_key,
+            )
+            .await?;
+
+            // Differential privacy agents never see rows, they have nothing to retrieve
+            let embedding_model = (!agent_db.dp_enabled).then_some(&self.ai_model);
+
+            let (ingest, redacted) =
+                ingest::ingest_dataset(&dataset, pii_actions, embedding_model).await?;
+
+            if let Some(redacted) = redacted {
+                self.dataset_store
+                    .put_file(&agent_db.dataset_path, redacted.path())
+                    .await?;
+            }
+
+            Ok(ingest)
+        })
+    }
+
+    fn load_agent<'a>(&'a self, agent_db: &'a AgentDb, load: AgentLoad) -> TeeFuture<'a, ()> {
+        Box::pin(async move {
+            let dataset = SealedDataset::fetch(
+                self.dataset_store.as_ref(),
+                &agent_db.dataset_path,
+                &APP_CONFIG.dataset_master_key,
+            )
+            .await?;
+
+            let agent = init_agent(&dataset, &self.ai_model, agent_db, load.chunks).await?;
+
+            self.guardrail
+                .insert(agent_db.id, load.fingerprints, load.pii_report.as_ref());
+            self.agents.insert(agent_db.id, Arc::new(agent));
+
+            Ok(())
+        })
+    }
+
+    fn prompt<'a>(
+        &'a self,
+        agent_id: i64,
+        prompt: &'a str,
+        history: &'a [ChatTurn],
+        tx_hash: &'a str,
+    ) -> TeeFuture<'a, TeeAnswer> {
+        Box::pin(async move {
+            let answer = self.agent(agent_id)?.answer(prompt, history).await?;
+
+            // The guarded answer is what the buyer receives, so it is what gets signed
+            let guarded = self.guardrail.check_answer(agent_id, &answer);
+
+            let attestation = attestation::attest_agent_response(
+                self.attestation.as_ref(),
+                agent_id,
+                prompt,
+                &guarded.response,
+                tx_hash,
+            )?;
+
+            Ok(TeeAnswer {
+                guarded,
+                attestation,
+            })
+        })
+    }
+
+    fn prompt_stream<'a>(
+        &'a self,
+        agent_id: i64,
+        prompt: &'a str,
+        history: &'a [ChatTurn],
+        tx_hash: &'a str,
+    ) -> TeeFuture<'a, TeeStream> {
+        Box::pin(async move {
+            let agent = self.agent(agent_id)?;
+
+            let provider = self.attestation.clone();
+            let (signed_prompt, tx_hash) = (prompt.to_string(), tx_hash.to_string());
+
+            let attest = move |response: &str| {
+                attestation::attest_agent_response(
+                    provider.as_ref(),
+                    agent_id,
+                    &signed_prompt,
+                    response,
+                    &tx_hash,
+                )
+            };
+
+            Ok(agent.answer_stream(
+                prompt.to_string(),
+                history.to_vec(),
+                self.guardrail.stream(agent_id),
+                attest,
+            ))
+        })
+    }
+
+    fn quote(&self) -> TeeFuture<'_, EnclaveQuote> {
+        Box::pin(async move { attestation::enclave_quote(self.attestation.as_ref()) })
+    }
+
+    fn has_agent(&self, agent_id: i64) -> bool {
+        self.agents.contains_key(&agent_id)
+    }
+
+    fn agent_count(&self) -> usize {
+        self.agents.len()
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use color_eyre::{Report, Result};
+use rig::providers::gemini;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    helpers::{
+        crypto::SealedDataset,
+        guardrail,
+        pii::{self, PiiError},
+        profile, rag,
+    },
+    tee::protocol::{TeeErrorCode, TeeResponse},
+    types::{DatasetChunkDb, DatasetProfile, PiiAction, PiiReport},
+};
+
+/// What the backend stores about a dataset. None of it holds rows, so the backend never has to
+/// decrypt the dataset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatasetIngest {
+    /// `None` when no PII actions were given, the dataset was then not scanned
+    pub pii_report: Option<PiiReport>,
+    pub profile: DatasetProfile,
+    pub fingerprints: Vec<i64>,
+    /// Empty when the dataset was not embedded
+    pub chunks: Vec<DatasetChunkDb>,
+}
+
+/// Redact the PII of a dataset when `pii_actions` are given, then fingerprint, profile and,
+/// with an `embedding_model`, embed what is left. Called by the executor holding the dataset.
+///
+/// Returns the redacted dataset when one was written, it must replace the stored dataset.
+pub async fn ingest_dataset(
+    dataset: &SealedDataset,
+    pii_actions: Option<&HashMap<String, PiiAction>>,
+    embedding_model: Option<&gemini::Client>,
+) -> Result<(DatasetIngest, Option<SealedDataset>)> {
+    let (redacted, pii_report) = match pii_actions {
+        Some(pii_actions) => {
+            let (redacted, pii_report) = pii::redact_dataset(dataset, pii_actions)?;
+
+            (redacted, Some(pii_report))
+        }
+        None => (None, None),
+    };
+
+    // Nothing reads the dataset before its PII is redacted
+    let ingested = redacted.as_ref().unwrap_or(dataset);
+
+    let fingerprints = guardrail::dataset_fingerprints(ingested)?;
+
+    let profile = profile::profile_dataset(ingested)?;
+
+    let chunks = match embedding_model {
+        Some(embedding_model) => rag::embed_dataset(ingested, embedding_model).await?,
+        None => Vec::new(),
+    };
+
+    let ingest = DatasetIngest {
+        pii_report,
+        profile,
+        fingerprints,
+        chunks,
+    };
+
+    Ok((ingest, redacted))
+}
+
+/// Response of a failed ingestion. Unknown PII columns are the owner mistake, they are reported
+/// apart so the backend can tell the owner.
+pub fn ingest_error(error: &Report) -> TeeResponse {
+    match error.downcast_ref::<PiiError>() {
+        Some(PiiError::UnknownColumn(column)) => TeeResponse::Error {
+            code: TeeErrorCode::UnknownPiiColumn,
+            message: column.clone(),
+        },
+        None => TeeResponse::Error {
+            code: TeeErrorCode::IngestFailed,
+            message: format!("Failed to ingest dataset: {}", error),
+        },
+    }
+}
@@ -0,0 +1,131 @@
+pub mod attestation;
+pub mod in_process;
+pub mod ingest;
+pub mod protocol;
+pub mod remote;
+
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+
+use color_eyre::Result;
+use futures_util::Stream;
+use rig::providers::gemini;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{APP_CONFIG, TeeBackend},
+    helpers::guardrail::GuardrailOutcome,
+    storage::DatasetStore,
+    tee::{
+        attestation::EnclaveQuote,
+        in_process::InProcessTeeExecutor,
+        ingest::DatasetIngest,
+        protocol::{ChatTurn, TokenUsage},
+        remote::RemoteTeeExecutor,
+    },
+    types::{AgentAttestation, AgentDb, DatasetChunkDb, PiiAction, PiiReport},
+};
+
+pub type TeeFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+pub type TeeStream = Pin<Box<dyn Stream<Item = Result<TeeStreamEvent>> + Send>>;
+
+/// Event of an answer streamed by an agent.
+#[derive(Debug)]
+pub enum TeeStreamEvent {
+    /// Next part of the answer text, already checked by the output guardrail
+    Chunk(String),
+    /// Last event of the answer, with the whole answer as the guardrail released it and its
+    /// signature. A blocked answer ends early.
+    End {
+        usage: Option<TokenUsage>,
+        answer: Box<TeeAnswer>,
+    },
+}
+
+/// Final answer of an agent, checked by the output guardrail then signed, both by the enclave.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeeAnswer {
+    pub guarded: GuardrailOutcome,
+    /// Signature of the guarded response for the payment of the chat turn
+    pub attestation: AgentAttestation,
+}
+
+/// What an executor needs to start an agent besides its stored dataset.
+#[derive(Debug, Clone, Default)]
+pub struct AgentLoad {
+    /// Embedded chunks of the dataset, none for differential privacy agents
+    pub chunks: Vec<DatasetChunkDb>,
+    /// Guardrail fingerprints computed by the executor when it ingested the dataset
+    pub fingerprints: Vec<i64>,
+    /// The PII its owner kept in the dataset may appear in answers
+    pub pii_report: Option<PiiReport>,
+}
+
+/// Boundary between the backend and the place where datasets and agents live.
+///
+/// The backend only hands datasets over and forwards prompts, it never keeps agents itself.
+/// Datasets are only decrypted behind this boundary, and answers are checked by the output
+/// guardrail and signed there.
+pub trait TeeExecutor: Send + Sync {
+    /// Backend name, for logs.
+    fn name(&self) -> &'static str;
+
+    /// Compute what the backend stores about the dataset of an agent (see `DatasetIngest`).
+    ///
+    /// With `pii_actions` the PII of the dataset is redacted first and the redacted dataset
+    /// replaces the stored one. Differential privacy datasets are not embedded.
+    fn ingest_dataset<'a>(
+        &'a self,
+        agent_db: &'a AgentDb,
+        pii_actions: Option<&'a HashMap<String, PiiAction>>,
+    ) -> TeeFuture<'a, DatasetIngest>;
+
+    /// Start an agent from its stored dataset. Loading an already running agent replaces it.
+    fn load_agent<'a>(&'a self, agent_db: &'a AgentDb, load: AgentLoad) -> TeeFuture<'a, ()>;
+
+    /// Ask a running agent a question, following the earlier turns of the chat, and get its final
+    /// answer once the output guardrail checked it, signed for the payment `tx_hash`.
+    fn prompt<'a>(
+        &'a self,
+        agent_id: i64,
+        prompt: &'a str,
+        history: &'a [ChatTurn],
+        tx_hash: &'a str,
+    ) -> TeeFuture<'a, TeeAnswer>;
+
+    /// Ask a running agent a question and get its answer as the output guardrail releases it.
+    /// The signed answer comes with the `End` event.
+    fn prompt_stream<'a>(
+        &'a self,
+        agent_id: i64,
+        prompt: &'a str,
+        history: &'a [ChatTurn],
+        tx_hash: &'a str,
+    ) -> TeeFuture<'a, TeeStream>;
+
+    /// Quote binding the key answers are signed with to the measurement of the enclave.
+    fn quote(&self) -> TeeFuture<'_, EnclaveQuote>;
+
+    fn has_agent(&self, agent_id: i64) -> bool;
+
+    fn agent_count(&self) -> usize;
+}
+
+/// Build the executor selected by `TEE_BACKEND`.
+pub fn new_executor(
+    ai_model: &gemini::Client,
+    dataset_store: Arc<dyn DatasetStore>,
+) -> Result<Box<dyn TeeExecutor>> {
+    match APP_CONFIG.tee_backend {
+        TeeBackend::InProcess => Ok(Box::new(InProcessTeeExecutor::new(
+            ai_model.clone(),
+            dataset_store,
+            attestation::new_attestation_provider()?,
+        ))),
+        // The enclave holds its own signing key
+        TeeBackend::Remote => Ok(Box::new(RemoteTeeExecutor::new(
+            APP_CONFIG.tee_remote_addr.clone(),
+            dataset_store,
+        ))),
+    }
+}
@@ -0,0 +1,291 @@
+//! Wire protocol between the backend and an enclave process.
+//!
+//! Every message is a JSON document sent in a length delimited frame (4 bytes big endian length).
+//! Datasets follow the message that needs them as raw frames, see `send_dataset`. They are sent
+//! as stored, encrypted, and only decrypted by the enclave.
+
+use std::{collections::HashMap, path::Path};
+
+use color_eyre::{Result, eyre::eyre};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufWriter},
+    net::TcpStream,
+};
+use tokio_util::{
+    bytes::Bytes,
+    codec::{Framed, LengthDelimitedCodec},
+};
+
+use crate::{
+    tee::{TeeAnswer, attestation::EnclaveQuote},
+    types::{DatasetChunkDb, DatasetProfile, DpColumnBounds, PiiAction, PiiReport},
+};
+
+/// Largest frame, datasets are split in frames of `DATASET_FRAME_SIZE` whatever their size.
+pub const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+pub const DATASET_FRAME_SIZE: usize = 1024 * 1024;
+
+// Batches stay well under `MAX_FRAME_LENGTH` once serialized
+pub const FINGERPRINTS_PER_MESSAGE: usize = 100_000;
+pub const CHUNKS_PER_MESSAGE: usize = 256;
+
+pub type TeeConnection = Framed<TcpStream, LengthDelimitedCodec>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TeeRequest {
+    /// Start (or restart) an agent inside the enclave. Its dataset follows, then its
+    /// `chunk_count` embedded chunks in `DatasetChunks` messages and its `fingerprint_count`
+    /// guardrail fingerprints in `Fingerprints` messages
+    LoadAgent {
+        agent_id: i64,
+        name: String,
+        description: String,
+        category: String,
+        owner_id: i64,
+        /// Epsilon of each answer when the agent is in differential privacy mode, its rows must
+        /// then never reach the model
+        #[serde(default)]
+        dp_epsilon: Option<f64>,
+        /// Clamping bounds of the columns a differential privacy agent can sum or average
+        #[serde(default)]
+        dp_bounds: Option<HashMap<String, DpColumnBounds>>,
+        #[serde(default)]
+        chunk_count: usize,
+        #[serde(default)]
+        fingerprint_count: usize,
+        /// Report of the upload, answers may contain the PII its owner kept
+        #[serde(default)]
+        pii_report: Option<PiiReport>,
+    },
+    /// Compute what the backend stores about a dataset, the dataset follows. It is answered by
+    /// `Ingested`
+    IngestDataset {
+        agent_id: i64,
+        /// Redact the PII of the dataset with these actions (column name -> action) first,
+        /// `None` to leave the dataset as it is
+        #[serde(default)]
+        pii_actions: Option<HashMap<String, PiiAction>>,
+        /// Embed the dataset chunks for retrieval
+        embed: bool,
+    },
+    /// Batch of the embedded chunks of an agent
+    DatasetChunks { chunks: Vec<DatasetChunkDb> },
+    /// Batch of the guardrail fingerprints of an agent, as the enclave computed them
+    Fingerprints { fingerprints: Vec<i64> },
+    /// Ask a loaded agent a question, its answer is checked by the output guardrail then signed
+    /// for the payment `tx_hash`
+    Prompt {
+        agent_id: i64,
+        prompt: String,
+        #[serde(default)]
+        history: Vec<ChatTurn>,
+        tx_hash: String,
+    },
+    /// Ask a loaded agent a question, its answer comes back as `Chunk` messages released by the
+    /// output guardrail, ended by `StreamEnd` with the answer signed for the payment `tx_hash`
+    PromptStream {
+        agent_id: i64,
+        prompt: String,
+        #[serde(default)]
+        history: Vec<ChatTurn>,
+        tx_hash: String,
+    },
+    /// Get the enclave quote
+    Quote,
+}
+
+/// Earlier turn of a chat session, with the answer of the agent as the buyer received it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatTurn {
+    pub prompt: String,
+    pub response: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TeeResponse {
+    Loaded {
+        agent_id: i64,
+    },
+    /// What the backend stores about an ingested dataset. The redacted dataset follows when
+    /// `redacted`, then `fingerprint_count` fingerprints in `Fingerprints` messages and
+    /// `chunk_count` chunks in `DatasetChunks` messages
+    Ingested {
+        agent_id: i64,
+        pii_report: Option<PiiReport>,
+        profile: DatasetProfile,
+        redacted: bool,
+        fingerprint_count: usize,
+        chunk_count: usize,
+    },
+    /// Batch of the guardrail fingerprints of an ingested dataset
+    Fingerprints {
+        fingerprints: Vec<i64>,
+    },
+    /// Batch of the embedded chunks of an ingested dataset
+    DatasetChunks {
+        chunks: Vec<DatasetChunkDb>,
+    },
+    Answer {
+        agent_id: i64,
+        answer: TeeAnswer,
+    },
+    Chunk {
+        agent_id: i64,
+        text: String,
+    },
+    /// End of a streamed answer, with the whole answer as the guardrail released it, signed
+    StreamEnd {
+        usage: Option<TokenUsage>,
+        answer: TeeAnswer,
+    },
+    Quote {
+        quote: EnclaveQuote,
+    },
+    Error {
+        code: TeeErrorCode,
+        message: String,
+    },
+}
+
+/// Tokens used by the model for an answer, sent at the end of a stream when the model reported them.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TeeErrorCode {
+    /// The enclave does not hold this agent, it must be loaded again (e.g. after a restart)
+    AgentNotLoaded,
+    InvalidRequest,
+    /// The PII actions name a column the dataset does not have, the message is the column
+    UnknownPiiColumn,
+    IngestFailed,
+    AttestationFailed,
+}
+
+pub fn framed(stream: TcpStream) -> TeeConnection {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(MAX_FRAME_LENGTH)
+        .new_framed(stream)
+}
+
+pub async fn send_message<T: Serialize>(connection: &mut TeeConnection, message: &T) -> Result<()> {
+    let payload = serde_json::to_vec(message)?;
+
+    connection.send(Bytes::from(payload)).await?;
+
+    Ok(())
+}
+
+/// Read the next message, `None` when the peer closed the connection.
+pub async fn read_message<T: DeserializeOwned>(
+    connection: &mut TeeConnection,
+) -> Result<Option<T>> {
+    let frame = match connection.next().await {
+        Some(frame) => frame?,
+        None => return Ok(None),
+    };
+
+    let message =
+        serde_json::from_slice(&frame).map_err(|e| eyre!("Invalid enclave message: {}", e))?;
+
+    Ok(Some(message))
+}
+
+/// Send a dataset as raw frames of at most `DATASET_FRAME_SIZE` bytes, ended by an empty frame.
+/// It is read as it is sent, never held whole in memory.
+pub async fn send_dataset<R: AsyncRead + Unpin>(
+    connection: &mut TeeConnection,
+    mut dataset: R,
+) -> Result<()> {
+    let mut buffer = vec![0u8; DATASET_FRAME_SIZE];
+
+    loop {
+        let read = dataset.read(&mut buffer).await?;
+
+        if read == 0 {
+            break;
+        }
+
+        connection
+            .send(Bytes::copy_from_slice(&buffer[..read]))
+            .await?;
+    }
+
+    connection.send(Bytes::new()).await?;
+
+    Ok(())
+}
+
+/// Write a dataset sent by `send_dataset` to `path`.
+pub async fn receive_dataset(connection: &mut TeeConnection, path: &Path) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path).await?);
+
+    loop {
+        let frame = connection
+            .next()
+            .await
+            .ok_or_else(|| eyre!("Connection closed in the middle of a dataset"))??;
+
+        if frame.is_empty() {
+            break;
+        }
+
+        file.write_all(&frame).await?;
+    }
+
+    file.flush().await?;
+
+    Ok(())
+}
+
+/// Send `items` in messages of at most `per_message` items, built by `message`.
+pub async fn send_batches<T: Clone, M: Serialize>(
+    connection: &mut TeeConnection,
+    items: &[T],
+    per_message: usize,
+    message: impl Fn(Vec<T>) -> M,
+) -> Result<()> {
+    for batch in items.chunks(per_message) {
+        send_message(connection, &message(batch.to_vec())).await?;
+    }
+
+    Ok(())
+}
+
+/// Read `count` items sent by `send_batches`, `items` gets the items out of each message.
+pub async fn read_batches<T, M: DeserializeOwned>(
+    connection: &mut TeeConnection,
+    count: usize,
+    items: impl Fn(M) -> Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    let mut all = Vec::with_capacity(count);
+
+    while all.len() < count {
+        let message = read_message::<M>(connection)
+            .await?
+            .ok_or_else(|| eyre!("Connection closed in the middle of a batch"))?;
+
+        let batch = items(message)?;
+
+        if batch.is_empty() || all.len() + batch.len() > count {
+            return Err(eyre!(
+                "Expected {} items, got a batch of {}",
+                count,
+                batch.len()
+            ));
+        }
+
+        all.extend(batch);
+    }
+
+    Ok(all)
+}
@@ -0,0 +1,435 @@
+use std::{collections::HashMap, future::Future, path::Path, sync::Arc, time::Duration};
+
+use color_eyre::{Result, eyre::eyre};
+use dashmap::DashMap;
+use tokio::{fs::File, net::TcpStream};
+
+use crate::{
+    config::{TEE_INGEST_TIMEOUT_SECS, TEE_REQUEST_TIMEOUT_SECS},
+    helpers::{pii::PiiError, temp_file::TempFile},
+    storage::DatasetStore,
+    tee::{
+        AgentLoad, TeeAnswer, TeeExecutor, TeeFuture, TeeStream, TeeStreamEvent,
+        attestation::EnclaveQuote,
+        ingest::DatasetIngest,
+        protocol::{
+            self, CHUNKS_PER_MESSAGE, ChatTurn, FINGERPRINTS_PER_MESSAGE, TeeConnection,
+            TeeErrorCode, TeeRequest, TeeResponse,
+        },
+    },
+    types::{AgentDb, PiiAction},
+};
+
+/// Forwards agents to a separate enclave process over the framed protocol in `tee::protocol`.
+///
+/// Datasets are sent as stored, encrypted: only the enclave decrypts them.
+/// The enclave keeps agents in memory only, so the agents sent to it are remembered here
+/// and loaded again when the enclave reports it lost one (e.g. after a restart).
+pub struct RemoteTeeExecutor {
+    addr: String,
+    dataset_store: Arc<dyn DatasetStore>,
+    agents: DashMap<i64, SentAgent>,
+}
+
+#[derive(Clone)]
+struct SentAgent {
+    agent_db: AgentDb,
+    load: AgentLoad,
+}
+
+impl RemoteTeeExecutor {
+    pub fn new(addr: String, dataset_store: Arc<dyn DatasetStore>) -> Self {
+        Self {
+            addr,
+            dataset_store,
+            agents: DashMap::new(),
+        }
+    }
+
+    /// One request per connection, the enclave answers with exactly one response.
+    async fn request(&self, request: &TeeRequest) -> Result<TeeResponse> {
+        self.timeout(TEE_REQUEST_TIMEOUT_SECS, async {
+            let mut connection = self.connect().await?;
+
+            protocol::send_message(&mut connection, request).await?;
+
+            read_response(&mut connection).await
+        })
+        .await
+    }
+
+    async fn connect(&self) -> Result<TeeConnection> {
+        let stream = TcpStream::connect(&self.addr).await?;
+
+        Ok(protocol::framed(stream))
+    }
+
+    async fn timeout<T>(&self, secs: u64, exchange: impl Future<Output = Result<T>>) -> Result<T> {
+        tokio::time::timeout(Duration::from_secs(secs), exchange)
+            .await
+            .map_err(|_| eyre!("Enclave at {} did not answer in time", self.addr))?
+    }
+
+    /// Send a request answered by several messages, returns the connection with the first one.
+    async fn open_stream(&self, request: &TeeRequest) -> Result<(TeeConnection, TeeResponse)> {
+        let mut connection = self
+            .timeout(TEE_REQUEST_TIMEOUT_SECS, self.connect())
+            .await?;
+
+        protocol::send_message(&mut connection, request).await?;
+
+        let response = next_response(&mut connection).await?;
+
+        Ok((connection, response))
+    }
+
+    /// Download a stored dataset as it is, encrypted.
+    async fn fetch_encrypted(&self, agent_db: &AgentDb) -> Result<TempFile> {
+        let file = TempFile::new("dataset");
+
+        self.dataset_store
+            .get_file(&agent_db.dataset_path, file.path())
+            .await?;
+
+        Ok(file)
+    }
+
+    /// Load an agent again after the enclave reported it lost it.
+    /// Returns false when the agent was never sent to this executor.
+    async fn reload_lost_agent(&self, agent_id: i64) -> Result<bool> {
+        let agent = self.agents.get(&agent_id).map(|agent| agent.clone());
+
+        let Some(agent) = agent else {
+            return Ok(false);
+        };
+
+        tracing::warn!("Enclave lost agent {}, loading it again", agent_id);
+
+        self.send_agent(&agent.agent_db, &agent.load).await?;
+
+        Ok(true)
+    }
+
+    async fn send_agent(&self, agent_db: &AgentDb, load: &AgentLoad) -> Result<()> {
+        let dataset = self.fetch_encrypted(agent_db).await?;
+
+        let request = TeeRequest::LoadAgent {
+            agent_id: agent_db.id,
+            name: agent_db.name.clone(),
+            description: agent_db.description.clone(),
+            category: agent_db.category.to_string(),
+            owner_id: agent_db.owner_id,
+            dp_epsilon: agent_db.dp_epsilon.filter(|_| agent_db.dp_enabled),
+            dp_bounds: agent_db
+                .dp_bounds
+                .as_ref()
+                .filter(|_| agent_db.dp_enabled)
+                .map(|bounds| bounds.0.clone()),
+            chunk_count: load.chunks.len(),
+            fingerprint_count: load.fingerprints.len(),
+            pii_report: load.pii_report.clone(),
+        };
+
+        let response = self
+            .timeout(TEE_REQUEST_TIMEOUT_SECS, async {
+                let mut connection = self.connect().await?;
+
+                protocol::send_message(&mut connection, &request).await?;
+                protocol::send_dataset(&mut connection, File::open(dataset.path()).await?).await?;
+                protocol::send_batches(
+                    &mut connection,
+                    &load.chunks,
+                    CHUNKS_PER_MESSAGE,
+                    |chunks| TeeRequest::DatasetChunks { chunks },
+                )
+                .await?;
+                protocol::send_batches(
+                    &mut connection,
+                    &load.fingerprints,
+                    FINGERPRINTS_PER_MESSAGE,
+                    |fingerprints| TeeRequest::Fingerprints { fingerprints },
+                )
+                .await?;
+
+                read_response(&mut connection).await
+            })
+            .await?;
+
+        match response {
+            TeeResponse::Loaded { agent_id } if agent_id == agent_db.id => Ok(()),
+            TeeResponse::Error { code, message } => Err(eyre!(
+                "Enclave failed to load agent {} ({:?}): {}",
+                agent_db.id,
+                code,
+                message
+            )),
+            response => Err(eyre!("Unexpected enclave response: {:?}", response)),
+        }
+    }
+}
+
+impl TeeExecutor for RemoteTeeExecutor {
+    fn name(&self) -> &'static str {
+        "remote"
+    }
+
+    fn ingest_dataset<'a>(
+        &'a self,
+        agent_db: &'a AgentDb,
+        pii_actions: Option<&'a HashMap<String, PiiAction>>,
+    ) -> TeeFuture<'a, DatasetIngest> {
+        Box::pin(async move {
+            let dataset = self.fetch_encrypted(agent_db).await?;
+            let redacted = TempFile::new("dataset");
+
+            let request = TeeRequest::IngestDataset {
+                agent_id: agent_db.id,
+                pii_actions: pii_actions.cloned(),
+                embed: !agent_db.dp_enabled,
+            };
+
+            // Redacting and embedding a whole dataset takes longer than an answer
+            let (ingest, is_redacted) = self
+                .timeout(TEE_INGEST_TIMEOUT_SECS, async {
+                    let mut connection = self.connect().await?;
+
+                    protocol::send_message(&mut connection, &request).await?;
+                    protocol::send_dataset(&mut connection, File::open(dataset.path()).await?)
+                        .await?;
+
+                    read_ingest(&mut connection, agent_db.id, redacted.path()).await
+                })
+                .await?;
+
+            if is_redacted {
+                self.dataset_store
+                    .put_file(&agent_db.dataset_path, redacted.path())
+                    .await?;
+            }
+
+            Ok(ingest)
+        })
+    }
+
+    fn load_agent<'a>(&'a self, agent_db: &'a AgentDb, load: AgentLoad) -> TeeFuture<'a, ()> {
+        Box::pin(async move {
+            self.send_agent(agent_db, &load).await?;
+
+            self.agents.insert(
+                agent_db.id,
+                SentAgent {
+                    agent_db: agent_db.clone(),
+                    load,
+                },
+            );
+
+            Ok(())
+        })
+    }
+
+    fn prompt<'a>(
+        &'a self,
+        agent_id: i64,
+        prompt: &'a str,
+        history: &'a [ChatTurn],
+        tx_hash: &'a str,
+    ) -> TeeFuture<'a, TeeAnswer> {
+        Box::pin(async move {
+            let request = TeeRequest::Prompt {
+                agent_id,
+                prompt: prompt.to_string(),
+                history: history.to_vec(),
+                tx_hash: tx_hash.to_string(),
+            };
+
+            let mut response = self.request(&request).await?;
+
+            if let TeeResponse::Error {
+                code: TeeErrorCode::AgentNotLoaded,
+                ..
+            } = response
+                && self.reload_lost_agent(agent_id).await?
+            {
+                response = self.request(&request).await?;
+            }
+
+            match response {
+                TeeResponse::Answer { answer, .. } => Ok(answer),
+                TeeResponse::Error { code, message } => Err(eyre!(
+                    "Enclave failed to answer with agent {} ({:?}): {}",
+                    agent_id,
+                    code,
+                    message
+                )),
+                response => Err(eyre!("Unexpected enclave response: {:?}", response)),
+            }
+        })
+    }
+
+    fn prompt_stream<'a>(
+        &'a self,
+        agent_id: i64,
+        prompt: &'a str,
+        history: &'a [ChatTurn],
+        tx_hash: &'a str,
+    ) -> TeeFuture<'a, TeeStream> {
+        Box::pin(async move {
+            let request = TeeRequest::PromptStream {
+                agent_id,
+                prompt: prompt.to_string(),
+                history: history.to_vec(),
+                tx_hash: tx_hash.to_string(),
+            };
+
+            let (mut connection, mut first) = self.open_stream(&request).await?;
+
+            if let TeeResponse::Error {
+                code: TeeErrorCode::AgentNotLoaded,
+                ..
+            } = first
+                && self.reload_lost_agent(agent_id).await?
+            {
+                (connection, first) = self.open_stream(&request).await?;
+            }
+
+            // The connection is dropped once the answer ended
+            let events = futures_util::stream::try_unfold(
+                Some((connection, Some(first))),
+                move |state| async move {
+                    let Some((mut connection, first)) = state else {
+                        return Ok(None);
+                    };
+
+                    let response = match first {
+                        Some(response) => response,
+                        None => next_response(&mut connection).await?,
+                    };
+
+                    match response {
+                        TeeResponse::Chunk { text, .. } => Ok(Some((
+                            TeeStreamEvent::Chunk(text),
+                            Some((connection, None)),
+                        ))),
+                        TeeResponse::StreamEnd { usage, answer } => {
+                            let answer = Box::new(answer);
+
+                            Ok(Some((TeeStreamEvent::End { usage, answer }, None)))
+                        }
+                        TeeResponse::Error { code, message } => Err(eyre!(
+                            "Enclave failed to answer with agent {} ({:?}): {}",
+                            agent_id,
+                            code,
+                            message
+                        )),
+                        response => Err(eyre!("Unexpected enclave response: {:?}", response)),
+                    }
+                },
+            );
+
+            Ok(Box::pin(events) as TeeStream)
+        })
+    }
+
+    fn quote(&self) -> TeeFuture<'_, EnclaveQuote> {
+        Box::pin(async move {
+            match self.request(&TeeRequest::Quote).await? {
+                TeeResponse::Quote { quote } => Ok(quote),
+                TeeResponse::Error { code, message } => {
+                    Err(eyre!("Enclave failed to quote ({:?}): {}", code, message))
+                }
+                response => Err(eyre!("Unexpected enclave response: {:?}", response)),
+            }
+        })
+    }
+
+    fn has_agent(&self, agent_id: i64) -> bool {
+        self.agents.contains_key(&agent_id)
+    }
+
+    fn agent_count(&self) -> usize {
+        self.agents.len()
+    }
+}
+
+/// Single response of a request.
+async fn read_response(connection: &mut TeeConnection) -> Result<TeeResponse> {
+    protocol::read_message::<TeeResponse>(connection)
+        .await?
+        .ok_or_else(|| eyre!("Enclave closed the connection without answering"))
+}
+
+/// Read the answer of an `IngestDataset` request, the redacted dataset is written to
+/// `redacted_path`. Returns whether the dataset was redacted.
+async fn read_ingest(
+    connection: &mut TeeConnection,
+    agent_id: i64,
+    redacted_path: &Path,
+) -> Result<(DatasetIngest, bool)> {
+    let (pii_report, profile, redacted, fingerprint_count, chunk_count) =
+        match read_response(connection).await? {
+            TeeResponse::Ingested {
+                agent_id: ingested_id,
+                pii_report,
+                profile,
+                redacted,
+                fingerprint_count,
+                chunk_count,
+            } if ingested_id == agent_id => (
+                pii_report,
+                profile,
+                redacted,
+                fingerprint_count,
+                chunk_count,
+            ),
+            TeeResponse::Error {
+                code: TeeErrorCode::UnknownPiiColumn,
+                message,
+            } => return Err(PiiError::UnknownColumn(message).into()),
+            TeeResponse::Error { code, message } => {
+                return Err(eyre!(
+                    "Enclave failed to ingest the dataset of agent {} ({:?}): {}",
+                    agent_id,
+                    code,
+                    message
+                ));
+            }
+            response => return Err(eyre!("Unexpected enclave response: {:?}", response)),
+        };
+
+    if redacted {
+        protocol::receive_dataset(connection, redacted_path).await?;
+    }
+
+    let fingerprints =
+        protocol::read_batches(connection, fingerprint_count, |response| match response {
+            TeeResponse::Fingerprints { fingerprints } => Ok(fingerprints),
+            response => Err(eyre!("Unexpected enclave response: {:?}", response)),
+        })
+        .await?;
+
+    let chunks = protocol::read_batches(connection, chunk_count, |response| match response {
+        TeeResponse::DatasetChunks { chunks } => Ok(chunks),
+        response => Err(eyre!("Unexpected enclave response: {:?}", response)),
+    })
+    .await?;
+
+    let ingest = DatasetIngest {
+        pii_report,
+        profile,
+        fingerprints,
+        chunks,
+    };
+
+    Ok((ingest, redacted))
+}
+
+/// Next message of a streamed answer, each one must arrive within `TEE_REQUEST_TIMEOUT_SECS`.
+async fn next_response(connection: &mut TeeConnection) -> Result<TeeResponse> {
+    tokio::time::timeout(
+        Duration::from_secs(TEE_REQUEST_TIMEOUT_SECS),
+        protocol::read_message::<TeeResponse>(connection),
+    )
+    .await
+    .map_err(|_| eyre!("Enclave stopped answering"))??
+    .ok_or_else(|| eyre!("Enclave closed the connection without answering"))
+}
@@ -1,5 +1,8 @@
+use std::collections::HashMap;
+
 use actix_web::web;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use rig::Embed;
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::Type;
 use utoipa::ToSchema;
@@ -18,8 +21,12 @@ pub struct DatasetUploadResponse {
     pub filename: Option<String>,
     /// File size in bytes
     pub file_size: Option<u64>,
-    /// Number of rows in the CSV (excluding header)
+    /// Number of rows in the dataset (excluding header)
     pub row_count: Option<usize>,
+    /// Format of the uploaded file
+    pub format: Option<DatasetFormat>,
+    /// PII found in the dataset and what was done with it
+    pub pii_report: Option<PiiReport>,
     /// Dataset metadata
     pub metadata: Option<DatasetMetadata>,
     /// Created Dataset ID
@@ -63,13 +70,13 @@ pub struct DatasetMetadata {
     pub category: AgentCategory,
 }
 
+// Only describes the multipart form in the OpenAPI docs, the form is read field by field
+#[allow(dead_code)]
 #[derive(ToSchema)]
 pub struct DatasetUploadRequest {
-    /// CSV file to upload
+    /// Dataset file to upload (CSV, TSV, JSON Lines, Parquet or XLSX)
     #[schema(value_type = String, format = Binary)]
     pub file: Vec<u8>,
-    /// Blockchain address of the user
-    pub user_address: String,
     /// Dataset price
     #[schema(minimum = 1.0, maximum = 5800000.0)]
     pub dataset_price: f64,
@@ -79,11 +86,21 @@ pub struct DatasetUploadRequest {
     pub name: String,
     // Category of dataset
     pub category: AgentCategory,
+    /// Optional JSON object mapping column names to a PII action: drop, hash, mask or keep.
+    /// Columns with detected PII and no action are hashed.
+    pub pii_actions: Option<String>,
+    /// Enables differential privacy mode: epsilon spent by each answer
+    pub dp_epsilon: Option<f64>,
+    /// Total epsilon each buyer may spend, required with dp_epsilon
+    pub dp_budget: Option<f64>,
+    /// Optional JSON object mapping numeric column names to their clamping bounds
+    /// ({"min": number, "max": number}). Only these columns can be summed or averaged.
+    pub dp_bounds: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DatasetDetailsGenerateRequest {
-    /// CSV file upload
+    /// Dataset file upload (CSV, TSV, JSON Lines, Parquet or XLSX)
     #[schema(value_type = String, format = Binary)]
     pub file: Vec<u8>,
 }
@@ -109,6 +126,26 @@ pub struct DatasetAIDetails {
     pub category: String,
 }
 
+/// A group of consecutive dataset rows, embedded for retrieval.
+#[derive(Embed, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DatasetChunk {
+    pub chunk_index: i32,
+    pub first_row: i64,
+    pub last_row: i64,
+    /// CSV header followed by the rows of the chunk
+    #[embed]
+    pub content: String,
+}
+
+/// Embedded chunk as stored, only its rows range: the rows are read again from the dataset.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DatasetChunkDb {
+    pub chunk_index: i32,
+    pub first_row: i64,
+    pub last_row: i64,
+    pub embedding: Vec<f64>,
+}
+
 #[derive(Serialize, Deserialize, sqlx::FromRow, Debug)]
 pub struct UserDb {
     pub id: i64,
@@ -125,6 +162,16 @@ pub struct AgentDb {
     pub owner_address: String,
     pub dataset_path: String,
     pub category: AgentCategory,
+    pub format: DatasetFormat,
+    /// Answers only come from noisy aggregates, see `helpers::dp`
+    pub dp_enabled: bool,
+    /// Epsilon spent by each answer in differential privacy mode
+    pub dp_epsilon: Option<f64>,
+    /// Total epsilon each buyer may spend on the agent
+    pub dp_budget: Option<f64>,
+    /// Clamping bounds of the columns sums and averages may use, chosen by the owner
+    #[schema(value_type = Option<HashMap<String, DpColumnBounds>>)]
+    pub dp_bounds: Option<sqlx::types::Json<HashMap<String, DpColumnBounds>>>,
     pub dataset_size: f64,
     pub nft_id: Option<i64>,
     pub nft_tx: Option<String>,
@@ -138,6 +185,13 @@ pub struct AgentDb {
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct GetAgentsForPromptRequest {
     pub prompt: String,
+    /// Only route to agents of this category
+    pub category: Option<String>,
+    /// Number of agents retrieved by similarity (default: 10, max: 50)
+    pub top_k: Option<i64>,
+    /// Let the router model keep and order only the retrieved agents that can answer
+    #[serde(default)]
+    pub rerank: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -147,14 +201,31 @@ pub struct GetAgentsForPromptResponse {
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct GetResponseFromAgentsRequest {
+    /// Agents of a new session, can be left empty to continue one
+    #[serde(default)]
     pub agent_ids: Vec<i64>,
     pub prompt: String,
+    /// Payment of the agents, needed to start a session and once its paid turns are used
+    #[serde(default)]
     pub tx_hash: String,
+    /// Chat session to continue, a new one is started without it
+    pub session_id: Option<i64>,
+    /// Merge the answers into a single response citing the agents, when several agents answer
+    #[serde(default)]
+    pub synthesize: bool,
+    /// Pay the agents with the credits of the buyer when no tx hash is given
+    #[serde(default)]
+    pub use_credits: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct GetResponseFromAgentsResponse {
     pub agent_responses: Vec<AgentResponse>,
+    /// Set when a synthesis was requested and several agents answered
+    pub synthesis: Option<AgentSynthesis>,
+    pub session_id: i64,
+    /// Turns of the session left before another payment is needed
+    pub turns_left: i32,
     pub success: bool,
 }
 
@@ -162,7 +233,166 @@ pub struct GetResponseFromAgentsResponse {
 pub struct AgentResponse {
     pub agent_id: i64,
     pub prompt: String,
+    /// Empty when the agent failed, except for the part of a streamed answer sent before it did
     pub response: String,
+    /// Proof that the answer was produced by the attested enclave for this payment
+    pub attestation: Option<AgentAttestation>,
+    /// Whether the output guardrail changed the answer, none when the agent gave no answer
+    pub guardrail_verdict: Option<GuardrailVerdict>,
+    pub status: AgentAnswerStatus,
+    /// Why the agent gave no answer, the buyer is credited for it unless part of it was streamed
+    pub error: Option<String>,
+    pub error_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, ToSchema)]
+#[sqlx(type_name = "agent_answer_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AgentAnswerStatus {
+    Answered,
+    Failed,
+    /// No answer within `AGENT_ANSWER_TIMEOUT_SECS`
+    TimedOut,
+}
+
+/// Agent that gave no answer to a paid turn of a chat session.
+#[derive(Debug, Clone)]
+pub struct NewFailedAgentQuery {
+    pub agent_id: i64,
+    pub session_id: i64,
+    pub turn: i32,
+    pub buyer_address: String,
+    pub tx_hash: String,
+    pub prompt: String,
+    pub status: AgentAnswerStatus,
+    pub error_code: String,
+    pub error: String,
+}
+
+/// Answer merged from the answers of several agents. It is written outside of the enclave and is
+/// not attested, its citations point to the attested answers.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentSynthesis {
+    /// Claims are followed by the agents backing them, as `[agent:ID]`
+    pub response: String,
+    pub cited_agent_ids: Vec<i64>,
+}
+
+/// Data of the server sent events of `/chat/agents/answer/stream`, the event name is its `type`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentStreamEvent {
+    /// Next part of the answer of an agent, already checked by the output guardrail
+    Chunk { agent_id: i64, text: String },
+    /// The output guardrail blocked the answer of an agent, drop the chunks received for it
+    Blocked { agent_id: i64 },
+    /// An agent failed to answer, drop the chunks received for it
+    Error {
+        agent_id: i64,
+        message: String,
+        error_code: String,
+    },
+    /// Last event, with the attested answers and the status of the agents that failed
+    Done {
+        agent_responses: Vec<AgentResponse>,
+        synthesis: Option<AgentSynthesis>,
+        usage: Vec<AgentUsage>,
+        session_id: i64,
+        turns_left: i32,
+    },
+}
+
+/// Tokens used by the model of an agent, when it reported them.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentUsage {
+    pub agent_id: i64,
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentAttestation {
+    /// Attestation provider that produced the quote (e.g. `simulated`)
+    pub provider: String,
+    /// Enclave measurement, hex encoded
+    pub measurement: String,
+    /// Address of the enclave signing key bound in the quote
+    pub signer_address: String,
+    /// Attestation quote binding `signer_address` to `measurement`, hex encoded
+    pub quote: String,
+    /// keccak256 of the prompt
+    pub prompt_hash: String,
+    /// keccak256 of the response
+    pub response_hash: String,
+    /// Payment transaction the answer was paid with
+    pub tx_hash: String,
+    /// keccak256(abi.encode(agent_id, prompt_hash, response_hash, tx_hash))
+    pub payload_hash: String,
+    /// EIP-191 signature of `payload_hash` by `signer_address`
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VerifyAttestationRequest {
+    pub agent_id: i64,
+    pub prompt: String,
+    pub response: String,
+    pub attestation: AgentAttestation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VerifyAttestationResponse {
+    pub success: bool,
+    /// True when the attestation covers the answer and matches this enclave measurement
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AttestationResponse {
+    pub success: bool,
+    pub provider: String,
+    /// Enclave measurement, hex encoded
+    pub measurement: String,
+    /// Address every agent response is signed with
+    pub signer_address: String,
+    /// Attestation quote binding `signer_address` to `measurement`, hex encoded
+    pub quote: String,
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize, Deserialize, Clone, ToSchema)]
+pub struct PaymentDb {
+    pub id: i64,
+    pub tx_hash: String,
+    pub payer_address: String,
+    pub agent_ids: Vec<i64>,
+    pub amounts: Vec<f64>,
+    pub block_number: i64,
+    #[schema(value_type = String, format = DateTime)]
+    pub consumed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PaymentResponse {
+    pub success: bool,
+    pub message: String,
+    pub payment: PaymentDb,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PaymentsResponse {
+    pub success: bool,
+    pub message: String,
+    pub payments: Vec<PaymentDb>,
+}
+
+/// Payment details extracted from an on-chain tx once it has been verified
+/// against the selected agents. Not yet consumed.
+#[derive(Debug, Clone)]
+pub struct VerifiedPayment {
+    pub tx_hash: String,
+    pub payer_address: String,
+    pub agent_ids: Vec<i64>,
+    pub amounts: Vec<f64>,
+    pub block_number: i64,
 }
 
 #[derive(Debug, Deserialize, ToSchema)]
@@ -189,6 +419,11 @@ pub struct AgentQueryResult {
     pub address: String,
     pub dataset_path: String,
     pub category: AgentCategory,
+    pub format: DatasetFormat,
+    pub dp_enabled: bool,
+    pub dp_epsilon: Option<f64>,
+    pub dp_budget: Option<f64>,
+    pub dp_bounds: Option<sqlx::types::Json<HashMap<String, DpColumnBounds>>>,
     pub dataset_size: f64,
     pub status: String,
     pub created_at: DateTime<Utc>,
@@ -215,19 +450,21 @@ pub enum AgentCategory {
     Environmental,
 }
 
-impl ToString for AgentCategory {
-    fn to_string(&self) -> String {
-        match self {
-            AgentCategory::Web3 => "Web3".to_string(),
-            AgentCategory::Financial => "Financial".to_string(),
-            AgentCategory::Analytics => "Analytics".to_string(),
-            AgentCategory::Healthcare => "Healthcare".to_string(),
-            AgentCategory::IoT => "IoT".to_string(),
-            AgentCategory::Gaming => "Gaming".to_string(),
-            AgentCategory::ConsumerData => "Consumer Data".to_string(),
-            AgentCategory::SocialMedia => "Social Media".to_string(),
-            AgentCategory::Environmental => "Environmental".to_string(),
-        }
+impl std::fmt::Display for AgentCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let category = match self {
+            AgentCategory::Web3 => "Web3",
+            AgentCategory::Financial => "Financial",
+            AgentCategory::Analytics => "Analytics",
+            AgentCategory::Healthcare => "Healthcare",
+            AgentCategory::IoT => "IoT",
+            AgentCategory::Gaming => "Gaming",
+            AgentCategory::ConsumerData => "Consumer Data",
+            AgentCategory::SocialMedia => "Social Media",
+            AgentCategory::Environmental => "Environmental",
+        };
+
+        f.write_str(category)
     }
 }
 
@@ -248,6 +485,302 @@ impl AgentCategory {
     }
 }
 
+/// Format of an uploaded dataset file. Every format is normalized to CSV before it is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, ToSchema)]
+#[sqlx(type_name = "dataset_format", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetFormat {
+    Csv,
+    Tsv,
+    #[serde(rename = "jsonl")]
+    #[sqlx(rename = "jsonl")]
+    JsonLines,
+    Parquet,
+    Xlsx,
+}
+
+impl DatasetFormat {
+    pub const SUPPORTED_EXTENSIONS: &'static str = ".csv, .tsv, .jsonl, .ndjson, .parquet, .xlsx";
+
+    pub fn from_filename(filename: &str) -> Option<DatasetFormat> {
+        let extension = filename.rsplit_once('.')?.1.to_lowercase();
+
+        match extension.as_str() {
+            "csv" => Some(DatasetFormat::Csv),
+            "tsv" | "tab" => Some(DatasetFormat::Tsv),
+            "jsonl" | "ndjson" => Some(DatasetFormat::JsonLines),
+            "parquet" => Some(DatasetFormat::Parquet),
+            "xlsx" => Some(DatasetFormat::Xlsx),
+            _ => None,
+        }
+    }
+}
+
+/// Column profile of an agent dataset, computed at upload time.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DatasetProfile {
+    pub row_count: i64,
+    pub columns: Vec<ColumnProfile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ColumnProfile {
+    /// Column name, as seen by the agent SQL tool
+    pub name: String,
+    pub inferred_type: InferredColumnType,
+    pub null_count: i64,
+    /// Share of empty values, between 0 and 1
+    pub null_ratio: f64,
+    /// Number of distinct non empty values, a lower bound when `distinct_capped` is set
+    pub distinct_count: i64,
+    pub distinct_capped: bool,
+    /// Numeric minimum for numbers, lexicographic otherwise
+    pub min: Option<String>,
+    pub max: Option<String>,
+    /// First distinct non empty values of the column
+    pub sample_values: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum InferredColumnType {
+    Integer,
+    Real,
+    Boolean,
+    Date,
+    Datetime,
+    Text,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PiiType {
+    Email,
+    Phone,
+    Iban,
+    CreditCard,
+    WalletAddress,
+    /// Only detected from the column name
+    PersonName,
+}
+
+/// What happens to the PII of a column before the dataset reaches its agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiAction {
+    /// Remove the column
+    Drop,
+    /// Replace PII with a salted hash, equal values keep equal hashes within the dataset
+    Hash,
+    /// Hide PII but a few characters
+    Mask,
+    Keep,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PiiColumnReport {
+    pub name: String,
+    pub detected: Vec<PiiType>,
+    /// The column name suggests PII
+    pub name_hint: bool,
+    /// Number of values where PII was found
+    pub matches: i64,
+    pub action: PiiAction,
+    /// Number of values changed by the action
+    pub redacted_values: i64,
+}
+
+/// Result of the PII scan run on a dataset before it becomes an agent.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PiiReport {
+    /// Columns with PII or an action chosen by the owner
+    pub columns: Vec<PiiColumnReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentDetailsResponse {
+    #[serde(flatten)]
+    pub agent: AgentDb,
+    /// Profile of the agent dataset, missing until it has been computed
+    pub profile: Option<DatasetProfile>,
+    /// PII found in the dataset at upload and what was done with it
+    pub pii_report: Option<PiiReport>,
+}
+
+/// Differential privacy settings chosen by the owner at upload.
+#[derive(Debug, Clone)]
+pub struct DpSettings {
+    /// Epsilon spent by each answer
+    pub epsilon: f64,
+    /// Total epsilon each buyer may spend
+    pub budget: f64,
+    /// Clamping bounds of the columns that can be summed or averaged
+    pub bounds: HashMap<String, DpColumnBounds>,
+}
+
+/// Range every value of a column is clamped to before it is summed. It must not depend on the
+/// data, it sets the noise of every aggregate over the column.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DpColumnBounds {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Outcome of the output guardrail on an agent answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, ToSchema)]
+#[sqlx(type_name = "guardrail_verdict", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum GuardrailVerdict {
+    Allowed,
+    /// PII or lines copied from the dataset were removed
+    Redacted,
+    /// The answer reproduced too much of the dataset and was withheld
+    Blocked,
+}
+
+/// Guardrail verdict on an answer, to be logged for the dataset owner.
+#[derive(Debug, Clone)]
+pub struct NewGuardrailEvent {
+    pub agent_id: i64,
+    pub tx_hash: String,
+    pub buyer_address: String,
+    pub prompt: String,
+    pub verdict: GuardrailVerdict,
+    pub overlap_ratio: f64,
+    pub pii_types: Vec<PiiType>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct GuardrailEventDb {
+    pub id: i64,
+    pub agent_id: i64,
+    pub tx_hash: String,
+    pub buyer_address: String,
+    pub prompt: String,
+    pub verdict: GuardrailVerdict,
+    /// Share of the answer words found verbatim in the dataset
+    pub overlap_ratio: f64,
+    /// PII found in the answer
+    #[schema(value_type = Vec<PiiType>)]
+    pub pii_types: sqlx::types::Json<Vec<PiiType>>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct GuardrailEventsResponse {
+    pub success: bool,
+    pub message: String,
+    /// Most recent first
+    pub events: Vec<GuardrailEventDb>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, ToSchema)]
+#[sqlx(type_name = "chat_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatSessionDb {
+    pub id: i64,
+    pub buyer_address: String,
+    pub agent_ids: Vec<i64>,
+    /// Latest payment of the session, the answers are attested for it
+    pub tx_hash: String,
+    pub turns_paid: i32,
+    pub turns_used: i32,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatMessageDb {
+    pub id: i64,
+    pub session_id: i64,
+    /// Turn of the session, starting at 1
+    pub turn: i32,
+    /// Agent that answered, none for the buyer prompt
+    pub agent_id: Option<i64>,
+    pub role: ChatRole,
+    pub content: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatSessionResponse {
+    pub success: bool,
+    pub session: ChatSessionDb,
+    /// Oldest first
+    pub messages: Vec<ChatMessageDb>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, ToSchema)]
+#[sqlx(type_name = "credit_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum CreditKind {
+    /// Share of a paid turn the agent gave no answer to
+    FailedAnswer,
+    /// Agents paid with credits instead of a tx
+    Spent,
+    /// Refunded to the buyer outside of the platform by an admin
+    Settled,
+}
+
+/// Entry of the credit ledger, positive when credited to the buyer and negative when debited.
+#[derive(Debug, Clone)]
+pub struct NewCreditEntry {
+    pub buyer_address: String,
+    pub amount: f64,
+    pub kind: CreditKind,
+    pub failed_query_id: Option<i64>,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreditBalanceDb {
+    pub buyer_address: String,
+    pub balance: f64,
+    /// Total credited for failed answers
+    pub credited: f64,
+    /// Total spent on answers
+    pub spent: f64,
+    /// Total settled by admins
+    pub settled: f64,
+    #[schema(value_type = String, format = DateTime)]
+    pub last_entry_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreditBalancesResponse {
+    pub success: bool,
+    /// Largest balances first
+    pub balances: Vec<CreditBalanceDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SettleCreditsRequest {
+    /// Amount refunded to the buyer, the whole balance when not set
+    pub amount: Option<f64>,
+    /// How the buyer was refunded, e.g. the refund tx hash
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SettleCreditsResponse {
+    pub success: bool,
+    pub buyer_address: String,
+    pub settled: f64,
+    pub balance: f64,
+}
+
 pub type WebAppState = web::Data<AppState>;
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -255,4 +788,180 @@ pub struct ProfileResponse {
     pub sucess: bool,
     pub message: String,
     pub agents: Vec<AgentDb>,
+    /// Earnings totals of the agents owned by the user
+    pub earnings: EarningsSummary,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EarningsQueryParams {
+    /// Number of days covered by the daily series (default: 30, max: 365)
+    pub days: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EarningsSummary {
+    /// Sum of every indexed `DatasetUsed` payment for the owner agents
+    pub total_earned: f64,
+    /// Sum of every indexed `AmountClaimed` withdrawal for the owner agents
+    pub total_claimed: f64,
+    /// Amount the owner can still claim, read from the contract when reachable
+    pub total_unclaimed: f64,
+    /// Number of paid queries on the owner agents
+    pub total_queries: i64,
+    /// `getTotalEarnedByOwner` from the contract, `None` when the RPC call failed
+    pub onchain_total_earned: Option<f64>,
+    /// `getTotalUnclaimedByOwner` from the contract, `None` when the RPC call failed
+    pub onchain_total_unclaimed: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct AgentEarningsDb {
+    pub agent_id: i64,
+    pub agent_name: String,
+    pub nft_id: Option<i64>,
+    pub price: f64,
+    pub total_earned: f64,
+    pub total_claimed: f64,
+    pub query_count: i64,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentEarnings {
+    #[serde(flatten)]
+    pub earnings: AgentEarningsDb,
+    /// `getUnclaimedAmount` of the agent NFT, falls back to earned - claimed
+    pub unclaimed: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct DailyEarningsDb {
+    #[schema(value_type = String, format = Date)]
+    pub day: NaiveDate,
+    pub earned: f64,
+    pub query_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EarningsResponse {
+    pub success: bool,
+    pub message: String,
+    pub address: String,
+    pub summary: EarningsSummary,
+    pub agents: Vec<AgentEarnings>,
+    /// Earnings per day, oldest first. Days without usage are omitted
+    pub daily: Vec<DailyEarningsDb>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuthNonceRequest {
+    /// Wallet address that wants to sign in
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuthNonceResponse {
+    pub success: bool,
+    /// One time nonce embedded in the message
+    pub nonce: String,
+    /// EIP-4361 message the wallet must sign
+    pub message: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuthVerifyRequest {
+    /// Nonce returned by `/auth/nonce`
+    pub nonce: String,
+    /// EIP-191 signature of the SIWE message
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuthVerifyResponse {
+    pub success: bool,
+    /// Bearer token to send in the `Authorization` header
+    pub token: String,
+    /// Verified wallet address
+    pub address: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct AuthNonceDb {
+    pub address: String,
+    pub message: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct SessionDb {
+    pub address: String,
+}
+
+/// Agent created from an uploaded dataset.
+#[derive(Debug, Clone)]
+pub struct NewAgent {
+    pub name: String,
+    pub description: String,
+    pub price: f64,
+    pub owner_id: i64,
+    pub dataset_path: String,
+    pub category: AgentCategory,
+    pub format: DatasetFormat,
+    pub dp: Option<DpSettings>,
+    /// Size of the uploaded file in bytes
+    pub dataset_size: f64,
+}
+
+/// Position of a contract log on chain, recorded when the log is applied.
+#[derive(Debug, Clone)]
+pub struct NewIngestedLog {
+    pub contract_address: String,
+    pub event_name: String,
+    pub block_number: i64,
+    pub block_hash: String,
+    pub tx_hash: String,
+    pub log_index: i64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct IngestedLogDb {
+    pub event_name: String,
+    pub block_number: i64,
+    pub agent_id: Option<i64>,
+    pub nft_id: Option<i64>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct IngestedBlockDb {
+    pub block_number: i64,
+    pub block_hash: String,
+}
+
+/// Decoded `DatasetUsed` or `AmountClaimed` event, ready to be stored in the local ledger.
+#[derive(Debug, Clone)]
+pub struct NewLedgerEvent {
+    pub nft_id: i64,
+    /// Buyer for usage events, dataset owner for claim events
+    pub address: String,
+    pub amount: f64,
+    pub amount_wei: String,
+    pub block_timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize, Deserialize, Clone, ToSchema)]
+pub struct UsageEventDb {
+    pub id: i64,
+    pub nft_id: i64,
+    pub agent_id: Option<i64>,
+    pub user_address: String,
+    pub amount: f64,
+    pub amount_wei: String,
+    pub tx_hash: String,
+    pub block_number: i64,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub block_timestamp: Option<DateTime<Utc>>,
 }
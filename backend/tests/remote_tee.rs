@@ -0,0 +1,300 @@
+//! Runs the remote executor against the mock enclave binary, over the real protocol.
+
+use std::{
+    collections::HashMap,
+    net::TcpListener,
+    path::PathBuf,
+    process::{Child, Command},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+use futures_util::StreamExt;
+
+use enclava_backend::{
+    helpers::crypto::{SealedDataset, encrypt_dataset_stream},
+    storage::{DatasetStore, fs::FsDatasetStore},
+    tee::{
+        AgentLoad, TeeExecutor, TeeStreamEvent, attestation::verify_agent_attestation,
+        protocol::ChatTurn, remote::RemoteTeeExecutor,
+    },
+    types::{AgentCategory, AgentDb, DatasetFormat, GuardrailVerdict, PiiAction, PiiType},
+};
+
+const MASTER_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+const SIGNING_KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+const TX_HASH: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+
+/// Mock enclave process, killed with the test.
+struct MockEnclave {
+    child: Child,
+    addr: String,
+}
+
+impl MockEnclave {
+    async fn start() -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("No free port")
+            .port();
+
+        let addr = format!("127.0.0.1:{}", port);
+
+        let child = Command::new(env!("CARGO_BIN_EXE_mock_enclave"))
+            .env("TEE_REMOTE_ADDR", &addr)
+            .env("DATASET_MASTER_KEY", MASTER_KEY)
+            .env("ATTESTATION_SIGNING_KEY", SIGNING_KEY)
+            .spawn()
+            .expect("Failed to start the mock enclave");
+
+        let enclave = Self { child, addr };
+
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(&enclave.addr).await.is_ok() {
+                return enclave;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("Mock enclave did not listen on {}", enclave.addr);
+    }
+}
+
+impl Drop for MockEnclave {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+    }
+}
+
+/// Store holding `csv` encrypted under `MASTER_KEY`, as an upload leaves it.
+async fn dataset_store(csv: &str) -> (PathBuf, Arc<dyn DatasetStore>) {
+    let root = std::env::temp_dir().join(format!("enclava-remote-tee-{}", uuid::Uuid::new_v4()));
+    let store: Arc<dyn DatasetStore> = Arc::new(FsDatasetStore::new(&root));
+
+    let mut encrypted = Vec::new();
+
+    encrypt_dataset_stream(&mut csv.as_bytes(), &mut encrypted, &master_key())
+        .await
+        .unwrap();
+
+    store.put("agent.csv", encrypted).await.unwrap();
+
+    (root, store)
+}
+
+fn master_key() -> Vec<u8> {
+    alloy::hex::decode(MASTER_KEY).unwrap()
+}
+
+fn agent_db() -> AgentDb {
+    AgentDb {
+        id: 1,
+        name: "Customers".to_string(),
+        description: "Customers and their spending".to_string(),
+        price: 1.0,
+        owner_id: 1,
+        owner_address: "0x0000000000000000000000000000000000000001".to_string(),
+        dataset_path: "agent.csv".to_string(),
+        category: AgentCategory::Analytics,
+        format: DatasetFormat::Csv,
+        dp_enabled: false,
+        dp_epsilon: None,
+        dp_budget: None,
+        dp_bounds: None,
+        dataset_size: 1.0,
+        nft_id: None,
+        nft_tx: None,
+        status: "active".to_string(),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+#[tokio::test]
+async fn remote_executor_ingests_loads_and_answers_through_the_mock_enclave() {
+    let enclave = MockEnclave::start().await;
+
+    let mut csv = String::from("name,email,amount\n");
+
+    for i in 0..120 {
+        csv.push_str(&format!("customer {},customer{}@example.com,{}\n", i, i, i));
+    }
+
+    let (root, store) = dataset_store(&csv).await;
+    let tee = RemoteTeeExecutor::new(enclave.addr.clone(), store.clone());
+    let agent_db = agent_db();
+
+    // The enclave redacts, profiles and chunks the dataset, the host only stores the results
+    let pii_actions = HashMap::from([("email".to_string(), PiiAction::Hash)]);
+
+    let ingest = tee
+        .ingest_dataset(&agent_db, Some(&pii_actions))
+        .await
+        .unwrap();
+
+    let pii_report = ingest.pii_report.unwrap();
+
+    assert_eq!(pii_report.columns[0].name, "email");
+    assert_eq!(pii_report.columns[0].redacted_values, 120);
+    assert_eq!(ingest.profile.row_count, 120);
+    assert!(!ingest.fingerprints.is_empty());
+    assert_eq!(ingest.chunks.len(), 3);
+    assert_eq!(ingest.chunks[2].first_row, 101);
+    assert_eq!(ingest.chunks[2].last_row, 120);
+
+    // The redacted dataset replaced the stored one
+    let stored = SealedDataset::fetch(store.as_ref(), "agent.csv", &master_key())
+        .await
+        .unwrap();
+    let first_row = stored
+        .csv_reader()
+        .unwrap()
+        .records()
+        .next()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&first_row[0], "customer 0");
+    assert_ne!(&first_row[1], "customer0@example.com");
+
+    assert!(!tee.has_agent(agent_db.id));
+
+    let load = AgentLoad {
+        chunks: ingest.chunks,
+        fingerprints: ingest.fingerprints,
+        pii_report: Some(pii_report),
+    };
+
+    tee.load_agent(&agent_db, load).await.unwrap();
+
+    assert!(tee.has_agent(agent_db.id));
+    assert_eq!(tee.agent_count(), 1);
+
+    let history = [ChatTurn {
+        prompt: "How many customers?".to_string(),
+        response: "120".to_string(),
+    }];
+
+    let quote = tee.quote().await.unwrap();
+
+    let answer = tee
+        .prompt(agent_db.id, "Who spent the most?", &history, TX_HASH)
+        .await
+        .unwrap();
+    let response = &answer.guarded.response;
+
+    assert_eq!(answer.guarded.verdict, GuardrailVerdict::Allowed);
+    assert!(
+        response
+            .contains("Customers holds 120 rows in 3 chunks with the columns name, email, amount")
+    );
+    assert!(response.ends_with("received after 1 earlier turns: Who spent the most?"));
+
+    // The enclave signed the answer it generated, under its own quote
+    assert_eq!(
+        answer.attestation.signer_address,
+        quote.signer_address.to_string()
+    );
+    assert!(
+        verify_agent_attestation(
+            &answer.attestation,
+            agent_db.id,
+            "Who spent the most?",
+            response,
+            Some(&quote.measurement),
+        )
+        .unwrap()
+    );
+
+    let mut stream = tee
+        .prompt_stream(agent_db.id, "Who spent the most?", &history, TX_HASH)
+        .await
+        .unwrap();
+
+    let mut streamed = String::new();
+    let mut end = None;
+
+    while let Some(event) = stream.next().await {
+        match event.unwrap() {
+            TeeStreamEvent::Chunk(text) => streamed.push_str(&text),
+            TeeStreamEvent::End { answer, .. } => end = Some(answer),
+        }
+    }
+
+    let end = end.unwrap();
+
+    assert_eq!(&streamed, response);
+    assert_eq!(&end.guarded.response, response);
+    assert!(
+        verify_agent_attestation(
+            &end.attestation,
+            agent_db.id,
+            "Who spent the most?",
+            &streamed,
+            Some(&quote.measurement),
+        )
+        .unwrap()
+    );
+
+    // The enclave runs the output guardrail before signing, the hashed emails must not leak
+    let answer = tee
+        .prompt(agent_db.id, "Write to customer7@example.com", &[], TX_HASH)
+        .await
+        .unwrap();
+
+    assert_eq!(answer.guarded.verdict, GuardrailVerdict::Redacted);
+    assert_eq!(answer.guarded.pii_types, vec![PiiType::Email]);
+    assert!(!answer.guarded.response.contains("customer7@example.com"));
+    assert!(
+        verify_agent_attestation(
+            &answer.attestation,
+            agent_db.id,
+            "Write to customer7@example.com",
+            &answer.guarded.response,
+            Some(&quote.measurement),
+        )
+        .unwrap()
+    );
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[tokio::test]
+async fn remote_executor_reports_agents_the_enclave_never_loaded() {
+    let enclave = MockEnclave::start().await;
+
+    let (root, store) = dataset_store("name,amount\nalice,1\n").await;
+    let tee = RemoteTeeExecutor::new(enclave.addr.clone(), store);
+
+    let error = tee
+        .prompt(42, "Anyone there?", &[], TX_HASH)
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("Agent 42 is not loaded"));
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[tokio::test]
+async fn remote_executor_reports_unknown_pii_columns() {
+    let enclave = MockEnclave::start().await;
+
+    let (root, store) = dataset_store("name,amount\nalice,1\n").await;
+    let tee = RemoteTeeExecutor::new(enclave.addr.clone(), store);
+
+    let pii_actions = HashMap::from([("email".to_string(), PiiAction::Drop)]);
+
+    let error = tee
+        .ingest_dataset(&agent_db(), Some(&pii_actions))
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("unknown column email"));
+
+    std::fs::remove_dir_all(root).ok();
+}
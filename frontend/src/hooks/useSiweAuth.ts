@@ -0,0 +1,67 @@
+import { useState } from "react";
+import { useAccount, useSignMessage } from "wagmi";
+import {
+  requestAuthNonce,
+  verifyAuthSignature,
+  getStoredSession,
+  storeSession,
+  clearStoredSession,
+} from "../services/api";
+
+export interface UseSiweAuthReturn {
+  // Returns the session token of the connected wallet, signing in first if needed
+  ensureSession: () => Promise<string>;
+  signOut: () => void;
+  isSigningIn: boolean;
+  isSignedIn: boolean;
+}
+
+export const useSiweAuth = (): UseSiweAuthReturn => {
+  const { address } = useAccount();
+  const { signMessageAsync } = useSignMessage();
+  const [isSigningIn, setIsSigningIn] = useState(false);
+  const [, setSessionVersion] = useState(0);
+
+  const ensureSession = async (): Promise<string> => {
+    if (!address) {
+      throw new Error("Wallet not connected");
+    }
+
+    const stored = getStoredSession(address);
+    if (stored) {
+      return stored.token;
+    }
+
+    try {
+      setIsSigningIn(true);
+
+      // Sign the EIP-4361 message of a fresh nonce with the wallet
+      const { nonce, message } = await requestAuthNonce(address);
+      const signature = await signMessageAsync({ message });
+      const session = await verifyAuthSignature(nonce, signature);
+
+      storeSession({
+        token: session.token,
+        address: session.address,
+        expires_at: session.expires_at,
+      });
+      setSessionVersion((version) => version + 1);
+
+      return session.token;
+    } finally {
+      setIsSigningIn(false);
+    }
+  };
+
+  const signOut = () => {
+    clearStoredSession();
+    setSessionVersion((version) => version + 1);
+  };
+
+  return {
+    ensureSession,
+    signOut,
+    isSigningIn,
+    isSignedIn: !!address && getStoredSession(address) !== null,
+  };
+};
@@ -15,9 +15,11 @@ import {
   getChatAnswer,
   ChatAgent,
   AgentResponse,
+  AgentSynthesis,
   ApiError,
 } from "../services/api";
 import { useDatasetPayment } from "../hooks/useDatasetPayment";
+import { useSiweAuth } from "../hooks/useSiweAuth";
 import { useAccount } from "wagmi";
 
 interface Message {
@@ -27,6 +29,14 @@ interface Message {
   timestamp: Date;
   suggestedAgents?: ChatAgent[];
   agentResponses?: AgentResponse[];
+  synthesis?: AgentSynthesis | null;
+}
+
+// Paid chat session, follow-up questions go to its agents until its turns run out
+interface ChatSession {
+  id: number;
+  agents: ChatAgent[];
+  turnsLeft: number;
 }
 
 enum ChatPhase {
@@ -37,6 +47,7 @@ enum ChatPhase {
 
 const Chat: React.FC = () => {
   const { isConnected } = useAccount();
+  const { ensureSession, isSigningIn } = useSiweAuth();
   const {
     payForDatasets,
     isPending: isPaymentPending,
@@ -62,9 +73,11 @@ const Chat: React.FC = () => {
   const [chatPhase, setChatPhase] = useState<ChatPhase>(ChatPhase.INITIAL);
   const [isLoading, setIsLoading] = useState(false);
   const [currentPrompt, setCurrentPrompt] = useState("");
+  const [chatSession, setChatSession] = useState<ChatSession | null>(null);
   const messagesEndRef = useRef<HTMLDivElement>(null);
   const [useScraping, setUseScraping] = useState(false);
   const [useOwnData, setUseOwnData] = useState(false);
+  const [useSynthesis, setUseSynthesis] = useState(false);
 
   const scrollToBottom = () => {
     messagesEndRef.current?.scrollIntoView({ behavior: "smooth" });
@@ -157,24 +170,54 @@ const Chat: React.FC = () => {
     }
   };
 
+  const showAgentResponses = (
+    responses: AgentResponse[],
+    synthesis: AgentSynthesis | null,
+    session: ChatSession
+  ) => {
+    const aiMessage: Message = {
+      id: Date.now().toString(),
+      type: "ai",
+      content: `Here are the insights from your selected datasets (${
+        session.turnsLeft
+      } follow-up question${session.turnsLeft === 1 ? "" : "s"} left):`,
+      timestamp: new Date(),
+      agentResponses: responses,
+      synthesis,
+    };
+    setMessages((prev) => [...prev, aiMessage]);
+  };
+
+  // Without a tx hash the agents are paid with the credits of the buyer
   const getAnswerFromSelectedDatasets = async (
     prompt: string,
-    txHash: string
+    txHash?: string
   ) => {
     try {
       setIsLoading(true);
-      const responses = await getChatAnswer(selectedAgents, prompt, txHash);
+      const token = await ensureSession();
+      const result = await getChatAnswer(
+        {
+          agent_ids: selectedAgents,
+          prompt,
+          tx_hash: txHash,
+          synthesize: useSynthesis,
+          use_credits: !txHash,
+        },
+        token
+      );
 
-      const aiMessage: Message = {
-        id: Date.now().toString(),
-        type: "ai",
-        content: "Here are the insights from your selected datasets:",
-        timestamp: new Date(),
-        agentResponses: responses,
+      const session: ChatSession = {
+        id: result.session_id,
+        agents: suggestedAgents.filter((agent) =>
+          selectedAgents.includes(agent.id)
+        ),
+        turnsLeft: result.turns_left,
       };
-      setMessages((prev) => [...prev, aiMessage]);
+      setChatSession(session);
+      showAgentResponses(result.agent_responses, result.synthesis, session);
 
-      // Reset state after getting answer - ready for next question cycle
+      // Reset state after getting answer - ready for follow-up questions
       setSelectedAgents([]);
       setSuggestedAgents([]);
       setChatPhase(ChatPhase.INITIAL);
@@ -198,6 +241,46 @@ const Chat: React.FC = () => {
     }
   };
 
+  // Follow-up question on a paid session, its history gives the agents the context
+  const getFollowUpAnswer = async (prompt: string, session: ChatSession) => {
+    try {
+      setIsLoading(true);
+      const token = await ensureSession();
+      const result = await getChatAnswer(
+        {
+          agent_ids: [],
+          prompt,
+          session_id: session.id,
+          synthesize: useSynthesis,
+        },
+        token
+      );
+
+      const updatedSession = { ...session, turnsLeft: result.turns_left };
+      setChatSession(updatedSession);
+      showAgentResponses(
+        result.agent_responses,
+        result.synthesis,
+        updatedSession
+      );
+    } catch (error) {
+      if (error instanceof ApiError) {
+        toast.error(`Error: ${error.message}`, {
+          position: "top-right",
+          autoClose: 5000,
+        });
+      } else {
+        toast.error("Failed to get answer from datasets. Please try again.", {
+          position: "top-right",
+          autoClose: 5000,
+        });
+      }
+      console.error("Error getting chat answer:", error);
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
   const handleSendMessage = async () => {
     if (!inputMessage.trim() || isLoading) return;
 
@@ -212,7 +295,14 @@ const Chat: React.FC = () => {
     const messageContent = inputMessage;
     setInputMessage("");
 
-    // Every question starts the workflow from the beginning
+    // Questions go to the paid session while it has turns left
+    if (chatSession && chatSession.turnsLeft > 0) {
+      await getFollowUpAnswer(messageContent, chatSession);
+      return;
+    }
+
+    // Otherwise the question starts the workflow from the beginning
+    setChatSession(null);
     await handleInitialQuestion(messageContent);
   };
 
@@ -248,6 +338,22 @@ const Chat: React.FC = () => {
       return;
     }
 
+    // Sign in before paying, the answer endpoint only serves the paying wallet
+    try {
+      await ensureSession();
+    } catch (error) {
+      toast.error(
+        `Sign in failed: ${
+          error instanceof Error ? error.message : "signature rejected"
+        }`,
+        {
+          position: "top-right",
+          autoClose: 5000,
+        }
+      );
+      return;
+    }
+
     const selectedAgentData = selectedAgents
       .map((id) => {
         const agent = suggestedAgents.find((agent) => agent.id === id);
@@ -307,7 +413,28 @@ const Chat: React.FC = () => {
     }
   };
 
+  const handlePayWithCredits = async () => {
+    if (selectedAgents.length === 0) {
+      toast.error("Please select at least one dataset to analyze.", {
+        position: "top-right",
+        autoClose: 3000,
+      });
+      return;
+    }
+
+    const systemMessage: Message = {
+      id: Date.now().toString(),
+      type: "system",
+      content: `Paying ${selectedAgents.length} dataset(s) with your credits...`,
+      timestamp: new Date(),
+    };
+    setMessages((prev) => [...prev, systemMessage]);
+
+    await getAnswerFromSelectedDatasets(currentPrompt);
+  };
+
   const resetChat = () => {
+    setChatSession(null);
     setSelectedAgents([]);
     setSuggestedAgents([]);
     setChatPhase(ChatPhase.INITIAL);
@@ -340,7 +467,7 @@ const Chat: React.FC = () => {
                 </p>
               </div>
             </div>
-            {chatPhase !== ChatPhase.INITIAL && (
+            {(chatPhase !== ChatPhase.INITIAL || chatSession) && (
               <button
                 onClick={resetChat}
                 className="px-4 py-2 bg-duck-yellow text-black font-black uppercase border-2 border-duck-yellow hover:bg-white hover:text-duck-yellow transition-colors rounded text-sm"
@@ -391,13 +518,26 @@ const Chat: React.FC = () => {
                     <div className="flex-1">
                       <p className="whitespace-pre-wrap">{message.content}</p>
 
+                      {/* Display the synthesis of the agent responses */}
+                      {message.synthesis && (
+                        <div className="mt-4 border-l-4 border-black pl-4 bg-yellow-50 p-3 rounded">
+                          <h4 className="font-black text-sm uppercase mb-2">
+                            Combined answer
+                          </h4>
+                          <p className="text-sm text-gray-800 whitespace-pre-wrap">
+                            {message.synthesis.response}
+                          </p>
+                        </div>
+                      )}
+
                       {/* Display agent responses */}
                       {message.agentResponses && (
                         <div className="mt-4 space-y-3">
                           {message.agentResponses.map((response, index) => {
-                            const agent = suggestedAgents.find(
-                              (a) => a.id === response.agent_id
-                            );
+                            const agent = [
+                              ...suggestedAgents,
+                              ...(chatSession?.agents ?? []),
+                            ].find((a) => a.id === response.agent_id);
                             return (
                               <div
                                 key={index}
@@ -407,9 +547,41 @@ const Chat: React.FC = () => {
                                   {agent?.name ||
                                     `Dataset ${response.agent_id}`}
                                 </h4>
-                                <p className="text-sm text-gray-800">
-                                  {response.response}
-                                </p>
+                                {response.status === "answered" ? (
+                                  <p className="text-sm text-gray-800">
+                                    {response.response}
+                                  </p>
+                                ) : (
+                                  <p className="text-sm text-red-600 font-mono">
+                                    {response.status === "timed_out"
+                                      ? "This dataset did not answer in time."
+                                      : `This dataset failed to answer: ${
+                                          response.error ?? "unknown error"
+                                        }.`}{" "}
+                                    Its price was added to your credits.
+                                  </p>
+                                )}
+                                {response.guardrail_verdict === "redacted" && (
+                                  <p className="text-xs text-yellow-700 font-mono mt-2">
+                                    Personal or raw dataset content was
+                                    removed from this answer
+                                  </p>
+                                )}
+                                {response.guardrail_verdict === "blocked" && (
+                                  <p className="text-xs text-red-600 font-mono mt-2">
+                                    This answer was withheld because it
+                                    reproduced the dataset
+                                  </p>
+                                )}
+                                {response.attestation && (
+                                  <p
+                                    className="text-xs text-gray-500 font-mono mt-2"
+                                    title={`Signed by ${response.attestation.signer_address}`}
+                                  >
+                                    Attested by {response.attestation.provider}{" "}
+                                    enclave
+                                  </p>
+                                )}
                               </div>
                             );
                           })}
@@ -465,9 +637,11 @@ const Chat: React.FC = () => {
                 onChange={(e) => setInputMessage(e.target.value)}
                 onKeyPress={handleKeyPress}
                 placeholder={
-                  chatPhase === ChatPhase.INITIAL
-                    ? "Ask me anything about the available datasets..."
-                    : "Select datasets from the sidebar to get your answer..."
+                  chatPhase !== ChatPhase.INITIAL
+                    ? "Select datasets from the sidebar to get your answer..."
+                    : chatSession && chatSession.turnsLeft > 0
+                    ? "Ask a follow-up question to the same datasets..."
+                    : "Ask me anything about the available datasets..."
                 }
                 className="w-full p-3 border-2 border-gray-300 rounded-lg font-mono text-black placeholder-gray-500 focus:border-duck-yellow focus:outline-none resize-none"
                 rows={2}
@@ -503,11 +677,23 @@ const Chat: React.FC = () => {
                 {chatPhase === ChatPhase.AGENT_SELECTION && (
                   <button
                     onClick={handleAnalyzeWithSelectedDatasets}
-                    disabled={!isConnected}
+                    disabled={!isConnected || isSigningIn}
                     className="w-full px-4 py-2 bg-duck-yellow text-black font-black uppercase border-2 border-duck-yellow hover:bg-white hover:text-duck-yellow transition-colors rounded disabled:opacity-50 disabled:cursor-not-allowed"
                   >
                     <CreditCard className="inline mr-2" size={16} />
-                    Pay & Analyze ({selectedAgents.length})
+                    {isSigningIn
+                      ? "Sign In With Wallet..."
+                      : `Pay & Analyze (${selectedAgents.length})`}
+                  </button>
+                )}
+
+                {chatPhase === ChatPhase.AGENT_SELECTION && (
+                  <button
+                    onClick={handlePayWithCredits}
+                    disabled={!isConnected || isSigningIn || isLoading}
+                    className="w-full mt-2 px-4 py-2 bg-black text-duck-yellow font-black uppercase border-2 border-duck-yellow hover:bg-duck-yellow hover:text-black transition-colors rounded disabled:opacity-50 disabled:cursor-not-allowed"
+                  >
+                    Use My Credits
                   </button>
                 )}
 
@@ -573,6 +759,14 @@ const Chat: React.FC = () => {
                               💰 {agent.price} TON
                             </span>
                           </div>
+                          {agent.dp_enabled && (
+                            <div
+                              className="text-xs text-blue-600 font-mono mt-1"
+                              title={`Each answer spends ε ${agent.dp_epsilon} of a ${agent.dp_budget} budget per buyer`}
+                            >
+                              🛡️ Differentially private aggregates only
+                            </div>
+                          )}
                           <div className="text-xs text-gray-500 font-mono mt-1">
                             Status:{" "}
                             <span className="uppercase font-black">
@@ -629,6 +823,17 @@ const Chat: React.FC = () => {
               Use Your Own Data
             </span>
           </label>
+          <label className="flex items-center gap-3 cursor-pointer">
+            <input
+              type="checkbox"
+              checked={useSynthesis}
+              onChange={() => setUseSynthesis(!useSynthesis)}
+              className="accent-duck-yellow w-5 h-5 rounded focus:ring-2 focus:ring-duck-yellow"
+            />
+            <span className="font-mono text-sm text-black">
+              Combine Answers Of Datasets
+            </span>
+          </label>
         </div>
       </div>
     </div>
@@ -12,8 +12,10 @@ import {
   UploadDatasetSuccessResponse,
   GenerateDatasetDetailsResponse,
   ApiError,
+  SUPPORTED_DATASET_EXTENSIONS,
 } from "../services/api";
 import { DATASET_NFT_CONTRACT } from "../contracts/DatasetNFT";
+import { useSiweAuth } from "../hooks/useSiweAuth";
 import { duckchainMainnet } from "../wagmi.config";
 
 interface FormData {
@@ -48,6 +50,7 @@ interface FormErrors {
 
 const UploadDataset: React.FC = () => {
   const { address, isConnected } = useAccount();
+  const { ensureSession, isSigningIn, isSignedIn } = useSiweAuth();
 
   // Step management
   const [currentStep, setCurrentStep] = useState<"upload" | "review">("upload");
@@ -93,8 +96,12 @@ const UploadDataset: React.FC = () => {
 
     if (!formData.file) {
       newErrors.file = "FILE UPLOAD IS REQUIRED";
-    } else if (!formData.file.name.toLowerCase().endsWith(".csv")) {
-      newErrors.file = "ONLY CSV FILES ARE SUPPORTED";
+    } else if (
+      !SUPPORTED_DATASET_EXTENSIONS.some((extension) =>
+        formData.file!.name.toLowerCase().endsWith(extension)
+      )
+    ) {
+      newErrors.file = "ONLY CSV, TSV, JSONL, PARQUET AND XLSX FILES ARE SUPPORTED";
     }
 
     setErrors(newErrors);
@@ -230,17 +237,36 @@ const UploadDataset: React.FC = () => {
       return;
     }
 
+    // The dataset is published under the wallet that signs in
+    let token: string;
+    try {
+      token = await ensureSession();
+    } catch (error) {
+      toast.error(
+        `Sign in failed: ${
+          error instanceof Error ? error.message : "signature rejected"
+        }`,
+        {
+          position: "top-right",
+          autoClose: 5000,
+        }
+      );
+      return;
+    }
+
     setIsSubmitting(true);
 
     try {
-      const response = await uploadDataset({
-        name: formData.name,
-        description: formData.description,
-        category: formData.category,
-        dataset_price: formData.dataset_price,
-        user_address: address!,
-        file: formData.file!,
-      });
+      const response = await uploadDataset(
+        {
+          name: formData.name,
+          description: formData.description,
+          category: formData.category,
+          dataset_price: formData.dataset_price,
+          file: formData.file!,
+        },
+        token
+      );
 
       setUploadResponse(response);
       setIsSubmitted(true);
@@ -257,6 +283,21 @@ const UploadDataset: React.FC = () => {
         }
       );
 
+      const redactedColumns = (response.pii_report?.columns ?? []).filter(
+        (column) => column.action !== "keep"
+      );
+      if (redactedColumns.length > 0) {
+        toast.info(
+          `Personal data found and redacted in: ${redactedColumns
+            .map((column) => `${column.name} (${column.action})`)
+            .join(", ")}`,
+          {
+            position: "top-right",
+            autoClose: 8000,
+          }
+        );
+      }
+
       // Start NFT minting process immediately after successful upload
       await mintDatasetNFT(response.dataset_id);
     } catch (error) {
@@ -686,14 +727,14 @@ const UploadDataset: React.FC = () => {
                         DROP FILE HERE OR CLICK TO UPLOAD
                       </p>
                       <p className="font-mono text-sm text-gray-500">
-                        SUPPORTED: CSV FILES ONLY
+                        SUPPORTED: CSV, TSV, JSONL, PARQUET, XLSX
                       </p>
                       <input
                         type="file"
                         onChange={handleFileSelect}
                         className="hidden"
                         id="file-upload"
-                        accept=".csv"
+                        accept={SUPPORTED_DATASET_EXTENSIONS.join(",")}
                       />
                       <label
                         htmlFor="file-upload"
@@ -720,7 +761,7 @@ const UploadDataset: React.FC = () => {
                   🤖 AI-POWERED DATASET ANALYSIS
                 </h3>
                 <p className="font-mono text-sm text-gray-700">
-                  Our AI will automatically analyze your CSV file and generate:
+                  Our AI will automatically analyze your dataset file and generate:
                 </p>
                 <ul className="font-mono text-sm text-gray-700 mt-2 space-y-1">
                   <li>• Dataset name based on content</li>
@@ -845,6 +886,9 @@ const UploadDataset: React.FC = () => {
                       <span className="text-green-600 font-black uppercase">
                         WALLET CONNECTED: {address.slice(0, 6)}...
                         {address.slice(-4)}
+                        {isSignedIn
+                          ? " - SIGNED IN"
+                          : " - YOU WILL SIGN IN ON LISTING"}
                       </span>
                     </div>
                   ) : (
@@ -915,14 +959,18 @@ const UploadDataset: React.FC = () => {
                 </button>
                 <button
                   type="submit"
-                  disabled={isSubmitting}
+                  disabled={isSubmitting || isSigningIn}
                   className={`flex-1 border-4 py-4 font-black uppercase text-lg transition-colors ${
-                    isSubmitting
+                    isSubmitting || isSigningIn
                       ? "border-gray-300 bg-gray-300 text-gray-500 cursor-not-allowed"
                       : "border-duck-yellow bg-duck-yellow text-black hover:bg-white hover:border-duck-yellow"
                   }`}
                 >
-                  {isSubmitting ? "📤 UPLOADING..." : "🚀 LIST DATASET"}
+                  {isSigningIn
+                    ? "✍️ SIGN IN WITH YOUR WALLET..."
+                    : isSubmitting
+                    ? "📤 UPLOADING..."
+                    : "🚀 LIST DATASET"}
                 </button>
               </div>
             </>
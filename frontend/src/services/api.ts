@@ -1,5 +1,7 @@
 const BASE_URL = "https://enclavaduck.eaglefi.io";
 
+const AUTH_STORAGE_KEY = "enclava_auth";
+
 export interface GenerateDatasetDetailsRequest {
   file: File;
 }
@@ -17,17 +19,30 @@ export interface UploadDatasetRequest {
   description: string;
   category: string;
   dataset_price: number;
-  user_address: string;
+  // Action per column name, columns with detected PII default to hash
+  pii_actions?: Record<string, PiiAction>;
+  // Differential privacy mode, epsilon per answer and per buyer budget
+  dp_epsilon?: number;
+  dp_budget?: number;
+  // Clamping bounds of the numeric columns that can be summed or averaged
+  dp_bounds?: Record<string, DpColumnBounds>;
   file: File;
 }
 
+export interface DpColumnBounds {
+  min: number;
+  max: number;
+}
+
 export interface UploadDatasetSuccessResponse {
   dataset_id: number;
   file_id: string;
   file_size: number;
   filename: string;
+  format: DatasetFormat;
   message: string;
   metadata: any;
+  pii_report: PiiReport | null;
   row_count: number;
   success: true;
 }
@@ -53,6 +68,139 @@ export class ApiError extends Error {
   }
 }
 
+// Auth API Interfaces
+export interface AuthNonceResponse {
+  success: boolean;
+  nonce: string;
+  // EIP-4361 message the wallet must sign
+  message: string;
+  expires_at: string;
+}
+
+export interface AuthVerifyResponse {
+  success: boolean;
+  // Bearer token to send in the Authorization header
+  token: string;
+  address: string;
+  expires_at: string;
+}
+
+export interface AuthSession {
+  token: string;
+  address: string;
+  expires_at: string;
+}
+
+// Auth API Functions
+export const requestAuthNonce = async (
+  address: string
+): Promise<AuthNonceResponse> => {
+  try {
+    const response = await fetch(`${BASE_URL}/auth/nonce`, {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ address }),
+    });
+
+    const result = await response.json();
+
+    if (!response.ok || !result.success) {
+      throw new ApiError(
+        result.message || `Failed to get sign in message: ${response.statusText}`,
+        response.status,
+        result.error_code
+      );
+    }
+
+    return result as AuthNonceResponse;
+  } catch (error) {
+    if (error instanceof ApiError) {
+      throw error;
+    }
+    throw new ApiError(
+      error instanceof Error ? error.message : "Network error occurred",
+      0
+    );
+  }
+};
+
+export const verifyAuthSignature = async (
+  nonce: string,
+  signature: string
+): Promise<AuthVerifyResponse> => {
+  try {
+    const response = await fetch(`${BASE_URL}/auth/verify`, {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ nonce, signature }),
+    });
+
+    const result = await response.json();
+
+    if (!response.ok || !result.success) {
+      throw new ApiError(
+        result.message || `Failed to sign in: ${response.statusText}`,
+        response.status,
+        result.error_code
+      );
+    }
+
+    return result as AuthVerifyResponse;
+  } catch (error) {
+    if (error instanceof ApiError) {
+      throw error;
+    }
+    throw new ApiError(
+      error instanceof Error ? error.message : "Network error occurred",
+      0
+    );
+  }
+};
+
+// Session of the wallet, if it has not expired yet
+export const getStoredSession = (address: string): AuthSession | null => {
+  const stored = localStorage.getItem(AUTH_STORAGE_KEY);
+  if (!stored) {
+    return null;
+  }
+
+  try {
+    const session: AuthSession = JSON.parse(stored);
+    if (
+      session.address.toLowerCase() !== address.toLowerCase() ||
+      new Date(session.expires_at).getTime() <= Date.now()
+    ) {
+      return null;
+    }
+    return session;
+  } catch {
+    return null;
+  }
+};
+
+export const storeSession = (session: AuthSession) => {
+  localStorage.setItem(AUTH_STORAGE_KEY, JSON.stringify(session));
+};
+
+export const clearStoredSession = () => {
+  localStorage.removeItem(AUTH_STORAGE_KEY);
+};
+
+const authHeaders = (token: string): Record<string, string> => ({
+  Authorization: `Bearer ${token}`,
+});
+
+// A rejected session token must be dropped so the next request signs in again
+const checkUnauthorized = (response: Response) => {
+  if (response.status === 401) {
+    clearStoredSession();
+  }
+};
+
 export const generateDatasetDetails = async (
   file: File
 ): Promise<GenerateDatasetDetailsResponse> => {
@@ -112,7 +260,8 @@ export const generateDatasetDetails = async (
 };
 
 export const uploadDataset = async (
-  data: UploadDatasetRequest
+  data: UploadDatasetRequest,
+  token: string
 ): Promise<UploadDatasetSuccessResponse> => {
   const formData = new FormData();
 
@@ -121,15 +270,26 @@ export const uploadDataset = async (
   formData.append("description", data.description);
   formData.append("category", data.category);
   formData.append("dataset_price", data.dataset_price.toString());
-  formData.append("user_address", data.user_address);
+  if (data.pii_actions) {
+    formData.append("pii_actions", JSON.stringify(data.pii_actions));
+  }
+  if (data.dp_epsilon !== undefined && data.dp_budget !== undefined) {
+    formData.append("dp_epsilon", data.dp_epsilon.toString());
+    formData.append("dp_budget", data.dp_budget.toString());
+    if (data.dp_bounds) {
+      formData.append("dp_bounds", JSON.stringify(data.dp_bounds));
+    }
+  }
   formData.append("file", data.file);
 
   try {
     const response = await fetch(`${BASE_URL}/dataset/upload`, {
       method: "POST",
+      headers: authHeaders(token),
       body: formData,
       // Don't set Content-Type header, let the browser set it with boundary for multipart/form-data
     });
+    checkUnauthorized(response);
 
     const result: UploadDatasetResponse = await response.json();
 
@@ -181,6 +341,18 @@ export const uploadDataset = async (
 };
 
 // Chat API Interfaces
+export type DatasetFormat = "csv" | "tsv" | "jsonl" | "parquet" | "xlsx";
+
+// Extensions accepted by the dataset upload, converted to CSV by the backend
+export const SUPPORTED_DATASET_EXTENSIONS = [
+  ".csv",
+  ".tsv",
+  ".jsonl",
+  ".ndjson",
+  ".parquet",
+  ".xlsx",
+];
+
 export interface ChatAgent {
   id: number;
   name: string;
@@ -189,6 +361,11 @@ export interface ChatAgent {
   owner_id: number;
   dataset_path: string;
   category: string;
+  format: DatasetFormat;
+  dp_enabled: boolean;
+  dp_epsilon: number | null;
+  dp_budget: number | null;
+  dp_bounds: Record<string, DpColumnBounds> | null;
   dataset_size: number;
   nft_id: number | null;
   nft_tx: string | null;
@@ -199,6 +376,12 @@ export interface ChatAgent {
 
 export interface ChatAgentsRequest {
   prompt: string;
+  // Only route to agents of this category
+  category?: string;
+  // Number of agents to return, by description similarity
+  top_k?: number;
+  // Let the model rerank the most similar agents
+  rerank?: boolean;
 }
 
 export interface ChatAgentsResponse {
@@ -206,31 +389,71 @@ export interface ChatAgentsResponse {
 }
 
 export interface ChatAnswerRequest {
+  // Agents of a new session, can be left empty to continue one
   agent_ids: number[];
   prompt: string;
-  tx_hashes: string[];
+  // Payment of a new session, or of more turns of session_id
+  tx_hash?: string;
+  session_id?: number;
+  // Also answer with one synthesis citing the agents
+  synthesize?: boolean;
+  // Pay with the credits of failed answers instead of a tx
+  use_credits?: boolean;
+}
+
+export interface AgentAttestation {
+  provider: string;
+  measurement: string;
+  signer_address: string;
+  quote: string;
+  prompt_hash: string;
+  response_hash: string;
+  tx_hash: string;
+  payload_hash: string;
+  signature: string;
 }
 
 export interface AgentResponse {
   agent_id: number;
   prompt: string;
   response: string;
+  attestation: AgentAttestation | null;
+  // Outcome of the output guardrail, null when the agent gave no answer
+  guardrail_verdict: "allowed" | "redacted" | "blocked" | null;
+  status: "answered" | "failed" | "timed_out";
+  // Why the agent gave no answer, when it failed or timed out
+  error: string | null;
+  error_code: string | null;
+}
+
+export interface AgentSynthesis {
+  response: string;
+  cited_agent_ids: number[];
 }
 
 export interface ChatAnswerResponse {
   agent_responses: AgentResponse[];
+  synthesis: AgentSynthesis | null;
+  session_id: number;
+  // Paid turns of the session that are left after this one
+  turns_left: number;
   success: boolean;
 }
 
 // Chat API Functions
-export const getChatAgents = async (prompt: string): Promise<ChatAgent[]> => {
+export const getChatAgents = async (
+  prompt: string,
+  options?: Omit<ChatAgentsRequest, "prompt">
+): Promise<ChatAgent[]> => {
   try {
+    const request: ChatAgentsRequest = { prompt, ...options };
+
     const response = await fetch(`${BASE_URL}/chat/agents`, {
       method: "POST",
       headers: {
         "Content-Type": "application/json",
       },
-      body: JSON.stringify({ prompt }),
+      body: JSON.stringify(request),
     });
 
     if (!response.ok) {
@@ -254,27 +477,27 @@ export const getChatAgents = async (prompt: string): Promise<ChatAgent[]> => {
 };
 
 export const getChatAnswer = async (
-  agentIds: number[],
-  prompt: string,
-  txHash: string
-): Promise<AgentResponse[]> => {
+  request: ChatAnswerRequest,
+  token: string
+): Promise<ChatAnswerResponse> => {
   try {
     const response = await fetch(`${BASE_URL}/chat/agents/answer`, {
       method: "POST",
       headers: {
         "Content-Type": "application/json",
+        ...authHeaders(token),
       },
-      body: JSON.stringify({
-        agent_ids: agentIds,
-        prompt,
-        tx_hash: txHash,
-      }),
+      body: JSON.stringify(request),
     });
+    checkUnauthorized(response);
 
     if (!response.ok) {
+      // Session and payment errors come with a message and an error code
+      const result = await response.json().catch(() => null);
       throw new ApiError(
-        `Failed to get chat answer: ${response.statusText}`,
-        response.status
+        result?.message || `Failed to get chat answer: ${response.statusText}`,
+        response.status,
+        result?.error_code
       );
     }
 
@@ -284,7 +507,7 @@ export const getChatAnswer = async (
       throw new ApiError("Chat answer request failed", response.status);
     }
 
-    return result.agent_responses;
+    return result;
   } catch (error) {
     if (error instanceof ApiError) {
       throw error;
@@ -306,6 +529,11 @@ export interface MarketplaceDataset {
   owner_address: string;
   dataset_path: string;
   category: string;
+  format: DatasetFormat;
+  dp_enabled: boolean;
+  dp_epsilon: number | null;
+  dp_budget: number | null;
+  dp_bounds: Record<string, DpColumnBounds> | null;
   dataset_size: number;
   nft_id: number | null;
   nft_tx: string | null;
@@ -314,6 +542,44 @@ export interface MarketplaceDataset {
   updated_at: string;
 }
 
+export interface ColumnProfile {
+  name: string;
+  inferred_type: "integer" | "real" | "boolean" | "date" | "datetime" | "text";
+  null_count: number;
+  null_ratio: number;
+  distinct_count: number;
+  distinct_capped: boolean;
+  min: string | null;
+  max: string | null;
+  sample_values: string[];
+}
+
+export interface DatasetProfile {
+  row_count: number;
+  columns: ColumnProfile[];
+}
+
+export type PiiAction = "drop" | "hash" | "mask" | "keep";
+
+export interface PiiColumnReport {
+  name: string;
+  detected: string[];
+  name_hint: boolean;
+  matches: number;
+  action: PiiAction;
+  redacted_values: number;
+}
+
+export interface PiiReport {
+  columns: PiiColumnReport[];
+}
+
+// Agent details are the agent fields with its dataset profile and PII report
+export interface DatasetDetails extends MarketplaceDataset {
+  profile: DatasetProfile | null;
+  pii_report: PiiReport | null;
+}
+
 export interface GetDatasetsRequest {
   search?: string;
   category?: string;
@@ -412,7 +678,7 @@ export const getUserProfile = async (
 // Dataset Details API Functions
 export const getDatasetDetails = async (
   datasetId: number
-): Promise<MarketplaceDataset> => {
+): Promise<DatasetDetails> => {
   try {
     const response = await fetch(`${BASE_URL}/agents/${datasetId}`, {
       method: "GET",
@@ -428,7 +694,7 @@ export const getDatasetDetails = async (
       );
     }
 
-    const result: MarketplaceDataset = await response.json();
+    const result: DatasetDetails = await response.json();
     return result;
   } catch (error) {
     if (error instanceof ApiError) {